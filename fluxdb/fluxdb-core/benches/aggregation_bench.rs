@@ -0,0 +1,42 @@
+//! Aggregation over many GROUP BY groups, sequential vs parallel
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fluxdb_core::query::{QueryExecutor, QueryParser, QueryPlanner};
+use fluxdb_core::{DataPoint, FieldValue, SeriesKey};
+
+const GROUPS: usize = 10_000;
+const POINTS_PER_GROUP: usize = 100;
+
+fn data() -> Vec<(SeriesKey, DataPoint)> {
+    (0..GROUPS * POINTS_PER_GROUP)
+        .map(|i| {
+            let key = SeriesKey::new("cpu").with_tag("host", format!("host-{}", i % GROUPS));
+            let value = FieldValue::Float((i % 1_000) as f64 / 10.0);
+            (key, DataPoint::new(i as i64, "usage", value))
+        })
+        .collect()
+}
+
+fn bench_group_by(c: &mut Criterion) {
+    let data = data();
+    let query = QueryParser::parse(
+        "SELECT mean(usage), median(usage), stddev(usage) FROM cpu GROUP BY host",
+    )
+    .unwrap();
+
+    let mut group = c.benchmark_group("aggregate_10k_groups");
+    group.sample_size(10);
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    for threads in [1, cores] {
+        let mut plan = QueryPlanner::plan(&query).unwrap();
+        plan.parallelism = threads;
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &plan, |b, plan| {
+            b.iter(|| pool.install(|| QueryExecutor::execute(plan, data.clone()).unwrap()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_group_by);
+criterion_main!(benches);
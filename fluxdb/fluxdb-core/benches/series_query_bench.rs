@@ -0,0 +1,46 @@
+//! One series out of many: typed API and SQL fast path vs a measurement scan
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use fluxdb_core::storage::{Database, StorageConfig};
+use fluxdb_core::{DataPoint, FieldValue, Point, SeriesKey, TimeRange};
+
+const SERIES: usize = 1_000;
+const POINTS_PER_SERIES: usize = 100;
+
+fn bench_single_series(c: &mut Criterion) {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let config = StorageConfig {
+        data_dir: temp_dir.path().to_path_buf(),
+        ..Default::default()
+    };
+    let db = Database::open("bench", &config).unwrap();
+    let points: Vec<Point> = (0..SERIES * POINTS_PER_SERIES)
+        .map(|i| {
+            let key = SeriesKey::new("cpu")
+                .with_tag("host", format!("host-{}", i % SERIES))
+                .with_tag("rack", format!("rack-{}", i % SERIES / 2));
+            let value = FieldValue::Float((i % 1_000) as f64 / 10.0);
+            Point::new(key, DataPoint::new((i / SERIES) as i64, "usage", value))
+        })
+        .collect();
+    db.write(&points).unwrap();
+
+    let key = SeriesKey::new("cpu").with_tag("host", "host-7").with_tag("rack", "rack-3");
+    let range = TimeRange::new(0, POINTS_PER_SERIES as i64);
+
+    let mut group = c.benchmark_group("single_series");
+    group.bench_function("query_field", |b| {
+        b.iter(|| db.query_field(&key, "usage", &range).unwrap())
+    });
+    group.bench_function("sql_single_series", |b| {
+        b.iter(|| db.query("SELECT usage FROM cpu WHERE host = 'host-7'").unwrap())
+    });
+    // Two series match, so the measurement is scanned
+    group.bench_function("sql_scan", |b| {
+        b.iter(|| db.query("SELECT usage FROM cpu WHERE rack = 'rack-3'").unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_single_series);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+//! Checksum algorithms for on-disk data
+
+use crate::{FluxError, Result};
+use serde::{Deserialize, Serialize};
+
+/// Checksum algorithm used for SSTable blocks and WAL entries
+///
+/// The kind is recorded alongside the data it protects, so readers always
+/// verify with the algorithm the writer used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum ChecksumKind {
+    /// CRC32 (IEEE), the original format
+    Crc32 = 0,
+    /// CRC32C (Castagnoli), hardware accelerated on modern CPUs
+    Crc32c = 1,
+}
+
+impl ChecksumKind {
+    /// Pick CRC32C when the CPU accelerates it, otherwise CRC32
+    pub fn detect() -> Self {
+        if Self::crc32c_accelerated() {
+            ChecksumKind::Crc32c
+        } else {
+            ChecksumKind::Crc32
+        }
+    }
+
+    /// Compute the checksum of `data`
+    pub fn checksum(&self, data: &[u8]) -> u32 {
+        match self {
+            ChecksumKind::Crc32 => crc32fast::hash(data),
+            ChecksumKind::Crc32c => crc32c::crc32c(data),
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn crc32c_accelerated() -> bool {
+        is_x86_feature_detected!("sse4.2")
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn crc32c_accelerated() -> bool {
+        std::arch::is_aarch64_feature_detected!("crc")
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn crc32c_accelerated() -> bool {
+        false
+    }
+}
+
+impl Default for ChecksumKind {
+    fn default() -> Self {
+        Self::detect()
+    }
+}
+
+impl TryFrom<u8> for ChecksumKind {
+    type Error = FluxError;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ChecksumKind::Crc32),
+            1 => Ok(ChecksumKind::Crc32c),
+            _ => Err(FluxError::InvalidFormat(format!(
+                "Invalid checksum kind: {}",
+                value
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_values() {
+        // Standard check values for "123456789"
+        assert_eq!(ChecksumKind::Crc32.checksum(b"123456789"), 0xCBF43926);
+        assert_eq!(ChecksumKind::Crc32c.checksum(b"123456789"), 0xE3069283);
+    }
+}
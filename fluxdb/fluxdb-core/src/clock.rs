@@ -0,0 +1,77 @@
+//! Wall-clock time source
+
+use crate::Timestamp;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+/// Source of the current time
+///
+/// Anything that needs "now" as a timestamp asks the configured clock, so
+/// tests can substitute a [`MockClock`] and control time exactly.
+pub trait Clock: Debug + Send + Sync {
+    /// Current time in nanoseconds since the Unix epoch
+    fn now(&self) -> Timestamp;
+}
+
+/// The system's real-time clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0)
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: AtomicI64,
+}
+
+impl MockClock {
+    /// Create a clock stopped at `now`
+    pub fn new(now: Timestamp) -> Self {
+        Self {
+            now: AtomicI64::new(now),
+        }
+    }
+
+    /// Jump to `now`
+    pub fn set(&self, now: Timestamp) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    /// Move forward by `by`
+    pub fn advance(&self, by: Duration) {
+        let nanos = i64::try_from(by.as_nanos()).unwrap_or(i64::MAX);
+        self.now.fetch_add(nanos, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(1_000);
+        assert_eq!(clock.now(), 1_000);
+
+        clock.advance(Duration::from_micros(2));
+        assert_eq!(clock.now(), 3_000);
+
+        clock.set(500);
+        assert_eq!(clock.now(), 500);
+
+        // The system clock is somewhere after 2020
+        assert!(SystemClock.now() > 1_577_836_800_000_000_000);
+    }
+}
@@ -119,8 +119,9 @@ impl<'a> GorillaDecoder<'a> {
             }
         };
         
-        let delta = self.prev_timestamp_delta + delta_of_delta;
-        let timestamp = self.prev_timestamp + delta;
+        // Mirrors the encoder's wrapping subtraction
+        let delta = self.prev_timestamp_delta.wrapping_add(delta_of_delta);
+        let timestamp = self.prev_timestamp.wrapping_add(delta);
         
         self.prev_timestamp_delta = delta;
         self.prev_timestamp = timestamp;
@@ -216,4 +217,33 @@ mod tests {
             assert!(ts >= 1000000000);
         }
     }
+
+    fn roundtrip_timestamps(timestamps: &[i64]) {
+        let mut encoder = GorillaEncoder::new();
+        for (i, ts) in timestamps.iter().enumerate() {
+            encoder.encode(*ts, i as f64);
+        }
+        let block = encoder.finish();
+        assert_eq!(block.first_timestamp, timestamps[0]);
+        assert_eq!(block.last_timestamp, *timestamps.last().unwrap());
+
+        let decoded = GorillaDecoder::new(&block.data, block.count).decode_all().unwrap();
+        let decoded: Vec<i64> = decoded.into_iter().map(|(ts, _)| ts).collect();
+        assert_eq!(decoded, timestamps);
+    }
+
+    #[test]
+    fn test_roundtrip_negative_timestamps() {
+        // Pre-1970, regular interval
+        let before_epoch: Vec<i64> = (0..100).map(|i| -1_000_000_000_000 + i * 10_000_000_000).collect();
+        roundtrip_timestamps(&before_epoch);
+
+        // Straddling zero with irregular gaps exercising every encoding width
+        roundtrip_timestamps(&[-5_000, -4_000, -3_990, -3_700, -1_000, -1, 0, 1, 2, 3_000, 1_000_000_000]);
+
+        // First timestamp at the extremes, then jumps across the whole range
+        roundtrip_timestamps(&[i64::MIN, i64::MIN + 1, i64::MIN + 10]);
+        roundtrip_timestamps(&[i64::MAX - 10, i64::MAX - 1, i64::MAX]);
+        roundtrip_timestamps(&[i64::MIN, i64::MAX, i64::MIN, 0, i64::MAX, -1]);
+    }
 }
@@ -45,6 +45,11 @@ impl GorillaEncoder {
         self.count += 1;
     }
 
+    /// Encoded size so far in bytes
+    pub fn size(&self) -> usize {
+        self.writer.len()
+    }
+
     /// Finish encoding and return compressed block
     pub fn finish(self) -> CompressedBlock {
         let last_timestamp = self.prev_timestamp;
@@ -60,7 +65,8 @@ impl GorillaEncoder {
         self.first_timestamp = timestamp;
         self.prev_timestamp = timestamp;
         
-        // Write first timestamp as full 64 bits
+        // Write first timestamp as full 64 bits; the two's complement bit
+        // pattern round-trips any i64, negative ones included
         self.writer.write_bits(timestamp as u64, 64);
         
         // Write first value as full 64 bits
@@ -70,8 +76,10 @@ impl GorillaEncoder {
     }
 
     fn encode_timestamp(&mut self, timestamp: i64) {
-        let delta = timestamp - self.prev_timestamp;
-        let delta_of_delta = delta - self.prev_timestamp_delta;
+        // Wrapping arithmetic: deltas between timestamps near i64::MIN and
+        // i64::MAX overflow, and the decoder's wrapping adds undo them exactly
+        let delta = timestamp.wrapping_sub(self.prev_timestamp);
+        let delta_of_delta = delta.wrapping_sub(self.prev_timestamp_delta);
         
         // Most consecutive timestamps have the same delta (e.g., every 10 seconds)
         // So delta-of-delta is usually 0, encoded as a single bit
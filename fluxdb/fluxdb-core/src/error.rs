@@ -52,6 +52,34 @@ pub enum FluxError {
     #[error("WAL recovery error: {0}")]
     WalRecovery(String),
 
+    /// WAL segment requested for replication has been truncated
+    #[error("WAL segment {0} is no longer available")]
+    WalSegmentUnavailable(u64),
+
+    /// Write attempted on a read-only (replica) instance
+    #[error("Read-only: {0}")]
+    ReadOnly(String),
+
+    /// Measurement, tag or field name rejected by name validation
+    #[error("Invalid name: {0}")]
+    InvalidName(String),
+
+    /// Point timestamp outside the configured bounds
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+
+    /// Write would create a series beyond a configured cardinality limit
+    #[error("Cardinality limit exceeded: {0}")]
+    CardinalityLimit(String),
+
+    /// Write whose idempotency key is held by another write still running
+    #[error("Write in progress: {0}")]
+    WriteInProgress(String),
+
+    /// Operation stopped by its cancellation token before committing
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
     /// Compaction error
     #[error("Compaction error: {0}")]
     Compaction(String),
@@ -64,7 +92,7 @@ pub enum FluxError {
 impl FluxError {
     /// Check if error is retryable
     pub fn is_retryable(&self) -> bool {
-        matches!(self, FluxError::Io(_))
+        matches!(self, FluxError::Io(_) | FluxError::WriteInProgress(_))
     }
 
     /// Check if error indicates corruption
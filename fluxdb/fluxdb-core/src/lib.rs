@@ -22,9 +22,13 @@ pub mod storage;
 pub mod wal;
 pub mod compaction;
 
+mod checksum;
+mod clock;
 mod error;
 mod types;
 
+pub use checksum::ChecksumKind;
+pub use clock::{Clock, MockClock, SystemClock};
 pub use error::{FluxError, Result};
 pub use types::*;
 
@@ -39,6 +43,21 @@ pub mod config {
     /// SSTable block size (4KB)
     pub const BLOCK_SIZE: usize = 4 * 1024;
     
+    /// SSTable size at which flushes and compactions start a new file (64MB)
+    pub const SSTABLE_TARGET_FILE_SIZE: u64 = 64 * 1024 * 1024;
+    
+    /// Immutable MemTables waiting to be flushed before writes block
+    pub const MAX_IMMUTABLE_MEMTABLES: usize = 4;
+    
+    /// How long a write waits for the flusher to drain before failing (30s)
+    pub const WRITE_STALL_TIMEOUT_MS: u64 = 30_000;
+    
+    /// SSTable readers a database keeps open at once
+    pub const MAX_OPEN_SSTABLES: usize = 256;
+    
+    /// Memory the engine's shared SSTable block cache may hold (64MB)
+    pub const BLOCK_CACHE_SIZE: usize = 64 * 1024 * 1024;
+    
     /// Maximum SSTables in L0 before compaction
     pub const L0_COMPACTION_TRIGGER: usize = 4;
     
@@ -50,4 +69,22 @@ pub mod config {
     
     /// Bloom filter false positive rate
     pub const BLOOM_FP_RATE: f64 = 0.01;
+    
+    /// Memory a single query may hold in intermediate results (512MB)
+    pub const MAX_QUERY_MEMORY: usize = 512 * 1024 * 1024;
+
+    /// Aggregate values a query buffers before spilling them to disk (64MB)
+    pub const AGGREGATION_SPILL_THRESHOLD: usize = 64 * 1024 * 1024;
+    
+    /// Queries running longer than this are logged as slow (1s)
+    pub const SLOW_QUERY_THRESHOLD_MS: u64 = 1_000;
+    
+    /// Idempotency keys the engine remembers
+    pub const IDEMPOTENCY_KEYS: usize = 100_000;
+    
+    /// How long an idempotency key is remembered (10 minutes)
+    pub const IDEMPOTENCY_TTL_MS: u64 = 10 * 60 * 1_000;
+    
+    /// Most sliding windows one point may fall in, i.e. window / step
+    pub const MAX_WINDOWS_PER_POINT: i64 = 10_000;
 }
@@ -1,23 +1,32 @@
-//! MemTable implementation using skip list
+//! MemTable implementation
 //!
 //! The MemTable is an in-memory data structure that stores recent writes
 //! in sorted order, allowing for fast writes and efficient range scans.
+//! It is backed by a skip list by default, or a `BTreeMap` (see
+//! [`MemTableKind`]).
 
 mod skiplist;
+mod store;
 
 use crate::{DataPoint, Point, SeriesKey, Timestamp, TimeRange, Result};
 use parking_lot::RwLock;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
 use std::time::Instant;
 
 pub use skiplist::SkipList;
+pub use store::{MemTableKind, OrderedStore};
 
 /// MemTable for in-memory writes
 pub struct MemTable {
-    /// Skip list storing data points indexed by (series_key, timestamp)
-    data: RwLock<SkipList<MemTableKey, DataPoint>>,
+    /// Data points indexed by (series_key, timestamp)
+    data: RwLock<Box<dyn OrderedStore<MemTableKey, DataPoint>>>,
+    /// Structure behind `data`
+    kind: MemTableKind,
     /// Approximate size in bytes
     size_bytes: AtomicUsize,
+    /// Smallest and largest timestamps inserted so far
+    min_timestamp: AtomicI64,
+    max_timestamp: AtomicI64,
     /// Creation time for age-based flushing
     created_at: Instant,
     /// Unique ID for this memtable
@@ -49,11 +58,19 @@ impl MemTableKey {
 }
 
 impl MemTable {
-    /// Create a new MemTable
+    /// Create a new skip list MemTable
     pub fn new(id: u64) -> Self {
+        Self::with_kind(id, MemTableKind::default())
+    }
+
+    /// Create a new MemTable backed by `kind`
+    pub fn with_kind(id: u64, kind: MemTableKind) -> Self {
         Self {
-            data: RwLock::new(SkipList::new()),
+            data: RwLock::new(kind.new_store()),
+            kind,
             size_bytes: AtomicUsize::new(0),
+            min_timestamp: AtomicI64::new(i64::MAX),
+            max_timestamp: AtomicI64::new(i64::MIN),
             created_at: Instant::now(),
             id,
         }
@@ -64,6 +81,11 @@ impl MemTable {
         self.id
     }
 
+    /// Get the structure backing this MemTable
+    pub fn kind(&self) -> MemTableKind {
+        self.kind
+    }
+
     /// Insert a point into the MemTable
     pub fn insert(&self, point: &Point) {
         let key = MemTableKey::new(point.key.clone(), point.data.timestamp);
@@ -72,6 +94,7 @@ impl MemTable {
         let mut data = self.data.write();
         data.insert(key, point.data.clone());
         self.size_bytes.fetch_add(entry_size, Ordering::Relaxed);
+        self.track_timestamp(point.data.timestamp);
     }
 
     /// Insert multiple points
@@ -84,14 +107,22 @@ impl MemTable {
             let entry_size = key.size() + point.data.size();
             data.insert(key, point.data.clone());
             total_size += entry_size;
+            self.track_timestamp(point.data.timestamp);
         }
 
         self.size_bytes.fetch_add(total_size, Ordering::Relaxed);
     }
 
-    /// Check if the MemTable should be flushed
-    pub fn should_flush(&self, size_limit: usize) -> bool {
+    fn track_timestamp(&self, timestamp: Timestamp) {
+        self.min_timestamp.fetch_min(timestamp, Ordering::Relaxed);
+        self.max_timestamp.fetch_max(timestamp, Ordering::Relaxed);
+    }
+
+    /// Check if the MemTable should be flushed: once it holds `size_limit`
+    /// bytes or, if given, `entry_limit` entries
+    pub fn should_flush(&self, size_limit: usize, entry_limit: Option<usize>) -> bool {
         self.size_bytes.load(Ordering::Relaxed) >= size_limit
+            || entry_limit.is_some_and(|limit| self.len() >= limit)
     }
 
     /// Get the current size in bytes
@@ -151,17 +182,40 @@ impl MemTable {
         keys
     }
 
-    /// Get the time range covered by this MemTable
-    pub fn time_range(&self) -> Option<TimeRange> {
+    /// Get the series keys of one measurement
+    ///
+    /// Seeks from one series to the next rather than walking every entry.
+    pub fn measurement_series(&self, measurement: &str) -> Vec<SeriesKey> {
         let data = self.data.read();
-        let first = data.iter().next();
-        let last = data.iter().last();
+        let mut keys = Vec::new();
 
-        match (first, last) {
-            (Some((first_key, _)), Some((last_key, _))) => {
-                Some(TimeRange::new(first_key.timestamp, last_key.timestamp))
+        // The tagless series with the smallest timestamp sorts first
+        let mut cursor = MemTableKey::new(SeriesKey::new(measurement), i64::MIN);
+        if data.get(&cursor).is_some() {
+            keys.push(cursor.series_key.clone());
+            cursor.timestamp = i64::MAX;
+        }
+
+        while let Some(next) = data.next_key(&cursor) {
+            if next.series_key.measurement != measurement {
+                break;
             }
-            _ => None,
+            keys.push(next.series_key.clone());
+            // Skip past the rest of this series
+            cursor = MemTableKey::new(next.series_key.clone(), i64::MAX);
+        }
+
+        keys
+    }
+
+    /// Get the time range covered by this MemTable
+    pub fn time_range(&self) -> Option<TimeRange> {
+        let min = self.min_timestamp.load(Ordering::Relaxed);
+        let max = self.max_timestamp.load(Ordering::Relaxed);
+        if min <= max {
+            Some(TimeRange::new(min, max))
+        } else {
+            None
         }
     }
 
@@ -188,12 +242,23 @@ impl MemTable {
 /// Immutable MemTable snapshot for flushing
 pub struct ImmutableMemTable {
     inner: MemTable,
+    wal_segment: u64,
 }
 
 impl ImmutableMemTable {
-    /// Create an immutable snapshot from a MemTable
-    pub fn from(memtable: MemTable) -> Self {
-        Self { inner: memtable }
+    /// Create an immutable snapshot from a MemTable whose writes are all
+    /// logged in WAL segments before `wal_segment`
+    pub fn from(memtable: MemTable, wal_segment: u64) -> Self {
+        Self {
+            inner: memtable,
+            wal_segment,
+        }
+    }
+
+    /// First WAL segment that may hold writes newer than this MemTable;
+    /// once it is flushed, the segments before it are no longer needed
+    pub fn wal_segment(&self) -> u64 {
+        self.wal_segment
     }
 
     /// Get the MemTable ID
@@ -220,6 +285,11 @@ impl ImmutableMemTable {
     pub fn time_range(&self) -> Option<TimeRange> {
         self.inner.time_range()
     }
+
+    /// Get the series keys of one measurement
+    pub fn measurement_series(&self, measurement: &str) -> Vec<SeriesKey> {
+        self.inner.measurement_series(measurement)
+    }
 }
 
 #[cfg(test)]
@@ -227,36 +297,118 @@ mod tests {
     use super::*;
     use crate::FieldValue;
 
+    /// Every test runs against each backing structure
+    const KINDS: [MemTableKind; 2] = [MemTableKind::SkipList, MemTableKind::BTreeMap];
+
+    fn point(key: &SeriesKey, ts: i64, value: f64) -> Point {
+        Point::new(key.clone(), DataPoint::new(ts, "value", FieldValue::Float(value)))
+    }
+
     #[test]
     fn test_memtable_insert_query() {
-        let memtable = MemTable::new(1);
+        for kind in KINDS {
+            let memtable = MemTable::with_kind(1, kind);
+            assert_eq!(memtable.kind(), kind);
+
+            let key = SeriesKey::new("temperature").with_tag("sensor", "s1");
+            for i in 0..100 {
+                memtable.insert(&point(&key, i * 1000, 20.0 + i as f64));
+            }
 
-        let key = SeriesKey::new("temperature").with_tag("sensor", "s1");
-        for i in 0..100 {
-            let data = DataPoint::new(i * 1000, "value", FieldValue::Float(20.0 + i as f64));
-            let point = Point::new(key.clone(), data);
-            memtable.insert(&point);
+            assert_eq!(memtable.len(), 100);
+
+            let range = TimeRange::new(50_000, 60_000);
+            let results = memtable.query(&key, &range);
+            assert_eq!(results.len(), 11, "{:?}", kind); // 50, 51, ..., 60
+            assert_eq!(results[0].timestamp, 50_000);
+            assert_eq!(results[10].timestamp, 60_000);
+            assert!(memtable.query(&key, &TimeRange::new(60_000, 50_000)).is_empty());
         }
+    }
 
-        assert_eq!(memtable.len(), 100);
+    #[test]
+    fn test_memtable_overwrite_and_iter() {
+        for kind in KINDS {
+            let memtable = MemTable::with_kind(1, kind);
+            let a = SeriesKey::new("cpu").with_tag("host", "a");
+            let b = SeriesKey::new("cpu").with_tag("host", "b");
+
+            memtable.insert_batch(&[point(&b, 2, 1.0), point(&a, 3, 1.0), point(&a, 1, 1.0)]);
+            // Same series and timestamp replaces the point
+            memtable.insert(&point(&a, 3, 9.0));
+            assert_eq!(memtable.len(), 3, "{:?}", kind);
+
+            let entries: Vec<(String, i64)> = memtable
+                .iter()
+                .into_iter()
+                .map(|(k, _)| (k.series_key.encode(), k.timestamp))
+                .collect();
+            assert_eq!(
+                entries,
+                vec![("cpu,host=a".into(), 1), ("cpu,host=a".into(), 3), ("cpu,host=b".into(), 2)]
+            );
+            assert_eq!(memtable.get_latest(&a).unwrap().fields.get("value"), Some(&FieldValue::Float(9.0)));
+            assert_eq!(memtable.series_keys(), vec![a.clone(), b.clone()]);
+        }
+    }
 
-        let range = TimeRange::new(50_000, 60_000);
-        let results = memtable.query(&key, &range);
-        assert_eq!(results.len(), 11); // 50, 51, ..., 60
+    #[test]
+    fn test_memtable_should_flush() {
+        for kind in KINDS {
+            let memtable = MemTable::with_kind(1, kind);
+            let key = SeriesKey::new("cpu");
+            for ts in 0..10 {
+                memtable.insert(&point(&key, ts, ts as f64));
+            }
+            let size = memtable.size();
+
+            // Either limit triggers a flush on its own
+            assert!(!memtable.should_flush(size + 1, None));
+            assert!(memtable.should_flush(size, None));
+            assert!(!memtable.should_flush(size + 1, Some(11)));
+            assert!(memtable.should_flush(size + 1, Some(10)));
+            assert!(memtable.should_flush(size, Some(11)));
+        }
     }
 
     #[test]
     fn test_memtable_latest() {
-        let memtable = MemTable::new(1);
+        for kind in KINDS {
+            let memtable = MemTable::with_kind(1, kind);
+
+            let key = SeriesKey::new("temperature");
+            for i in 0..10 {
+                memtable.insert(&point(&key, i * 1000, i as f64));
+            }
 
-        let key = SeriesKey::new("temperature");
-        for i in 0..10 {
-            let data = DataPoint::new(i * 1000, "value", FieldValue::Float(i as f64));
-            let point = Point::new(key.clone(), data);
-            memtable.insert(&point);
+            let latest = memtable.get_latest(&key).unwrap();
+            assert_eq!(latest.timestamp, 9000, "{:?}", kind);
+            assert!(memtable.get_latest(&SeriesKey::new("other")).is_none());
         }
+    }
 
-        let latest = memtable.get_latest(&key).unwrap();
-        assert_eq!(latest.timestamp, 9000);
+    #[test]
+    fn test_memtable_measurement_series() {
+        for kind in KINDS {
+            let memtable = MemTable::with_kind(1, kind);
+
+            let keys = [
+                SeriesKey::new("cpu"),
+                SeriesKey::new("cpu").with_tag("host", "a"),
+                SeriesKey::new("cpu").with_tag("host", "b"),
+                SeriesKey::new("cpu2"),
+                SeriesKey::new("mem").with_tag("host", "a"),
+            ];
+            for key in &keys {
+                for ts in [i64::MIN, 0, 1000, i64::MAX] {
+                    memtable.insert(&Point::new(key.clone(), DataPoint::new(ts, "value", FieldValue::Integer(1))));
+                }
+            }
+
+            assert_eq!(memtable.measurement_series("cpu"), keys[..3].to_vec(), "{:?}", kind);
+            assert_eq!(memtable.measurement_series("mem"), keys[4..].to_vec());
+            assert!(memtable.measurement_series("disk").is_empty());
+            assert_eq!(memtable.time_range(), Some(TimeRange::new(i64::MIN, i64::MAX)));
+        }
     }
 }
@@ -161,6 +161,29 @@ impl<K: Ord + Clone, V: Clone> SkipList<K, V> {
         results.into_iter()
     }
 
+    /// Find the first key strictly greater than `key`
+    pub fn next_key(&self, key: &K) -> Option<&K> {
+        let mut current = self.head.as_ref() as *const Node<K, V>;
+
+        for i in (0..self.level).rev() {
+            unsafe {
+                while let Some(next) = (*current).forward[i] {
+                    if let Some(ref next_key) = (*next.as_ptr()).key {
+                        if next_key <= key {
+                            current = next.as_ptr();
+                        } else {
+                            break;
+                        }
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
+
+        unsafe { (*current).forward[0].and_then(|next| (*next.as_ptr()).key.as_ref()) }
+    }
+
     /// Iterate over all entries
     pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
         let mut results = Vec::new();
@@ -276,6 +299,20 @@ mod tests {
         assert_eq!(results[10], (&35, &350));
     }
 
+    #[test]
+    fn test_skiplist_next_key() {
+        let mut list = SkipList::new();
+
+        for i in 0..100 {
+            list.insert(i * 2, i);
+        }
+
+        assert_eq!(list.next_key(&-1), Some(&0));
+        assert_eq!(list.next_key(&10), Some(&12));
+        assert_eq!(list.next_key(&11), Some(&12));
+        assert_eq!(list.next_key(&198), None);
+    }
+
     #[test]
     fn test_skiplist_update() {
         let mut list = SkipList::new();
@@ -0,0 +1,154 @@
+//! Ordered key-value stores backing the MemTable
+
+use super::SkipList;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/// Which structure backs a MemTable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemTableKind {
+    /// Hand-written skip list (fastest inserts)
+    #[default]
+    SkipList,
+    /// Standard library `BTreeMap`, free of `unsafe` code
+    BTreeMap,
+}
+
+impl MemTableKind {
+    /// Create an empty store of this kind
+    pub fn new_store<K, V>(&self) -> Box<dyn OrderedStore<K, V>>
+    where
+        K: Ord + Clone + Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        match self {
+            MemTableKind::SkipList => Box::new(SkipList::new()),
+            MemTableKind::BTreeMap => Box::new(BTreeMap::new()),
+        }
+    }
+}
+
+/// Sorted map operations the MemTable relies on
+///
+/// Inserting an existing key replaces its value.
+pub trait OrderedStore<K, V>: Send + Sync {
+    /// Insert or replace a value
+    fn insert(&mut self, key: K, value: V);
+
+    /// Get a value by key
+    fn get(&self, key: &K) -> Option<&V>;
+
+    /// Entries from `start` to `end`, both inclusive, in key order; empty
+    /// if `start > end`
+    fn range<'a>(&'a self, start: &K, end: &K) -> Box<dyn Iterator<Item = (&'a K, &'a V)> + 'a>;
+
+    /// The first key strictly greater than `key`
+    fn next_key(&self, key: &K) -> Option<&K>;
+
+    /// All entries in key order
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, &'a V)> + 'a>;
+
+    /// Number of entries
+    fn len(&self) -> usize;
+
+    /// Check if empty
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> OrderedStore<K, V> for SkipList<K, V>
+where
+    K: Ord + Clone + Send + Sync,
+    V: Clone + Send + Sync,
+{
+    fn insert(&mut self, key: K, value: V) {
+        SkipList::insert(self, key, value)
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        SkipList::get(self, key)
+    }
+
+    fn range<'a>(&'a self, start: &K, end: &K) -> Box<dyn Iterator<Item = (&'a K, &'a V)> + 'a> {
+        Box::new(SkipList::range(self, start, end))
+    }
+
+    fn next_key(&self, key: &K) -> Option<&K> {
+        SkipList::next_key(self, key)
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, &'a V)> + 'a> {
+        Box::new(SkipList::iter(self))
+    }
+
+    fn len(&self) -> usize {
+        SkipList::len(self)
+    }
+}
+
+impl<K, V> OrderedStore<K, V> for BTreeMap<K, V>
+where
+    K: Ord + Send + Sync,
+    V: Send + Sync,
+{
+    fn insert(&mut self, key: K, value: V) {
+        BTreeMap::insert(self, key, value);
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        BTreeMap::get(self, key)
+    }
+
+    fn range<'a>(&'a self, start: &K, end: &K) -> Box<dyn Iterator<Item = (&'a K, &'a V)> + 'a> {
+        // BTreeMap::range panics on an inverted range
+        if start > end {
+            return Box::new(std::iter::empty());
+        }
+        Box::new(BTreeMap::range(self, (Bound::Included(start), Bound::Included(end))))
+    }
+
+    fn next_key(&self, key: &K) -> Option<&K> {
+        BTreeMap::range(self, (Bound::Excluded(key), Bound::Unbounded))
+            .next()
+            .map(|(k, _)| k)
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a K, &'a V)> + 'a> {
+        Box::new(BTreeMap::iter(self))
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stores_agree() {
+        for kind in [MemTableKind::SkipList, MemTableKind::BTreeMap] {
+            let mut store = kind.new_store::<i64, i64>();
+            for i in (0..50).rev() {
+                store.insert(i * 2, i);
+            }
+            store.insert(10, -1);
+            assert_eq!(store.len(), 50, "{:?}", kind);
+            assert_eq!(store.get(&10), Some(&-1));
+            assert_eq!(store.get(&11), None);
+
+            let range: Vec<i64> = store.range(&9, &15).map(|(k, _)| *k).collect();
+            assert_eq!(range, vec![10, 12, 14]);
+            assert_eq!(store.range(&15, &9).count(), 0);
+
+            assert_eq!(store.next_key(&-5), Some(&0));
+            assert_eq!(store.next_key(&10), Some(&12));
+            assert_eq!(store.next_key(&98), None);
+
+            let keys: Vec<i64> = store.iter().map(|(k, _)| *k).collect();
+            assert_eq!(keys, (0..50).map(|i| i * 2).collect::<Vec<_>>());
+        }
+    }
+}
@@ -249,11 +249,24 @@ impl Accumulator for LastAccumulator {
 }
 
 /// Standard deviation accumulator (Welford's algorithm)
+///
+/// Population standard deviation by default; see [`StddevAccumulator::sample`].
 #[derive(Debug, Default)]
 pub struct StddevAccumulator {
     count: u64,
     mean: f64,
     m2: f64,
+    sample: bool,
+}
+
+impl StddevAccumulator {
+    /// Accumulator for the sample standard deviation (divides by N - 1)
+    pub fn sample() -> Self {
+        Self {
+            sample: true,
+            ..Self::default()
+        }
+    }
 }
 
 impl Accumulator for StddevAccumulator {
@@ -266,8 +279,9 @@ impl Accumulator for StddevAccumulator {
     }
     
     fn result(&self) -> Option<f64> {
-        if self.count > 1 {
-            Some((self.m2 / self.count as f64).sqrt())
+        let divisor = if self.sample { self.count.checked_sub(1)? } else { self.count };
+        if divisor > 0 {
+            Some((self.m2 / divisor as f64).sqrt())
         } else {
             None
         }
@@ -319,5 +333,15 @@ mod tests {
         }
         let stddev = acc.result().unwrap();
         assert!((stddev - 2.0).abs() < 0.01);
+
+        let mut acc = StddevAccumulator::sample();
+        for v in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            acc.add(v);
+        }
+        assert!((acc.result().unwrap() - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+
+        let mut acc = StddevAccumulator::sample();
+        acc.add(1.0);
+        assert_eq!(acc.result(), None);
     }
 }
@@ -8,13 +8,19 @@
 //! - OFFSET for pagination
 
 use super::{
-    planner::{Aggregation, AdvancedFilter, FieldSelection, QueryPlan, SortOrder},
-    AggregateFunc, CompareOp, QueryResult, QueryRow, QueryValue,
+    grouped::{FinishedState, GroupedAggregation},
+    planner::{Aggregation, AdvancedFilter, FieldSelection, PlanType, QueryPlan, QueryPlanner, SortOrder, Transform},
+    AggregateFunc, BinaryOp, CompareOp, Expr, FillOption, IntegerAggregates, IntegerOverflow, QueryResult, QueryRow, QueryValue, TransformFunc,
+    NullSemantics, TypeMismatch,
 };
-use crate::{DataPoint, FieldValue, Result, SeriesKey, TimeRange};
-use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::{Clock, DataPoint, FieldValue, Fields, FluxError, Point, Result, SeriesKey, SystemClock, TimeRange};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::time::Instant;
 
+/// Pseudo-column aggregates can read a point's timestamp from
+const TIME_COLUMN: &str = "time";
+
 /// Query executor
 pub struct QueryExecutor;
 
@@ -22,19 +28,22 @@ impl QueryExecutor {
     /// Execute a query plan against data points
     pub fn execute(plan: &QueryPlan, data: Vec<(SeriesKey, DataPoint)>) -> Result<QueryResult> {
         let start = Instant::now();
+        if let PlanType::Constant(columns) = &plan.plan_type {
+            return Self::execute_constant(plan, columns);
+        }
+        let mut memory = MemoryTracker::new(plan.max_memory);
 
-        // Filter by basic conditions
-        let filtered: Vec<_> = data
-            .into_iter()
-            .filter(|(key, point)| Self::matches_basic_filters(plan, key, point))
-            .filter(|(key, point)| Self::matches_advanced_filters(plan, key, point))
-            .collect();
-
-        // Group and aggregate if needed
-        let result = if !plan.aggregations.is_empty() {
-            Self::execute_aggregation(plan, filtered)?
+        // Aggregates that fold each point into its group's running state
+        // take points straight from the source instead of buffering them;
+        // SLIMIT/SOFFSET and GROUP BY * need every matching series first
+        let incremental = plan.slimit.is_none()
+            && plan.soffset.is_none()
+            && !plan.group_by_all_tags
+            && GroupedAggregation::supports(plan);
+        let result = if incremental {
+            Self::execute_grouped_aggregation(plan, data, &mut memory)?
         } else {
-            Self::execute_select(plan, filtered)?
+            Self::execute_buffered(plan, data, &mut memory)?
         };
 
         let execution_time_ms = start.elapsed().as_secs_f64() * 1000.0;
@@ -44,47 +53,267 @@ impl QueryExecutor {
             rows: result.1,
             execution_time_ms,
             rows_affected: None,
+            memory_bytes: memory.peak(),
         })
     }
 
-    fn matches_basic_filters(plan: &QueryPlan, key: &SeriesKey, point: &DataPoint) -> bool {
-        // Check tag filters
-        for (tag_name, tag_value) in &plan.tag_filters {
-            if key.tags.get(tag_name) != Some(tag_value) {
-                return false;
+    /// Filter `data` into memory, then select, transform or aggregate it
+    fn execute_buffered(
+        plan: &QueryPlan,
+        data: Vec<(SeriesKey, DataPoint)>,
+        memory: &mut MemoryTracker,
+    ) -> Result<(Vec<String>, Vec<QueryRow>)> {
+        // Filter by basic conditions
+        let mut filtered = Vec::with_capacity(data.len());
+        for (key, point) in data {
+            if Self::matches_filters(plan, &key, &point)? {
+                memory.reserve(key.size() + point.size() + MemoryTracker::ENTRY_OVERHEAD)?;
+                filtered.push((key, point));
+            }
+        }
+
+        // Restrict to the series selected by SLIMIT/SOFFSET
+        if plan.slimit.is_some() || plan.soffset.is_some() {
+            filtered = Self::apply_series_limit(plan, filtered);
+        }
+
+        // GROUP BY * groups by the tag keys of the series that matched
+        let expanded;
+        let plan = if plan.group_by_all_tags {
+            expanded = QueryPlanner::expand_all_tags(plan, filtered.iter().map(|(key, _)| key));
+            &expanded
+        } else {
+            plan
+        };
+
+        // Group and aggregate if needed
+        if !plan.aggregations.is_empty() {
+            Self::execute_aggregation(plan, filtered, memory)
+        } else if !plan.transforms.is_empty() {
+            Self::execute_transform(plan, filtered)
+        } else {
+            Self::execute_select(plan, filtered)
+        }
+    }
+
+    /// The single row of a query without FROM
+    fn execute_constant(plan: &QueryPlan, columns: &[(String, Expr)]) -> Result<QueryResult> {
+        let start = Instant::now();
+        let now = plan.now.unwrap_or_else(|| SystemClock.now());
+        let values = columns
+            .iter()
+            .map(|(_, expr)| Self::eval_constant(expr, now))
+            .collect::<Result<_>>()?;
+        Ok(QueryResult {
+            columns: columns.iter().map(|(name, _)| name.clone()).collect(),
+            rows: vec![QueryRow { time: None, series: None, values }],
+            execution_time_ms: start.elapsed().as_secs_f64() * 1000.0,
+            ..Default::default()
+        })
+    }
+
+    /// Evaluate an expression that reads no fields; `now()` is `now` in
+    /// nanoseconds
+    fn eval_constant(expr: &Expr, now: i64) -> Result<QueryValue> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Function { name, .. } if name == "now" => Ok(QueryValue::Integer(now)),
+            Expr::BinaryOp { left, op, right } => {
+                let left = Self::eval_constant(left, now)?;
+                let right = Self::eval_constant(right, now)?;
+                Self::eval_arithmetic(&left, *op, &right)
+            }
+            _ => Err(FluxError::Query(format!("Not a constant expression: {:?}", expr))),
+        }
+    }
+
+    /// Integers stay integers, failing on overflow and division by zero;
+    /// anything involving a float is computed in floats
+    fn eval_arithmetic(left: &QueryValue, op: BinaryOp, right: &QueryValue) -> Result<QueryValue> {
+        if let (QueryValue::Integer(a), QueryValue::Integer(b)) = (left, right) {
+            let result = match op {
+                BinaryOp::Add => a.checked_add(*b),
+                BinaryOp::Subtract => a.checked_sub(*b),
+                BinaryOp::Multiply => a.checked_mul(*b),
+                BinaryOp::Divide | BinaryOp::Modulo if *b == 0 => {
+                    return Err(FluxError::Query("division by zero".into()));
+                }
+                BinaryOp::Divide => a.checked_div(*b),
+                BinaryOp::Modulo => a.checked_rem(*b),
+            };
+            return result
+                .map(QueryValue::Integer)
+                .ok_or_else(|| FluxError::Query("integer overflow".into()));
+        }
+
+        let (Some(a), Some(b)) = (left.as_f64(), right.as_f64()) else {
+            return Err(FluxError::Query(format!(
+                "Can't apply {:?} to {:?} and {:?}",
+                op, left, right
+            )));
+        };
+        Ok(QueryValue::Float(match op {
+            BinaryOp::Add => a + b,
+            BinaryOp::Subtract => a - b,
+            BinaryOp::Multiply => a * b,
+            BinaryOp::Divide => a / b,
+            BinaryOp::Modulo => a % b,
+        }))
+    }
+
+    /// Convert result rows into points for `SELECT ... INTO`
+    ///
+    /// The row time becomes the timestamp (0 for rows without one), GROUP BY
+    /// tag columns become tags and every other value column a field. Null
+    /// values are skipped, and so are rows left without any field.
+    pub fn into_points(plan: &QueryPlan, result: &QueryResult, measurement: &str) -> Vec<Point> {
+        Self::rows_to_points(plan, result, measurement, false)
+    }
+
+    /// Convert a CTE's result rows into the points queries over it read
+    ///
+    /// Like [`into_points`](Self::into_points), except rows that name their
+    /// series keep that series' tags, so raw rows of different series
+    /// don't collapse into one.
+    pub fn materialize(plan: &QueryPlan, result: &QueryResult, name: &str) -> Vec<(SeriesKey, DataPoint)> {
+        Self::rows_to_points(plan, result, name, true)
+            .into_iter()
+            .map(|point| (point.key, point.data))
+            .collect()
+    }
+
+    fn rows_to_points(plan: &QueryPlan, result: &QueryResult, measurement: &str, series_tags: bool) -> Vec<Point> {
+        let mut points = Vec::new();
+
+        for row in &result.rows {
+            // Time and series columns live on the row, not in its values
+            let names = &result.columns[result.columns.len() - row.values.len()..];
+
+            // Row series come from SeriesKey::encode, so always decode
+            let mut key = match row.series.as_deref().map(SeriesKey::decode) {
+                Some(Ok(series)) if series_tags => SeriesKey {
+                    measurement: measurement.to_string(),
+                    ..series
+                },
+                _ => SeriesKey::new(measurement),
+            };
+            let mut fields = Fields::new();
+            for (name, value) in names.iter().zip(&row.values) {
+                if plan.group_by_tags.contains(name) {
+                    if let Some(tag) = value.as_string() {
+                        key = key.with_tag(name.clone(), tag);
+                    }
+                    continue;
+                }
+                let field = match value {
+                    QueryValue::Null => continue,
+                    QueryValue::Float(v) => FieldValue::Float(*v),
+                    QueryValue::Integer(v) => FieldValue::Integer(*v),
+                    QueryValue::String(v) => FieldValue::String(v.clone()),
+                    QueryValue::Boolean(v) => FieldValue::Boolean(*v),
+                };
+                fields.insert(name.clone(), field);
             }
+
+            if !fields.0.is_empty() {
+                let data = DataPoint {
+                    timestamp: row.time.unwrap_or(0),
+                    fields,
+                };
+                points.push(Point::new(key, data));
+            }
+        }
+
+        points
+    }
+
+    fn apply_series_limit(
+        plan: &QueryPlan,
+        data: Vec<(SeriesKey, DataPoint)>,
+    ) -> Vec<(SeriesKey, DataPoint)> {
+        let series: BTreeSet<&SeriesKey> = data.iter().map(|(key, _)| key).collect();
+        let selected: HashSet<SeriesKey> = series
+            .into_iter()
+            .skip(plan.soffset.unwrap_or(0))
+            .take(plan.slimit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+
+        data.into_iter()
+            .filter(|(key, _)| selected.contains(key))
+            .collect()
+    }
+
+    /// Whether `point` of series `key` passes the plan's WHERE clause
+    pub(crate) fn matches_filters(plan: &QueryPlan, key: &SeriesKey, point: &DataPoint) -> Result<bool> {
+        Ok(Self::matches_basic_filters(plan, key, point)? && Self::matches_advanced_filters(plan, key, point))
+    }
+
+    fn matches_basic_filters(plan: &QueryPlan, key: &SeriesKey, point: &DataPoint) -> Result<bool> {
+        // Check tag filters
+        if !plan.matches_tags(key) {
+            return Ok(false);
         }
 
         // Check time range
         if !plan.time_range.contains(point.timestamp) {
-            return false;
+            return Ok(false);
         }
 
         // Check field filters
         for filter in &plan.field_filters {
             if let Some(field_val) = point.fields.get(&filter.field) {
-                if let Some(val) = field_val.as_f64() {
-                    let passes = match filter.op {
-                        CompareOp::Eq => (val - filter.value).abs() < f64::EPSILON,
-                        CompareOp::Ne => (val - filter.value).abs() >= f64::EPSILON,
-                        CompareOp::Lt => val < filter.value,
-                        CompareOp::Le => val <= filter.value,
-                        CompareOp::Gt => val > filter.value,
-                        CompareOp::Ge => val >= filter.value,
-                        _ => true, // Other ops handled differently
-                    };
-                    if !passes {
-                        return false;
-                    }
+                let val = match Self::coerce_numeric(field_val) {
+                    Some(val) => val,
+                    None => match plan.type_mismatch {
+                        TypeMismatch::Exclude => return Ok(false),
+                        TypeMismatch::Error => {
+                            return Err(FluxError::Query(format!(
+                                "Cannot compare non-numeric field '{}' to {}",
+                                filter.field, filter.value
+                            )));
+                        }
+                    },
+                };
+                let passes = match filter.op {
+                    CompareOp::Eq => (val - filter.value).abs() < f64::EPSILON,
+                    CompareOp::Ne => (val - filter.value).abs() >= f64::EPSILON,
+                    CompareOp::Lt => val < filter.value,
+                    CompareOp::Le => val <= filter.value,
+                    CompareOp::Gt => val > filter.value,
+                    CompareOp::Ge => val >= filter.value,
+                    _ => true, // Other ops handled differently
+                };
+                if !passes {
+                    return Ok(false);
                 }
+            } else if plan.null_semantics == NullSemantics::Sql {
+                return Ok(false);
             }
         }
 
-        true
+        Ok(true)
+    }
+
+    /// Coerce a field value for numeric comparison (booleans compare as 0/1)
+    fn coerce_numeric(field: &FieldValue) -> Option<f64> {
+        match field {
+            FieldValue::Float(v) => Some(*v),
+            FieldValue::Integer(v) => Some(*v as f64),
+            FieldValue::Boolean(v) => Some(if *v { 1.0 } else { 0.0 }),
+            FieldValue::String(_) => None,
+        }
     }
 
     fn matches_advanced_filters(plan: &QueryPlan, _key: &SeriesKey, point: &DataPoint) -> bool {
         for filter in &plan.advanced_filters {
+            if plan.null_semantics == NullSemantics::Sql {
+                if let Some(field) = filter.compared_field() {
+                    if point.fields.get(field).is_none() {
+                        return false;
+                    }
+                }
+            }
             match filter {
                 AdvancedFilter::In { field, values, negated } => {
                     if let Some(field_val) = point.fields.get(field) {
@@ -151,6 +380,13 @@ impl QueryExecutor {
                         }
                     }
                 }
+                AdvancedFilter::BooleanEquals { field, value } => {
+                    if let Some(FieldValue::Boolean(b)) = point.fields.get(field) {
+                        if b != value {
+                            return false;
+                        }
+                    }
+                }
             }
         }
         true
@@ -184,14 +420,20 @@ impl QueryExecutor {
         let mut columns = vec!["time".to_string(), "series".to_string()];
         
         let field_names: Vec<String> = match &plan.fields {
-            FieldSelection::All => {
-                // Collect all unique field names
+            FieldSelection::All | FieldSelection::Matching { .. } => {
+                // Collect all unique selected field names
                 let mut names: Vec<String> = data
                     .iter()
-                    .flat_map(|(_, dp)| dp.fields.0.keys().cloned())
+                    .flat_map(|(_, dp)| dp.fields.0.keys())
+                    .filter(|name| plan.fields.matches(name))
+                    .cloned()
                     .collect();
                 names.sort();
                 names.dedup();
+                if !plan.field_order.is_empty() {
+                    // Stable, so unlisted fields stay alphabetical
+                    names.sort_by_key(|name| plan.field_order.iter().position(|f| f == name).unwrap_or(usize::MAX));
+                }
                 names
             }
             FieldSelection::Fields(fields) => fields.clone(),
@@ -218,41 +460,46 @@ impl QueryExecutor {
 
                 QueryRow {
                     time: Some(dp.timestamp),
-                    series: Some(key.canonical()),
+                    series: Some(key.encode()),
                     values,
                 }
             })
             .collect();
 
+        // Sort by the ORDER BY key, then series and time, so equal keys
+        // (and queries without ORDER BY) come out the same way every run
+        // whatever order storage produced the points in. Sorting first
+        // also makes DISTINCT keep the same row of each duplicate set.
+        let sort_key = plan.sort.as_ref().and_then(|sort| {
+            let column = match sort.field.as_str() {
+                "time" => None,
+                field => Some(field_names.iter().position(|n| n == field)?),
+            };
+            Some((column, sort.descending))
+        });
+        rows.sort_by(|a, b| {
+            let primary = match sort_key {
+                Some((None, descending)) => Self::directed(a.time.cmp(&b.time), descending),
+                Some((Some(idx), descending)) => {
+                    Self::directed(a.values.get(idx).cmp(&b.values.get(idx)), descending)
+                }
+                None => std::cmp::Ordering::Equal,
+            };
+            primary
+                .then_with(|| a.series.cmp(&b.series))
+                .then_with(|| a.time.cmp(&b.time))
+        });
+
+        // Resume after the cursor; only meaningful without ORDER BY
+        if let Some(after) = &plan.after {
+            let after = (Some(after.series.as_str()), Some(after.timestamp));
+            rows.retain(|row| (row.series.as_deref(), row.time) > after);
+        }
+
         // Apply DISTINCT
         if plan.distinct {
-            let mut seen = HashSet::new();
-            rows.retain(|row| {
-                let key = format!("{:?}", row.values);
-                seen.insert(key)
-            });
-        }
-
-        // Sort if needed
-        if let Some(sort) = &plan.sort {
-            let field_idx = field_names.iter().position(|n| n == &sort.field);
-            if sort.field == "time" {
-                if sort.descending {
-                    rows.sort_by(|a, b| b.time.cmp(&a.time));
-                } else {
-                    rows.sort_by(|a, b| a.time.cmp(&b.time));
-                }
-            } else if let Some(idx) = field_idx {
-                rows.sort_by(|a, b| {
-                    let av = a.values.get(idx).and_then(|v| v.as_f64());
-                    let bv = b.values.get(idx).and_then(|v| v.as_f64());
-                    if sort.descending {
-                        bv.partial_cmp(&av).unwrap_or(std::cmp::Ordering::Equal)
-                    } else {
-                        av.partial_cmp(&bv).unwrap_or(std::cmp::Ordering::Equal)
-                    }
-                });
-            }
+            let mut seen = BTreeSet::new();
+            rows.retain(|row| seen.insert(row.values.clone()));
         }
 
         // Apply offset
@@ -272,78 +519,388 @@ impl QueryExecutor {
         Ok((columns, rows))
     }
 
+    fn directed(ordering: std::cmp::Ordering, descending: bool) -> std::cmp::Ordering {
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
     fn execute_aggregation(
         plan: &QueryPlan,
         data: Vec<(SeriesKey, DataPoint)>,
+        memory: &mut MemoryTracker,
     ) -> Result<(Vec<String>, Vec<QueryRow>)> {
+        // Naming `series` in the SELECT adds the canonical key of each tag
+        // group's first series (in key order), so every row of the group,
+        // whatever its time bucket, maps back to the same series
+        let include_series = matches!(&plan.fields, FieldSelection::Fields(fields) if fields.iter().any(|f| f == "series"));
+        let mut representatives: HashMap<Vec<(String, String)>, SeriesKey> = HashMap::new();
+
         // Group data
         let mut groups: HashMap<GroupKey, Vec<(SeriesKey, DataPoint)>> = HashMap::new();
 
         for (key, point) in data {
-            let group_key = GroupKey {
-                time_bucket: plan.time_bucket.map(|b| (point.timestamp / b) * b),
-                tags: plan
-                    .group_by_tags
+            let tags = Self::group_tags(plan, &key);
+            if include_series {
+                Self::note_representative(&mut representatives, &tags, &key);
+            }
+
+            for time_bucket in Self::time_buckets(plan, point.timestamp) {
+                let group_key = GroupKey {
+                    time_bucket,
+                    tags: tags.clone(),
+                };
+
+                if !groups.contains_key(&group_key) {
+                    memory.reserve(group_key.size() + MemoryTracker::ENTRY_OVERHEAD)?;
+                }
+                groups.entry(group_key).or_default().push((key.clone(), point.clone()));
+            }
+        }
+
+        if let [agg] = plan.aggregations.as_slice() {
+            if let AggregateFunc::Histogram { width } = agg.function {
+                return Self::execute_histogram(plan, groups, agg, width);
+            }
+        }
+
+        // A lone selector (first, last, max_by, min_by) without GROUP BY
+        // time() reports when its point occurred, along with that point's
+        // values for any plain fields in the SELECT; with time buckets rows
+        // keep the bucket start so they line up with FILL and other
+        // aggregates
+        let selector = match plan.aggregations.as_slice() {
+            [agg] if plan.time_bucket.is_none() && Self::is_selector(agg.function) => Some(agg),
+            _ => None,
+        };
+        let sibling_fields: Vec<&String> = match (&plan.fields, selector) {
+            (FieldSelection::Fields(fields), Some(_)) => fields.iter().filter(|f| *f != "series").collect(),
+            _ => Vec::new(),
+        };
+
+        let mut columns = Self::aggregate_columns(plan, selector.is_some(), include_series);
+        columns.extend(sibling_fields.iter().map(|f| f.to_string()));
+
+        // Columns whose values are counts or come straight from integer
+        // fields, so filled values are rounded
+        let integer_columns: Vec<bool> = plan
+            .aggregations
+            .iter()
+            .map(|agg| match agg.function {
+                AggregateFunc::Count => true,
+                AggregateFunc::Min | AggregateFunc::Max if agg.field == TIME_COLUMN => true,
+                AggregateFunc::Sum
+                | AggregateFunc::Min
+                | AggregateFunc::Max
+                | AggregateFunc::First
+                | AggregateFunc::Last
+                | AggregateFunc::MaxBy
+                | AggregateFunc::MinBy => {
+                    let mut values = groups
+                        .values()
+                        .flatten()
+                        .filter_map(|(_, dp)| dp.fields.get(&agg.field))
+                        .peekable();
+                    values.peek().is_some() && values.all(|v| matches!(v, FieldValue::Integer(_)))
+                }
+                _ => false,
+            })
+            .collect();
+
+        // Compute aggregates for each group; groups are independent, so
+        // with parallelism they are spread over the rayon pool and put back
+        // in order below
+        let compute = |(group_key, points): (GroupKey, Vec<(SeriesKey, DataPoint)>)| {
+            let mut values = Self::group_tag_values(plan, &group_key);
+
+            // Compute each aggregation
+            for agg in &plan.aggregations {
+                let field_values: Vec<f64> = points
                     .iter()
-                    .filter_map(|t| key.tags.get(t).map(|v| (t.clone(), v.clone())))
-                    .collect(),
+                    .filter_map(|(_, dp)| dp.fields.get(&agg.field))
+                    .filter_map(|v| v.as_f64())
+                    .collect();
+
+                let result = match Self::distinct_aggregate(agg, &points)
+                    .or_else(|| Self::time_aggregate(agg, &points))
+                {
+                    Some(result) => result,
+                    None => match Self::floored_integer_aggregate(plan, agg, &points)? {
+                        Some(result) => result,
+                        None => Self::compute_aggregate(agg.function, &agg.field, &field_values, &points),
+                    },
+                };
+                values.push(result);
+            }
+
+            let time = match selector {
+                Some(agg) => {
+                    let selected = Self::select_point(agg.function, &agg.field, &points);
+                    for field in &sibling_fields {
+                        values.push(
+                            selected
+                                .and_then(|dp| dp.fields.get(field))
+                                .map(Self::field_to_query_value)
+                                .unwrap_or(QueryValue::Null),
+                        );
+                    }
+                    selected.map(|dp| dp.timestamp)
+                }
+                None => group_key.time_bucket,
             };
+            Ok(QueryRow {
+                time,
+                series: representatives.get(&group_key.tags).map(SeriesKey::encode),
+                values,
+            })
+        };
+        let rows: Vec<QueryRow> = if plan.parallelism > 1 {
+            groups.into_par_iter().map(compute).collect::<Result<_>>()?
+        } else {
+            groups.into_iter().map(compute).collect::<Result<_>>()?
+        };
+
+        let rows = Self::order_aggregate_rows(plan, rows, &integer_columns, memory)?;
+        Ok((columns, rows))
+    }
+
+    /// Aggregate `data` with each group holding only running state, or the
+    /// values of aggregates that need them all; see [`GroupedAggregation`]
+    fn execute_grouped_aggregation(
+        plan: &QueryPlan,
+        data: Vec<(SeriesKey, DataPoint)>,
+        memory: &mut MemoryTracker,
+    ) -> Result<(Vec<String>, Vec<QueryRow>)> {
+        let include_series = matches!(&plan.fields, FieldSelection::Fields(fields) if fields.iter().any(|f| f == "series"));
+        let mut representatives: HashMap<Vec<(String, String)>, SeriesKey> = HashMap::new();
+        let mut groups = GroupedAggregation::new(plan);
+
+        for (key, point) in data {
+            if !(Self::matches_basic_filters(plan, &key, &point)?
+                && Self::matches_advanced_filters(plan, &key, &point))
+            {
+                continue;
+            }
+            let tags = Self::group_tags(plan, &key);
+            if include_series {
+                Self::note_representative(&mut representatives, &tags, &key);
+            }
+            for time_bucket in Self::time_buckets(plan, point.timestamp) {
+                groups.add(GroupKey { time_bucket, tags: tags.clone() }, &point, memory)?;
+            }
+        }
+
+        let columns = Self::aggregate_columns(plan, false, include_series);
+        let integer_columns: Vec<bool> = plan
+            .aggregations
+            .iter()
+            .zip(groups.integer_fields())
+            .map(|(agg, integers)| match agg.function {
+                AggregateFunc::Count => true,
+                AggregateFunc::Sum | AggregateFunc::Min | AggregateFunc::Max => integers,
+                _ => false,
+            })
+            .collect();
+
+        let rows = groups.finish(|group_key, states| {
+            let mut values = Self::group_tag_values(plan, &group_key);
+            for (agg, state) in plan.aggregations.iter().zip(states) {
+                values.push(match state {
+                    FinishedState::Running { result: None, .. } => QueryValue::Null,
+                    FinishedState::Running { count, .. } if agg.function == AggregateFunc::Count => {
+                        QueryValue::Integer(count as i64)
+                    }
+                    FinishedState::Running { count, integer_sum: Some(total), .. }
+                        if plan.integer_aggregates == IntegerAggregates::Floor
+                            && matches!(agg.function, AggregateFunc::Sum | AggregateFunc::Mean) =>
+                    {
+                        if agg.function == AggregateFunc::Sum {
+                            Self::integer_sum(plan, agg, total)?
+                        } else {
+                            QueryValue::Integer(total.div_euclid(count as i128) as i64)
+                        }
+                    }
+                    FinishedState::Running { result: Some(result), .. } => QueryValue::Float(result),
+                    FinishedState::Values(field_values) => {
+                        Self::compute_aggregate(agg.function, &agg.field, &field_values, &[])
+                    }
+                });
+            }
+            Ok(QueryRow {
+                time: group_key.time_bucket,
+                series: representatives.get(&group_key.tags).map(SeriesKey::encode),
+                values,
+            })
+        })?;
+
+        let rows = Self::order_aggregate_rows(plan, rows, &integer_columns, memory)?;
+        Ok((columns, rows))
+    }
 
-            groups.entry(group_key).or_default().push((key, point));
+    /// Tags of `key` that the plan groups by
+    fn group_tags(plan: &QueryPlan, key: &SeriesKey) -> Vec<(String, String)> {
+        plan.group_by_tags
+            .iter()
+            .filter_map(|t| key.tags.get(t).map(|v| (t.clone(), v.clone())))
+            .collect()
+    }
+
+    /// Keep the smallest series key of each tag group
+    fn note_representative(
+        representatives: &mut HashMap<Vec<(String, String)>, SeriesKey>,
+        tags: &[(String, String)],
+        key: &SeriesKey,
+    ) {
+        match representatives.get_mut(tags) {
+            Some(representative) if *representative <= *key => {}
+            Some(representative) => *representative = key.clone(),
+            None => {
+                representatives.insert(tags.to_vec(), key.clone());
+            }
+        }
+    }
+
+    /// Start of each time bucket a point at `timestamp` falls in; `None`
+    /// without GROUP BY time
+    fn time_buckets(plan: &QueryPlan, timestamp: i64) -> Vec<Option<i64>> {
+        match (plan.time_bucket, plan.time_step) {
+            (Some(window), Some(step)) => Self::sliding_windows(timestamp, window, step),
+            (Some(b), None) => vec![Some((timestamp / b) * b)],
+            (None, _) => vec![None],
         }
+    }
 
-        // Build columns
+    /// Columns of an aggregation: time, series, GROUP BY tags, then the
+    /// aggregates; `timed` adds time without GROUP BY time
+    fn aggregate_columns(plan: &QueryPlan, timed: bool, include_series: bool) -> Vec<String> {
         let mut columns = Vec::new();
-        if plan.time_bucket.is_some() {
+        if plan.time_bucket.is_some() || timed {
             columns.push("time".to_string());
         }
+        if include_series {
+            columns.push("series".to_string());
+        }
         for tag in &plan.group_by_tags {
             columns.push(tag.clone());
         }
         for agg in &plan.aggregations {
             columns.push(agg.alias.clone());
         }
+        columns
+    }
 
-        // Compute aggregates for each group
-        let mut rows: Vec<QueryRow> = groups
-            .into_iter()
-            .map(|(group_key, points)| {
-                let mut values = Vec::new();
+    /// Values of the GROUP BY tags for a group's row
+    fn group_tag_values(plan: &QueryPlan, group_key: &GroupKey) -> Vec<QueryValue> {
+        plan.group_by_tags
+            .iter()
+            .map(|tag| {
+                group_key
+                    .tags
+                    .iter()
+                    .find(|(k, _)| k == tag)
+                    .map(|(_, v)| QueryValue::String(v.clone()))
+                    .unwrap_or(QueryValue::Null)
+            })
+            .collect()
+    }
+
+    /// Sort aggregate rows, fill gaps and apply OFFSET and LIMIT
+    fn order_aggregate_rows(
+        plan: &QueryPlan,
+        mut rows: Vec<QueryRow>,
+        integer_columns: &[bool],
+        memory: &mut MemoryTracker,
+    ) -> Result<Vec<QueryRow>> {
+        // Groups come out of a hash map: order rows by time, if they have
+        // one, then by their tag values so tag-only groupings are stable too
+        let tag_count = plan.group_by_tags.len();
+        rows.sort_by_cached_key(|row| {
+            let tags: Vec<Option<String>> = row.values[..tag_count].iter().map(|v| v.as_string()).collect();
+            (row.time, tags)
+        });
+
+        if let Some(fill) = &plan.fill {
+            rows = Self::fill_gaps(plan, fill, rows, integer_columns, memory)?;
+        }
+
+        // Apply offset
+        if let Some(offset) = plan.offset {
+            if offset < rows.len() {
+                rows = rows.into_iter().skip(offset).collect();
+            } else {
+                rows.clear();
+            }
+        }
+
+        // Apply limit
+        if let Some(limit) = plan.limit {
+            rows.truncate(limit);
+        }
+
+        Ok(rows)
+    }
+
+    /// Count each group's values per fixed-width value bucket
+    ///
+    /// Columns are the time bucket (when grouping by time), the GROUP BY
+    /// tags, `le` and the count (named `count` unless aliased). Buckets are
+    /// `(le - width, le]`, as with Prometheus' `le` label, but counts are per
+    /// bucket rather than cumulative, and only non-empty buckets are
+    /// returned. FILL does not apply.
+    fn execute_histogram(
+        plan: &QueryPlan,
+        groups: HashMap<GroupKey, Vec<(SeriesKey, DataPoint)>>,
+        agg: &Aggregation,
+        width: f64,
+    ) -> Result<(Vec<String>, Vec<QueryRow>)> {
+        let mut columns = Vec::new();
+        if plan.time_bucket.is_some() {
+            columns.push("time".to_string());
+        }
+        columns.extend(plan.group_by_tags.iter().cloned());
+        columns.push("le".to_string());
+        columns.push(agg.alias.clone());
+
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_by(|(a, _), (b, _)| (a.time_bucket, &a.tags).cmp(&(b.time_bucket, &b.tags)));
+
+        let mut rows = Vec::new();
+        for (group_key, points) in groups {
+            // Bucket index k covers ((k - 1) * width, k * width]
+            let mut counts: BTreeMap<i64, i64> = BTreeMap::new();
+            for value in points
+                .iter()
+                .filter_map(|(_, dp)| dp.fields.get(&agg.field))
+                .filter_map(|v| v.as_f64())
+                .filter(|v| v.is_finite())
+            {
+                *counts.entry((value / width).ceil() as i64).or_insert(0) += 1;
+            }
 
-                // Add group-by tag values
-                for tag in &plan.group_by_tags {
-                    let val = group_key
+            let tags: Vec<QueryValue> = plan
+                .group_by_tags
+                .iter()
+                .map(|tag| {
+                    group_key
                         .tags
                         .iter()
                         .find(|(k, _)| k == tag)
                         .map(|(_, v)| QueryValue::String(v.clone()))
-                        .unwrap_or(QueryValue::Null);
-                    values.push(val);
-                }
-
-                // Compute each aggregation
-                for agg in &plan.aggregations {
-                    let field_values: Vec<f64> = points
-                        .iter()
-                        .filter_map(|(_, dp)| dp.fields.get(&agg.field))
-                        .filter_map(|v| v.as_f64())
-                        .collect();
-
-                    let result = Self::compute_aggregate(agg.function, &field_values, &points);
-                    values.push(result);
-                }
-
-                QueryRow {
+                        .unwrap_or(QueryValue::Null)
+                })
+                .collect();
+            for (bucket, count) in counts {
+                let mut values = tags.clone();
+                values.push(QueryValue::Float(bucket as f64 * width));
+                values.push(QueryValue::Integer(count));
+                rows.push(QueryRow {
                     time: group_key.time_bucket,
                     series: None,
                     values,
-                }
-            })
-            .collect();
-
-        // Sort by time if time bucketing
-        if plan.time_bucket.is_some() {
-            rows.sort_by(|a, b| a.time.cmp(&b.time));
+                });
+            }
         }
 
         // Apply offset
@@ -363,58 +920,443 @@ impl QueryExecutor {
         Ok((columns, rows))
     }
 
-    fn compute_aggregate(
-        func: AggregateFunc,
-        values: &[f64],
-        points: &[(SeriesKey, DataPoint)],
-    ) -> QueryValue {
-        if values.is_empty() {
-            return QueryValue::Null;
+    /// Insert rows for empty time buckets according to the FILL option
+    ///
+    /// Each tag group is filled separately, across the queried time range
+    /// when it is bounded and across the group's own data otherwise.
+    fn fill_gaps(
+        plan: &QueryPlan,
+        fill: &FillOption,
+        rows: Vec<QueryRow>,
+        integer_columns: &[bool],
+        memory: &mut MemoryTracker,
+    ) -> Result<Vec<QueryRow>> {
+        let interval = match plan.time_step.or(plan.time_bucket) {
+            Some(interval) if interval > 0 => interval,
+            _ => return Ok(rows),
+        };
+        if matches!(fill, FillOption::None) || rows.is_empty() {
+            return Ok(rows);
         }
+        let align = |ts: i64| (ts / interval) * interval;
+        let tag_count = plan.group_by_tags.len();
 
-        match func {
-            AggregateFunc::Count => QueryValue::Integer(values.len() as i64),
-            AggregateFunc::Sum => QueryValue::Float(values.iter().sum()),
-            AggregateFunc::Mean => {
-                QueryValue::Float(values.iter().sum::<f64>() / values.len() as f64)
+        let mut groups: BTreeMap<Vec<Option<String>>, Vec<QueryRow>> = BTreeMap::new();
+        for row in rows {
+            let tags = row.values[..tag_count]
+                .iter()
+                .map(|v| match v {
+                    QueryValue::String(s) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect();
+            groups.entry(tags).or_default().push(row);
+        }
+
+        let mut filled = Vec::new();
+        for group in groups.into_values() {
+            let first = group.first().and_then(|r| r.time).unwrap_or(0);
+            let last = group.last().and_then(|r| r.time).unwrap_or(0);
+            let start = if plan.time_range.start != i64::MIN {
+                align(plan.time_range.start).min(first)
+            } else {
+                first
+            };
+            let end = if plan.time_range.end != i64::MAX {
+                align(plan.time_range.end).max(last)
+            } else {
+                last
+            };
+
+            // Known (time, value) pairs per aggregate column, for interpolation
+            let known: Vec<Vec<(i64, QueryValue)>> = (0..integer_columns.len())
+                .map(|j| {
+                    group
+                        .iter()
+                        .filter(|r| !r.values[tag_count + j].is_null())
+                        .filter_map(|r| r.time.map(|t| (t, r.values[tag_count + j].clone())))
+                        .collect()
+                })
+                .collect();
+
+            let tags = group[0].values[..tag_count].to_vec();
+            let series = group[0].series.clone();
+            let mut existing = group.into_iter().peekable();
+            let mut previous: Vec<QueryValue> = vec![QueryValue::Null; integer_columns.len()];
+            let mut t = start;
+            while t <= end {
+                if let Some(row) = existing.next_if(|r| r.time == Some(t)) {
+                    for (j, prev) in previous.iter_mut().enumerate() {
+                        if !row.values[tag_count + j].is_null() {
+                            *prev = row.values[tag_count + j].clone();
+                        }
+                    }
+                    filled.push(row);
+                } else {
+                    memory.reserve(MemoryTracker::ENTRY_OVERHEAD)?;
+                    let mut values = tags.clone();
+                    for (j, is_integer) in integer_columns.iter().enumerate() {
+                        values.push(match fill {
+                            FillOption::Null | FillOption::None => QueryValue::Null,
+                            FillOption::Previous => previous[j].clone(),
+                            FillOption::Value(v) => QueryValue::Float(*v),
+                            FillOption::Linear => Self::interpolate(&known[j], t, *is_integer),
+                        });
+                    }
+                    filled.push(QueryRow {
+                        time: Some(t),
+                        series: series.clone(),
+                        values,
+                    });
+                }
+                t += interval;
             }
-            AggregateFunc::Min => QueryValue::Float(
-                values.iter().cloned().fold(f64::INFINITY, f64::min),
-            ),
-            AggregateFunc::Max => QueryValue::Float(
-                values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
-            ),
-            AggregateFunc::First => {
-                // Get value with earliest timestamp
-                points
-                    .iter()
-                    .min_by_key(|(_, dp)| dp.timestamp)
-                    .and_then(|(_, dp)| dp.fields.0.values().next())
-                    .and_then(|v| v.as_f64())
-                    .map(QueryValue::Float)
-                    .unwrap_or(QueryValue::Null)
+        }
+
+        filled.sort_by_key(|r| r.time);
+        Ok(filled)
+    }
+
+    /// Linearly interpolate a column at `t` between its nearest known values
+    ///
+    /// Gaps without a known value on both sides stay null.
+    fn interpolate(known: &[(i64, QueryValue)], t: i64, is_integer: bool) -> QueryValue {
+        let idx = known.partition_point(|(time, _)| *time < t);
+        let (before, after) = match (idx.checked_sub(1).map(|i| &known[i]), known.get(idx)) {
+            (Some(before), Some(after)) => (before, after),
+            _ => return QueryValue::Null,
+        };
+        let (v0, v1) = match (before.1.as_f64(), after.1.as_f64()) {
+            (Some(v0), Some(v1)) => (v0, v1),
+            _ => return QueryValue::Null,
+        };
+
+        let value = v0 + (v1 - v0) * (t - before.0) as f64 / (after.0 - before.0) as f64;
+        match (&before.1, &after.1) {
+            (QueryValue::Integer(_), QueryValue::Integer(_)) => QueryValue::Integer(value.round() as i64),
+            _ if is_integer => QueryValue::Float(value.round()),
+            _ => QueryValue::Float(value),
+        }
+    }
+
+    /// Times of the sliding windows containing `timestamp`
+    ///
+    /// Windows end on multiples of `step` and cover `[end - window, end)`,
+    /// so each row aggregates the window preceding its time. Windows whose
+    /// end doesn't fit in an `i64` are left out.
+    fn sliding_windows(timestamp: i64, window: i64, step: i64) -> Vec<Option<i64>> {
+        let mut end = timestamp
+            .div_euclid(step)
+            .checked_mul(step)
+            .and_then(|start| start.checked_add(step));
+        let mut windows = Vec::new();
+        while let Some(e) = end.filter(|e| e.saturating_sub(window) <= timestamp) {
+            windows.push(Some(e));
+            end = e.checked_add(step);
+        }
+        windows
+    }
+
+    fn execute_transform(
+        plan: &QueryPlan,
+        data: Vec<(SeriesKey, DataPoint)>,
+    ) -> Result<(Vec<String>, Vec<QueryRow>)> {
+        if let [Transform { function: TransformFunc::Gaps { threshold }, field, alias }] = plan.transforms.as_slice() {
+            return Self::execute_gaps(plan, data, field, *threshold, alias);
+        }
+
+        let mut columns = vec!["time".to_string(), "series".to_string()];
+        columns.extend(plan.transforms.iter().map(|t| t.alias.clone()));
+
+        // Transformations work on each series in time order
+        let mut series: BTreeMap<String, Vec<DataPoint>> = BTreeMap::new();
+        for (key, point) in data {
+            series.entry(key.encode()).or_default().push(point);
+        }
+
+        let mut rows = Vec::new();
+        for (series_key, mut points) in series {
+            points.sort_by_key(|p| p.timestamp);
+
+            // Previous timestamp seen for each transform's field
+            let mut prev: Vec<Option<i64>> = vec![None; plan.transforms.len()];
+            for point in &points {
+                let mut values = Vec::with_capacity(plan.transforms.len());
+                for (i, transform) in plan.transforms.iter().enumerate() {
+                    if point.fields.get(&transform.field).is_none() {
+                        values.push(QueryValue::Null);
+                        continue;
+                    }
+                    let value = match (transform.function, prev[i]) {
+                        (TransformFunc::Elapsed { unit }, Some(prev_ts)) => {
+                            QueryValue::Integer((point.timestamp - prev_ts) / unit)
+                        }
+                        (TransformFunc::Elapsed { .. }, None) => QueryValue::Null,
+                        // Runs on its own in execute_gaps
+                        (TransformFunc::Gaps { .. }, _) => QueryValue::Null,
+                    };
+                    prev[i] = Some(point.timestamp);
+                    values.push(value);
+                }
+
+                // The first point of a series has nothing to compare against
+                if values.iter().all(|v| v.is_null()) {
+                    continue;
+                }
+                rows.push(QueryRow {
+                    time: Some(point.timestamp),
+                    series: Some(series_key.clone()),
+                    values,
+                });
             }
-            AggregateFunc::Last => {
-                // Get value with latest timestamp
-                points
-                    .iter()
-                    .max_by_key(|(_, dp)| dp.timestamp)
-                    .and_then(|(_, dp)| dp.fields.0.values().next())
+        }
+
+        // Apply offset
+        if let Some(offset) = plan.offset {
+            if offset < rows.len() {
+                rows = rows.into_iter().skip(offset).collect();
+            } else {
+                rows.clear();
+            }
+        }
+
+        // Apply limit
+        if let Some(limit) = plan.limit {
+            rows.truncate(limit);
+        }
+
+        Ok((columns, rows))
+    }
+
+    /// One row per interval between consecutive points carrying `field`
+    /// that is longer than `threshold`: the series, the timestamps of the
+    /// points on either side and the interval's length in nanoseconds
+    fn execute_gaps(
+        plan: &QueryPlan,
+        data: Vec<(SeriesKey, DataPoint)>,
+        field: &str,
+        threshold: i64,
+        alias: &str,
+    ) -> Result<(Vec<String>, Vec<QueryRow>)> {
+        let columns = vec!["series".to_string(), "start".to_string(), "end".to_string(), alias.to_string()];
+
+        let mut series: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+        for (key, point) in data {
+            if point.fields.get(field).is_some() {
+                series.entry(key.encode()).or_default().push(point.timestamp);
+            }
+        }
+
+        let mut rows = Vec::new();
+        for (series_key, mut timestamps) in series {
+            timestamps.sort_unstable();
+            for pair in timestamps.windows(2) {
+                let (start, end) = (pair[0], pair[1]);
+                if end - start > threshold {
+                    rows.push(QueryRow {
+                        time: None,
+                        series: Some(series_key.clone()),
+                        values: vec![
+                            QueryValue::Integer(start),
+                            QueryValue::Integer(end),
+                            QueryValue::Integer(end - start),
+                        ],
+                    });
+                }
+            }
+        }
+
+        // Apply offset
+        if let Some(offset) = plan.offset {
+            if offset < rows.len() {
+                rows = rows.into_iter().skip(offset).collect();
+            } else {
+                rows.clear();
+            }
+        }
+
+        // Apply limit
+        if let Some(limit) = plan.limit {
+            rows.truncate(limit);
+        }
+
+        Ok((columns, rows))
+    }
+
+    /// The earliest (`First`) or latest (`Last`) point carrying `field`
+    /// Aggregates whose value comes from a single point
+    fn is_selector(func: AggregateFunc) -> bool {
+        matches!(
+            func,
+            AggregateFunc::First | AggregateFunc::Last | AggregateFunc::MaxBy | AggregateFunc::MinBy
+        )
+    }
+
+    /// The point a selector picks; `max_by` and `min_by` break ties in
+    /// favour of the earliest point
+    fn select_point<'a>(
+        func: AggregateFunc,
+        field: &str,
+        points: &'a [(SeriesKey, DataPoint)],
+    ) -> Option<&'a DataPoint> {
+        let with_field = points
+            .iter()
+            .filter_map(|(_, dp)| Some((dp, dp.fields.get(field)?.as_f64()?)));
+        match func {
+            AggregateFunc::First => with_field.min_by_key(|(dp, _)| dp.timestamp),
+            AggregateFunc::Last => with_field.max_by_key(|(dp, _)| dp.timestamp),
+            AggregateFunc::MaxBy => {
+                with_field.max_by(|(a, va), (b, vb)| va.total_cmp(vb).then(b.timestamp.cmp(&a.timestamp)))
+            }
+            AggregateFunc::MinBy => {
+                with_field.min_by(|(a, va), (b, vb)| va.total_cmp(vb).then(a.timestamp.cmp(&b.timestamp)))
+            }
+            _ => None,
+        }
+        .map(|(dp, _)| dp)
+    }
+
+    /// An aggregate over the distinct values of its field in the group, or
+    /// of the tag of that name for points without the field; `None` unless
+    /// the aggregation is `DISTINCT`
+    ///
+    /// `count` counts values of any type, the rest aggregate the distinct
+    /// numeric values.
+    fn distinct_aggregate(agg: &Aggregation, points: &[(SeriesKey, DataPoint)]) -> Option<QueryValue> {
+        if !agg.distinct {
+            return None;
+        }
+        let values: BTreeSet<QueryValue> = points
+            .iter()
+            .filter_map(|(key, dp)| match dp.fields.get(&agg.field) {
+                Some(value) => Some(Self::field_to_query_value(value)),
+                None => key.tags.get(&agg.field).map(|tag| QueryValue::String(tag.clone())),
+            })
+            .collect();
+        if agg.function == AggregateFunc::Count {
+            return Some(QueryValue::Integer(values.len() as i64));
+        }
+        // An integer and an equal float are one number
+        let mut numbers: Vec<f64> = values.iter().filter_map(QueryValue::as_f64).collect();
+        numbers.dedup();
+        Some(Self::compute_aggregate(agg.function, &agg.field, &numbers, points))
+    }
+
+    /// `min`, `max` or `count` over the `time` pseudo-column, as integer
+    /// nanosecond timestamps; `None` for other aggregates and fields
+    fn time_aggregate(agg: &Aggregation, points: &[(SeriesKey, DataPoint)]) -> Option<QueryValue> {
+        if agg.field != TIME_COLUMN {
+            return None;
+        }
+        let timestamps = points.iter().map(|(_, dp)| dp.timestamp);
+        match agg.function {
+            AggregateFunc::Min => Some(timestamps.min().map_or(QueryValue::Null, QueryValue::Integer)),
+            AggregateFunc::Max => Some(timestamps.max().map_or(QueryValue::Null, QueryValue::Integer)),
+            AggregateFunc::Count => Some(QueryValue::Integer(points.len() as i64)),
+            _ => None,
+        }
+    }
+
+    /// `mean`, `median` or `sum` of a field whose values in the group are
+    /// all integers, floored, when the plan asks for
+    /// `IntegerAggregates::Floor`; `None` leaves the aggregate to
+    /// `compute_aggregate`. A sum that overflows an i64 is handled as
+    /// `plan.integer_overflow` says
+    fn floored_integer_aggregate(
+        plan: &QueryPlan,
+        agg: &Aggregation,
+        points: &[(SeriesKey, DataPoint)],
+    ) -> Result<Option<QueryValue>> {
+        if plan.integer_aggregates != IntegerAggregates::Floor
+            || !matches!(agg.function, AggregateFunc::Mean | AggregateFunc::Median | AggregateFunc::Sum)
+        {
+            return Ok(None);
+        }
+        let Some(mut values) = points
+            .iter()
+            .filter_map(|(_, dp)| dp.fields.get(&agg.field))
+            .map(|v| match v {
+                FieldValue::Integer(i) => Some(*i as i128),
+                _ => None,
+            })
+            .collect::<Option<Vec<i128>>>()
+        else {
+            return Ok(None);
+        };
+        if values.is_empty() {
+            return Ok(None);
+        }
+
+        if agg.function == AggregateFunc::Sum {
+            return Self::integer_sum(plan, agg, values.iter().sum()).map(Some);
+        }
+
+        // Both lie between the smallest and largest value, so fit in an i64
+        let result = if agg.function == AggregateFunc::Mean {
+            values.iter().sum::<i128>().div_euclid(values.len() as i128)
+        } else {
+            values.sort_unstable();
+            let mid = values.len() / 2;
+            if values.len() % 2 == 0 {
+                (values[mid - 1] + values[mid]).div_euclid(2)
+            } else {
+                values[mid]
+            }
+        };
+        Ok(Some(QueryValue::Integer(result as i64)))
+    }
+
+    /// An exact integer sum as an i64, or as `plan.integer_overflow` says
+    /// when it doesn't fit
+    fn integer_sum(plan: &QueryPlan, agg: &Aggregation, total: i128) -> Result<QueryValue> {
+        match (i64::try_from(total), plan.integer_overflow) {
+            (Ok(total), _) => Ok(QueryValue::Integer(total)),
+            (Err(_), IntegerOverflow::Float) => {
+                tracing::warn!("sum of {} overflows an i64, returning it as a rounded float", agg.alias);
+                Ok(QueryValue::Float(total as f64))
+            }
+            (Err(_), IntegerOverflow::Error) => Err(FluxError::Query("integer overflow in sum".to_string())),
+        }
+    }
+
+    fn compute_aggregate(
+        func: AggregateFunc,
+        field: &str,
+        values: &[f64],
+        points: &[(SeriesKey, DataPoint)],
+    ) -> QueryValue {
+        if values.is_empty() {
+            return QueryValue::Null;
+        }
+
+        match func {
+            AggregateFunc::Count => QueryValue::Integer(values.len() as i64),
+            AggregateFunc::Sum => QueryValue::Float(values.iter().sum()),
+            AggregateFunc::Mean => {
+                QueryValue::Float(values.iter().sum::<f64>() / values.len() as f64)
+            }
+            AggregateFunc::Min => QueryValue::Float(
+                values.iter().cloned().fold(f64::INFINITY, f64::min),
+            ),
+            AggregateFunc::Max => QueryValue::Float(
+                values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            ),
+            AggregateFunc::First | AggregateFunc::Last | AggregateFunc::MaxBy | AggregateFunc::MinBy => {
+                Self::select_point(func, field, points)
+                    .and_then(|dp| dp.fields.get(field))
                     .and_then(|v| v.as_f64())
                     .map(QueryValue::Float)
                     .unwrap_or(QueryValue::Null)
             }
-            AggregateFunc::Stddev => {
-                let mean = values.iter().sum::<f64>() / values.len() as f64;
-                let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>()
-                    / values.len() as f64;
-                QueryValue::Float(variance.sqrt())
+            AggregateFunc::Stddev | AggregateFunc::StddevSample => {
+                Self::variance(values, func == AggregateFunc::StddevSample)
+                    .map(|v| QueryValue::Float(v.sqrt()))
+                    .unwrap_or(QueryValue::Null)
             }
-            AggregateFunc::Variance => {
-                let mean = values.iter().sum::<f64>() / values.len() as f64;
-                let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>()
-                    / values.len() as f64;
-                QueryValue::Float(variance)
+            AggregateFunc::Variance | AggregateFunc::VarianceSample => {
+                Self::variance(values, func == AggregateFunc::VarianceSample)
+                    .map(QueryValue::Float)
+                    .unwrap_or(QueryValue::Null)
             }
             AggregateFunc::Median => {
                 let mut sorted = values.to_vec();
@@ -433,7 +1375,21 @@ impl QueryExecutor {
                 let idx = (sorted.len() as f64 * 0.5) as usize;
                 QueryValue::Float(sorted.get(idx).cloned().unwrap_or(0.0))
             }
+            // Produces one row per bucket; see execute_histogram
+            AggregateFunc::Histogram { .. } => QueryValue::Null,
+        }
+    }
+
+    /// Variance dividing by N, or by N - 1 for a sample; a sample of one
+    /// value has no variance
+    fn variance(values: &[f64], sample: bool) -> Option<f64> {
+        let n = values.len();
+        let divisor = if sample { n.checked_sub(1)? } else { n };
+        if divisor == 0 {
+            return None;
         }
+        let mean = values.iter().sum::<f64>() / n as f64;
+        Some(values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / divisor as f64)
     }
 
     fn field_to_query_value(field: &FieldValue) -> QueryValue {
@@ -447,7 +1403,1077 @@ impl QueryExecutor {
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
-struct GroupKey {
+pub(super) struct GroupKey {
     time_bucket: Option<i64>,
     tags: Vec<(String, String)>,
 }
+
+impl GroupKey {
+    /// Get the size in bytes (approximate)
+    pub(super) fn size(&self) -> usize {
+        8 + self.tags.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>()
+    }
+}
+
+/// Approximate accounting of bytes held in intermediate query results
+pub(super) struct MemoryTracker {
+    used: usize,
+    peak: usize,
+    limit: Option<usize>,
+}
+
+impl MemoryTracker {
+    /// Estimated bookkeeping cost per row or group (allocations, map slots)
+    pub(super) const ENTRY_OVERHEAD: usize = 64;
+
+    fn new(limit: Option<usize>) -> Self {
+        Self { used: 0, peak: 0, limit }
+    }
+
+    /// Account for `bytes` more, failing once the limit is exceeded
+    pub(super) fn reserve(&mut self, bytes: usize) -> Result<()> {
+        self.used += bytes;
+        self.peak = self.peak.max(self.used);
+        match self.limit {
+            Some(limit) if self.used > limit => {
+                Err(FluxError::Query("query exceeded memory limit".into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Account for `bytes` no longer held, as when moved to disk
+    pub(super) fn release(&mut self, bytes: usize) {
+        self.used = self.used.saturating_sub(bytes);
+    }
+
+    /// Most bytes held at once
+    fn peak(&self) -> usize {
+        self.peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{QueryParser, QueryPlanner};
+
+    fn plan(sql: &str) -> QueryPlan {
+        QueryPlanner::plan(&QueryParser::parse(sql).unwrap()).unwrap()
+    }
+
+    fn point(ts: i64, field: &str, value: FieldValue) -> (SeriesKey, DataPoint) {
+        (SeriesKey::new("devices"), DataPoint::new(ts, field, value))
+    }
+
+    #[test]
+    fn test_numeric_compare_boolean_field() {
+        let data = vec![
+            point(1, "active", FieldValue::Boolean(true)),
+            point(2, "active", FieldValue::Boolean(false)),
+            point(3, "active", FieldValue::Boolean(true)),
+        ];
+
+        let result = QueryExecutor::execute(&plan("SELECT * FROM devices WHERE active > 0"), data.clone()).unwrap();
+        let times: Vec<_> = result.rows.iter().map(|r| r.time.unwrap()).collect();
+        assert_eq!(times, vec![1, 3]);
+
+        let result = QueryExecutor::execute(&plan("SELECT * FROM devices WHERE active = 0"), data).unwrap();
+        let times: Vec<_> = result.rows.iter().map(|r| r.time.unwrap()).collect();
+        assert_eq!(times, vec![2]);
+    }
+
+    #[test]
+    fn test_boolean_field_conditions() {
+        let data = vec![
+            point(1, "active", FieldValue::Boolean(true)),
+            point(2, "active", FieldValue::Boolean(false)),
+            point(3, "active", FieldValue::Boolean(true)),
+            point(4, "reading", FieldValue::Float(1.0)),
+        ];
+        let times = |sql: &str| -> Vec<i64> {
+            let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+            result.rows.iter().map(|r| r.time.unwrap()).collect()
+        };
+
+        // Points without the field aren't filtered
+        assert_eq!(times("SELECT * FROM devices WHERE active = true"), vec![1, 3, 4]);
+        assert_eq!(times("SELECT * FROM devices WHERE active = false"), vec![2, 4]);
+        assert_eq!(times("SELECT * FROM devices WHERE active != false"), vec![1, 3, 4]);
+        assert_eq!(times("SELECT * FROM devices WHERE active"), vec![1, 3, 4]);
+        assert_eq!(times("SELECT * FROM devices WHERE NOT active"), vec![2, 4]);
+        assert_eq!(times("SELECT * FROM devices WHERE active AND time >= 2"), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_group_by_multiple_tags() {
+        let reading = |sensor: &str, region: Option<&str>, ts: i64, value: f64| {
+            let mut key = SeriesKey::new("temperature").with_tag("sensor", sensor);
+            if let Some(region) = region {
+                key = key.with_tag("region", region);
+            }
+            (key, DataPoint::new(ts, "value", FieldValue::Float(value)))
+        };
+        let data = vec![
+            reading("s2", Some("eu"), 1, 4.0),
+            reading("s1", Some("us"), 2, 2.0),
+            reading("s1", Some("eu"), 3, 1.0),
+            reading("s1", Some("us"), 4, 3.0),
+            reading("s3", None, 5, 7.0),
+            reading("s1", Some("eu"), 6, 1.5),
+            reading("s1", Some("eu"), 7, 2.5),
+        ];
+
+        let sql = "SELECT count(value), sum(value) FROM temperature GROUP BY sensor, region";
+        let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+
+        // Tags first in GROUP BY order, then aggregates; no time column
+        assert_eq!(result.columns, vec!["sensor", "region", "count_value", "sum_value"]);
+        let rows: Vec<(Option<String>, Option<String>, QueryValue, QueryValue)> = result
+            .rows
+            .iter()
+            .map(|r| {
+                assert_eq!(r.time, None);
+                (r.values[0].as_string(), r.values[1].as_string(), r.values[2].clone(), r.values[3].clone())
+            })
+            .collect();
+        let s = |v: &str| Some(v.to_string());
+        assert_eq!(
+            rows,
+            vec![
+                (s("s1"), s("eu"), QueryValue::Integer(3), QueryValue::Float(5.0)),
+                (s("s1"), s("us"), QueryValue::Integer(2), QueryValue::Float(5.0)),
+                (s("s2"), s("eu"), QueryValue::Integer(1), QueryValue::Float(4.0)),
+                // Series without a grouped tag form their own group
+                (s("s3"), None, QueryValue::Integer(1), QueryValue::Float(7.0)),
+            ]
+        );
+
+        // Same result whatever order the data arrives in
+        let mut reversed = data;
+        reversed.reverse();
+        let again = QueryExecutor::execute(&plan(sql), reversed).unwrap();
+        let values = |rows: &[QueryRow]| rows.iter().map(|r| r.values.clone()).collect::<Vec<_>>();
+        assert_eq!(values(&again.rows), values(&result.rows));
+    }
+
+    #[test]
+    fn test_group_by_all_tags() {
+        let reading = |sensor: &str, region: &str, ts: i64, value: f64| {
+            let key = SeriesKey::new("temperature").with_tag("sensor", sensor).with_tag("region", region);
+            (key, DataPoint::new(ts, "value", FieldValue::Float(value)))
+        };
+        let data = vec![
+            reading("s1", "eu", 1, 1.0),
+            reading("s1", "us", 2, 2.0),
+            reading("s2", "eu", 3, 3.0),
+            reading("s1", "eu", 4, 4.0),
+            reading("s2", "us", 5, 5.0),
+        ];
+        let groups = |sql: &str| -> (Vec<String>, Vec<Vec<QueryValue>>) {
+            let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+            (result.columns, result.rows.into_iter().map(|r| r.values).collect())
+        };
+        let s = |v: &str| QueryValue::String(v.to_string());
+
+        // One group per combination, tag keys in name order
+        let (columns, rows) = groups("SELECT count(value) FROM temperature GROUP BY *");
+        assert_eq!(columns, vec!["region", "sensor", "count_value"]);
+        assert_eq!(rows, vec![
+            vec![s("eu"), s("s1"), QueryValue::Integer(2)],
+            vec![s("eu"), s("s2"), QueryValue::Integer(1)],
+            vec![s("us"), s("s1"), QueryValue::Integer(1)],
+            vec![s("us"), s("s2"), QueryValue::Integer(1)],
+        ]);
+
+        // Named tags keep their place ahead of the rest
+        let (columns, rows) = groups("SELECT count(value) FROM temperature GROUP BY sensor, *");
+        assert_eq!(columns, vec!["sensor", "region", "count_value"]);
+        assert_eq!(rows.len(), 4);
+
+        // Only tag keys of the matched series count
+        let (columns, _) = groups("SELECT count(value) FROM temperature WHERE time > 100 GROUP BY *");
+        assert_eq!(columns, vec!["count_value"]);
+    }
+
+    #[test]
+    fn test_aggregate_series_column() {
+        const MINUTE: i64 = 60_000_000_000;
+        let reading = |sensor: &str, floor: &str, ts: i64, value: f64| {
+            let key = SeriesKey::new("temperature").with_tag("sensor", sensor).with_tag("floor", floor);
+            (key, DataPoint::new(ts, "value", FieldValue::Float(value)))
+        };
+        // s1 moved floors; its group maps back to the first series in key order
+        let data = vec![
+            reading("s1", "2", 10, 20.0),
+            reading("s1", "1", MINUTE + 10, 22.0),
+            reading("s2", "3", 20, 30.0),
+            reading("s2", "3", 2 * MINUTE + 20, 34.0),
+        ];
+
+        let sql = "SELECT mean(value), series FROM temperature GROUP BY sensor";
+        let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        assert_eq!(result.columns, vec!["series", "sensor", "mean_value"]);
+        let rows: Vec<_> = result.rows.iter().map(|r| (r.series.as_deref(), r.values.clone())).collect();
+        assert_eq!(rows, vec![
+            (Some("temperature,floor=1,sensor=s1"), vec![QueryValue::String("s1".into()), QueryValue::Float(21.0)]),
+            (Some("temperature,floor=3,sensor=s2"), vec![QueryValue::String("s2".into()), QueryValue::Float(32.0)]),
+        ]);
+
+        // Every time bucket of a group, filled ones included, names the same series
+        let sql = "SELECT mean(value), series FROM temperature GROUP BY time('1m'), sensor FILL(null)";
+        let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        assert_eq!(result.columns, vec!["time", "series", "sensor", "mean_value"]);
+        assert_eq!(result.rows.len(), 5);
+        for row in &result.rows {
+            let expected = match row.values[0].as_string().as_deref() {
+                Some("s1") => "temperature,floor=1,sensor=s1",
+                _ => "temperature,floor=3,sensor=s2",
+            };
+            assert_eq!(row.series.as_deref(), Some(expected), "{:?}", row);
+        }
+
+        // Without asking for it there's no series column
+        let result = QueryExecutor::execute(&plan("SELECT mean(value) FROM temperature GROUP BY sensor"), data).unwrap();
+        assert_eq!(result.columns, vec!["sensor", "mean_value"]);
+        assert!(result.rows.iter().all(|r| r.series.is_none()));
+    }
+
+    #[test]
+    fn test_numeric_compare_string_field() {
+        let data = vec![
+            point(1, "reading", FieldValue::String("high".into())),
+            point(2, "reading", FieldValue::Integer(42)),
+        ];
+
+        // Incompatible types are excluded by default
+        let result = QueryExecutor::execute(&plan("SELECT * FROM devices WHERE reading > 0"), data.clone()).unwrap();
+        let times: Vec<_> = result.rows.iter().map(|r| r.time.unwrap()).collect();
+        assert_eq!(times, vec![2]);
+
+        // ...or fail the query when configured to
+        let mut strict = plan("SELECT * FROM devices WHERE reading > 0");
+        strict.type_mismatch = TypeMismatch::Error;
+        assert!(matches!(QueryExecutor::execute(&strict, data), Err(FluxError::Query(_))));
+    }
+
+    #[test]
+    fn test_negated_filters_on_missing_field() {
+        let data = vec![
+            point(1, "code", FieldValue::Integer(200)),
+            point(2, "code", FieldValue::Integer(404)),
+            point(3, "other", FieldValue::Integer(1)),
+        ];
+        let times = |sql: &str, nulls: NullSemantics| {
+            let mut plan = plan(sql);
+            plan.null_semantics = nulls;
+            let result = QueryExecutor::execute(&plan, data.clone()).unwrap();
+            result.rows.iter().map(|r| r.time.unwrap()).collect::<Vec<_>>()
+        };
+
+        let queries = [
+            ("SELECT * FROM devices WHERE code NOT IN (404, 500)", [1]),
+            ("SELECT * FROM devices WHERE code NOT BETWEEN 300 AND 499", [1]),
+            ("SELECT * FROM devices WHERE code IN (200)", [1]),
+            ("SELECT * FROM devices WHERE code BETWEEN 100 AND 299", [1]),
+            ("SELECT * FROM devices WHERE code < 300", [1]),
+        ];
+        for (sql, matching) in queries {
+            // Lenient keeps the point without `code` either way
+            assert_eq!(times(sql, NullSemantics::Lenient), vec![matching[0], 3], "{}", sql);
+            // SQL semantics treat the missing value as unknown
+            assert_eq!(times(sql, NullSemantics::Sql), matching.to_vec(), "{}", sql);
+        }
+
+        // IS NULL is unaffected
+        let sql = "SELECT * FROM devices WHERE code IS NULL";
+        assert_eq!(times(sql, NullSemantics::Sql), vec![3]);
+        assert_eq!(times(sql, NullSemantics::Lenient), vec![3]);
+    }
+
+    fn heartbeat(timestamps: &[i64]) -> Vec<(SeriesKey, DataPoint)> {
+        timestamps
+            .iter()
+            .map(|ts| {
+                let key = SeriesKey::new("heartbeat").with_tag("host", "a");
+                (key, DataPoint::new(ts * 1_000_000_000, "up", FieldValue::Boolean(true)))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_elapsed_regular_series() {
+        let data = heartbeat(&[0, 10, 20, 30, 40]);
+        let result = QueryExecutor::execute(&plan("SELECT elapsed(up, '1s') FROM heartbeat"), data).unwrap();
+
+        assert_eq!(result.columns, vec!["time", "series", "elapsed_up"]);
+        assert_eq!(result.rows.len(), 4);
+        for row in &result.rows {
+            assert_eq!(row.values, vec![QueryValue::Integer(10)]);
+        }
+    }
+
+    #[test]
+    fn test_elapsed_with_gap() {
+        let data = heartbeat(&[0, 10, 20, 90, 100]);
+        let result = QueryExecutor::execute(&plan("SELECT elapsed(up, '1s') AS gap FROM heartbeat"), data).unwrap();
+
+        assert_eq!(result.columns, vec!["time", "series", "gap"]);
+        let elapsed: Vec<_> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+        assert_eq!(
+            elapsed,
+            vec![
+                QueryValue::Integer(10),
+                QueryValue::Integer(10),
+                QueryValue::Integer(70),
+                QueryValue::Integer(10),
+            ]
+        );
+        assert_eq!(result.rows[2].time, Some(90_000_000_000));
+    }
+
+    #[test]
+    fn test_gaps() {
+        const SECOND: i64 = 1_000_000_000;
+        // One 2-minute gap between 40s and 160s; the 30s steps don't count
+        let mut data = heartbeat(&[0, 10, 20, 40, 160, 170, 200]);
+        data.push((SeriesKey::new("heartbeat").with_tag("host", "b"), DataPoint::new(0, "up", FieldValue::Boolean(true))));
+
+        let result = QueryExecutor::execute(&plan("SELECT gaps(up, '30s') FROM heartbeat"), data.clone()).unwrap();
+        assert_eq!(result.columns, vec!["series", "start", "end", "duration"]);
+        assert_eq!(result.rows.len(), 1);
+        let row = &result.rows[0];
+        assert_eq!(row.series.as_deref(), Some("heartbeat,host=a"));
+        assert_eq!(row.time, None);
+        assert_eq!(
+            row.values,
+            vec![
+                QueryValue::Integer(40 * SECOND),
+                QueryValue::Integer(160 * SECOND),
+                QueryValue::Integer(120 * SECOND),
+            ]
+        );
+
+        let result = QueryExecutor::execute(&plan("SELECT gaps(up, '5s') AS missing FROM heartbeat"), data).unwrap();
+        assert_eq!(result.columns, vec!["series", "start", "end", "missing"]);
+        assert_eq!(result.rows.len(), 6);
+
+        let query = QueryParser::parse("SELECT gaps(up, '30s'), elapsed(up) FROM heartbeat").unwrap();
+        assert!(QueryPlanner::plan(&query).is_err());
+        assert!(QueryParser::parse("SELECT gaps(up) FROM heartbeat").is_err());
+    }
+
+    #[test]
+    fn test_series_limit() {
+        let data: Vec<_> = ["d", "b", "a", "c"]
+            .iter()
+            .flat_map(|sensor| {
+                (0..3).map(move |i| {
+                    let key = SeriesKey::new("temperature").with_tag("sensor", *sensor);
+                    (key, DataPoint::new(i, "value", FieldValue::Float(i as f64)))
+                })
+            })
+            .collect();
+        let series_of = |result: &QueryResult| {
+            let mut series: Vec<String> = result.rows.iter().filter_map(|r| r.series.clone()).collect();
+            series.dedup();
+            series
+        };
+
+        let result = QueryExecutor::execute(&plan("SELECT * FROM temperature ORDER BY time SLIMIT 2"), data.clone()).unwrap();
+        assert_eq!(result.rows.len(), 6);
+        let mut series = series_of(&result);
+        series.sort();
+        series.dedup();
+        assert_eq!(series, vec!["temperature,sensor=a", "temperature,sensor=b"]);
+
+        let result = QueryExecutor::execute(&plan("SELECT * FROM temperature SLIMIT 2 SOFFSET 1"), data.clone()).unwrap();
+        assert_eq!(series_of(&result), vec!["temperature,sensor=b", "temperature,sensor=c"]);
+
+        // Row-level LIMIT applies after the series are chosen
+        let result = QueryExecutor::execute(&plan("SELECT * FROM temperature LIMIT 4 SLIMIT 3"), data).unwrap();
+        assert_eq!(result.rows.len(), 4);
+    }
+
+    #[test]
+    fn test_query_memory_limit() {
+        let data: Vec<_> = (0..1000)
+            .map(|i| {
+                let key = SeriesKey::new("cpu").with_tag("host", format!("host-{}", i));
+                (key, DataPoint::new(i, "usage", FieldValue::Float(i as f64)))
+            })
+            .collect();
+        let sql = "SELECT mean(usage) FROM cpu GROUP BY host";
+
+        let unlimited = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        assert_eq!(unlimited.rows.len(), 1000);
+        let needed = unlimited.memory_bytes;
+        assert!(needed > 0);
+
+        let mut at_limit = plan(sql);
+        at_limit.max_memory = Some(needed);
+        assert_eq!(QueryExecutor::execute(&at_limit, data.clone()).unwrap().rows.len(), 1000);
+
+        let mut over_limit = plan(sql);
+        over_limit.max_memory = Some(needed - 1);
+        let err = QueryExecutor::execute(&over_limit, data).unwrap_err();
+        assert!(matches!(err, FluxError::Query(msg) if msg == "query exceeded memory limit"));
+    }
+
+    #[test]
+    fn test_high_cardinality_aggregation_memory() {
+        // 5k hosts with 20 points each: host i holds i..i+19
+        let data: Vec<_> = (0..5_000i64)
+            .flat_map(|i| {
+                let key = SeriesKey::new("cpu").with_tag("host", format!("host-{:05}", i));
+                (0..20).map(move |j| (key.clone(), DataPoint::new(j, "usage", FieldValue::Integer(i + j))))
+            })
+            .collect();
+        let check = |result: &QueryResult| {
+            assert_eq!(result.rows.len(), 5_000);
+            for (i, row) in result.rows.iter().enumerate() {
+                let i = i as i64;
+                assert_eq!(row.values[0], QueryValue::String(format!("host-{:05}", i)));
+                assert_eq!(
+                    row.values[1..],
+                    [
+                        QueryValue::Integer(20),
+                        QueryValue::Float((20 * i + 190) as f64),
+                        QueryValue::Float(i as f64 + 9.5),
+                        QueryValue::Float((i + 19) as f64),
+                    ]
+                );
+            }
+        };
+
+        // Groups hold running state rather than their points; SLIMIT makes
+        // the query buffer every point first, as all aggregations used to
+        let sql = "SELECT count(usage), sum(usage), mean(usage), max(usage) FROM cpu GROUP BY host";
+        let incremental = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        let buffered = QueryExecutor::execute(&plan(&format!("{} SLIMIT 100000", sql)), data.clone()).unwrap();
+        check(&incremental);
+        check(&buffered);
+        assert!(incremental.memory_bytes * 4 < buffered.memory_bytes);
+
+        // Medians need every value: past the spill threshold they go to
+        // disk and stop counting against the memory budget
+        let sql = "SELECT median(usage), variance(usage) FROM cpu GROUP BY host";
+        let mut spilling = plan(sql);
+        spilling.spill_threshold = Some(64 * 1024);
+        let result = QueryExecutor::execute(&spilling, data.clone()).unwrap();
+        assert_eq!(result.rows.len(), 5_000);
+        for (i, row) in result.rows.iter().enumerate() {
+            assert_eq!(row.values[1..], [QueryValue::Float(i as f64 + 9.5), QueryValue::Float(33.25)]);
+        }
+
+        let mut in_memory = plan(sql);
+        in_memory.max_memory = Some(result.memory_bytes);
+        let err = QueryExecutor::execute(&in_memory, data).unwrap_err();
+        assert!(matches!(err, FluxError::Query(msg) if msg == "query exceeded memory limit"));
+    }
+
+    #[test]
+    fn test_sliding_window_aggregates() {
+        const MINUTE: i64 = 60_000_000_000;
+        let data: Vec<_> = (0..10)
+            .map(|m| point(m * MINUTE, "value", FieldValue::Float(m as f64)))
+            .collect();
+
+        let sql = "SELECT mean(value), sum(value) FROM devices GROUP BY time('5m', '1m')";
+        let result = QueryExecutor::execute(&plan(sql), data).unwrap();
+
+        // Windows ending at 1m..14m each hold at least one point
+        let times: Vec<_> = result.rows.iter().map(|r| r.time.unwrap() / MINUTE).collect();
+        assert_eq!(times, (1..=14).collect::<Vec<_>>());
+
+        let row_at = |m: i64| result.rows.iter().find(|r| r.time == Some(m * MINUTE)).unwrap();
+        // [-4m, 1m) holds only the first point
+        assert_eq!(row_at(1).values, vec![QueryValue::Float(0.0), QueryValue::Float(0.0)]);
+        // [0m, 5m) and the overlapping [2m, 7m)
+        assert_eq!(row_at(5).values, vec![QueryValue::Float(2.0), QueryValue::Float(10.0)]);
+        assert_eq!(row_at(7).values, vec![QueryValue::Float(4.0), QueryValue::Float(20.0)]);
+        // [9m, 14m) holds only the last point
+        assert_eq!(row_at(14).values, vec![QueryValue::Float(9.0), QueryValue::Float(9.0)]);
+    }
+
+    #[test]
+    fn test_sliding_window_limits() {
+        let plan_err = |sql| QueryPlanner::plan(&QueryParser::parse(sql).unwrap()).unwrap_err().to_string();
+
+        // A window this much longer than its step is rejected when planning
+        let err = plan_err("SELECT sum(value) FROM devices GROUP BY time('1d', '1ns')");
+        assert!(err.contains("more than 10000 windows"), "{}", err);
+        assert!(plan_err("SELECT sum(value) FROM devices GROUP BY time('0s')").contains("must be positive"));
+
+        // Windows near the ends of the time range don't overflow
+        let data = vec![
+            point(i64::MAX - 1, "value", FieldValue::Float(1.0)),
+            point(i64::MIN, "value", FieldValue::Float(2.0)),
+        ];
+        let result = QueryExecutor::execute(&plan("SELECT sum(value) FROM devices GROUP BY time('5m', '1m')"), data).unwrap();
+        assert!(result.rows.iter().all(|r| r.values == vec![QueryValue::Float(1.0)] || r.values == vec![QueryValue::Float(2.0)]));
+    }
+
+    #[test]
+    fn test_tumbling_window_unchanged() {
+        const MINUTE: i64 = 60_000_000_000;
+        let data: Vec<_> = (0..10)
+            .map(|m| point(m * MINUTE, "value", FieldValue::Float(m as f64)))
+            .collect();
+
+        let result = QueryExecutor::execute(&plan("SELECT sum(value) FROM devices GROUP BY time('5m')"), data).unwrap();
+        let times: Vec<_> = result.rows.iter().map(|r| r.time.unwrap() / MINUTE).collect();
+        assert_eq!(times, vec![0, 5]);
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(10.0)]);
+        assert_eq!(result.rows[1].values, vec![QueryValue::Float(35.0)]);
+    }
+
+    #[test]
+    fn test_fill_linear_interior_gap() {
+        const SECOND: i64 = 1_000_000_000;
+        let data = vec![
+            point(0, "value", FieldValue::Float(1.0)),
+            point(3 * SECOND, "value", FieldValue::Float(4.0)),
+        ];
+
+        let sql = "SELECT mean(value) FROM devices GROUP BY time('1s') FILL(linear)";
+        let result = QueryExecutor::execute(&plan(sql), data).unwrap();
+        let values: Vec<_> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+        assert_eq!(values, vec![
+            QueryValue::Float(1.0),
+            QueryValue::Float(2.0),
+            QueryValue::Float(3.0),
+            QueryValue::Float(4.0),
+        ]);
+    }
+
+    #[test]
+    fn test_fill_linear_edges_stay_null() {
+        const SECOND: i64 = 1_000_000_000;
+        let data = vec![
+            point(SECOND, "value", FieldValue::Float(1.0)),
+            point(2 * SECOND, "value", FieldValue::Float(2.0)),
+        ];
+
+        let sql = "SELECT mean(value) FROM devices WHERE time >= 0 AND time <= 3000000000 \
+                   GROUP BY time('1s') FILL(linear)";
+        let result = QueryExecutor::execute(&plan(sql), data).unwrap();
+        let times: Vec<_> = result.rows.iter().map(|r| r.time.unwrap() / SECOND).collect();
+        assert_eq!(times, vec![0, 1, 2, 3]);
+        let values: Vec<_> = result.rows.iter().map(|r| r.values[0].clone()).collect();
+        assert_eq!(values, vec![
+            QueryValue::Null,
+            QueryValue::Float(1.0),
+            QueryValue::Float(2.0),
+            QueryValue::Null,
+        ]);
+    }
+
+    #[test]
+    fn test_fill_linear_rounds_integers() {
+        const SECOND: i64 = 1_000_000_000;
+        let data = vec![
+            point(0, "value", FieldValue::Integer(1)),
+            point(2 * SECOND, "value", FieldValue::Integer(2)),
+        ];
+
+        let sql = "SELECT max(value), count(value) FROM devices GROUP BY time('1s') FILL(linear)";
+        let result = QueryExecutor::execute(&plan(sql), data).unwrap();
+        assert_eq!(result.rows.len(), 3);
+        // 1.5 rounds away from zero
+        assert_eq!(result.rows[1].values, vec![QueryValue::Float(2.0), QueryValue::Integer(1)]);
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        const MINUTE: i64 = 60_000_000_000;
+        // First minute: 3 values in (0, 10], 1 in (10, 20], 2 in (40, 50]
+        // Second minute: 1 value in (10, 20]
+        let latencies = [(0, 1.0), (0, 5.0), (0, 10.0), (0, 12.0), (0, 45.0), (0, 50.0), (1, 15.0)];
+        let data: Vec<_> = latencies
+            .iter()
+            .enumerate()
+            .map(|(i, (m, v))| point(m * MINUTE + i as i64, "latency", FieldValue::Float(*v)))
+            .collect();
+
+        let sql = "SELECT histogram(latency, 10) FROM devices GROUP BY time('1m')";
+        let result = QueryExecutor::execute(&plan(sql), data).unwrap();
+        assert_eq!(result.columns, vec!["time", "le", "count"]);
+
+        let rows: Vec<_> = result
+            .rows
+            .iter()
+            .map(|r| (r.time.unwrap() / MINUTE, r.values[0].clone(), r.values[1].clone()))
+            .collect();
+        assert_eq!(rows, vec![
+            (0, QueryValue::Float(10.0), QueryValue::Integer(3)),
+            (0, QueryValue::Float(20.0), QueryValue::Integer(1)),
+            (0, QueryValue::Float(50.0), QueryValue::Integer(2)),
+            (1, QueryValue::Float(20.0), QueryValue::Integer(1)),
+        ]);
+
+        // Histograms produce their own rows and can't share a SELECT
+        let sql = "SELECT histogram(latency, 10), mean(latency) FROM devices";
+        assert!(QueryPlanner::plan(&QueryParser::parse(sql).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_first_last_report_point_time() {
+        const MINUTE: i64 = 60_000_000_000;
+        // The 1m point only carries `other`, so it's never selected
+        let data = vec![
+            point(30_000_000_000, "value", FieldValue::Float(1.0)),
+            point(MINUTE, "other", FieldValue::Float(9.0)),
+            point(MINUTE + 10, "value", FieldValue::Float(2.0)),
+            point(2 * MINUTE + 5, "value", FieldValue::Float(3.0)),
+            point(2 * MINUTE + 50, "value", FieldValue::Float(4.0)),
+        ];
+
+        let result = QueryExecutor::execute(&plan("SELECT first(value) FROM devices"), data.clone()).unwrap();
+        assert_eq!(result.columns, vec!["time", "first_value"]);
+        assert_eq!(result.rows[0].time, Some(30_000_000_000));
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(1.0)]);
+
+        let result = QueryExecutor::execute(&plan("SELECT last(value) FROM devices"), data.clone()).unwrap();
+        assert_eq!(result.rows[0].time, Some(2 * MINUTE + 50));
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(4.0)]);
+
+        // Time buckets keep their start time
+        let sql = "SELECT first(value) FROM devices GROUP BY time('1m')";
+        let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        let rows: Vec<_> = result.rows.iter().map(|r| (r.time, r.values[0].clone())).collect();
+        assert_eq!(rows, vec![
+            (Some(0), QueryValue::Float(1.0)),
+            (Some(MINUTE), QueryValue::Float(2.0)),
+            (Some(2 * MINUTE), QueryValue::Float(3.0)),
+        ]);
+
+        // Several aggregates don't share a point, so there's no time
+        let result = QueryExecutor::execute(&plan("SELECT first(value), last(value) FROM devices"), data).unwrap();
+        assert_eq!(result.columns, vec!["first_value", "last_value"]);
+        assert_eq!(result.rows[0].time, None);
+    }
+
+    #[test]
+    fn test_max_by_min_by_return_whole_point() {
+        let reading = |ts: i64, value: f64, humidity: i64, status: &str| {
+            let mut fields = Fields::new();
+            fields.insert("value", FieldValue::Float(value));
+            fields.insert("humidity", FieldValue::Integer(humidity));
+            fields.insert("status", FieldValue::String(status.into()));
+            (SeriesKey::new("devices"), DataPoint { timestamp: ts, fields })
+        };
+        let data = vec![
+            reading(10, 21.5, 40, "ok"),
+            reading(20, 29.0, 35, "hot"),
+            reading(30, 18.0, 55, "ok"),
+            reading(40, 29.0, 30, "hot"),
+            reading(50, 18.0, 60, "cold"),
+        ];
+
+        let sql = "SELECT max_by(value), humidity, status FROM devices";
+        let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        assert_eq!(result.columns, vec!["time", "max_by_value", "humidity", "status"]);
+        // The earliest of the tied maxima
+        assert_eq!(result.rows[0].time, Some(20));
+        assert_eq!(
+            result.rows[0].values,
+            vec![QueryValue::Float(29.0), QueryValue::Integer(35), QueryValue::String("hot".into())]
+        );
+
+        let sql = "SELECT min_by(value), status FROM devices";
+        let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        assert_eq!(result.columns, vec!["time", "min_by_value", "status"]);
+        assert_eq!(result.rows[0].time, Some(30));
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(18.0), QueryValue::String("ok".into())]);
+
+        // Alongside other aggregates it's just the extreme value
+        let sql = "SELECT max_by(value), count(value) FROM devices";
+        let result = QueryExecutor::execute(&plan(sql), data).unwrap();
+        assert_eq!(result.columns, vec!["max_by_value", "count_value"]);
+        assert_eq!(result.rows[0].time, None);
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(29.0), QueryValue::Integer(5)]);
+    }
+
+    #[test]
+    fn test_time_aggregates() {
+        let data: Vec<_> = [(30, "s1"), (10, "s1"), (20, "s1"), (50, "s2")]
+            .into_iter()
+            .map(|(ts, sensor)| {
+                let key = SeriesKey::new("temperature").with_tag("sensor", sensor);
+                (key, DataPoint::new(ts, "value", FieldValue::Float(20.0)))
+            })
+            .collect();
+
+        let sql = "SELECT max(time), min(time), count(time) FROM temperature WHERE sensor = 's1'";
+        let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        assert_eq!(result.columns, vec!["max_time", "min_time", "count_time"]);
+        assert_eq!(
+            result.rows[0].values,
+            vec![QueryValue::Integer(30), QueryValue::Integer(10), QueryValue::Integer(3)]
+        );
+
+        let sql = "SELECT max(time) FROM temperature GROUP BY sensor";
+        let result = QueryExecutor::execute(&plan(sql), data).unwrap();
+        let last: Vec<_> = result.rows.iter().map(|row| row.values[1].clone()).collect();
+        assert_eq!(last, vec![QueryValue::Integer(30), QueryValue::Integer(50)]);
+    }
+
+    #[test]
+    fn test_distinct_aggregates_per_bucket() {
+        const HOUR: i64 = 3_600_000_000_000;
+        let reading = |hour: i64, minute: i64, sensor: &str, value: f64| {
+            let key = SeriesKey::new("temperature").with_tag("sensor", sensor);
+            (key, DataPoint::new(hour * HOUR + minute * 60_000_000_000, "value", FieldValue::Float(value)))
+        };
+        let data = vec![
+            reading(0, 0, "s1", 20.0),
+            reading(0, 10, "s1", 20.0),
+            reading(0, 20, "s2", 21.0),
+            reading(1, 0, "s1", 22.0),
+            reading(1, 5, "s2", 22.0),
+            reading(1, 30, "s3", 23.0),
+            reading(1, 45, "s3", 22.0),
+            reading(2, 15, "s2", 24.0),
+        ];
+
+        let sql = "SELECT count(DISTINCT sensor), sum(DISTINCT value) FROM temperature GROUP BY time('1h')";
+        let result = QueryExecutor::execute(&plan(sql), data).unwrap();
+        assert_eq!(result.columns, vec!["time", "count_distinct_sensor", "sum_distinct_value"]);
+        let rows: Vec<_> = result.rows.iter().map(|r| (r.time.unwrap() / HOUR, r.values.clone())).collect();
+        assert_eq!(rows, vec![
+            (0, vec![QueryValue::Integer(2), QueryValue::Float(41.0)]),
+            (1, vec![QueryValue::Integer(3), QueryValue::Float(45.0)]),
+            (2, vec![QueryValue::Integer(1), QueryValue::Float(24.0)]),
+        ]);
+
+        // Selectors pick a point, so DISTINCT means nothing to them
+        assert!(QueryParser::parse("SELECT first(DISTINCT value) FROM temperature").is_err());
+    }
+
+    #[test]
+    fn test_integer_mean_and_median_modes() {
+        let data: Vec<_> = [1, 2, 5, 6]
+            .into_iter()
+            .enumerate()
+            .map(|(ts, v)| point(ts as i64, "count", FieldValue::Integer(v)))
+            .collect();
+        let sql = "SELECT mean(count), median(count) FROM devices";
+
+        let mut floats = plan(sql);
+        floats.integer_aggregates = IntegerAggregates::Float;
+        let result = QueryExecutor::execute(&floats, data.clone()).unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(3.5), QueryValue::Float(3.5)]);
+
+        let mut floored = plan(sql);
+        floored.integer_aggregates = IntegerAggregates::Floor;
+        let result = QueryExecutor::execute(&floored, data.clone()).unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Integer(3), QueryValue::Integer(3)]);
+
+        // Even count with a fractional middle, floored towards negative infinity
+        let negative = vec![
+            point(1, "count", FieldValue::Integer(-3)),
+            point(2, "count", FieldValue::Integer(-2)),
+        ];
+        let result = QueryExecutor::execute(&floats, negative.clone()).unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(-2.5), QueryValue::Float(-2.5)]);
+        let result = QueryExecutor::execute(&floored, negative).unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Integer(-3), QueryValue::Integer(-3)]);
+
+        // A float value in the group keeps the float result
+        let mut mixed = data;
+        mixed.push(point(9, "count", FieldValue::Float(2.0)));
+        let result = QueryExecutor::execute(&floored, mixed).unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(3.2), QueryValue::Float(2.0)]);
+    }
+
+    #[test]
+    fn test_integer_sum_overflow() {
+        let sql = "SELECT sum(count) FROM devices";
+        let mut floored = plan(sql);
+        floored.integer_aggregates = IntegerAggregates::Floor;
+
+        // Exact while it fits, where a float would round
+        let exact = vec![
+            point(1, "count", FieldValue::Integer(i64::MAX - 1)),
+            point(2, "count", FieldValue::Integer(1)),
+        ];
+        let result = QueryExecutor::execute(&floored, exact).unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Integer(i64::MAX)]);
+
+        let overflowing = vec![
+            point(1, "count", FieldValue::Integer(i64::MAX)),
+            point(2, "count", FieldValue::Integer(i64::MAX)),
+        ];
+        let result = QueryExecutor::execute(&floored, overflowing.clone()).unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(i64::MAX as f64 * 2.0)]);
+
+        floored.integer_overflow = IntegerOverflow::Error;
+        let err = QueryExecutor::execute(&floored, overflowing.clone()).unwrap_err();
+        assert!(matches!(err, FluxError::Query(msg) if msg == "integer overflow in sum"));
+        floored.parallelism = 4;
+        assert!(QueryExecutor::execute(&floored, overflowing).is_err());
+    }
+
+    #[test]
+    fn test_parallel_aggregation_matches_sequential() {
+        let data: Vec<(SeriesKey, DataPoint)> = (0..5_000)
+            .map(|i| {
+                let key = SeriesKey::new("devices")
+                    .with_tag("host", format!("h{}", i % 97))
+                    .with_tag("dc", format!("dc{}", i % 3));
+                (key, DataPoint::new(i, "value", FieldValue::Float((i % 13) as f64)))
+            })
+            .collect();
+
+        for sql in [
+            "SELECT mean(value), count(value), max(value) FROM devices GROUP BY host, dc",
+            "SELECT sum(value) FROM devices GROUP BY time('100ns'), host",
+            "SELECT last(value) FROM devices GROUP BY dc",
+        ] {
+            let sequential = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+            let mut parallel_plan = plan(sql);
+            parallel_plan.parallelism = 4;
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+            let parallel = pool.install(|| QueryExecutor::execute(&parallel_plan, data.clone())).unwrap();
+
+            assert_eq!(parallel.columns, sequential.columns, "{}", sql);
+            assert_eq!(
+                serde_json::to_string(&parallel.rows).unwrap(),
+                serde_json::to_string(&sequential.rows).unwrap(),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_parallel_grouped_aggregation_finishes_groups_on_the_pool() {
+        let data: Vec<(SeriesKey, DataPoint)> = (0..6_400)
+            .map(|i| {
+                let key = SeriesKey::new("devices").with_tag("host", format!("h{:02}", i % 64));
+                (key, DataPoint::new(i, "value", FieldValue::Float((i % 13) as f64)))
+            })
+            .collect();
+        let sql = "SELECT median(value), count(value) FROM devices GROUP BY host";
+        let mut parallel_plan = plan(sql);
+        parallel_plan.spill_threshold = Some(1024);
+        parallel_plan.parallelism = 4;
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+
+        // Groups, spilled values and all, are finished on several threads
+        let threads = std::sync::Mutex::new(HashSet::new());
+        let medians = pool
+            .install(|| {
+                let mut groups = GroupedAggregation::new(&parallel_plan);
+                let mut memory = MemoryTracker::new(None);
+                for (key, point) in &data {
+                    let group = GroupKey { time_bucket: None, tags: QueryExecutor::group_tags(&parallel_plan, key) };
+                    groups.add(group, point, &mut memory)?;
+                }
+                groups.finish(|_, states| {
+                    threads.lock().unwrap().insert(std::thread::current().id());
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                    match &states[0] {
+                        FinishedState::Values(values) => Ok(values.len()),
+                        FinishedState::Running { .. } => unreachable!(),
+                    }
+                })
+            })
+            .unwrap();
+        assert_eq!(medians, vec![100; 64]);
+        assert!(threads.lock().unwrap().len() > 1);
+
+        // With the same results as finishing them one at a time
+        let sequential = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        let parallel = pool.install(|| QueryExecutor::execute(&parallel_plan, data)).unwrap();
+        assert_eq!(
+            serde_json::to_string(&parallel.rows).unwrap(),
+            serde_json::to_string(&sequential.rows).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_select_except_and_field_globs() {
+        let mut fields = Fields::new();
+        for (name, value) in [("temp_in", 20.0), ("temp_out", 5.0), ("humidity", 40.0), ("debug", 1.0), ("attempt", 3.0)] {
+            fields.insert(name, FieldValue::Float(value));
+        }
+        let data = vec![(SeriesKey::new("sensors"), DataPoint { timestamp: 1, fields })];
+        let columns = |sql: &str| QueryExecutor::execute(&plan(sql), data.clone()).unwrap().columns;
+
+        assert_eq!(
+            columns("SELECT * EXCEPT (debug) FROM sensors"),
+            vec!["time", "series", "attempt", "humidity", "temp_in", "temp_out"]
+        );
+        assert_eq!(
+            columns("SELECT * EXCEPT (debug, attempt) FROM sensors"),
+            vec!["time", "series", "humidity", "temp_in", "temp_out"]
+        );
+        assert_eq!(columns("SELECT temp_* FROM sensors"), vec!["time", "series", "temp_in", "temp_out"]);
+        assert_eq!(columns("SELECT *_out, humidity FROM sensors"), vec!["time", "series", "humidity", "temp_out"]);
+        assert_eq!(columns("SELECT t*p* FROM sensors"), vec!["time", "series", "temp_in", "temp_out"]);
+
+        let result = QueryExecutor::execute(&plan("SELECT temp_* FROM sensors"), data.clone()).unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(20.0), QueryValue::Float(5.0)]);
+    }
+
+    #[test]
+    fn test_query_value_order() {
+        use QueryValue::*;
+        let mut values = vec![
+            String("b".into()),
+            Float(f64::NAN),
+            Integer(2),
+            Boolean(true),
+            Float(1.5),
+            Null,
+            String("a".into()),
+            Float(2.0),
+            Boolean(false),
+            Integer(-3),
+            Float(f64::NEG_INFINITY),
+        ];
+        values.sort();
+        let expected = vec![
+            Null,
+            Boolean(false),
+            Boolean(true),
+            Float(f64::NEG_INFINITY),
+            Integer(-3),
+            Float(1.5),
+            Integer(2),
+            Float(2.0),
+            Float(f64::NAN),
+            String("a".into()),
+            String("b".into()),
+        ];
+        assert_eq!(format!("{:?}", values), format!("{:?}", expected));
+
+        assert_eq!(Float(f64::NAN), Float(f64::NAN));
+        assert_eq!(Float(-0.0), Float(0.0));
+        assert_ne!(Integer(2), Float(2.0));
+        // Exact even past f64's integer precision
+        assert!(Integer(i64::MAX) < Float(i64::MAX as f64));
+        assert!(Integer((1 << 53) + 1) > Float((1u64 << 53) as f64));
+    }
+
+    #[test]
+    fn test_sort_and_distinct_mixed_types() {
+        let data = vec![
+            point(1, "v", FieldValue::String("beta".into())),
+            point(2, "v", FieldValue::Integer(10)),
+            point(3, "v", FieldValue::Boolean(true)),
+            point(4, "v", FieldValue::Float(2.5)),
+            point(5, "v", FieldValue::String("alpha".into())),
+            point(6, "v", FieldValue::Integer(10)),
+            point(7, "v", FieldValue::Float(f64::NAN)),
+            point(8, "v", FieldValue::String("beta".into())),
+            point(9, "v", FieldValue::Float(f64::NAN)),
+            point(10, "other", FieldValue::Float(0.0)),
+        ];
+        let values = |sql: &str| -> Vec<String> {
+            let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+            result.rows.iter().map(|r| format!("{:?}", r.values[0])).collect()
+        };
+
+        assert_eq!(
+            values("SELECT v FROM devices ORDER BY v"),
+            vec![
+                "Null", "Boolean(true)", "Float(2.5)", "Integer(10)", "Integer(10)",
+                "Float(NaN)", "Float(NaN)", "String(\"alpha\")", "String(\"beta\")", "String(\"beta\")",
+            ]
+        );
+        assert_eq!(
+            values("SELECT DISTINCT v FROM devices ORDER BY v DESC"),
+            vec!["String(\"beta\")", "String(\"alpha\")", "Float(NaN)", "Integer(10)", "Float(2.5)", "Boolean(true)", "Null"]
+        );
+    }
+
+    #[test]
+    fn test_select_order_is_deterministic() {
+        // Few distinct values, so ORDER BY value has many ties
+        let data: Vec<(SeriesKey, DataPoint)> = (0..30)
+            .map(|i| {
+                let key = SeriesKey::new("devices").with_tag("host", format!("h{}", i % 3));
+                (key, DataPoint::new(i, "value", FieldValue::Integer(i % 4)))
+            })
+            .collect();
+        let orderings = |data: &[(SeriesKey, DataPoint)]| {
+            let mut reversed = data.to_vec();
+            reversed.reverse();
+            let mut rotated = data.to_vec();
+            rotated.rotate_left(11);
+            let (even, odd): (Vec<_>, Vec<_>) = data.iter().cloned().enumerate().partition(|(i, _)| i % 2 == 0);
+            let interleaved = odd.into_iter().chain(even).map(|(_, row)| row).collect();
+            vec![data.to_vec(), reversed, rotated, interleaved]
+        };
+
+        for sql in [
+            "SELECT * FROM devices ORDER BY value DESC LIMIT 7 OFFSET 5",
+            "SELECT * FROM devices ORDER BY time DESC",
+            "SELECT * FROM devices",
+            "SELECT DISTINCT value FROM devices",
+        ] {
+            let outputs: Vec<String> = orderings(&data)
+                .into_iter()
+                .map(|input| {
+                    let result = QueryExecutor::execute(&plan(sql), input).unwrap();
+                    serde_json::to_string(&result.rows).unwrap()
+                })
+                .collect();
+            assert!(outputs.windows(2).all(|w| w[0] == w[1]), "{}", sql);
+        }
+
+        // Ties on the ORDER BY key fall back to series, then time
+        let result = QueryExecutor::execute(&plan("SELECT * FROM devices ORDER BY value DESC LIMIT 4"), data).unwrap();
+        let rows: Vec<_> = result.rows.iter().map(|r| (r.series.clone().unwrap(), r.time.unwrap())).collect();
+        let expected = [("devices,host=h0", 3), ("devices,host=h0", 15), ("devices,host=h0", 27), ("devices,host=h1", 7)];
+        assert_eq!(rows, expected.map(|(s, t)| (s.to_string(), t)));
+    }
+
+    #[test]
+    fn test_offset_without_limit() {
+        let data: Vec<_> = (0..10).map(|ts| point(ts, "value", FieldValue::Float(ts as f64))).collect();
+
+        let result = QueryExecutor::execute(&plan("SELECT value FROM devices ORDER BY time OFFSET 7"), data.clone()).unwrap();
+        let times: Vec<_> = result.rows.iter().map(|r| r.time.unwrap()).collect();
+        assert_eq!(times, vec![7, 8, 9]);
+
+        // Skipping every row leaves an empty result rather than an error
+        let result = QueryExecutor::execute(&plan("SELECT value FROM devices OFFSET 10"), data.clone()).unwrap();
+        assert!(result.rows.is_empty());
+        let result = QueryExecutor::execute(&plan("SELECT count(value) FROM devices OFFSET 3"), data.clone()).unwrap();
+        assert!(result.rows.is_empty());
+
+        // A limit past the end returns what's there
+        let result = QueryExecutor::execute(&plan("SELECT value FROM devices LIMIT 50 OFFSET 8"), data).unwrap();
+        assert_eq!(result.rows.len(), 2);
+    }
+
+    #[test]
+    fn test_population_and_sample_variance() {
+        // Mean 5, squared deviations sum to 32
+        let data: Vec<_> = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]
+            .iter()
+            .enumerate()
+            .map(|(i, v)| point(i as i64, "value", FieldValue::Float(*v)))
+            .collect();
+
+        let sql = "SELECT var_pop(value), var_samp(value), stddev_pop(value), stddev_samp(value), variance(value) FROM devices";
+        let result = QueryExecutor::execute(&plan(sql), data.clone()).unwrap();
+        assert_eq!(result.columns, vec!["variance_value", "var_samp_value", "stddev_value", "stddev_samp_value", "variance_value"]);
+        let values: Vec<f64> = result.rows[0].values.iter().map(|v| v.as_f64().unwrap()).collect();
+        assert_eq!(values[0], 4.0);
+        assert!((values[1] - 32.0 / 7.0).abs() < 1e-12);
+        assert_eq!(values[2], 2.0);
+        assert!((values[3] - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+        // Unqualified names stay population
+        assert_eq!(values[4], 4.0);
+
+        // One value has a population variance of 0 but no sample variance
+        let sql = "SELECT var_pop(value), var_samp(value), stddev_samp(value) FROM devices";
+        let result = QueryExecutor::execute(&plan(sql), data[..1].to_vec()).unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(0.0), QueryValue::Null, QueryValue::Null]);
+    }
+}
@@ -0,0 +1,296 @@
+//! Aggregation that keeps each group's running state instead of its points
+//!
+//! `count`, `sum`, `mean`, `min` and `max` fold each value into an
+//! [`Accumulator`] as it arrives, so a group costs the same however many
+//! points it has. Functions that need every value (`median`, `percentile`,
+//! `stddev`, `variance`) keep just the numeric values; once those of all
+//! groups outgrow the plan's spill threshold they are moved to a temp file
+//! and read back one group at a time when the results are computed. With
+//! parallelism groups are finished on the rayon pool.
+
+use super::executor::{GroupKey, MemoryTracker};
+use super::planner::QueryPlan;
+use super::{
+    Accumulator, AggregateFunc, CountAccumulator, MaxAccumulator, MeanAccumulator, MinAccumulator, SumAccumulator,
+};
+use crate::{DataPoint, FieldValue, Result};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// Groups of an aggregation, each holding one state per aggregation
+pub(super) struct GroupedAggregation<'a> {
+    plan: &'a QueryPlan,
+    groups: HashMap<GroupKey, Vec<AggState>>,
+    /// Bytes of values held in memory across all groups
+    buffered: usize,
+    spill: Option<SpillFile>,
+    /// Per aggregation, whether every value of its field was an integer;
+    /// `None` until a value is seen
+    integer_fields: Vec<Option<bool>>,
+}
+
+/// What a group keeps for one aggregation
+pub(super) enum AggState {
+    Running {
+        accumulator: Box<dyn Accumulator>,
+        /// Numeric values folded in
+        count: u64,
+        /// Exact total while every value is an integer, for floored
+        /// integer aggregates
+        integer_sum: Option<i128>,
+    },
+    Values {
+        /// Values still in memory, after those spilled
+        values: Vec<f64>,
+        spilled: Vec<SpilledRun>,
+    },
+}
+
+/// Values one group moved to the spill file at once
+pub(super) struct SpilledRun {
+    offset: u64,
+    count: usize,
+}
+
+impl<'a> GroupedAggregation<'a> {
+    pub(super) fn new(plan: &'a QueryPlan) -> Self {
+        Self {
+            plan,
+            groups: HashMap::new(),
+            buffered: 0,
+            spill: None,
+            integer_fields: vec![None; plan.aggregations.len()],
+        }
+    }
+
+    /// Whether every aggregation of `plan` can be computed this way
+    pub(super) fn supports(plan: &QueryPlan) -> bool {
+        !plan.aggregations.is_empty()
+            && plan.aggregations.iter().all(|agg| {
+                !agg.distinct
+                    && agg.field != "time"
+                    && match agg.function {
+                        AggregateFunc::Count
+                        | AggregateFunc::Sum
+                        | AggregateFunc::Mean
+                        | AggregateFunc::Min
+                        | AggregateFunc::Max
+                        | AggregateFunc::Percentile
+                        | AggregateFunc::Stddev
+                        | AggregateFunc::StddevSample
+                        | AggregateFunc::Variance
+                        | AggregateFunc::VarianceSample => true,
+                        // A floored integer median needs the exact values
+                        AggregateFunc::Median => plan.integer_aggregates != super::IntegerAggregates::Floor,
+                        _ => false,
+                    }
+            })
+    }
+
+    /// Fold `point` into the group `key`
+    pub(super) fn add(&mut self, key: GroupKey, point: &DataPoint, memory: &mut MemoryTracker) -> Result<()> {
+        let plan = self.plan;
+        if !self.groups.contains_key(&key) {
+            memory.reserve(
+                key.size() + MemoryTracker::ENTRY_OVERHEAD + plan.aggregations.len() * std::mem::size_of::<AggState>(),
+            )?;
+        }
+        let states = self
+            .groups
+            .entry(key)
+            .or_insert_with(|| plan.aggregations.iter().map(|agg| AggState::new(agg.function)).collect());
+
+        let mut added = 0;
+        for ((agg, state), integers) in plan.aggregations.iter().zip(states).zip(&mut self.integer_fields) {
+            let Some(value) = point.fields.get(&agg.field) else {
+                continue;
+            };
+            let is_integer = matches!(value, FieldValue::Integer(_));
+            *integers = Some(integers.unwrap_or(true) && is_integer);
+
+            match state {
+                AggState::Running { accumulator, count, integer_sum } => {
+                    *integer_sum = match (*integer_sum, value) {
+                        (Some(sum), FieldValue::Integer(i)) => Some(sum + *i as i128),
+                        _ => None,
+                    };
+                    if let Some(v) = value.as_f64() {
+                        accumulator.add(v);
+                        *count += 1;
+                    }
+                }
+                AggState::Values { values, .. } => {
+                    if let Some(v) = value.as_f64() {
+                        values.push(v);
+                        added += std::mem::size_of::<f64>();
+                    }
+                }
+            }
+        }
+
+        if added > 0 {
+            memory.reserve(added)?;
+            self.buffered += added;
+            if plan.spill_threshold.is_some_and(|threshold| self.buffered > threshold) {
+                self.spill(memory)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move every group's in-memory values to the spill file
+    fn spill(&mut self, memory: &mut MemoryTracker) -> Result<()> {
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => self.spill.insert(SpillFile::create()?),
+        };
+        let mut bytes = Vec::with_capacity(self.buffered);
+        for states in self.groups.values_mut() {
+            for state in states {
+                let AggState::Values { values, spilled } = state else {
+                    continue;
+                };
+                if values.is_empty() {
+                    continue;
+                }
+                let offset = spill.len + bytes.len() as u64;
+                for value in values.iter() {
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+                spilled.push(SpilledRun { offset, count: values.len() });
+                *values = Vec::new();
+            }
+        }
+        spill.append(&bytes)?;
+        memory.release(self.buffered);
+        self.buffered = 0;
+        Ok(())
+    }
+
+    /// Whether each aggregation's field held only integers, as `false` when
+    /// it held nothing
+    pub(super) fn integer_fields(&self) -> Vec<bool> {
+        self.integer_fields.iter().map(|i| i.unwrap_or(false)).collect()
+    }
+
+    /// Hand each group's states to `finish`, with spilled values read back
+    /// into memory one group at a time
+    ///
+    /// With parallelism groups are spread over the rayon pool, each
+    /// reading its own spilled values before `finish` sees it.
+    pub(super) fn finish<T: Send>(
+        mut self,
+        finish: impl Fn(GroupKey, Vec<FinishedState>) -> Result<T> + Sync,
+    ) -> Result<Vec<T>> {
+        let groups = std::mem::take(&mut self.groups);
+        let spill = self.spill.as_ref();
+        let finish_group = |(key, states): (GroupKey, Vec<AggState>)| {
+            let states = states
+                .into_iter()
+                .map(|state| match state {
+                    AggState::Running { accumulator, count, integer_sum } => Ok(FinishedState::Running {
+                        result: accumulator.result().filter(|_| count > 0),
+                        count,
+                        integer_sum,
+                    }),
+                    AggState::Values { values, spilled } => {
+                        let mut all = Vec::new();
+                        if let Some(spill) = spill {
+                            spill.read(&spilled, &mut all)?;
+                        }
+                        all.extend(values);
+                        Ok(FinishedState::Values(all))
+                    }
+                })
+                .collect::<Result<_>>()?;
+            finish(key, states)
+        };
+        if self.plan.parallelism > 1 {
+            groups.into_par_iter().map(finish_group).collect()
+        } else {
+            groups.into_iter().map(finish_group).collect()
+        }
+    }
+}
+
+/// One aggregation's state at the end of a group
+pub(super) enum FinishedState {
+    Running {
+        /// `None` when no numeric value was seen
+        result: Option<f64>,
+        count: u64,
+        integer_sum: Option<i128>,
+    },
+    /// Every numeric value, in the order seen
+    Values(Vec<f64>),
+}
+
+impl AggState {
+    fn new(function: AggregateFunc) -> Self {
+        let accumulator: Box<dyn Accumulator> = match function {
+            AggregateFunc::Count => Box::new(CountAccumulator::default()),
+            AggregateFunc::Sum => Box::new(SumAccumulator::default()),
+            AggregateFunc::Mean => Box::new(MeanAccumulator::default()),
+            AggregateFunc::Min => Box::new(MinAccumulator::default()),
+            AggregateFunc::Max => Box::new(MaxAccumulator::default()),
+            _ => {
+                return AggState::Values {
+                    values: Vec::new(),
+                    spilled: Vec::new(),
+                }
+            }
+        };
+        AggState::Running {
+            accumulator,
+            count: 0,
+            integer_sum: Some(0),
+        }
+    }
+}
+
+/// Temp file of spilled values, removed when dropped
+struct SpillFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl SpillFile {
+    fn create() -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("fluxdb-aggregate-{}.spill", uuid::Uuid::new_v4()));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        Ok(Self { path, file, len: 0 })
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(bytes)?;
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Append the values of `runs` to `into`, through a handle of its own
+    /// so groups finishing in parallel don't share a file position
+    fn read(&self, runs: &[SpilledRun], into: &mut Vec<f64>) -> Result<()> {
+        if runs.is_empty() {
+            return Ok(());
+        }
+        let mut file = File::open(&self.path)?;
+        for run in runs {
+            let mut bytes = vec![0; run.count * std::mem::size_of::<f64>()];
+            file.seek(SeekFrom::Start(run.offset))?;
+            file.read_exact(&mut bytes)?;
+            into.extend(bytes.chunks_exact(8).map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap())));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
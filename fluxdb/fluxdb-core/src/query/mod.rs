@@ -11,14 +11,18 @@ mod parser;
 mod planner;
 mod executor;
 mod aggregates;
+mod grouped;
 
 pub use parser::QueryParser;
-pub use planner::{QueryPlan, QueryPlanner};
+pub use planner::{PlanType, QueryPlan, QueryPlanner};
 pub use executor::QueryExecutor;
 pub use aggregates::*;
 
-use crate::{DataPoint, Result, SeriesKey, TimeRange, Timestamp};
+use crate::{DataPoint, FluxError, Result, SeriesKey, TimeRange, Timestamp};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
 // ============================================================================
@@ -37,6 +41,9 @@ pub struct QueryResult {
     /// Number of rows affected (for UPDATE/DELETE)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rows_affected: Option<usize>,
+    /// Approximate bytes held in intermediate results while executing
+    #[serde(default)]
+    pub memory_bytes: usize,
 }
 
 impl Default for QueryResult {
@@ -46,6 +53,7 @@ impl Default for QueryResult {
             rows: Vec::new(),
             execution_time_ms: 0.0,
             rows_affected: None,
+            memory_bytes: 0,
         }
     }
 }
@@ -61,8 +69,54 @@ pub struct QueryRow {
     pub values: Vec<QueryValue>,
 }
 
+/// Position after which the next page of a paged query starts
+///
+/// Pages hold raw rows in series then time order, so the last row's series
+/// and timestamp identify where a page ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    /// Canonical series key of the last row returned
+    pub series: String,
+    /// Timestamp of the last row returned
+    pub timestamp: Timestamp,
+}
+
+impl Cursor {
+    /// Cursor resuming after `row`; `None` for rows without a series or time
+    pub fn after(row: &QueryRow) -> Option<Self> {
+        Some(Self { series: row.series.clone()?, timestamp: row.time? })
+    }
+
+    /// Opaque form for clients: URL-safe unpadded base64 of the timestamp
+    /// (8 bytes, big endian) followed by the series key
+    pub fn encode(&self) -> String {
+        let mut bytes = self.timestamp.to_be_bytes().to_vec();
+        bytes.extend_from_slice(self.series.as_bytes());
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Parse a cursor produced by [`Cursor::encode`]
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let invalid = || FluxError::Query(format!("Invalid cursor: {}", encoded));
+        let bytes = URL_SAFE_NO_PAD.decode(encoded).map_err(|_| invalid())?;
+        if bytes.len() < 8 {
+            return Err(invalid());
+        }
+        let (timestamp, series) = bytes.split_at(8);
+        Ok(Self {
+            series: String::from_utf8(series.to_vec()).map_err(|_| invalid())?,
+            timestamp: Timestamp::from_be_bytes(timestamp.try_into().map_err(|_| invalid())?),
+        })
+    }
+}
+
 /// Query value types
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+///
+/// Values are totally ordered: Null < Boolean < numbers < String. Integers
+/// and floats compare by numeric value, an integer before an equal float,
+/// with NaN after every other number. Equality follows the same order, so
+/// `-0.0 == 0.0` and NaN equals NaN.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum QueryValue {
     Null,
@@ -94,8 +148,69 @@ impl QueryValue {
     pub fn is_null(&self) -> bool {
         matches!(self, QueryValue::Null)
     }
+
+    /// Position of the variant in the ordering; numbers share a rank
+    fn rank(&self) -> u8 {
+        match self {
+            QueryValue::Null => 0,
+            QueryValue::Boolean(_) => 1,
+            QueryValue::Integer(_) | QueryValue::Float(_) => 2,
+            QueryValue::String(_) => 3,
+        }
+    }
+}
+
+/// Compare floats with every NaN equal and above all numbers
+fn cmp_floats(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        (a_nan, b_nan) => a_nan.cmp(&b_nan),
+    }
+}
+
+/// Compare an integer with a float by exact numeric value
+fn cmp_int_float(i: i64, f: f64) -> Ordering {
+    if f.is_nan() {
+        return Ordering::Less;
+    }
+    // Floats close enough to compare equal after rounding `i` are whole
+    // numbers, so settle those in integer arithmetic
+    (i as f64)
+        .partial_cmp(&f)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| (i as i128).cmp(&(f as i128)))
+}
+
+impl Ord for QueryValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (QueryValue::Boolean(a), QueryValue::Boolean(b)) => a.cmp(b),
+            (QueryValue::Integer(a), QueryValue::Integer(b)) => a.cmp(b),
+            (QueryValue::Float(a), QueryValue::Float(b)) => cmp_floats(*a, *b),
+            (QueryValue::Integer(a), QueryValue::Float(b)) => cmp_int_float(*a, *b).then(Ordering::Less),
+            (QueryValue::Float(a), QueryValue::Integer(b)) => {
+                cmp_int_float(*b, *a).reverse().then(Ordering::Greater)
+            }
+            (QueryValue::String(a), QueryValue::String(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for QueryValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueryValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
 }
 
+impl Eq for QueryValue {}
+
 // ============================================================================
 // Statement Types (Top-level SQL statements)
 // ============================================================================
@@ -104,13 +219,15 @@ impl QueryValue {
 #[derive(Debug, Clone)]
 pub enum Statement {
     /// SELECT query
-    Select(Query),
+    Select(Box<Query>),
     /// INSERT statement
     Insert(InsertStatement),
     /// UPDATE statement
     Update(UpdateStatement),
     /// DELETE statement
     Delete(DeleteStatement),
+    /// DROP MEASUREMENT statement
+    DropMeasurement(DropMeasurementStatement),
     /// Set operation (UNION, INTERSECT, EXCEPT)
     SetOperation(SetOperation),
 }
@@ -148,6 +265,13 @@ pub struct DeleteStatement {
     pub where_clause: WhereClause,
 }
 
+/// DROP MEASUREMENT statement
+#[derive(Debug, Clone)]
+pub struct DropMeasurementStatement {
+    /// Measurement whose series are all removed
+    pub measurement: String,
+}
+
 /// Assignment in UPDATE
 #[derive(Debug, Clone)]
 pub struct Assignment {
@@ -197,10 +321,40 @@ pub struct Query {
     pub having: Option<WhereClause>,
     /// ORDER BY
     pub order_by: Option<OrderBy>,
-    /// LIMIT
+    /// LIMIT - maximum number of rows returned; a limit above the row
+    /// count returns every row
     pub limit: Option<usize>,
-    /// OFFSET
+    /// OFFSET - number of rows skipped before LIMIT applies; an offset at
+    /// or past the row count returns no rows
     pub offset: Option<usize>,
+    /// SLIMIT - maximum number of series returned
+    ///
+    /// Series are ordered by series key and SLIMIT/SOFFSET pick from that
+    /// order before any row-level processing; ORDER BY, LIMIT and OFFSET then
+    /// apply to the rows of the selected series.
+    pub slimit: Option<usize>,
+    /// SOFFSET - number of series skipped
+    pub soffset: Option<usize>,
+    /// INTO - write the results as points instead of returning them
+    pub into: Option<IntoTarget>,
+    /// WITH - named queries the FROM clause may read like measurements
+    pub with: Vec<CommonTableExpr>,
+}
+
+/// A `WITH name AS (query)` binding
+#[derive(Debug, Clone)]
+pub struct CommonTableExpr {
+    pub name: String,
+    pub query: Query,
+}
+
+/// Target of `SELECT ... INTO`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntoTarget {
+    /// Database to write into; `None` means the queried database
+    pub database: Option<String>,
+    /// Measurement to write into
+    pub measurement: String,
 }
 
 /// FROM clause - can be a simple table or a JOIN
@@ -212,6 +366,9 @@ pub enum FromClause {
     Join(Box<JoinClause>),
     /// Subquery
     Subquery(Box<Query>, String), // Query and alias
+    /// No FROM clause: the SELECT list holds only constant expressions,
+    /// evaluated once without reading storage
+    Empty,
 }
 
 /// JOIN clause
@@ -249,6 +406,11 @@ pub enum JoinCondition {
 pub enum SelectItem {
     /// All fields (*)
     All,
+    /// All fields but the listed ones (* EXCEPT (a, b))
+    AllExcept(Vec<String>),
+    /// Fields whose names match a glob where `*` stands for any run of
+    /// characters (temp_*)
+    FieldPattern(String),
     /// Qualified wildcard (table.*)
     QualifiedAll(String),
     /// Field name
@@ -260,6 +422,14 @@ pub enum SelectItem {
         function: AggregateFunc,
         field: String,
         alias: Option<String>,
+        /// Aggregate each distinct value once, as in `count(DISTINCT sensor)`
+        distinct: bool,
+    },
+    /// Transformation function (one output value per input point)
+    Transform {
+        function: TransformFunc,
+        field: String,
+        alias: Option<String>,
     },
     /// Expression with alias
     Expression {
@@ -309,7 +479,7 @@ pub enum BinaryOp {
 }
 
 /// Aggregate function
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AggregateFunc {
     Count,
     Sum,
@@ -318,10 +488,23 @@ pub enum AggregateFunc {
     Max,
     First,
     Last,
+    /// The value of the point where the field is largest; like `first` and
+    /// `last`, on its own it reports that point's time and other fields
+    MaxBy,
+    /// The value of the point where the field is smallest
+    MinBy,
+    /// Population standard deviation (divides by N)
     Stddev,
+    /// Sample standard deviation (divides by N - 1)
+    StddevSample,
+    /// Population variance (divides by N)
     Variance,
+    /// Sample variance (divides by N - 1)
+    VarianceSample,
     Median,
     Percentile,
+    /// Counts of values per fixed-width value bucket; one row per bucket
+    Histogram { width: f64 },
 }
 
 impl AggregateFunc {
@@ -334,8 +517,12 @@ impl AggregateFunc {
             "max" => Some(AggregateFunc::Max),
             "first" => Some(AggregateFunc::First),
             "last" => Some(AggregateFunc::Last),
-            "stddev" | "stdev" => Some(AggregateFunc::Stddev),
-            "variance" | "var" => Some(AggregateFunc::Variance),
+            "max_by" => Some(AggregateFunc::MaxBy),
+            "min_by" => Some(AggregateFunc::MinBy),
+            "stddev" | "stdev" | "stddev_pop" => Some(AggregateFunc::Stddev),
+            "stddev_samp" => Some(AggregateFunc::StddevSample),
+            "variance" | "var" | "var_pop" => Some(AggregateFunc::Variance),
+            "var_samp" => Some(AggregateFunc::VarianceSample),
             "median" => Some(AggregateFunc::Median),
             "percentile" => Some(AggregateFunc::Percentile),
             _ => None,
@@ -343,6 +530,25 @@ impl AggregateFunc {
     }
 }
 
+/// Transformation function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransformFunc {
+    /// Time between consecutive points of a series, in multiples of `unit` nanoseconds
+    Elapsed { unit: i64 },
+    /// Intervals between consecutive points of a series longer than
+    /// `threshold` nanoseconds, one row each
+    Gaps { threshold: i64 },
+}
+
+impl TransformFunc {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransformFunc::Elapsed { .. } => "elapsed",
+            TransformFunc::Gaps { .. } => "gaps",
+        }
+    }
+}
+
 // ============================================================================
 // WHERE Clause and Conditions
 // ============================================================================
@@ -360,10 +566,27 @@ pub enum Condition {
     TimeRange(TimeRange),
     /// Tag equals value
     TagEquals { tag: String, value: String },
-    /// Field comparison
+    /// Field comparison against a numeric literal
+    ///
+    /// Field values are coerced to a number before comparing:
+    /// - `Float` compares as-is
+    /// - `Integer` compares as its `f64` value
+    /// - `Boolean` compares as `0` (false) or `1` (true)
+    /// - `String` is incompatible; the plan's [`TypeMismatch`] policy decides
+    ///   whether the point is excluded or the query fails
+    ///
+    /// Points that don't have the field at all are handled by the plan's
+    /// [`NullSemantics`] policy.
     FieldCompare { field: String, op: CompareOp, value: f64 },
     /// String field comparison
     StringCompare { field: String, op: CompareOp, value: String },
+    /// Boolean field equality (`active = true`, `active != false`, or a
+    /// bare `active`)
+    ///
+    /// Only `Boolean` field values are tested; points with a field of
+    /// another type are not filtered, points without it follow the plan's
+    /// [`NullSemantics`] policy.
+    BooleanEquals { field: String, value: bool },
     /// IN operator (field IN (value1, value2, ...))
     In { field: String, values: Vec<QueryValue>, negated: bool },
     /// BETWEEN operator
@@ -384,6 +607,54 @@ pub enum Condition {
     Not(Box<Condition>),
 }
 
+/// Policy for numeric comparisons against fields that can't be coerced to a number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TypeMismatch {
+    /// Exclude the point from the result
+    #[default]
+    Exclude,
+    /// Fail the query with an error
+    Error,
+}
+
+/// What `mean`, `median` and `sum` return for a group whose values are all
+/// integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegerAggregates {
+    /// A float, so the median of 1 and 2 is 1.5
+    #[default]
+    Float,
+    /// An integer rounded towards negative infinity, so the median of 1 and
+    /// 2 is 1; computed without going through floats, so a sum is exact
+    /// unless it overflows, see [`IntegerOverflow`]
+    Floor,
+}
+
+/// What an integer `sum` returns when the total doesn't fit in an i64
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegerOverflow {
+    /// The total as a float, which is rounded to 53 bits of precision
+    #[default]
+    Float,
+    /// Fail the query with an error
+    Error,
+}
+
+/// How comparisons treat points that don't have the compared field
+///
+/// Applies to field comparisons, `IN`, `BETWEEN` and `LIKE`, negated or
+/// not. `IS NULL` always tests for the field's absence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NullSemantics {
+    /// The comparison is skipped and the point kept, so both `x IN (1, 2)`
+    /// and `x NOT IN (1, 2)` keep points without `x`
+    #[default]
+    Lenient,
+    /// SQL three-valued logic: comparing a missing value is unknown, which
+    /// excludes the point whether or not the condition is negated
+    Sql,
+}
+
 /// Comparison operator
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompareOp {
@@ -408,8 +679,15 @@ pub enum CompareOp {
 pub struct GroupBy {
     /// Time bucket interval (for time-series grouping)
     pub time_bucket: Option<i64>,
+    /// Step between sliding windows, from `time(window, step)`
+    ///
+    /// When set, `time_bucket` is the window length and one row is emitted
+    /// every step, aggregating the window that ends at the row's time.
+    pub time_step: Option<i64>,
     /// Tag columns to group by
     pub tags: Vec<String>,
+    /// `GROUP BY *`: group by every tag key of the matched series
+    pub all_tags: bool,
     /// FILL option for time grouping
     pub fill: Option<FillOption>,
 }
@@ -2,26 +2,63 @@
 //!
 //! Supports:
 //! - SELECT with DISTINCT, aggregations, expressions
+//! - SELECT ... INTO to materialize results into a measurement
 //! - JOIN operations (INNER, LEFT, RIGHT, FULL OUTER)
 //! - Set operations (UNION, INTERSECT, EXCEPT)
 //! - UPDATE and DELETE statements
 //! - Advanced conditions (IN, BETWEEN, LIKE, IS NULL)
 
 use super::{
-    AggregateFunc, Assignment, CompareOp, Condition, DeleteStatement, FromClause, 
-    GroupBy, JoinClause, JoinCondition, JoinType, OrderBy, OrderByItem, Query, 
-    QueryValue, SelectItem, SetOpType, SetOperation, Statement, UpdateStatement, 
-    WhereClause,
+    AggregateFunc, Assignment, BinaryOp, CommonTableExpr, CompareOp, Condition, DeleteStatement, DropMeasurementStatement,
+    Expr as QueryExpr, FillOption, FromClause, 
+    GroupBy, IntoTarget, JoinClause, JoinCondition, JoinType, OrderBy, OrderByItem, Query, 
+    QueryValue, SelectItem, SetOpType, SetOperation, Statement, TransformFunc, 
+    UpdateStatement, WhereClause,
 };
 use crate::{FluxError, Result, TimeRange};
 use sqlparser::ast::{
-    BinaryOperator, Expr, Function, FunctionArg, FunctionArgExpr, Ident,
-    Join, JoinConstraint, JoinOperator, Query as SqlQuery, Select, 
-    SelectItem as SqlSelectItem, SetExpr, SetOperator, Statement as SqlStatement, 
-    TableFactor, TableWithJoins, Value,
+    BinaryOperator, ExcludeSelectItem, Expr, Function, FunctionArg, FunctionArgExpr, Ident,
+    Join, JoinConstraint, JoinOperator, ObjectName, Query as SqlQuery, Select, 
+    SelectInto, SelectItem as SqlSelectItem, SetExpr, SetOperator, Statement as SqlStatement, 
+    TableFactor, TableWithJoins, UnaryOperator, Value,
 };
+use regex::Regex;
 use sqlparser::dialect::GenericDialect;
+use sqlparser::keywords::Keyword;
 use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::sync::LazyLock;
+
+/// Trailing InfluxQL `SLIMIT n` or `SOFFSET n`
+static SERIES_LIMIT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\s+(slimit|soffset)\s+(\S+)\s*;?\s*$").expect("valid regex")
+});
+
+/// InfluxQL `FILL(option)`
+static FILL_CLAUSE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\s+fill\s*\(\s*([^)]*?)\s*\)").expect("valid regex")
+});
+
+/// InfluxQL `DROP MEASUREMENT name`
+static DROP_MEASUREMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?is)^\s*drop\s+measurement\b\s*(.*?)\s*;?\s*$").expect("valid regex")
+});
+
+/// `EXPLAIN` prefix and the statement it asks about
+static EXPLAIN_PREFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?is)^\s*explain\s+(.*\S)").expect("valid regex")
+});
+
+/// GROUP BY items, up to any clause that follows them
+static GROUP_BY_ITEMS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?is)\bgroup\s+by\s+(.*?)(\s+(?:having|order|limit|offset)\b.*|\s*;?\s*)$")
+        .expect("valid regex")
+});
+
+/// A bare `*` item in a GROUP BY list
+static GROUP_BY_WILDCARD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(^|,)(\s*)\*(\s*)(,|$)").expect("valid regex")
+});
 
 /// SQL query parser
 pub struct QueryParser;
@@ -29,6 +66,10 @@ pub struct QueryParser;
 impl QueryParser {
     /// Parse a SQL query string into a Statement
     pub fn parse_statement(sql: &str) -> Result<Statement> {
+        if let Some(drop) = Self::parse_drop_measurement(sql)? {
+            return Ok(Statement::DropMeasurement(drop));
+        }
+
         let dialect = GenericDialect {};
         let statements = Parser::parse_sql(&dialect, sql)
             .map_err(|e| FluxError::SqlParse(e.to_string()))?;
@@ -55,18 +96,218 @@ impl QueryParser {
 
     /// Parse a SQL query string (legacy method for backward compatibility)
     pub fn parse(sql: &str) -> Result<Query> {
+        let (sql, fill) = Self::extract_fill(sql)?;
+        let (sql, slimit, soffset) = Self::extract_series_limits(&sql)?;
+        let sql = Self::quote_field_globs(sql);
+        let sql = Self::quote_group_by_wildcard(sql);
+
         let dialect = GenericDialect {};
-        let statements = Parser::parse_sql(&dialect, sql)
+        let statements = Parser::parse_sql(&dialect, &sql)
             .map_err(|e| FluxError::SqlParse(e.to_string()))?;
 
         if statements.is_empty() {
             return Err(FluxError::SqlParse("Empty query".into()));
         }
 
-        match &statements[0] {
-            SqlStatement::Query(query) => Self::parse_query(query),
-            _ => Err(FluxError::SqlParse("Only SELECT queries are supported".into())),
+        let mut query = match &statements[0] {
+            SqlStatement::Query(query) => Self::parse_query(query)?,
+            _ => return Err(FluxError::SqlParse("Only SELECT queries are supported".into())),
+        };
+        query.slimit = slimit;
+        query.soffset = soffset;
+        if let Some(fill) = fill {
+            match query.group_by.as_mut() {
+                Some(group_by) if group_by.time_bucket.is_some() => group_by.fill = Some(fill),
+                _ => return Err(FluxError::SqlParse("FILL requires GROUP BY time(...)".into())),
+            }
+        }
+        Ok(query)
+    }
+
+    /// Parse InfluxQL's `DROP MEASUREMENT name`, which the SQL grammar
+    /// doesn't know about
+    ///
+    /// Returns `None` for any other statement. The name may be
+    /// double-quoted like any identifier.
+    pub fn parse_drop_measurement(sql: &str) -> Result<Option<DropMeasurementStatement>> {
+        // Matched against the masked text so a quoted name can't end early
+        let masked = mask_quoted(sql);
+        let Some(caps) = DROP_MEASUREMENT.captures(&masked) else {
+            return Ok(None);
+        };
+
+        let name = &sql[caps.get(1).unwrap().range()];
+        let measurement = match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+            Some(quoted) if !quoted.is_empty() => quoted.replace("\"\"", "\""),
+            _ if !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '"') => {
+                name.to_string()
+            }
+            _ => {
+                return Err(FluxError::SqlParse(format!(
+                    "Invalid measurement in DROP MEASUREMENT: {:?}",
+                    name
+                )))
+            }
+        };
+        Ok(Some(DropMeasurementStatement { measurement }))
+    }
+
+    /// The statement an `EXPLAIN` prefix asks about, or `None` for a
+    /// statement without one
+    pub fn parse_explain(sql: &str) -> Option<&str> {
+        EXPLAIN_PREFIX.captures(sql).and_then(|caps| caps.get(1)).map(|m| m.as_str())
+    }
+
+    /// Strip an InfluxQL `FILL(...)` clause, which follows GROUP BY
+    fn extract_fill(sql: &str) -> Result<(String, Option<FillOption>)> {
+        // Matched against the masked text so literals can't hold the clause
+        let masked = mask_quoted(sql);
+        let mut matches = FILL_CLAUSE.captures_iter(&masked);
+        let caps = match matches.next() {
+            Some(caps) => caps,
+            None => return Ok((sql.to_string(), None)),
+        };
+        if matches.next().is_some() {
+            return Err(FluxError::SqlParse("Duplicate FILL clause".into()));
+        }
+
+        let option = &sql[caps.get(1).unwrap().range()];
+        let fill = match option.to_lowercase().as_str() {
+            "null" => FillOption::Null,
+            "previous" => FillOption::Previous,
+            "linear" => FillOption::Linear,
+            "none" => FillOption::None,
+            value => FillOption::Value(value.parse().map_err(|_| {
+                FluxError::SqlParse(format!("Invalid FILL option: {}", option))
+            })?),
+        };
+
+        let range = caps.get(0).unwrap().range();
+        let stripped = format!("{}{}", &sql[..range.start], &sql[range.end..]);
+        Ok((stripped, Some(fill)))
+    }
+
+    /// Strip trailing InfluxQL `SLIMIT n` / `SOFFSET n` clauses, which the
+    /// SQL grammar doesn't know about
+    fn extract_series_limits(sql: &str) -> Result<(&str, Option<usize>, Option<usize>)> {
+        // Matched against the masked text so literals can't end the query
+        let masked = mask_quoted(sql);
+        let mut end = sql.len();
+        let mut slimit = None;
+        let mut soffset = None;
+        while let Some(caps) = SERIES_LIMIT.captures(&masked[..end]) {
+            let clause = caps[1].to_uppercase();
+            let value = sql[caps.get(2).unwrap().range()].parse::<usize>()
+                .map_err(|_| FluxError::SqlParse(format!("Invalid {} value", clause)))?;
+            let slot = if clause == "SLIMIT" { &mut slimit } else { &mut soffset };
+            if slot.replace(value).is_some() {
+                return Err(FluxError::SqlParse(format!("Duplicate {} clause", clause)));
+            }
+            end = caps.get(0).unwrap().start();
         }
+
+        Ok((&sql[..end], slimit, soffset))
+    }
+
+    /// Quote field globs like `temp_*` in select lists, which the SQL
+    /// grammar can't parse, so they parse as identifiers
+    ///
+    /// A glob is a select item of unquoted words and `*` with no spaces
+    /// that starts or ends with `*`; `a*b` stays a multiplication, and
+    /// string literals and quoted identifiers are left alone.
+    fn quote_field_globs(sql: &str) -> String {
+        let Ok(mut tokens) = Tokenizer::new(&GenericDialect {}, sql).tokenize() else {
+            // Left for the parser to report
+            return sql.to_string();
+        };
+
+        let mut globs = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            if matches!(token, Token::Word(word) if word.keyword == Keyword::SELECT) {
+                globs.extend(Self::select_list_globs(&tokens, i + 1));
+            }
+        }
+        if globs.is_empty() {
+            return sql.to_string();
+        }
+
+        globs.sort_by_key(|range| range.start);
+        for range in globs.into_iter().rev() {
+            let glob: String = tokens[range.clone()].iter().map(|token| token.to_string()).collect();
+            tokens.splice(range, [Token::make_word(&glob, Some('"'))]);
+        }
+        tokens.iter().map(|token| token.to_string()).collect()
+    }
+
+    /// Token ranges of the globs among the items of the select list
+    /// starting at `start`, which ends at its FROM or closing parenthesis
+    fn select_list_globs(tokens: &[Token], start: usize) -> Vec<std::ops::Range<usize>> {
+        let mut globs = Vec::new();
+        let mut item_start = start;
+        let mut depth = 0;
+        let mut end = tokens.len();
+        for (i, token) in tokens.iter().enumerate().skip(start) {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen if depth == 0 => {
+                    end = i;
+                    break;
+                }
+                Token::RParen => depth -= 1,
+                Token::Word(word) if depth == 0 && word.keyword == Keyword::FROM => {
+                    end = i;
+                    break;
+                }
+                Token::Comma if depth == 0 => {
+                    globs.extend(Self::glob_item(tokens, item_start..i, item_start == start));
+                    item_start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        globs.extend(Self::glob_item(tokens, item_start..end, item_start == start));
+        globs
+    }
+
+    /// The range of the select item in `item` if it is a glob; the first
+    /// item may follow DISTINCT
+    fn glob_item(tokens: &[Token], item: std::ops::Range<usize>, first: bool) -> Option<std::ops::Range<usize>> {
+        let is_space = |token: &Token| matches!(token, Token::Whitespace(_));
+        let mut start = item.start + tokens[item.clone()].iter().position(|t| !is_space(t))?;
+        if first && matches!(&tokens[start], Token::Word(word) if word.keyword == Keyword::DISTINCT) {
+            start += 1 + tokens[start + 1..item.end].iter().position(|t| !is_space(t))?;
+        }
+        let end = item.start + tokens[item.clone()].iter().rposition(|t| !is_space(t))? + 1;
+
+        let glob = &tokens[start..end];
+        let unquoted_word = |token: &Token| matches!(token, Token::Word(word) if word.quote_style.is_none());
+        let is_glob = glob.iter().all(|t| unquoted_word(t) || *t == Token::Mul)
+            && glob.iter().any(unquoted_word)
+            && (glob[0] == Token::Mul || glob[glob.len() - 1] == Token::Mul);
+        is_glob.then_some(start..end)
+    }
+
+    /// Quote InfluxQL's `GROUP BY *`, which the SQL grammar rejects, so it
+    /// parses as an identifier; it may sit among other GROUP BY items
+    fn quote_group_by_wildcard(sql: String) -> String {
+        // Matched against the masked text so literals can't hold the
+        // clause or a wildcard
+        let masked = mask_quoted(&sql);
+        let Some(items) = GROUP_BY_ITEMS.captures(&masked).and_then(|caps| caps.get(1)) else {
+            return sql;
+        };
+
+        // Neighbouring items share a comma, so quote one at a time, in
+        // both lists to keep their offsets equal
+        let mut masked_list = items.as_str().to_string();
+        let mut list = sql[items.range()].to_string();
+        while let Some(caps) = GROUP_BY_WILDCARD.captures(&masked_list) {
+            let range = caps.get(0).unwrap().range();
+            let quoted = format!("{}{}\"*\"{}{}", &caps[1], &caps[2], &caps[3], &caps[4]);
+            masked_list.replace_range(range.clone(), &quoted);
+            list.replace_range(range, &quoted);
+        }
+        format!("{}{}{}", &sql[..items.start()], list, &sql[items.end()..])
     }
 
     fn parse_query_to_statement(query: &SqlQuery) -> Result<Statement> {
@@ -93,7 +334,7 @@ impl QueryParser {
             }
             _ => {
                 let q = Self::parse_query(query)?;
-                Ok(Statement::Select(q))
+                Ok(Statement::Select(Box::new(q)))
             }
         }
     }
@@ -103,7 +344,7 @@ impl QueryParser {
             SetExpr::Select(select) => {
                 // Create a minimal query for this select
                 let query = Self::parse_select_to_query(select, None, None, None)?;
-                Ok(Statement::Select(query))
+                Ok(Statement::Select(Box::new(query)))
             }
             SetExpr::Query(query) => {
                 Self::parse_query_to_statement(query)
@@ -146,7 +387,33 @@ impl QueryParser {
         let limit = Self::parse_limit(query)?;
         let offset = Self::parse_offset(query)?;
         
-        Self::parse_select_to_query(select, order_by, limit, offset)
+        let mut parsed = Self::parse_select_to_query(select, order_by, limit, offset)?;
+        parsed.with = Self::parse_with(query)?;
+        Ok(parsed)
+    }
+
+    /// Parse the CTEs of a `WITH` clause, in order
+    fn parse_with(query: &SqlQuery) -> Result<Vec<CommonTableExpr>> {
+        let Some(with) = &query.with else {
+            return Ok(Vec::new());
+        };
+        if with.recursive {
+            return Err(FluxError::SqlParse("WITH RECURSIVE is not supported".into()));
+        }
+
+        let mut ctes: Vec<CommonTableExpr> = Vec::new();
+        for cte in &with.cte_tables {
+            let name = cte.alias.name.value.clone();
+            if ctes.iter().any(|c| c.name == name) {
+                return Err(FluxError::SqlParse(format!("Duplicate CTE name: {}", name)));
+            }
+            let query = Self::parse_query(&cte.query)?;
+            if !query.with.is_empty() {
+                return Err(FluxError::SqlParse(format!("CTE {} can't have its own WITH clause", name)));
+            }
+            ctes.push(CommonTableExpr { name, query });
+        }
+        Ok(ctes)
     }
 
     fn parse_select_to_query(
@@ -156,11 +423,20 @@ impl QueryParser {
         offset: Option<usize>,
     ) -> Result<Query> {
         let from = Self::parse_from(select)?;
-        let select_items = Self::parse_select_items(select)?;
+        let constant = matches!(from, FromClause::Empty);
+        let select_items = if constant {
+            Self::parse_constant_items(select)?
+        } else {
+            Self::parse_select_items(select)?
+        };
         let where_clause = Self::parse_where(select)?;
         let group_by = Self::parse_group_by(select)?;
         let having = Self::parse_having(select)?;
+        if constant && (where_clause.is_some() || group_by.is_some() || having.is_some()) {
+            return Err(FluxError::SqlParse("WHERE, GROUP BY and HAVING require a FROM clause".into()));
+        }
         let distinct = select.distinct.is_some();
+        let into = select.into.as_ref().map(Self::parse_into).transpose()?;
 
         Ok(Query {
             distinct,
@@ -172,12 +448,34 @@ impl QueryParser {
             order_by,
             limit,
             offset,
+            slimit: None,
+            soffset: None,
+            into,
+            with: Vec::new(),
         })
     }
 
+    fn parse_into(into: &SelectInto) -> Result<IntoTarget> {
+        let mut parts = into.name.0.iter().map(|ident| ident.value.clone());
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(measurement), None, None) => Ok(IntoTarget {
+                database: None,
+                measurement,
+            }),
+            (Some(database), Some(measurement), None) => Ok(IntoTarget {
+                database: Some(database),
+                measurement,
+            }),
+            _ => Err(FluxError::SqlParse(format!(
+                "Invalid INTO target '{}': expected measurement or database.measurement",
+                into.name
+            ))),
+        }
+    }
+
     fn parse_from(select: &Select) -> Result<FromClause> {
         if select.from.is_empty() {
-            return Err(FluxError::SqlParse("Missing FROM clause".into()));
+            return Ok(FromClause::Empty);
         }
 
         let table_with_joins = &select.from[0];
@@ -200,10 +498,16 @@ impl QueryParser {
         Ok(result)
     }
 
+    /// The name an `ObjectName` refers to, without the quotes of quoted
+    /// parts: `"my measurement"` names `my measurement`
+    fn object_name(name: &ObjectName) -> String {
+        name.0.iter().map(|ident| ident.value.as_str()).collect::<Vec<_>>().join(".")
+    }
+
     fn parse_table_factor(tf: &TableFactor) -> Result<FromClause> {
         match tf {
             TableFactor::Table { name, alias, .. } => {
-                let table_name = name.to_string();
+                let table_name = Self::object_name(name);
                 // If there's an alias, we still just use the table name for now
                 let _ = alias;
                 Ok(FromClause::Table(table_name))
@@ -272,19 +576,40 @@ impl QueryParser {
 
         for item in &select.projection {
             match item {
-                SqlSelectItem::Wildcard(_) => {
-                    items.push(SelectItem::All);
+                SqlSelectItem::Wildcard(options) => {
+                    // EXCEPT and EXCLUDE are spellings of the same thing
+                    let mut excluded: Vec<String> = Vec::new();
+                    if let Some(except) = &options.opt_except {
+                        excluded.push(except.first_element.value.clone());
+                        excluded.extend(except.additional_elements.iter().map(|i| i.value.clone()));
+                    }
+                    match &options.opt_exclude {
+                        Some(ExcludeSelectItem::Single(ident)) => excluded.push(ident.value.clone()),
+                        Some(ExcludeSelectItem::Multiple(idents)) => {
+                            excluded.extend(idents.iter().map(|i| i.value.clone()))
+                        }
+                        None => {}
+                    }
+                    if excluded.is_empty() {
+                        items.push(SelectItem::All);
+                    } else {
+                        items.push(SelectItem::AllExcept(excluded));
+                    }
                 }
                 SqlSelectItem::QualifiedWildcard(name, _) => {
-                    items.push(SelectItem::QualifiedAll(name.to_string()));
+                    items.push(SelectItem::QualifiedAll(Self::object_name(name)));
                 }
                 SqlSelectItem::UnnamedExpr(expr) => {
                     items.push(Self::parse_select_expr(expr)?);
                 }
                 SqlSelectItem::ExprWithAlias { expr, alias } => {
                     let mut item = Self::parse_select_expr(expr)?;
-                    if let SelectItem::Aggregate { alias: ref mut a, .. } = item {
-                        *a = Some(alias.value.clone());
+                    match item {
+                        SelectItem::Aggregate { alias: ref mut a, .. }
+                        | SelectItem::Transform { alias: ref mut a, .. } => {
+                            *a = Some(alias.value.clone());
+                        }
+                        _ => {}
                     }
                     items.push(item);
                 }
@@ -294,8 +619,75 @@ impl QueryParser {
         Ok(items)
     }
 
+    /// Parse the SELECT list of a query without FROM, which may only hold
+    /// constant expressions such as `1 + 1` and `now()`; each column is
+    /// named by its alias or else the expression's text
+    fn parse_constant_items(select: &Select) -> Result<Vec<SelectItem>> {
+        select.projection.iter()
+            .map(|item| {
+                let (expr, alias) = match item {
+                    SqlSelectItem::UnnamedExpr(expr) => (expr, expr.to_string()),
+                    SqlSelectItem::ExprWithAlias { expr, alias } => (expr, alias.value.clone()),
+                    _ => return Err(FluxError::SqlParse("SELECT * requires a FROM clause".into())),
+                };
+                Ok(SelectItem::Expression {
+                    expr: Box::new(Self::parse_constant_expr(expr)?),
+                    alias: Some(alias),
+                })
+            })
+            .collect()
+    }
+
+    fn parse_constant_expr(expr: &Expr) -> Result<QueryExpr> {
+        match expr {
+            Expr::Value(value) => Ok(QueryExpr::Literal(Self::parse_value(value)?)),
+            Expr::Nested(inner) => Self::parse_constant_expr(inner),
+            Expr::UnaryOp { op: UnaryOperator::Minus, expr } => Ok(QueryExpr::BinaryOp {
+                left: Box::new(QueryExpr::Literal(QueryValue::Integer(0))),
+                op: BinaryOp::Subtract,
+                right: Box::new(Self::parse_constant_expr(expr)?),
+            }),
+            Expr::BinaryOp { left, op, right } => {
+                let op = match op {
+                    BinaryOperator::Plus => BinaryOp::Add,
+                    BinaryOperator::Minus => BinaryOp::Subtract,
+                    BinaryOperator::Multiply => BinaryOp::Multiply,
+                    BinaryOperator::Divide => BinaryOp::Divide,
+                    BinaryOperator::Modulo => BinaryOp::Modulo,
+                    _ => return Err(FluxError::SqlParse(format!("Unsupported operator in constant expression: {}", op))),
+                };
+                Ok(QueryExpr::BinaryOp {
+                    left: Box::new(Self::parse_constant_expr(left)?),
+                    op,
+                    right: Box::new(Self::parse_constant_expr(right)?),
+                })
+            }
+            Expr::Function(func) => {
+                let name = func.name.to_string().to_lowercase();
+                if name != "now" {
+                    return Err(FluxError::SqlParse(format!("Unknown function without FROM: {}", name)));
+                }
+                if !func.args.is_empty() {
+                    return Err(FluxError::SqlParse("now() takes no arguments".into()));
+                }
+                Ok(QueryExpr::Function { name, args: Vec::new() })
+            }
+            Expr::Identifier(ident) => Err(FluxError::SqlParse(format!(
+                "Column {} requires a FROM clause",
+                ident.value
+            ))),
+            _ => Err(FluxError::SqlParse(format!(
+                "Unsupported constant expression: {}",
+                expr
+            ))),
+        }
+    }
+
     fn parse_select_expr(expr: &Expr) -> Result<SelectItem> {
         match expr {
+            Expr::Identifier(ident) if ident.value.contains('*') => {
+                Ok(SelectItem::FieldPattern(ident.value.clone()))
+            }
             Expr::Identifier(ident) => Ok(SelectItem::Field(ident.value.clone())),
             Expr::CompoundIdentifier(idents) if idents.len() == 2 => {
                 Ok(SelectItem::QualifiedField {
@@ -313,6 +705,25 @@ impl QueryParser {
 
     fn parse_function(func: &Function) -> Result<SelectItem> {
         let name = func.name.to_string().to_lowercase();
+        // Selectors pick a point and the rest aren't aggregates of values,
+        // so DISTINCT means nothing to them
+        let distinct_unsupported = matches!(name.as_str(), "elapsed" | "histogram" | "gaps")
+            || AggregateFunc::from_name(&name).is_some_and(|f| {
+                matches!(f, AggregateFunc::First | AggregateFunc::Last | AggregateFunc::MaxBy | AggregateFunc::MinBy)
+            });
+        if func.distinct && distinct_unsupported {
+            return Err(FluxError::SqlParse(format!("DISTINCT isn't supported in {}()", name)));
+        }
+        if name == "elapsed" {
+            return Self::parse_elapsed(func);
+        }
+        if name == "histogram" {
+            return Self::parse_histogram(func);
+        }
+        if name == "gaps" {
+            return Self::parse_gaps(func);
+        }
+
         let agg_func = AggregateFunc::from_name(&name)
             .ok_or_else(|| FluxError::SqlParse(format!("Unknown function: {}", name)))?;
 
@@ -336,6 +747,99 @@ impl QueryParser {
             function: agg_func,
             field,
             alias: None,
+            distinct: func.distinct,
+        })
+    }
+
+    /// Parse `elapsed(field)` or `elapsed(field, '1s')`; the unit defaults to 1ns
+    fn parse_elapsed(func: &Function) -> Result<SelectItem> {
+        let field = match func.args.first() {
+            Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(ident)))) => {
+                ident.value.clone()
+            }
+            _ => return Err(FluxError::SqlParse("elapsed() requires a field argument".into())),
+        };
+
+        let unit = match func.args.get(1) {
+            None => 1,
+            Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                Value::SingleQuotedString(interval),
+            )))) => Self::parse_interval(interval)?,
+            Some(_) => {
+                return Err(FluxError::SqlParse(
+                    "elapsed() unit must be a duration string such as '1s'".into(),
+                ))
+            }
+        };
+        if unit <= 0 {
+            return Err(FluxError::SqlParse("elapsed() unit must be positive".into()));
+        }
+
+        Ok(SelectItem::Transform {
+            function: TransformFunc::Elapsed { unit },
+            field,
+            alias: None,
+        })
+    }
+
+    /// Parse `gaps(field, '30s')`
+    fn parse_gaps(func: &Function) -> Result<SelectItem> {
+        let field = match func.args.first() {
+            Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(ident)))) => {
+                ident.value.clone()
+            }
+            _ => return Err(FluxError::SqlParse("gaps() requires a field argument".into())),
+        };
+
+        let threshold = match func.args.get(1) {
+            Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                Value::SingleQuotedString(interval),
+            )))) => Self::parse_interval(interval)?,
+            _ => {
+                return Err(FluxError::SqlParse(
+                    "gaps() requires a threshold duration string such as '30s'".into(),
+                ))
+            }
+        };
+        if threshold <= 0 {
+            return Err(FluxError::SqlParse("gaps() threshold must be positive".into()));
+        }
+
+        Ok(SelectItem::Transform {
+            function: TransformFunc::Gaps { threshold },
+            field,
+            alias: None,
+        })
+    }
+
+    /// Parse `histogram(field, bucket_width)`
+    fn parse_histogram(func: &Function) -> Result<SelectItem> {
+        let field = match func.args.first() {
+            Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(ident)))) => {
+                ident.value.clone()
+            }
+            _ => return Err(FluxError::SqlParse("histogram() requires a field argument".into())),
+        };
+
+        let width = match func.args.get(1) {
+            Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(Value::Number(n, _))))) => {
+                n.parse::<f64>().map_err(|_| FluxError::SqlParse(format!("Invalid bucket width: {}", n)))?
+            }
+            _ => {
+                return Err(FluxError::SqlParse(
+                    "histogram() requires a numeric bucket width".into(),
+                ))
+            }
+        };
+        if !(width > 0.0 && width.is_finite()) {
+            return Err(FluxError::SqlParse("histogram() bucket width must be positive".into()));
+        }
+
+        Ok(SelectItem::Aggregate {
+            function: AggregateFunc::Histogram { width },
+            field,
+            alias: None,
+            distinct: false,
         })
     }
 
@@ -425,14 +929,21 @@ impl QueryParser {
             Expr::UnaryOp { op, expr } => {
                 use sqlparser::ast::UnaryOperator;
                 match op {
-                    UnaryOperator::Not => {
-                        let inner = Self::parse_condition(expr)?;
-                        Ok(Condition::Not(Box::new(inner)))
-                    }
+                    UnaryOperator::Not => match Self::parse_condition(expr)? {
+                        Condition::BooleanEquals { field, value } => {
+                            Ok(Condition::BooleanEquals { field, value: !value })
+                        }
+                        inner => Ok(Condition::Not(Box::new(inner))),
+                    },
                     _ => Err(FluxError::SqlParse(format!("Unsupported unary operator: {:?}", op))),
                 }
             }
             Expr::Nested(inner) => Self::parse_condition(inner),
+            // A bare column is a boolean field test
+            Expr::Identifier(_) | Expr::CompoundIdentifier(_) => Ok(Condition::BooleanEquals {
+                field: Self::extract_field_name(expr)?,
+                value: true,
+            }),
             _ => Err(FluxError::SqlParse(format!(
                 "Unsupported WHERE expression: {:?}",
                 expr
@@ -530,10 +1041,31 @@ impl QueryParser {
             });
         }
 
+        if let Expr::Value(Value::Boolean(b)) = right {
+            let value = match compare_op {
+                CompareOp::Eq => *b,
+                CompareOp::Ne => !*b,
+                _ => {
+                    return Err(FluxError::SqlParse(format!(
+                        "Boolean field {} only supports = and !=",
+                        field
+                    )))
+                }
+            };
+            return Ok(Condition::BooleanEquals { field, value });
+        }
+
         // Field comparison (numeric)
         let value = match right {
             Expr::Value(Value::Number(n, _)) => n.parse::<f64>()
                 .map_err(|_| FluxError::SqlParse("Invalid number".into()))?,
+            // Double quotes delimit identifiers, not strings
+            Expr::Identifier(Ident { value, quote_style: Some('"') }) => {
+                return Err(FluxError::SqlParse(format!(
+                    "\"{}\" is a quoted identifier; use single quotes for the string '{}'",
+                    value, value
+                )))
+            }
             _ => return Err(FluxError::SqlParse("Unsupported value type".into())),
         };
 
@@ -548,22 +1080,43 @@ impl QueryParser {
         match expr {
             Expr::Value(Value::Number(n, _)) => n.parse::<i64>()
                 .map_err(|_| FluxError::SqlParse("Invalid timestamp".into())),
-            Expr::Value(Value::SingleQuotedString(s)) => {
-                // Try to parse as ISO 8601
-                chrono::DateTime::parse_from_rfc3339(s)
-                    .map(|dt| dt.timestamp_nanos_opt().unwrap_or(0))
-                    .map_err(|_| FluxError::SqlParse("Invalid timestamp format".into()))
-            }
+            Expr::Value(Value::SingleQuotedString(s)) => Self::parse_datetime(s),
             _ => Err(FluxError::SqlParse("Unsupported timestamp expression".into())),
         }
     }
 
+    /// Parse an RFC 3339 datetime into UTC nanoseconds
+    ///
+    /// A datetime with an offset (`+02:00` or `Z`) is converted to UTC; one
+    /// without (`2024-01-01T00:00:00`, or with a space before the time) is
+    /// taken to already be UTC.
+    fn parse_datetime(s: &str) -> Result<i64> {
+        let utc = match chrono::DateTime::parse_from_rfc3339(s) {
+            Ok(dt) => dt.naive_utc(),
+            Err(_) => ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+                .iter()
+                .find_map(|format| chrono::NaiveDateTime::parse_from_str(s, format).ok())
+                .ok_or_else(|| {
+                    FluxError::SqlParse(format!(
+                        "Invalid timestamp '{}': expected RFC 3339 such as \
+                         '2024-01-01T00:00:00Z', or without an offset for UTC",
+                        s
+                    ))
+                })?,
+        };
+        utc.and_utc()
+            .timestamp_nanos_opt()
+            .ok_or_else(|| FluxError::SqlParse(format!("Timestamp '{}' is out of range", s)))
+    }
+
     fn parse_group_by(select: &Select) -> Result<Option<GroupBy>> {
         use sqlparser::ast::GroupByExpr;
         
         let expressions = match &select.group_by {
             GroupByExpr::Expressions(exprs) => exprs,
-            GroupByExpr::All => return Ok(None),
+            GroupByExpr::All => {
+                return Ok(Some(GroupBy { time_bucket: None, time_step: None, tags: Vec::new(), all_tags: true, fill: None }))
+            }
         };
 
         if expressions.is_empty() {
@@ -571,18 +1124,34 @@ impl QueryParser {
         }
 
         let mut time_bucket = None;
+        let mut time_step = None;
         let mut tags = Vec::new();
+        let mut all_tags = false;
 
         for expr in expressions {
             match expr {
                 Expr::Function(func) if func.name.to_string().to_lowercase() == "time" => {
-                    // time(1h) style grouping
+                    // time(1h) style grouping, or time(5m, 1m) for sliding windows
                     if let Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
                         Value::SingleQuotedString(interval),
                     )))) = func.args.first()
                     {
                         time_bucket = Some(Self::parse_interval(interval)?);
                     }
+                    if let Some(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                        Value::SingleQuotedString(step),
+                    )))) = func.args.get(1)
+                    {
+                        let step = Self::parse_interval(step)?;
+                        if step <= 0 {
+                            return Err(FluxError::SqlParse("Window step must be positive".into()));
+                        }
+                        time_step = Some(step);
+                    }
+                }
+                // A bare `*`, quoted by `quote_group_by_wildcard`
+                Expr::Identifier(ident) if ident.value == "*" && ident.quote_style.is_some() => {
+                    all_tags = true;
                 }
                 Expr::Identifier(ident) => {
                     tags.push(ident.value.clone());
@@ -593,7 +1162,9 @@ impl QueryParser {
 
         Ok(Some(GroupBy { 
             time_bucket, 
+            time_step,
             tags,
+            all_tags,
             fill: None,
         }))
     }
@@ -620,7 +1191,8 @@ impl QueryParser {
             _ => return Err(FluxError::SqlParse(format!("Unknown time unit: {}", unit))),
         };
 
-        Ok(num * multiplier)
+        num.checked_mul(multiplier)
+            .ok_or_else(|| FluxError::SqlParse(format!("Interval out of range: {}", s)))
     }
 
     fn parse_order_by(query: &SqlQuery) -> Result<Option<OrderBy>> {
@@ -649,30 +1221,33 @@ impl QueryParser {
     }
 
     fn parse_limit(query: &SqlQuery) -> Result<Option<usize>> {
-        match &query.limit {
-            Some(Expr::Value(Value::Number(n, _))) => {
-                let limit = n.parse::<usize>()
-                    .map_err(|_| FluxError::SqlParse("Invalid LIMIT value".into()))?;
-                Ok(Some(limit))
-            }
-            Some(_) => Err(FluxError::SqlParse("Unsupported LIMIT expression".into())),
-            None => Ok(None),
-        }
+        query.limit.as_ref().map(|expr| Self::parse_row_count("LIMIT", expr)).transpose()
     }
 
     fn parse_offset(query: &SqlQuery) -> Result<Option<usize>> {
-        match &query.offset {
-            Some(offset) => {
-                match &offset.value {
-                    Expr::Value(Value::Number(n, _)) => {
-                        let off = n.parse::<usize>()
-                            .map_err(|_| FluxError::SqlParse("Invalid OFFSET value".into()))?;
-                        Ok(Some(off))
-                    }
-                    _ => Err(FluxError::SqlParse("Unsupported OFFSET expression".into())),
-                }
-            }
-            None => Ok(None),
+        query.offset.as_ref().map(|offset| Self::parse_row_count("OFFSET", &offset.value)).transpose()
+    }
+
+    /// Parse a LIMIT or OFFSET row count, which must be a non-negative
+    /// integer literal
+    fn parse_row_count(clause: &str, expr: &Expr) -> Result<usize> {
+        match expr {
+            Expr::Value(Value::Number(n, _)) => n.parse::<usize>().map_err(|_| {
+                FluxError::SqlParse(format!(
+                    "{} must be a whole number no larger than {}, got {}",
+                    clause,
+                    usize::MAX,
+                    n
+                ))
+            }),
+            Expr::UnaryOp { op: UnaryOperator::Minus, .. } => Err(FluxError::SqlParse(format!(
+                "{} must not be negative, got {}",
+                clause, expr
+            ))),
+            _ => Err(FluxError::SqlParse(format!(
+                "{} must be an integer literal, got {}",
+                clause, expr
+            ))),
         }
     }
 
@@ -686,7 +1261,7 @@ impl QueryParser {
         selection: &Option<Expr>,
     ) -> Result<Statement> {
         let measurement = match &table.relation {
-            TableFactor::Table { name, .. } => name.to_string(),
+            TableFactor::Table { name, .. } => Self::object_name(name),
             _ => return Err(FluxError::SqlParse("Invalid table in UPDATE".into())),
         };
 
@@ -728,7 +1303,7 @@ impl QueryParser {
         // In sqlparser 0.41.0, DELETE uses Vec<TableWithJoins> directly
         let measurement = if !from.is_empty() {
             match &from[0].relation {
-                TableFactor::Table { name, .. } => name.to_string(),
+                TableFactor::Table { name, .. } => Self::object_name(name),
                 _ => return Err(FluxError::SqlParse("Invalid table in DELETE".into())),
             }
         } else {
@@ -754,6 +1329,30 @@ impl QueryParser {
     }
 }
 
+/// `sql` with the contents of its string literals and quoted identifiers
+/// replaced by `#`, byte for byte, so patterns matched against it can't
+/// match inside them and their offsets still apply to `sql`
+fn mask_quoted(sql: &str) -> String {
+    let mut masked = String::with_capacity(sql.len());
+    let mut quote = None;
+    for c in sql.chars() {
+        match quote {
+            Some(q) if c == q => {
+                quote = None;
+                masked.push(c);
+            }
+            Some(_) => masked.extend(std::iter::repeat('#').take(c.len_utf8())),
+            None => {
+                if matches!(c, '\'' | '"' | '`') {
+                    quote = Some(c);
+                }
+                masked.push(c);
+            }
+        }
+    }
+    masked
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -790,12 +1389,318 @@ mod tests {
         assert!(query.where_clause.is_some());
     }
 
+    #[test]
+    fn test_parse_datetime() {
+        let start = |sql_time: &str| -> Result<i64> {
+            let sql = format!("SELECT * FROM temperature WHERE time >= '{}'", sql_time);
+            let query = QueryParser::parse(&sql)?;
+            match &query.where_clause.unwrap().conditions[0] {
+                Condition::TimeRange(range) => Ok(range.start),
+                other => panic!("Expected time range, got {:?}", other),
+            }
+        };
+        let midnight = 1_704_067_200_000_000_000;
+
+        assert_eq!(start("2024-01-01T00:00:00Z").unwrap(), midnight);
+        assert_eq!(start("2024-01-01T02:00:00+02:00").unwrap(), midnight);
+        assert_eq!(start("2023-12-31T19:00:00-05:00").unwrap(), midnight);
+
+        // No offset means UTC
+        assert_eq!(start("2024-01-01T00:00:00").unwrap(), midnight);
+        assert_eq!(start("2024-01-01 00:00:00.5").unwrap(), midnight + 500_000_000);
+
+        let err = start("01/01/2024").unwrap_err();
+        assert!(err.to_string().contains("without an offset"), "{}", err);
+        assert!(start("2500-01-01T00:00:00Z").is_err());
+    }
+
+    #[test]
+    fn test_parse_quoted_identifiers() {
+        let query = QueryParser::parse(
+            r#"SELECT "cpu-usage", "load avg", max("select") FROM "my measurement" WHERE "load avg" > 2 AND "data-center" = 'eu west' GROUP BY "host name""#
+        ).unwrap();
+
+        assert!(matches!(&query.from, FromClause::Table(name) if name == "my measurement"));
+        assert!(matches!(&query.select[0], SelectItem::Field(name) if name == "cpu-usage"));
+        assert!(matches!(&query.select[1], SelectItem::Field(name) if name == "load avg"));
+        assert!(matches!(&query.select[2], SelectItem::Aggregate { field, .. } if field == "select"));
+
+        let conditions = &query.where_clause.unwrap().conditions;
+        let Condition::And(left, right) = &conditions[0] else {
+            panic!("Expected AND, got {:?}", conditions[0]);
+        };
+        assert!(matches!(left.as_ref(), Condition::FieldCompare { field, .. } if field == "load avg"));
+        assert!(matches!(
+            right.as_ref(),
+            Condition::TagEquals { tag, value } if tag == "data-center" && value == "eu west"
+        ));
+        assert_eq!(query.group_by.unwrap().tags, vec!["host name"]);
+
+        // A double-quoted value is an identifier, not a string
+        let err = QueryParser::parse(r#"SELECT * FROM cpu WHERE host = "web-1""#).unwrap_err();
+        assert!(err.to_string().contains("single quotes"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_drop_measurement() {
+        let drop = |sql: &str| match QueryParser::parse_statement(sql) {
+            Ok(Statement::DropMeasurement(drop)) => Ok(drop.measurement),
+            Ok(other) => panic!("Expected DROP MEASUREMENT, got {:?}", other),
+            Err(e) => Err(e),
+        };
+
+        assert_eq!(drop("DROP MEASUREMENT temperature").unwrap(), "temperature");
+        assert_eq!(drop("  drop measurement cpu;").unwrap(), "cpu");
+        assert_eq!(drop(r#"DROP MEASUREMENT "disk io""#).unwrap(), "disk io");
+        assert_eq!(drop(r#"DROP MEASUREMENT "disk; io ";"#).unwrap(), "disk; io ");
+        assert_eq!(drop(r#"DROP MEASUREMENT "say ""hi""""#).unwrap(), r#"say "hi""#);
+        assert!(drop("DROP MEASUREMENT").is_err());
+        assert!(drop("DROP MEASUREMENT a b").is_err());
+
+        // Anything else is left to the SQL grammar
+        assert!(QueryParser::parse_drop_measurement("SELECT * FROM measurement").unwrap().is_none());
+        assert!(QueryParser::parse_drop_measurement("DROP TABLE cpu").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_explain() {
+        assert_eq!(QueryParser::parse_explain("EXPLAIN SELECT * FROM cpu"), Some("SELECT * FROM cpu"));
+        assert_eq!(QueryParser::parse_explain("  explain\n select 1 "), Some("select 1"));
+        assert_eq!(QueryParser::parse_explain("SELECT explain FROM cpu"), None);
+        assert_eq!(QueryParser::parse_explain("EXPLAIN"), None);
+    }
+
+    #[test]
+    fn test_parse_single_cte() {
+        let query = QueryParser::parse(
+            "WITH hot AS (SELECT temp FROM sensors WHERE temp > 80) SELECT mean(temp) FROM hot",
+        )
+        .unwrap();
+        assert_eq!(query.with.len(), 1);
+        assert_eq!(query.with[0].name, "hot");
+        assert!(matches!(&query.with[0].query.from, FromClause::Table(t) if t == "sensors"));
+        assert!(query.with[0].query.where_clause.is_some());
+        assert!(matches!(&query.from, FromClause::Table(t) if t == "hot"));
+
+        let plan = crate::query::QueryPlanner::plan(&query).unwrap();
+        assert!(matches!(&plan.plan_type, crate::query::PlanType::CteScan(name) if name == "hot"));
+        assert!(matches!(plan.ctes[0].1.plan_type, crate::query::PlanType::TableScan));
+    }
+
+    #[test]
+    fn test_parse_cte_referenced_twice() {
+        let query = QueryParser::parse(
+            "WITH hot AS (SELECT temp FROM sensors WHERE temp > 80), \
+             peak AS (SELECT max(temp) FROM hot GROUP BY room) \
+             SELECT temp FROM hot",
+        )
+        .unwrap();
+        let names: Vec<&str> = query.with.iter().map(|cte| cte.name.as_str()).collect();
+        assert_eq!(names, vec!["hot", "peak"]);
+        assert!(matches!(&query.with[1].query.from, FromClause::Table(t) if t == "hot"));
+        assert!(matches!(&query.from, FromClause::Table(t) if t == "hot"));
+
+        // Both references resolve to the one plan of `hot`
+        let plan = crate::query::QueryPlanner::plan(&query).unwrap();
+        assert_eq!(plan.ctes.len(), 2);
+        assert!(matches!(&plan.ctes[1].1.plan_type, crate::query::PlanType::CteScan(name) if name == "hot"));
+        assert!(matches!(&plan.plan_type, crate::query::PlanType::CteScan(name) if name == "hot"));
+
+        assert!(QueryParser::parse("WITH a AS (SELECT x FROM m), a AS (SELECT y FROM m) SELECT x FROM a").is_err());
+        assert!(QueryParser::parse("WITH RECURSIVE a AS (SELECT x FROM m) SELECT x FROM a").is_err());
+    }
+
+    #[test]
+    fn test_parse_field_globs_and_except() {
+        let query = QueryParser::parse("SELECT temp_*, *_rate,humidity FROM sensors").unwrap();
+        assert!(matches!(&query.select[0], SelectItem::FieldPattern(p) if p == "temp_*"));
+        assert!(matches!(&query.select[1], SelectItem::FieldPattern(p) if p == "*_rate"));
+        assert!(matches!(&query.select[2], SelectItem::Field(f) if f == "humidity"));
+
+        let query = QueryParser::parse("SELECT * EXCEPT (debug, raw) FROM sensors").unwrap();
+        assert!(matches!(&query.select[0], SelectItem::AllExcept(f) if f == &["debug", "raw"]));
+
+        // Aggregates over every field keep their plain wildcard
+        let query = QueryParser::parse("SELECT count(*) FROM sensors").unwrap();
+        assert!(matches!(&query.select[0], SelectItem::Aggregate { field, .. } if field == "*"));
+
+        // Products, string literals and subqueries aren't globs
+        assert_eq!(
+            QueryParser::quote_field_globs("SELECT DISTINCT a*b, temp_*,\n*_in FROM sensors"),
+            "SELECT DISTINCT a*b, \"temp_*\",\n\"*_in\" FROM sensors",
+        );
+        let query = QueryParser::parse("SELECT value FROM sensors WHERE note = 'x, temp_* FROM'").unwrap();
+        let where_clause = format!("{:?}", query.where_clause);
+        assert!(where_clause.contains("\"x, temp_* FROM\""), "{}", where_clause);
+        let query = QueryParser::parse("SELECT temp_* FROM (SELECT *_in, value FROM sensors)").unwrap();
+        assert!(matches!(&query.select[0], SelectItem::FieldPattern(p) if p == "temp_*"));
+    }
+
     #[test]
     fn test_parse_limit() {
         let query = QueryParser::parse("SELECT * FROM temperature LIMIT 100").unwrap();
         assert_eq!(query.limit, Some(100));
     }
 
+    #[test]
+    fn test_parse_offset() {
+        let query = QueryParser::parse("SELECT * FROM temperature OFFSET 5").unwrap();
+        assert_eq!(query.limit, None);
+        assert_eq!(query.offset, Some(5));
+
+        let invalid = [
+            "SELECT * FROM temperature LIMIT -1",
+            "SELECT * FROM temperature OFFSET -5",
+            "SELECT * FROM temperature OFFSET 2.5",
+            "SELECT * FROM temperature OFFSET 1 + 1",
+            "SELECT * FROM temperature LIMIT 99999999999999999999999",
+        ];
+        for sql in invalid {
+            match QueryParser::parse(sql) {
+                Err(FluxError::SqlParse(msg)) => assert!(msg.contains("LIMIT") || msg.contains("OFFSET"), "{}", msg),
+                other => panic!("{} should fail to parse, got {:?}", sql, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_slimit_soffset() {
+        let query = QueryParser::parse(
+            "SELECT * FROM temperature LIMIT 10 SLIMIT 20 SOFFSET 5"
+        ).unwrap();
+        assert_eq!(query.limit, Some(10));
+        assert_eq!(query.slimit, Some(20));
+        assert_eq!(query.soffset, Some(5));
+
+        assert!(QueryParser::parse("SELECT * FROM temperature SLIMIT -1").is_err());
+        assert!(QueryParser::parse("SELECT * FROM temperature SLIMIT 1 SLIMIT 2").is_err());
+
+        // Nor are they read from string literals or quoted names
+        for sql in [
+            "SELECT * FROM temperature WHERE note = 'a slimit 5'",
+            "SELECT * FROM \"temperature soffset 2\"",
+        ] {
+            let query = QueryParser::parse(sql).unwrap();
+            assert_eq!((query.slimit, query.soffset), (None, None), "{}", sql);
+        }
+        let query = QueryParser::parse("SELECT * FROM temperature WHERE note = 'a slimit 5' SLIMIT 3").unwrap();
+        assert_eq!(query.slimit, Some(3));
+    }
+
+    #[test]
+    fn test_parse_sliding_window() {
+        let query = QueryParser::parse(
+            "SELECT mean(value) FROM cpu GROUP BY time('5m', '1m')"
+        ).unwrap();
+        let group_by = query.group_by.unwrap();
+        assert_eq!(group_by.time_bucket, Some(5 * 60_000_000_000));
+        assert_eq!(group_by.time_step, Some(60_000_000_000));
+
+        let query = QueryParser::parse("SELECT mean(value) FROM cpu GROUP BY time('5m')").unwrap();
+        assert_eq!(query.group_by.unwrap().time_step, None);
+
+        assert!(QueryParser::parse("SELECT mean(value) FROM cpu GROUP BY time('5m', '0s')").is_err());
+    }
+
+    #[test]
+    fn test_parse_group_by_all_tags() {
+        for sql in [
+            "SELECT count(value) FROM cpu GROUP BY *",
+            "SELECT count(value) FROM cpu GROUP BY ALL",
+            "SELECT count(value) FROM cpu group by * ORDER BY time LIMIT 5",
+        ] {
+            let group_by = QueryParser::parse(sql).unwrap().group_by.unwrap();
+            assert!(group_by.all_tags, "{}", sql);
+            assert!(group_by.tags.is_empty());
+        }
+
+        let query = QueryParser::parse("SELECT count(value) FROM cpu GROUP BY time('1m'), host, * FILL(0)").unwrap();
+        let group_by = query.group_by.unwrap();
+        assert!(group_by.all_tags);
+        assert_eq!(group_by.tags, vec!["host"]);
+        assert_eq!(group_by.time_bucket, Some(60_000_000_000));
+
+        // Nor is one in a literal
+        let sql = "SELECT count(value) FROM cpu WHERE note = 'x group by *, y' GROUP BY host".to_string();
+        assert_eq!(QueryParser::quote_group_by_wildcard(sql.clone()), sql);
+
+        // A glob in the select list is left alone
+        let query = QueryParser::parse("SELECT * FROM cpu GROUP BY host").unwrap();
+        assert!(!query.group_by.unwrap().all_tags);
+    }
+
+    #[test]
+    fn test_parse_select_without_from() {
+        let query = QueryParser::parse("SELECT 1 + 1, now() AS ts").unwrap();
+        assert!(matches!(query.from, FromClause::Empty));
+        let names: Vec<Option<String>> = query.select.iter()
+            .map(|item| match item {
+                SelectItem::Expression { alias, .. } => alias.clone(),
+                other => panic!("unexpected item {:?}", other),
+            })
+            .collect();
+        assert_eq!(names, vec![Some("1 + 1".to_string()), Some("ts".to_string())]);
+
+        for sql in ["SELECT *", "SELECT usage", "SELECT 1 WHERE time > 0", "SELECT mean(1)"] {
+            assert!(QueryParser::parse(sql).is_err(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_fill() {
+        let query = QueryParser::parse(
+            "SELECT mean(value) FROM cpu GROUP BY time('1m') FILL(linear) LIMIT 10"
+        ).unwrap();
+        assert!(matches!(query.group_by.unwrap().fill, Some(FillOption::Linear)));
+        assert_eq!(query.limit, Some(10));
+
+        let query = QueryParser::parse("SELECT mean(value) FROM cpu GROUP BY time('1m') fill(0)").unwrap();
+        assert!(matches!(query.group_by.unwrap().fill, Some(FillOption::Value(v)) if v == 0.0));
+
+        assert!(QueryParser::parse("SELECT mean(value) FROM cpu FILL(linear)").is_err());
+        assert!(QueryParser::parse("SELECT mean(value) FROM cpu GROUP BY time('1m') FILL(sideways)").is_err());
+
+        // A literal holding the clause is just a literal
+        let query = QueryParser::parse("SELECT mean(value) FROM cpu WHERE note = 'x fill(0)'").unwrap();
+        assert!(query.group_by.is_none());
+        let query = QueryParser::parse(
+            "SELECT mean(value) FROM cpu WHERE note = 'x fill(0)' GROUP BY time('1m') FILL(previous)"
+        ).unwrap();
+        assert!(matches!(query.group_by.unwrap().fill, Some(FillOption::Previous)));
+    }
+
+    #[test]
+    fn test_parse_histogram() {
+        let query = QueryParser::parse("SELECT histogram(latency, 0.5) AS n FROM req").unwrap();
+        match &query.select[0] {
+            SelectItem::Aggregate { function, field, alias, .. } => {
+                assert_eq!(*function, AggregateFunc::Histogram { width: 0.5 });
+                assert_eq!(field, "latency");
+                assert_eq!(alias.as_deref(), Some("n"));
+            }
+            other => panic!("unexpected select item: {:?}", other),
+        }
+
+        assert!(QueryParser::parse("SELECT histogram(latency) FROM req").is_err());
+        assert!(QueryParser::parse("SELECT histogram(latency, 0) FROM req").is_err());
+        assert!(QueryParser::parse("SELECT histogram(latency, '10') FROM req").is_err());
+    }
+
+    #[test]
+    fn test_parse_into() {
+        let query = QueryParser::parse(
+            "SELECT mean(value) INTO temperature_1h FROM temperature GROUP BY time('1h')"
+        ).unwrap();
+        assert_eq!(query.into, Some(IntoTarget { database: None, measurement: "temperature_1h".into() }));
+        assert!(matches!(query.from, FromClause::Table(ref t) if t == "temperature"));
+
+        let query = QueryParser::parse("SELECT mean(value) INTO rollups.temperature_1h FROM temperature").unwrap();
+        assert_eq!(query.into.unwrap().database.as_deref(), Some("rollups"));
+
+        assert_eq!(QueryParser::parse("SELECT value FROM temperature").unwrap().into, None);
+        assert!(QueryParser::parse("SELECT value INTO a.b.c FROM temperature").is_err());
+    }
+
     #[test]
     fn test_parse_distinct() {
         let query = QueryParser::parse("SELECT DISTINCT sensor_id FROM temperature").unwrap();
@@ -816,6 +1721,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_boolean_condition() {
+        let cases = [
+            ("active = true", true),
+            ("active = false", false),
+            ("active != true", false),
+            ("active", true),
+            ("NOT active", false),
+            ("NOT (active = false)", true),
+        ];
+        for (condition, expected) in cases {
+            let sql = format!("SELECT * FROM devices WHERE {}", condition);
+            let query = QueryParser::parse(&sql).unwrap();
+            match &query.where_clause.unwrap().conditions[0] {
+                Condition::BooleanEquals { field, value } => {
+                    assert_eq!(field, "active");
+                    assert_eq!(*value, expected, "{}", condition);
+                }
+                other => panic!("Expected boolean condition for {}, got {:?}", condition, other),
+            }
+        }
+
+        assert!(QueryParser::parse("SELECT * FROM devices WHERE active > true").is_err());
+    }
+
     #[test]
     fn test_parse_in() {
         let query = QueryParser::parse(
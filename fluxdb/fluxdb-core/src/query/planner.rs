@@ -7,11 +7,11 @@
 //! - Time-based queries
 
 use super::{
-    Query, SelectItem, Condition, GroupBy, AggregateFunc, FromClause, 
-    JoinClause, JoinType, QueryValue,
+    Query, SelectItem, Condition, Expr, GroupBy, AggregateFunc, FillOption, FromClause, 
+    Cursor, IntegerAggregates, IntegerOverflow, JoinClause, JoinType, NullSemantics, QueryValue, TransformFunc, TypeMismatch,
 };
-use crate::{Result, SeriesKey, TimeRange};
-use std::collections::HashSet;
+use crate::{FluxError, Result, SeriesKey, TimeRange, Timestamp};
+use std::collections::{BTreeSet, HashSet};
 
 /// Query execution plan
 #[derive(Debug, Clone)]
@@ -26,24 +26,253 @@ pub struct QueryPlan {
     pub tag_filters: Vec<(String, String)>,
     /// Field filters
     pub field_filters: Vec<FieldFilter>,
+    /// How field filters treat values that can't be compared numerically
+    pub type_mismatch: TypeMismatch,
+    /// How comparisons treat points missing the compared field
+    pub null_semantics: NullSemantics,
     /// Advanced filters (IN, BETWEEN, LIKE, etc.)
     pub advanced_filters: Vec<AdvancedFilter>,
     /// Fields to select
     pub fields: FieldSelection,
     /// Aggregations to perform
     pub aggregations: Vec<Aggregation>,
-    /// Time bucket for grouping (nanoseconds)
+    /// Per-point transformations to perform
+    pub transforms: Vec<Transform>,
+    /// Time bucket for grouping (nanoseconds); the window length when
+    /// `time_step` is set
     pub time_bucket: Option<i64>,
+    /// Step between sliding windows (nanoseconds)
+    pub time_step: Option<i64>,
+    /// How to fill time buckets with no data
+    pub fill: Option<FillOption>,
     /// Tags to group by
     pub group_by_tags: Vec<String>,
+    /// `GROUP BY *`: also group by every other tag key of the matched
+    /// series, see [`QueryPlanner::expand_all_tags`]
+    pub group_by_all_tags: bool,
     /// Sort order
     pub sort: Option<SortOrder>,
     /// Result limit
     pub limit: Option<usize>,
     /// Result offset
     pub offset: Option<usize>,
+    /// Maximum number of series
+    pub slimit: Option<usize>,
+    /// Number of series to skip
+    pub soffset: Option<usize>,
     /// DISTINCT modifier
     pub distinct: bool,
+    /// Approximate memory limit for intermediate results (bytes)
+    pub max_memory: Option<usize>,
+    /// Above 1, aggregate groups in parallel on the current rayon pool
+    pub parallelism: usize,
+    /// Result type of `mean`, `median` and `sum` over integer fields
+    pub integer_aggregates: IntegerAggregates,
+    /// Result of an integer `sum` that overflows an i64
+    pub integer_overflow: IntegerOverflow,
+    /// Only return raw rows after this position in series then time order
+    pub after: Option<Cursor>,
+    /// Time `now()` evaluates to; `None` reads the system clock
+    pub now: Option<Timestamp>,
+    /// Bytes of values buffered for median, percentile, stddev and
+    /// variance past which they are spilled to a temp file; `None` keeps
+    /// them in memory
+    pub spill_threshold: Option<usize>,
+    /// Order `SELECT *` lists fields in; fields not named here follow
+    /// alphabetically
+    pub field_order: Vec<String>,
+    /// CTE plans by name, in the order they must be materialized; each may
+    /// read those before it
+    pub ctes: Vec<(String, QueryPlan)>,
+}
+
+impl QueryPlan {
+    /// One-line description of the plan's shape, without literal values
+    pub fn summary(&self) -> String {
+        let mut parts = vec![match &self.plan_type {
+            PlanType::TableScan => format!("scan {}", self.measurement),
+            PlanType::Join(join) => format!(
+                "{:?} join {} with {}",
+                join.join_type, join.left.measurement, join.right.measurement
+            ),
+            PlanType::Subquery(inner) => format!("subquery over {}", inner.measurement),
+            PlanType::CteScan(name) => format!("scan cte {}", name),
+            PlanType::Constant(_) => "constant".to_string(),
+        }];
+
+        let select: Vec<String> = if !self.aggregations.is_empty() {
+            self.aggregations.iter()
+                .map(|a| {
+                    let distinct = if a.distinct { "distinct " } else { "" };
+                    format!("{}({}{})", QueryPlanner::func_name(a.function), distinct, a.field)
+                })
+                .collect()
+        } else if !self.transforms.is_empty() {
+            self.transforms.iter()
+                .map(|t| format!("{:?}({})", t.function, t.field).to_lowercase())
+                .collect()
+        } else {
+            match &self.fields {
+                FieldSelection::All => vec!["*".to_string()],
+                FieldSelection::Fields(fields) => fields.clone(),
+                FieldSelection::QualifiedFields(fields) => {
+                    fields.iter().map(|(_, f)| f.clone()).collect()
+                }
+                FieldSelection::Matching { include, exclude } => include
+                    .iter()
+                    .cloned()
+                    .chain(exclude.iter().map(|f| format!("-{}", f)))
+                    .collect(),
+            }
+        };
+        parts.push(format!("select={}", select.join(",")));
+
+        let time_bounded = self.time_range.start != i64::MIN || self.time_range.end != i64::MAX;
+        if time_bounded {
+            parts.push(format!("time={}ns", self.time_range.duration()));
+        }
+        let filters = self.tag_filters.len() + self.field_filters.len() + self.advanced_filters.len();
+        if filters > 0 {
+            parts.push(format!("filters={}", filters));
+        }
+
+        let mut group_by = Vec::new();
+        if let Some(bucket) = self.time_bucket {
+            match self.time_step {
+                Some(step) => group_by.push(format!("time({}ns,{}ns)", bucket, step)),
+                None => group_by.push(format!("time({}ns)", bucket)),
+            }
+        }
+        group_by.extend(self.group_by_tags.iter().cloned());
+        if self.group_by_all_tags {
+            group_by.push("*".to_string());
+        }
+        if !group_by.is_empty() {
+            parts.push(format!("group_by={}", group_by.join(",")));
+        }
+        if let Some(fill) = &self.fill {
+            let fill = match fill {
+                FillOption::Null => "null",
+                FillOption::Previous => "previous",
+                FillOption::Value(_) => "value",
+                FillOption::Linear => "linear",
+                FillOption::None => "none",
+            };
+            parts.push(format!("fill={}", fill));
+        }
+        if let Some(sort) = &self.sort {
+            let direction = if sort.descending { "desc" } else { "asc" };
+            parts.push(format!("order_by={} {}", sort.field, direction));
+        }
+        if self.distinct {
+            parts.push("distinct".to_string());
+        }
+        if self.limit.is_some() || self.offset.is_some() {
+            parts.push("limit".to_string());
+        }
+        if self.slimit.is_some() || self.soffset.is_some() {
+            parts.push("slimit".to_string());
+        }
+
+        parts.join(" ")
+    }
+
+    /// Whether series `key` passes the tag filters; tags belong to the
+    /// series, so this decides for all of its points at once
+    pub fn matches_tags(&self, key: &SeriesKey) -> bool {
+        self.tag_filters.iter().all(|(tag, value)| key.tags.get(tag) == Some(value))
+    }
+
+    /// The part of the time range to read for series `key`: all of it
+    /// without a cursor, the rest after the cursor's timestamp for its own
+    /// series, and `None` for series the cursor is already past
+    pub fn series_time_range(&self, key: &SeriesKey) -> Option<TimeRange> {
+        let Some(after) = &self.after else {
+            return Some(self.time_range);
+        };
+        match key.encode().as_str().cmp(&after.series) {
+            std::cmp::Ordering::Less => None,
+            std::cmp::Ordering::Equal => self
+                .time_range
+                .intersect(&TimeRange::new(after.timestamp.checked_add(1)?, Timestamp::MAX)),
+            std::cmp::Ordering::Greater => Some(self.time_range),
+        }
+    }
+
+    /// Whether no aggregation [requires a scan](Aggregation::requires_scan)
+    /// and they cover a whole, unfiltered and ungrouped scan, so the single
+    /// row can be built from value summaries without reading points
+    pub(crate) fn summarizable(&self) -> bool {
+        !self.aggregations.is_empty()
+            && self.aggregations.iter().all(|agg| !agg.requires_scan)
+            && matches!(self.plan_type, PlanType::TableScan)
+            && matches!(self.fields, FieldSelection::All)
+            && self.ctes.is_empty()
+            && self.field_filters.is_empty()
+            && self.advanced_filters.is_empty()
+            && self.transforms.is_empty()
+            && self.time_bucket.is_none()
+            && self.fill.is_none()
+            && self.group_by_tags.is_empty()
+            && !self.group_by_all_tags
+            && self.sort.is_none()
+            && self.limit.is_none()
+            && self.offset.is_none()
+            && self.slimit.is_none()
+            && self.soffset.is_none()
+            && !self.distinct
+            && self.after.is_none()
+            && self.integer_aggregates == IntegerAggregates::Float
+    }
+
+    /// For a raw scan ordered by time with a LIMIT, whether it is ordered
+    /// newest first and how many points, counting the OFFSET, it returns
+    ///
+    /// Every point of such a scan is a row, so only the first points in
+    /// time order need reading. `None` when the scan is ordered otherwise,
+    /// filters points on their fields or reshapes them, which needs all of
+    /// them read.
+    pub(crate) fn time_limit(&self) -> Option<(bool, usize)> {
+        let sort = self.sort.as_ref().filter(|sort| sort.field == "time")?;
+        let limit = self.limit?;
+        let pushable = matches!(self.plan_type, PlanType::TableScan)
+            && self.aggregations.is_empty()
+            && self.transforms.is_empty()
+            && self.field_filters.is_empty()
+            && self.advanced_filters.is_empty()
+            && self.time_bucket.is_none()
+            && self.group_by_tags.is_empty()
+            && !self.group_by_all_tags
+            && self.slimit.is_none()
+            && self.soffset.is_none()
+            && !self.distinct
+            && self.after.is_none();
+        pushable.then(|| (sort.descending, limit.saturating_add(self.offset.unwrap_or(0))))
+    }
+
+    /// For a raw scan in series then time order with a LIMIT, such as a
+    /// page, how many points passing its filters it returns, counting the
+    /// OFFSET
+    ///
+    /// Every point that passes is a row, and rows come out in series then
+    /// time order, so reading can stop once that many have passed. `None`
+    /// when the scan is ordered otherwise or reshapes points, or when a
+    /// point past the limit could still fail the query on a type mismatch.
+    pub(crate) fn row_limit(&self) -> Option<usize> {
+        let limit = self.limit?;
+        let pushable = matches!(self.plan_type, PlanType::TableScan)
+            && self.sort.is_none()
+            && self.aggregations.is_empty()
+            && self.transforms.is_empty()
+            && self.time_bucket.is_none()
+            && self.group_by_tags.is_empty()
+            && !self.group_by_all_tags
+            && self.slimit.is_none()
+            && self.soffset.is_none()
+            && !self.distinct
+            && (self.field_filters.is_empty() || self.type_mismatch == TypeMismatch::Exclude);
+        pushable.then(|| limit.saturating_add(self.offset.unwrap_or(0)))
+    }
 }
 
 /// Plan type
@@ -55,6 +284,10 @@ pub enum PlanType {
     Join(JoinPlan),
     /// Subquery
     Subquery(Box<QueryPlan>),
+    /// Scan of the materialized rows of the named CTE
+    CteScan(String),
+    /// One row of constant expressions, by output column name
+    Constant(Vec<(String, Expr)>),
 }
 
 /// Join execution plan
@@ -79,6 +312,46 @@ pub enum FieldSelection {
     All,
     Fields(Vec<String>),
     QualifiedFields(Vec<(Option<String>, String)>), // (table, field)
+    /// Fields matching any `include` glob and none of the `exclude` names
+    Matching { include: Vec<String>, exclude: Vec<String> },
+}
+
+impl FieldSelection {
+    /// Whether the field `name` is selected, for selections that are
+    /// resolved against the fields present in the data
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            FieldSelection::All => true,
+            FieldSelection::Matching { include, exclude } => {
+                include.iter().any(|pattern| glob_match(pattern, name))
+                    && !exclude.iter().any(|excluded| excluded == name)
+            }
+            FieldSelection::Fields(fields) => fields.iter().any(|f| f == name),
+            FieldSelection::QualifiedFields(fields) => fields.iter().any(|(_, f)| f == name),
+        }
+    }
+}
+
+/// Match `name` against `pattern`, where `*` stands for any run of
+/// characters and everything else matches itself
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
 }
 
 /// Field filter
@@ -117,6 +390,25 @@ pub enum AdvancedFilter {
         op: super::CompareOp,
         value: String,
     },
+    BooleanEquals {
+        field: String,
+        value: bool,
+    },
+}
+
+impl AdvancedFilter {
+    /// The field whose value the filter compares; `None` for `IS NULL`,
+    /// which tests presence rather than value
+    pub fn compared_field(&self) -> Option<&str> {
+        match self {
+            AdvancedFilter::In { field, .. }
+            | AdvancedFilter::Between { field, .. }
+            | AdvancedFilter::Like { field, .. }
+            | AdvancedFilter::StringCompare { field, .. }
+            | AdvancedFilter::BooleanEquals { field, .. } => Some(field),
+            AdvancedFilter::IsNull { .. } => None,
+        }
+    }
 }
 
 /// Aggregation specification
@@ -125,6 +417,19 @@ pub struct Aggregation {
     pub function: AggregateFunc,
     pub field: String,
     pub alias: String,
+    /// Over distinct values only; the field may also name a tag
+    pub distinct: bool,
+    /// Needs every value decoded, as opposed to being computable from the
+    /// count, sum and extremes stored per SSTable block
+    pub requires_scan: bool,
+}
+
+/// Transformation specification
+#[derive(Debug, Clone)]
+pub struct Transform {
+    pub function: TransformFunc,
+    pub field: String,
+    pub alias: String,
 }
 
 /// Sort order
@@ -138,8 +443,78 @@ pub struct SortOrder {
 pub struct QueryPlanner;
 
 impl QueryPlanner {
+    /// Resolve `GROUP BY *` against the series a query matched: the plan
+    /// groups by its named tags, then by every other tag key of `series`
+    /// in name order
+    pub fn expand_all_tags<'a>(plan: &QueryPlan, series: impl IntoIterator<Item = &'a SeriesKey>) -> QueryPlan {
+        let mut keys: BTreeSet<&str> = BTreeSet::new();
+        for key in series {
+            keys.extend(key.tags.keys().map(String::as_str));
+        }
+        let mut expanded = plan.clone();
+        for key in keys {
+            if !expanded.group_by_tags.iter().any(|tag| tag == key) {
+                expanded.group_by_tags.push(key.to_string());
+            }
+        }
+        expanded.group_by_all_tags = false;
+        expanded
+    }
+
     /// Create an execution plan from a parsed query
     pub fn plan(query: &Query) -> Result<QueryPlan> {
+        let mut ctes: Vec<(String, QueryPlan)> = Vec::new();
+        for cte in &query.with {
+            let mut cte_plan = Self::plan(&cte.query)?;
+            Self::resolve_cte_scans(&mut cte_plan, &ctes)?;
+            ctes.push((cte.name.clone(), cte_plan));
+        }
+        let mut plan = Self::plan_select(query)?;
+        Self::resolve_cte_scans(&mut plan, &ctes)?;
+        plan.ctes = ctes;
+        Ok(plan)
+    }
+
+    /// Turn each scan of a measurement named like one of `ctes` into a scan
+    /// of that CTE, in subqueries at any depth too
+    ///
+    /// Joins read their sides from stored measurements, so one naming a
+    /// CTE is rejected rather than silently reading a measurement.
+    fn resolve_cte_scans(plan: &mut QueryPlan, ctes: &[(String, QueryPlan)]) -> Result<()> {
+        let is_cte = |name: &str| ctes.iter().any(|(cte, _)| cte == name);
+        match &mut plan.plan_type {
+            PlanType::TableScan if is_cte(&plan.measurement) => {
+                plan.plan_type = PlanType::CteScan(plan.measurement.clone());
+            }
+            PlanType::Subquery(inner) => Self::resolve_cte_scans(inner, ctes)?,
+            PlanType::Join(join) => {
+                let mut tables = Vec::new();
+                Self::scanned_tables(&join.left, &mut tables);
+                Self::scanned_tables(&join.right, &mut tables);
+                if let Some(name) = tables.into_iter().find(|name| is_cte(name)) {
+                    return Err(FluxError::Query(format!("CTE {} can't be used in a JOIN", name)));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Measurements `plan` scans, through joins and subqueries
+    fn scanned_tables<'a>(plan: &'a QueryPlan, tables: &mut Vec<&'a str>) {
+        match &plan.plan_type {
+            PlanType::TableScan => tables.push(&plan.measurement),
+            PlanType::Join(join) => {
+                Self::scanned_tables(&join.left, tables);
+                Self::scanned_tables(&join.right, tables);
+            }
+            PlanType::Subquery(inner) => Self::scanned_tables(inner, tables),
+            _ => {}
+        }
+    }
+
+    /// Plan a query apart from its CTEs
+    fn plan_select(query: &Query) -> Result<QueryPlan> {
         let mut time_range = TimeRange::new(i64::MIN, i64::MAX);
         let mut tag_filters = Vec::new();
         let mut field_filters = Vec::new();
@@ -170,16 +545,28 @@ impl QueryPlanner {
                 let sub_plan = Self::plan(subquery)?;
                 (PlanType::Subquery(Box::new(sub_plan)), "subquery".to_string())
             }
+            FromClause::Empty => {
+                let columns = query.select.iter()
+                    .filter_map(|item| match item {
+                        SelectItem::Expression { expr, alias } => {
+                            Some((alias.clone().unwrap_or_default(), (**expr).clone()))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                (PlanType::Constant(columns), String::new())
+            }
         };
 
         // Parse SELECT
-        let (fields, aggregations) = Self::extract_select_items(&query.select)?;
+        let (fields, aggregations, transforms) = Self::extract_select_items(&query.select)?;
 
         // Parse GROUP BY
-        let (time_bucket, group_by_tags) = match &query.group_by {
-            Some(gb) => (gb.time_bucket, gb.tags.clone()),
-            None => (None, Vec::new()),
+        let (time_bucket, time_step, fill, group_by_tags, group_by_all_tags) = match &query.group_by {
+            Some(gb) => (gb.time_bucket, gb.time_step, gb.fill.clone(), gb.tags.clone(), gb.all_tags),
+            None => (None, None, None, Vec::new(), false),
         };
+        Self::check_time_windows(time_bucket, time_step)?;
 
         // Parse ORDER BY
         let sort = query.order_by.as_ref().map(|ob| {
@@ -197,15 +584,32 @@ impl QueryPlanner {
             time_range,
             tag_filters,
             field_filters,
+            type_mismatch: TypeMismatch::default(),
+            null_semantics: NullSemantics::default(),
             advanced_filters,
             fields,
             aggregations,
+            transforms,
             time_bucket,
+            time_step,
+            fill,
             group_by_tags,
+            group_by_all_tags,
             sort,
             limit: query.limit,
             offset: query.offset,
+            slimit: query.slimit,
+            soffset: query.soffset,
             distinct: query.distinct,
+            max_memory: None,
+            parallelism: 1,
+            integer_aggregates: IntegerAggregates::default(),
+            integer_overflow: IntegerOverflow::default(),
+            after: None,
+            now: None,
+            spill_threshold: None,
+            field_order: Vec::new(),
+            ctes: Vec::new(),
         })
     }
 
@@ -241,15 +645,32 @@ impl QueryPlanner {
                 time_range: TimeRange::new(i64::MIN, i64::MAX),
                 tag_filters: Vec::new(),
                 field_filters: Vec::new(),
+                type_mismatch: TypeMismatch::default(),
+                null_semantics: NullSemantics::default(),
                 advanced_filters: Vec::new(),
                 fields: FieldSelection::All,
                 aggregations: Vec::new(),
+                transforms: Vec::new(),
                 time_bucket: None,
+                time_step: None,
+                fill: None,
                 group_by_tags: Vec::new(),
+                group_by_all_tags: false,
                 sort: None,
                 limit: None,
                 offset: None,
+                slimit: None,
+                soffset: None,
                 distinct: false,
+                max_memory: None,
+                parallelism: 1,
+                integer_aggregates: IntegerAggregates::default(),
+                integer_overflow: IntegerOverflow::default(),
+                after: None,
+                now: None,
+                spill_threshold: None,
+                field_order: Vec::new(),
+                ctes: Vec::new(),
             }),
             FromClause::Join(join) => {
                 let join_plan = Self::plan_join(join)?;
@@ -260,18 +681,36 @@ impl QueryPlanner {
                     time_range: TimeRange::new(i64::MIN, i64::MAX),
                     tag_filters: Vec::new(),
                     field_filters: Vec::new(),
+                    type_mismatch: TypeMismatch::default(),
+                    null_semantics: NullSemantics::default(),
                     advanced_filters: Vec::new(),
                     fields: FieldSelection::All,
                     aggregations: Vec::new(),
+                    transforms: Vec::new(),
                     time_bucket: None,
+                    time_step: None,
+                    fill: None,
                     group_by_tags: Vec::new(),
+                    group_by_all_tags: false,
                     sort: None,
                     limit: None,
                     offset: None,
+                    slimit: None,
+                    soffset: None,
                     distinct: false,
+                    max_memory: None,
+                    parallelism: 1,
+                    integer_aggregates: IntegerAggregates::default(),
+                    integer_overflow: IntegerOverflow::default(),
+                    after: None,
+                    now: None,
+                    spill_threshold: None,
+                    field_order: Vec::new(),
+                    ctes: Vec::new(),
                 })
             }
             FromClause::Subquery(query, _) => Self::plan(query),
+            FromClause::Empty => Err(FluxError::SqlParse("JOIN needs a measurement on each side".into())),
         }
     }
 
@@ -280,6 +719,7 @@ impl QueryPlanner {
             FromClause::Table(name) => name.clone(),
             FromClause::Join(inner) => Self::get_measurement_from_join(inner),
             FromClause::Subquery(_, alias) => alias.clone(),
+            FromClause::Empty => String::new(),
         }
     }
 
@@ -297,10 +737,15 @@ impl QueryPlanner {
         }
     }
 
-    fn extract_select_items(items: &[SelectItem]) -> Result<(FieldSelection, Vec<Aggregation>)> {
+    fn extract_select_items(
+        items: &[SelectItem],
+    ) -> Result<(FieldSelection, Vec<Aggregation>, Vec<Transform>)> {
         let mut field_names = Vec::new();
         let mut aggregations = Vec::new();
+        let mut transforms = Vec::new();
         let mut has_all = false;
+        let mut patterns = Vec::new();
+        let mut excluded = Vec::new();
 
         for item in items {
             match item {
@@ -310,20 +755,42 @@ impl QueryPlanner {
                 SelectItem::QualifiedAll(_table) => {
                     has_all = true;
                 }
+                SelectItem::AllExcept(fields) => {
+                    has_all = true;
+                    excluded.extend(fields.iter().cloned());
+                }
+                SelectItem::FieldPattern(pattern) => {
+                    patterns.push(pattern.clone());
+                }
                 SelectItem::Field(name) => {
                     field_names.push(name.clone());
                 }
                 SelectItem::QualifiedField { table: _, field } => {
                     field_names.push(field.clone());
                 }
-                SelectItem::Aggregate { function, field, alias } => {
-                    let alias = alias.clone().unwrap_or_else(|| {
-                        format!("{}_{}", Self::func_name(*function), field)
+                SelectItem::Aggregate { function, field, alias, distinct } => {
+                    let alias = alias.clone().unwrap_or_else(|| match function {
+                        AggregateFunc::Histogram { .. } => "count".to_string(),
+                        _ if *distinct => format!("{}_distinct_{}", Self::func_name(*function), field),
+                        _ => format!("{}_{}", Self::func_name(*function), field),
                     });
                     aggregations.push(Aggregation {
                         function: *function,
                         field: field.clone(),
                         alias,
+                        distinct: *distinct,
+                        requires_scan: Self::requires_scan(*function, field, *distinct),
+                    });
+                }
+                SelectItem::Transform { function, field, alias } => {
+                    let alias = alias.clone().unwrap_or_else(|| match function {
+                        TransformFunc::Gaps { .. } => "duration".to_string(),
+                        _ => format!("{}_{}", function.name(), field),
+                    });
+                    transforms.push(Transform {
+                        function: *function,
+                        field: field.clone(),
+                        alias,
                     });
                 }
                 SelectItem::Expression { .. } => {
@@ -332,13 +799,66 @@ impl QueryPlanner {
             }
         }
 
-        let fields = if has_all || field_names.is_empty() {
+        let fields = if !patterns.is_empty() || !excluded.is_empty() {
+            // Named fields alongside globs are globs that match only
+            // themselves
+            let mut include = patterns;
+            if has_all {
+                include.push("*".to_string());
+            }
+            include.extend(field_names);
+            FieldSelection::Matching { include, exclude: excluded }
+        } else if has_all || field_names.is_empty() {
             FieldSelection::All
         } else {
             FieldSelection::Fields(field_names)
         };
 
-        Ok((fields, aggregations))
+        if !aggregations.is_empty() && !transforms.is_empty() {
+            return Err(FluxError::Query(
+                "Cannot mix aggregate and transformation functions".into(),
+            ));
+        }
+        let gaps = transforms
+            .iter()
+            .any(|t| matches!(t.function, TransformFunc::Gaps { .. }));
+        if gaps && transforms.len() > 1 {
+            return Err(FluxError::Query(
+                "gaps() can't be combined with other functions".into(),
+            ));
+        }
+        let histogram = aggregations
+            .iter()
+            .any(|a| matches!(a.function, AggregateFunc::Histogram { .. }));
+        if histogram && aggregations.len() > 1 {
+            return Err(FluxError::Query(
+                "histogram() can't be combined with other aggregates".into(),
+            ));
+        }
+
+        Ok((fields, aggregations, transforms))
+    }
+
+    /// Reject GROUP BY time intervals the executor can't bucket: empty or
+    /// negative ones, and sliding windows so much longer than their step
+    /// that each point would land in too many of them
+    fn check_time_windows(time_bucket: Option<i64>, time_step: Option<i64>) -> Result<()> {
+        let Some(window) = time_bucket else {
+            return Ok(());
+        };
+        if window <= 0 {
+            return Err(FluxError::Query("GROUP BY time interval must be positive".into()));
+        }
+        if let Some(step) = time_step {
+            let limit = crate::config::MAX_WINDOWS_PER_POINT;
+            if window / step > limit {
+                return Err(FluxError::Query(format!(
+                    "Sliding window of {}ns with a {}ns step puts each point in more than {} windows",
+                    window, step, limit
+                )));
+            }
+        }
+        Ok(())
     }
 
     fn extract_conditions(
@@ -372,6 +892,12 @@ impl QueryPlanner {
                     value: value.clone(),
                 });
             }
+            Condition::BooleanEquals { field, value } => {
+                advanced_filters.push(AdvancedFilter::BooleanEquals {
+                    field: field.clone(),
+                    value: *value,
+                });
+            }
             Condition::In { field, values, negated } => {
                 advanced_filters.push(AdvancedFilter::In {
                     field: field.clone(),
@@ -418,6 +944,17 @@ impl QueryPlanner {
         }
     }
 
+    /// Whether an aggregate needs every value rather than per-block counts,
+    /// sums and extremes: order statistics, spread measures, selectors,
+    /// DISTINCT and anything over `time` do
+    fn requires_scan(func: AggregateFunc, field: &str, distinct: bool) -> bool {
+        let summarizable = matches!(
+            func,
+            AggregateFunc::Count | AggregateFunc::Sum | AggregateFunc::Mean | AggregateFunc::Min | AggregateFunc::Max
+        );
+        !summarizable || distinct || field == "time"
+    }
+
     fn func_name(func: AggregateFunc) -> &'static str {
         match func {
             AggregateFunc::Count => "count",
@@ -427,10 +964,15 @@ impl QueryPlanner {
             AggregateFunc::Max => "max",
             AggregateFunc::First => "first",
             AggregateFunc::Last => "last",
+            AggregateFunc::MaxBy => "max_by",
+            AggregateFunc::MinBy => "min_by",
             AggregateFunc::Stddev => "stddev",
+            AggregateFunc::StddevSample => "stddev_samp",
             AggregateFunc::Variance => "variance",
+            AggregateFunc::VarianceSample => "var_samp",
             AggregateFunc::Median => "median",
             AggregateFunc::Percentile => "percentile",
+            AggregateFunc::Histogram { .. } => "histogram",
         }
     }
 }
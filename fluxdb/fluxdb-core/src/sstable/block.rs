@@ -1,6 +1,7 @@
 //! SSTable data block implementation
 
-use crate::{DataPoint, FieldValue, Fields, Result, FluxError};
+use super::BlockSummary;
+use crate::{ChecksumKind, DataPoint, FieldValue, Fields, Result, FluxError};
 use crate::compression::{GorillaEncoder, GorillaDecoder};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::collections::BTreeMap;
@@ -72,6 +73,7 @@ pub struct BlockBuilder {
     field_name: String,
     encoder: GorillaEncoder,
     count: usize,
+    summary: BlockSummary,
 }
 
 impl BlockBuilder {
@@ -81,6 +83,7 @@ impl BlockBuilder {
             field_name: field_name.into(),
             encoder: GorillaEncoder::new(),
             count: 0,
+            summary: BlockSummary::default(),
         }
     }
 
@@ -88,6 +91,7 @@ impl BlockBuilder {
     pub fn add(&mut self, timestamp: i64, value: f64) {
         self.encoder.encode(timestamp, value);
         self.count += 1;
+        self.summary.add(value);
     }
 
     /// Check if block has data
@@ -100,6 +104,16 @@ impl BlockBuilder {
         self.count
     }
 
+    /// Encoded size so far in bytes
+    pub fn size(&self) -> usize {
+        self.encoder.size()
+    }
+
+    /// Summary of the values added so far
+    pub fn summary(&self) -> BlockSummary {
+        self.summary
+    }
+
     /// Finish building and return the data block
     pub fn finish(self) -> DataBlock {
         let compressed = self.encoder.finish();
@@ -131,7 +145,11 @@ impl DataBlock {
     }
 
     /// Serialize to bytes with optional LZ4 compression
-    pub fn to_bytes(&self, use_lz4: bool) -> Bytes {
+    ///
+    /// Gorilla output is often dense enough that LZ4 can't shrink it, so
+    /// with `use_lz4` the data is only stored compressed when that makes it
+    /// smaller; the flag byte records which form was written.
+    pub fn to_bytes(&self, use_lz4: bool, checksum: ChecksumKind) -> Bytes {
         let mut buf = BytesMut::new();
         
         // Field name
@@ -144,26 +162,31 @@ impl DataBlock {
         buf.put_i64_le(self.last_timestamp);
         
         // Data (with optional LZ4)
-        if use_lz4 {
-            let compressed = lz4_flex::compress_prepend_size(&self.data);
-            buf.put_u8(1); // LZ4 flag
-            buf.put_u32_le(compressed.len() as u32);
-            buf.put_slice(&compressed);
-        } else {
-            buf.put_u8(0); // No LZ4
-            buf.put_u32_le(self.data.len() as u32);
-            buf.put_slice(&self.data);
+        let compressed = use_lz4
+            .then(|| lz4_flex::compress_prepend_size(&self.data))
+            .filter(|compressed| compressed.len() < self.data.len());
+        match compressed {
+            Some(compressed) => {
+                buf.put_u8(1); // LZ4 flag
+                buf.put_u32_le(compressed.len() as u32);
+                buf.put_slice(&compressed);
+            }
+            None => {
+                buf.put_u8(0); // No LZ4
+                buf.put_u32_le(self.data.len() as u32);
+                buf.put_slice(&self.data);
+            }
         }
         
         // Checksum
-        let checksum = crc32fast::hash(&buf);
+        let checksum = checksum.checksum(&buf);
         buf.put_u32_le(checksum);
         
         buf.freeze()
     }
 
-    /// Deserialize from bytes
-    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+    /// Deserialize from bytes, verifying with the given checksum kind
+    pub fn from_bytes(data: &[u8], checksum: ChecksumKind) -> Result<Self> {
         if data.len() < 10 {
             return Err(FluxError::InvalidFormat("Block too short".into()));
         }
@@ -205,7 +228,7 @@ impl DataBlock {
             let mut c = std::io::Cursor::new(&data[checksum_pos..]);
             c.get_u32_le()
         };
-        let actual_checksum = crc32fast::hash(&data[..checksum_pos]);
+        let actual_checksum = checksum.checksum(&data[..checksum_pos]);
         
         if expected_checksum != actual_checksum {
             return Err(FluxError::ChecksumMismatch {
@@ -254,13 +277,70 @@ mod tests {
         }
         
         let block = builder.finish();
-        let bytes = block.to_bytes(true);
-        
-        let restored = DataBlock::from_bytes(&bytes).unwrap();
-        assert_eq!(restored.count, 50);
-        assert_eq!(restored.field_name, "value");
+        for kind in [ChecksumKind::Crc32, ChecksumKind::Crc32c] {
+            let bytes = block.to_bytes(true, kind);
+            
+            let restored = DataBlock::from_bytes(&bytes, kind).unwrap();
+            assert_eq!(restored.count, 50);
+            assert_eq!(restored.field_name, "value");
+            
+            let points = restored.decompress().unwrap();
+            assert_eq!(points.len(), 50);
+        }
+    }
+
+    /// The LZ4 flag byte of a serialized block
+    fn lz4_flag(block: &DataBlock, bytes: &[u8]) -> u8 {
+        bytes[2 + block.field_name.len() + 4 + 8 + 8]
+    }
+
+    #[test]
+    fn test_lz4_only_when_smaller() {
+        // Random values leave Gorilla nothing to exploit and LZ4 nothing
+        // to find
+        let mut builder = BlockBuilder::new("noise");
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for i in 0..500 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            builder.add(i * 1000 + (state % 997) as i64, f64::from_bits(state >> 2));
+        }
+        let noisy = builder.finish();
+        let bytes = noisy.to_bytes(true, ChecksumKind::Crc32);
+        assert_eq!(lz4_flag(&noisy, &bytes), 0);
+        assert_eq!(bytes.len(), noisy.to_bytes(false, ChecksumKind::Crc32).len());
+        let restored = DataBlock::from_bytes(&bytes, ChecksumKind::Crc32).unwrap();
+        assert_eq!(restored.decompress().unwrap(), noisy.decompress().unwrap());
+
+        // A short repeating pattern of values compresses well
+        let mut builder = BlockBuilder::new("cycle");
+        for i in 0..500 {
+            builder.add(i * 1000, [1.5, -20.25, 1e9, 3.75][i as usize % 4]);
+        }
+        let cyclic = builder.finish();
+        let bytes = cyclic.to_bytes(true, ChecksumKind::Crc32);
+        assert_eq!(lz4_flag(&cyclic, &bytes), 1);
+        assert!(bytes.len() < cyclic.to_bytes(false, ChecksumKind::Crc32).len());
+        let restored = DataBlock::from_bytes(&bytes, ChecksumKind::Crc32).unwrap();
+        assert_eq!(restored.decompress().unwrap(), cyclic.decompress().unwrap());
+    }
+
+    #[test]
+    fn test_block_corruption_detected() {
+        let mut builder = BlockBuilder::new("value");
+        for i in 0..50 {
+            builder.add(i * 1000, i as f64);
+        }
+        let block = builder.finish();
         
-        let points = restored.decompress().unwrap();
-        assert_eq!(points.len(), 50);
+        for kind in [ChecksumKind::Crc32, ChecksumKind::Crc32c] {
+            let mut bytes = block.to_bytes(false, kind).to_vec();
+            let last = bytes.len() - 5;
+            bytes[last] ^= 0xFF;
+            
+            let result = DataBlock::from_bytes(&bytes, kind);
+            assert!(matches!(result, Err(FluxError::ChecksumMismatch { .. })));
+        }
     }
 }
@@ -0,0 +1,233 @@
+//! Process-wide cache of SSTable data blocks
+//!
+//! One cache is shared by every reader the engine opens, so a hot block
+//! stays cached whichever query or reader touches it, including readers
+//! reopened after the table cache closed them, and the memory all cached
+//! blocks take is bounded by a single budget. Blocks are keyed by table and
+//! file offset; SSTable ids are only unique within a database, so the cache
+//! gives each file path its own table id.
+
+use super::DataBlock;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// LRU of decoded-from-disk data blocks, bounded by their compressed size
+pub struct BlockCache {
+    budget: usize,
+    state: Mutex<CacheState>,
+}
+
+type BlockKey = (u64, u64);
+
+#[derive(Default)]
+struct CacheState {
+    blocks: HashMap<BlockKey, CachedBlock>,
+    /// Keys by last use, least recently used first
+    order: BTreeMap<u64, BlockKey>,
+    tables: HashMap<PathBuf, u64>,
+    next_table: u64,
+    tick: u64,
+    size: usize,
+    hits: u64,
+    misses: u64,
+}
+
+struct CachedBlock {
+    block: Arc<DataBlock>,
+    last_used: u64,
+}
+
+impl BlockCache {
+    /// Create a cache holding at most `budget` bytes of blocks
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Id under which the blocks of the SSTable at `path` are cached
+    pub fn table_id(&self, path: &Path) -> u64 {
+        let mut state = self.state.lock();
+        if let Some(&id) = state.tables.get(path) {
+            return id;
+        }
+        let id = state.next_table;
+        state.next_table += 1;
+        state.tables.insert(path.to_path_buf(), id);
+        id
+    }
+
+    /// Cached block of `table` at `offset`, marking it recently used
+    pub fn get(&self, table: u64, offset: u64) -> Option<Arc<DataBlock>> {
+        let mut state = self.state.lock();
+        state.tick += 1;
+        let tick = state.tick;
+        let Some(cached) = state.blocks.get_mut(&(table, offset)) else {
+            state.misses += 1;
+            return None;
+        };
+        let previous = std::mem::replace(&mut cached.last_used, tick);
+        let block = cached.block.clone();
+        state.order.remove(&previous);
+        state.order.insert(tick, (table, offset));
+        state.hits += 1;
+        Some(block)
+    }
+
+    /// Cache a block, evicting the least recently used ones to stay within
+    /// the budget; a block larger than the whole budget isn't kept
+    pub fn insert(&self, table: u64, offset: u64, block: Arc<DataBlock>) {
+        let size = block.data.len();
+        if size > self.budget {
+            return;
+        }
+        let mut state = self.state.lock();
+        state.remove(&(table, offset));
+        while state.size + size > self.budget {
+            let Some((_, oldest)) = state.order.pop_first() else {
+                break;
+            };
+            state.remove(&oldest);
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.order.insert(tick, (table, offset));
+        state.blocks.insert((table, offset), CachedBlock { block, last_used: tick });
+        state.size += size;
+    }
+
+    /// Drop every block of the SSTable at `path`, which is being removed
+    pub fn forget(&self, path: &Path) {
+        let mut state = self.state.lock();
+        let Some(table) = state.tables.remove(path) else {
+            return;
+        };
+        let keys: Vec<BlockKey> = state.blocks.keys().filter(|(t, _)| *t == table).copied().collect();
+        for key in keys {
+            state.remove(&key);
+        }
+    }
+
+    /// Drop the blocks of every SSTable under `dir`, which is being removed,
+    /// so files later created at the same paths don't see them
+    pub fn forget_dir(&self, dir: &Path) {
+        let mut state = self.state.lock();
+        let tables: Vec<u64> = state
+            .tables
+            .iter()
+            .filter(|(path, _)| path.starts_with(dir))
+            .map(|(_, &table)| table)
+            .collect();
+        if tables.is_empty() {
+            return;
+        }
+        state.tables.retain(|path, _| !path.starts_with(dir));
+        let keys: Vec<BlockKey> = state.blocks.keys().filter(|(t, _)| tables.contains(t)).copied().collect();
+        for key in keys {
+            state.remove(&key);
+        }
+    }
+
+    /// Bytes of blocks currently cached
+    pub fn size(&self) -> usize {
+        self.state.lock().size
+    }
+
+    /// Lookups answered from the cache and lookups that missed it
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        let state = self.state.lock();
+        (state.hits, state.misses)
+    }
+}
+
+impl CacheState {
+    fn remove(&mut self, key: &BlockKey) {
+        if let Some(cached) = self.blocks.remove(key) {
+            self.order.remove(&cached.last_used);
+            self.size -= cached.block.data.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sstable::{SSTableBuilder, SSTableConfig, SSTableReader};
+    use crate::{DataPoint, FieldValue, SeriesKey, TimeRange};
+    use tempfile::TempDir;
+
+    fn block(len: usize) -> Arc<DataBlock> {
+        Arc::new(DataBlock {
+            field_name: "value".to_string(),
+            data: vec![0; len],
+            count: 1,
+            first_timestamp: 0,
+            last_timestamp: 0,
+        })
+    }
+
+    #[test]
+    fn test_block_cache_hits_across_readers() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sst_00000000000000000001.flux");
+        let key = SeriesKey::new("cpu").with_tag("host", "a");
+        let mut builder = SSTableBuilder::new(path.clone(), 1, 0, SSTableConfig::default());
+        for ts in 0..100 {
+            builder.add(&key, &DataPoint::new(ts, "value", FieldValue::Float(ts as f64))).unwrap();
+        }
+        builder.finish().unwrap();
+
+        // Two readers of the same file, as when the table cache closes one
+        // and a later query reopens it
+        let cache = Arc::new(BlockCache::new(1024 * 1024));
+        let range = TimeRange::new(0, 99);
+        let first = SSTableReader::open_with_cache(path.clone(), cache.clone()).unwrap();
+        let points = first.query(&key, &range).unwrap();
+        let (hits, misses) = cache.hits_and_misses();
+        assert_eq!(hits, 0);
+        assert!(misses > 0);
+
+        let second = SSTableReader::open_with_cache(path.clone(), cache.clone()).unwrap();
+        assert_eq!(second.query(&key, &range).unwrap(), points);
+        assert_eq!(cache.hits_and_misses(), (misses, misses));
+
+        // Removing the file drops its blocks
+        assert!(cache.size() > 0);
+        cache.forget(&path);
+        assert_eq!(cache.size(), 0);
+
+        // As does removing its directory, and a file recreated at the same
+        // path gets a fresh id
+        let third = SSTableReader::open_with_cache(path.clone(), cache.clone()).unwrap();
+        third.query(&key, &range).unwrap();
+        let id = cache.table_id(&path);
+        assert!(cache.size() > 0);
+        cache.forget_dir(temp_dir.path());
+        assert_eq!(cache.size(), 0);
+        assert_ne!(cache.table_id(&path), id);
+    }
+
+    #[test]
+    fn test_block_cache_evicts_least_recently_used() {
+        let cache = BlockCache::new(300);
+        cache.insert(0, 0, block(100));
+        cache.insert(0, 100, block(100));
+        cache.insert(1, 0, block(100));
+        assert!(cache.get(0, 0).is_some());
+
+        // (0, 100) is now least recently used
+        cache.insert(1, 100, block(100));
+        assert_eq!(cache.size(), 300);
+        assert!(cache.get(0, 100).is_none());
+        assert!(cache.get(0, 0).is_some());
+        assert!(cache.get(1, 0).is_some());
+
+        // Larger than the budget, so not kept and nothing evicted for it
+        cache.insert(2, 0, block(301));
+        assert!(cache.get(2, 0).is_none());
+        assert_eq!(cache.size(), 300);
+    }
+}
@@ -1,6 +1,6 @@
 //! SSTable builder for writing sorted data to disk
 
-use super::{BloomFilter, DataBlock, SSTableConfig, SSTableMeta, FORMAT_VERSION};
+use super::{BlockSummary, BloomFilter, DataBlock, SSTableConfig, SSTableMeta, FORMAT_VERSION};
 use super::block::BlockBuilder;
 use crate::{DataPoint, FieldValue, Point, Result, FluxError, SeriesKey, Timestamp};
 use crate::memtable::{ImmutableMemTable, MemTableKey};
@@ -13,7 +13,9 @@ use std::path::PathBuf;
 /// SSTable builder
 pub struct SSTableBuilder {
     config: SSTableConfig,
+    /// File to write, or with `name_in_dir` the directory to write it in
     path: PathBuf,
+    name_in_dir: bool,
     id: u64,
     level: u32,
     
@@ -28,6 +30,8 @@ pub struct SSTableBuilder {
     
     // Stats
     entry_count: usize,
+    /// Encoded bytes of the series already moved into `blocks`
+    data_size: u64,
     min_timestamp: Timestamp,
     max_timestamp: Timestamp,
     min_key: Option<SeriesKey>,
@@ -36,7 +40,7 @@ pub struct SSTableBuilder {
 
 struct BlockData {
     series_key: SeriesKey,
-    blocks: Vec<DataBlock>,
+    blocks: Vec<(DataBlock, BlockSummary)>,
     offset: u64,
 }
 
@@ -48,6 +52,9 @@ struct IndexEntry {
     size: u32,
     min_time: Timestamp,
     max_time: Timestamp,
+    count: u32,
+    encoded_size: u32,
+    summary: BlockSummary,
 }
 
 impl SSTableBuilder {
@@ -56,6 +63,7 @@ impl SSTableBuilder {
         Self {
             config,
             path,
+            name_in_dir: false,
             id,
             level,
             blocks: Vec::new(),
@@ -64,6 +72,7 @@ impl SSTableBuilder {
             index_entries: Vec::new(),
             bloom_filter: BloomFilter::new(1000, 10),
             entry_count: 0,
+            data_size: 0,
             min_timestamp: i64::MAX,
             max_timestamp: i64::MIN,
             min_key: None,
@@ -71,13 +80,24 @@ impl SSTableBuilder {
         }
     }
 
+    /// Create a builder for an SSTable in `dir`, named by [`file_name`]
+    /// once finished
+    ///
+    /// [`file_name`]: super::file_name
+    pub fn in_dir(dir: PathBuf, id: u64, level: u32, config: SSTableConfig) -> Self {
+        Self {
+            name_in_dir: true,
+            ..Self::new(dir, id, level, config)
+        }
+    }
+
     /// Add a point to the SSTable
     pub fn add(&mut self, key: &SeriesKey, point: &DataPoint) -> Result<()> {
         // Check if we're starting a new series
         if self.current_series.as_ref() != Some(key) {
             self.flush_current_series()?;
             self.current_series = Some(key.clone());
-            self.bloom_filter.add(&key.canonical());
+            self.bloom_filter.add(&key.encode());
         }
 
         // Update stats
@@ -103,6 +123,12 @@ impl SSTableBuilder {
         Ok(())
     }
 
+    /// Approximate size of the data added so far in bytes, before LZ4
+    pub fn estimated_size(&self) -> u64 {
+        let pending: usize = self.current_blocks.values().map(|b| b.size()).sum();
+        self.data_size + pending as u64
+    }
+
     /// Build from an immutable memtable
     pub fn build_from_memtable(
         path: PathBuf,
@@ -133,7 +159,10 @@ impl SSTableBuilder {
         for key in keys {
             if let Some(builder) = self.current_blocks.remove(&key) {
                 if !builder.is_empty() {
-                    blocks.push(builder.finish());
+                    let summary = builder.summary();
+                    let block = builder.finish();
+                    self.data_size += block.data.len() as u64;
+                    blocks.push((block, summary));
                 }
             }
         }
@@ -152,6 +181,10 @@ impl SSTableBuilder {
     /// Finish building and write to disk
     pub fn finish(mut self) -> Result<SSTableMeta> {
         self.flush_current_series()?;
+        if self.name_in_dir {
+            let name = super::file_name(self.level, self.id, self.min_timestamp, self.max_timestamp);
+            self.path = self.path.join(name);
+        }
 
         let mut file = BufWriter::new(File::create(&self.path)?);
         let mut offset = 0u64;
@@ -164,8 +197,8 @@ impl SSTableBuilder {
         for block_data in &mut self.blocks {
             block_data.offset = offset;
             
-            for block in &block_data.blocks {
-                let bytes = block.to_bytes(self.config.compression);
+            for (block, summary) in &block_data.blocks {
+                let bytes = block.to_bytes(self.config.compression, self.config.checksum);
                 
                 self.index_entries.push(IndexEntry {
                     series_key: block_data.series_key.clone(),
@@ -174,6 +207,9 @@ impl SSTableBuilder {
                     size: bytes.len() as u32,
                     min_time: block.first_timestamp,
                     max_time: block.last_timestamp,
+                    count: block.count as u32,
+                    encoded_size: block.data.len() as u32,
+                    summary: *summary,
                 });
                 
                 file.write_all(&bytes)?;
@@ -194,7 +230,9 @@ impl SSTableBuilder {
         // Write footer
         self.write_footer(&mut file, index_offset, index_size as u64, bloom_offset, bloom_size as u64)?;
 
-        file.flush()?;
+        // Synced, as the WAL segments holding its points may be removed
+        // once it is published
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
 
         let file_size = offset + 32; // footer size
 
@@ -223,6 +261,8 @@ impl SSTableBuilder {
         // Timestamp range
         buf.put_i64_le(self.min_timestamp);
         buf.put_i64_le(self.max_timestamp);
+        // Block checksum kind
+        buf.put_u8(self.config.checksum as u8);
         
         file.write_all(&buf)?;
         Ok(buf.len())
@@ -234,7 +274,7 @@ impl SSTableBuilder {
         buf.put_u32_le(self.index_entries.len() as u32);
         
         for entry in &self.index_entries {
-            let key_bytes = entry.series_key.canonical();
+            let key_bytes = entry.series_key.encode();
             buf.put_u16_le(key_bytes.len() as u16);
             buf.put_slice(key_bytes.as_bytes());
             
@@ -245,6 +285,11 @@ impl SSTableBuilder {
             buf.put_u32_le(entry.size);
             buf.put_i64_le(entry.min_time);
             buf.put_i64_le(entry.max_time);
+            buf.put_u32_le(entry.count);
+            buf.put_u32_le(entry.encoded_size);
+            buf.put_f64_le(entry.summary.sum);
+            buf.put_f64_le(entry.summary.min);
+            buf.put_f64_le(entry.summary.max);
         }
         
         file.write_all(&buf)?;
@@ -285,3 +330,72 @@ impl SSTableBuilder {
         Ok(buf.len())
     }
 }
+
+/// Writes a sorted stream of points into as many SSTables as it takes to
+/// keep each near the configured target file size
+///
+/// A new file is started at the first series boundary after the current one
+/// reaches `target_file_size`, so each series lives in exactly one file and
+/// the files' key ranges don't overlap. `next_file` allocates the directory
+/// and ID of each new file, which is named for its level, ID and time range
+/// as [`SSTableBuilder::in_dir`] names it.
+pub struct SplittingBuilder<F> {
+    config: SSTableConfig,
+    level: u32,
+    next_file: F,
+    current: Option<SSTableBuilder>,
+    current_series: Option<SeriesKey>,
+    finished: Vec<SSTableMeta>,
+}
+
+impl<F: FnMut() -> (PathBuf, u64)> SplittingBuilder<F> {
+    /// Create a builder writing SSTables at `level`
+    pub fn new(level: u32, config: SSTableConfig, next_file: F) -> Self {
+        Self {
+            config,
+            level,
+            next_file,
+            current: None,
+            current_series: None,
+            finished: Vec::new(),
+        }
+    }
+
+    /// Add a point; points must arrive in series key order
+    pub fn add(&mut self, key: &SeriesKey, point: &DataPoint) -> Result<()> {
+        if self.current_series.as_ref() != Some(key) {
+            let full = self
+                .current
+                .as_ref()
+                .is_some_and(|b| b.estimated_size() >= self.config.target_file_size);
+            if full {
+                let builder = self.current.take().unwrap();
+                self.finished.push(builder.finish()?);
+            }
+            self.current_series = Some(key.clone());
+        }
+
+        let builder = match &mut self.current {
+            Some(builder) => builder,
+            None => {
+                let (dir, id) = (self.next_file)();
+                self.current.insert(SSTableBuilder::in_dir(dir, id, self.level, self.config.clone()))
+            }
+        };
+        builder.add(key, point)
+    }
+
+    /// Total size of the files finished so far
+    pub fn finished_bytes(&self) -> u64 {
+        self.finished.iter().map(|meta| meta.file_size).sum()
+    }
+
+    /// Finish the last file and return the metadata of every file written,
+    /// in key order
+    pub fn finish(mut self) -> Result<Vec<SSTableMeta>> {
+        if let Some(builder) = self.current.take() {
+            self.finished.push(builder.finish()?);
+        }
+        Ok(self.finished)
+    }
+}
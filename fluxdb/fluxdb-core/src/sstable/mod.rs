@@ -6,20 +6,61 @@
 //! - Bloom filters for existence checks
 
 mod block;
+mod block_cache;
 mod builder;
 mod reader;
 mod bloom;
+mod table_cache;
 
 pub use block::{DataBlock, BlockHeader};
-pub use builder::SSTableBuilder;
+pub use block_cache::BlockCache;
+pub use builder::{SSTableBuilder, SplittingBuilder};
 pub use reader::SSTableReader;
 pub use bloom::BloomFilter;
+pub use table_cache::TableCache;
 
-use crate::{SeriesKey, Timestamp};
+use crate::{ChecksumKind, SeriesKey, Timestamp};
 use std::path::PathBuf;
 
 /// SSTable file format version
-pub const FORMAT_VERSION: u32 = 1;
+///
+/// Version 2 records the block checksum kind in the header; version 1 files
+/// always use CRC32. Version 3 adds each block's point count and encoded
+/// size to its index entry, and version 4 the sum, minimum and maximum of
+/// its values. Version 5 escapes series keys in the index and bloom filter
+/// as [`SeriesKey::encode`] does; older files store them unescaped.
+pub const FORMAT_VERSION: u32 = 5;
+
+/// Oldest SSTable format version this build still reads
+///
+/// Raising it makes older files unreadable, so it only moves after a
+/// release that rewrites them.
+pub const MIN_READ_VERSION: u32 = 1;
+
+/// File name of SSTable `id` at `level` holding points from
+/// `min_timestamp` to `max_timestamp`, e.g.
+/// `sst_L1_00000000000000000007_1000_2000.flux`
+pub fn file_name(level: u32, id: u64, min_timestamp: Timestamp, max_timestamp: Timestamp) -> String {
+    format!("sst_L{}_{:020}_{}_{}.flux", level, id, min_timestamp, max_timestamp)
+}
+
+/// ID and level of an SSTable from its file name
+///
+/// Files written before names recorded the level are named `sst_<id>.flux`
+/// and give `None` for it.
+pub fn parse_file_name(name: &str) -> Option<(u64, Option<u32>)> {
+    let stem = name.strip_prefix("sst_")?.strip_suffix(".flux")?;
+    let Some(rest) = stem.strip_prefix('L') else {
+        return stem.parse().ok().map(|id| (id, None));
+    };
+    let parts: Vec<&str> = rest.split('_').collect();
+    let [level, id, min_timestamp, max_timestamp] = parts[..] else {
+        return None;
+    };
+    min_timestamp.parse::<Timestamp>().ok()?;
+    max_timestamp.parse::<Timestamp>().ok()?;
+    Some((id.parse().ok()?, Some(level.parse().ok()?)))
+}
 
 /// SSTable metadata
 #[derive(Debug, Clone)]
@@ -56,6 +97,88 @@ impl SSTableMeta {
     }
 }
 
+/// Count, sum and extremes of a run of field values
+///
+/// Stored for each block in its index entry, so aggregates over whole
+/// blocks don't need to decode them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockSummary {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for BlockSummary {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl BlockSummary {
+    /// Account for one more value
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Account for the values `other` summarizes
+    pub fn merge(&mut self, other: &BlockSummary) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// Compression statistics for one field of one series
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldCompression {
+    /// Series the field belongs to
+    pub series_key: SeriesKey,
+    /// Field name
+    pub field: String,
+    /// Points stored
+    pub points: u64,
+    /// Gorilla-encoded bytes, before LZ4
+    pub encoded_bytes: u64,
+    /// Bytes on disk, including block headers, LZ4 and checksums
+    pub stored_bytes: u64,
+}
+
+impl FieldCompression {
+    /// Raw size of a point: 8-byte timestamp and 8-byte value
+    pub const RAW_POINT_SIZE: u64 = 16;
+
+    /// Encoded bytes per point, as [`CompressedBlock::bytes_per_point`]
+    ///
+    /// [`CompressedBlock::bytes_per_point`]: crate::compression::CompressedBlock::bytes_per_point
+    pub fn bytes_per_point(&self) -> f64 {
+        self.encoded_bytes as f64 / self.points as f64
+    }
+
+    /// Raw size over encoded size, as [`CompressedBlock::compression_ratio`]
+    ///
+    /// [`CompressedBlock::compression_ratio`]: crate::compression::CompressedBlock::compression_ratio
+    pub fn compression_ratio(&self) -> f64 {
+        (self.points * Self::RAW_POINT_SIZE) as f64 / self.encoded_bytes as f64
+    }
+
+    /// Add another block's (or table's) statistics for the same field
+    pub fn merge(&mut self, other: &FieldCompression) {
+        self.points += other.points;
+        self.encoded_bytes += other.encoded_bytes;
+        self.stored_bytes += other.stored_bytes;
+    }
+}
+
 /// SSTable configuration
 #[derive(Debug, Clone)]
 pub struct SSTableConfig {
@@ -65,6 +188,11 @@ pub struct SSTableConfig {
     pub compression: bool,
     /// Bloom filter bits per key
     pub bloom_bits_per_key: usize,
+    /// Checksum algorithm for data blocks
+    pub checksum: ChecksumKind,
+    /// Size at which a [`SplittingBuilder`] starts a new SSTable; files are
+    /// only split between series, so one may exceed it by a series' data
+    pub target_file_size: u64,
 }
 
 impl Default for SSTableConfig {
@@ -73,6 +201,8 @@ impl Default for SSTableConfig {
             block_size: 4096,
             compression: true,
             bloom_bits_per_key: 10,
+            checksum: ChecksumKind::default(),
+            target_file_size: crate::config::SSTABLE_TARGET_FILE_SIZE,
         }
     }
 }
@@ -1,22 +1,31 @@
 //! SSTable reader for querying data
 
-use super::{BloomFilter, DataBlock, SSTableMeta, FORMAT_VERSION};
-use crate::{DataPoint, FieldValue, Fields, Result, FluxError, SeriesKey, TimeRange, Timestamp};
+use super::{BlockCache, BlockSummary, BloomFilter, DataBlock, FieldCompression, SSTableMeta, FORMAT_VERSION, MIN_READ_VERSION};
+use crate::{ChecksumKind, DataPoint, FieldValue, Fields, Result, FluxError, SeriesKey, TimeRange, Timestamp};
 use bytes::Buf;
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use parking_lot::RwLock;
+use parking_lot::Mutex;
 
 /// SSTable reader
 pub struct SSTableReader {
-    path: PathBuf,
     meta: SSTableMeta,
     index: Vec<IndexEntry>,
     bloom_filter: BloomFilter,
-    cache: Arc<RwLock<BlockCache>>,
+    /// Whether `bloom_filter` holds escaped series keys; unescaped index
+    /// keys are re-encoded on open, but the filter can't be
+    escaped_keys: bool,
+    checksum: ChecksumKind,
+    cache: Arc<BlockCache>,
+    /// This file's id in `cache`
+    cache_id: u64,
+    files: FilePool,
+    /// Blocks decompressed so far
+    decoded: AtomicUsize,
 }
 
 #[derive(Debug, Clone)]
@@ -27,46 +36,173 @@ struct IndexEntry {
     size: u32,
     min_time: Timestamp,
     max_time: Timestamp,
+    /// Point count and Gorilla-encoded size; not recorded before version 3
+    block_stats: Option<(u32, u32)>,
+    /// Sum and extremes of the block's values; not recorded before version 4
+    summary: Option<BlockSummary>,
 }
 
-struct BlockCache {
-    blocks: BTreeMap<u64, DataBlock>,
-    max_size: usize,
-    current_size: usize,
+/// File handles reused across block reads
+///
+/// A read takes an idle handle, or opens one if all are in use by
+/// concurrent reads, and returns it afterwards. At most
+/// [`FilePool::MAX_IDLE`] handles are kept, so with the table cache bounding
+/// open readers a database holds a bounded number of descriptors.
+struct FilePool {
+    path: PathBuf,
+    idle: Mutex<Vec<File>>,
+    /// Handles opened after the reader itself
+    opened: AtomicUsize,
 }
 
-impl BlockCache {
-    fn new(max_size: usize) -> Self {
+impl FilePool {
+    const MAX_IDLE: usize = 4;
+
+    fn new(path: PathBuf, file: File) -> Self {
         Self {
-            blocks: BTreeMap::new(),
-            max_size,
-            current_size: 0,
+            path,
+            idle: Mutex::new(vec![file]),
+            opened: AtomicUsize::new(0),
         }
     }
 
-    fn get(&self, offset: u64) -> Option<&DataBlock> {
-        self.blocks.get(&offset)
+    fn read_at(&self, offset: u64, size: u32) -> Result<Vec<u8>> {
+        let pooled = self.idle.lock().pop();
+        let mut file = match pooled {
+            Some(file) => file,
+            None => {
+                self.opened.fetch_add(1, Ordering::Relaxed);
+                File::open(&self.path)?
+            }
+        };
+
+        let mut data = vec![0u8; size as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)?;
+
+        let mut idle = self.idle.lock();
+        if idle.len() < Self::MAX_IDLE {
+            idle.push(file);
+        }
+        Ok(data)
     }
+}
 
-    fn insert(&mut self, offset: u64, block: DataBlock) {
-        let size = block.data.len();
-        if self.current_size + size > self.max_size {
-            // Simple eviction: remove oldest
-            if let Some((&key, _)) = self.blocks.iter().next() {
-                if let Some(removed) = self.blocks.remove(&key) {
-                    self.current_size -= removed.data.len();
-                }
-            }
+/// How one format version lays out the parts that changed between versions
+///
+/// Files of every version from `MIN_READ_VERSION` to `FORMAT_VERSION` are
+/// read through this, so a format change adds a case here and older files
+/// stay readable without a rewrite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FormatLayout {
+    /// Block checksum kind stored after the fixed header; version 1 files
+    /// always use CRC32
+    checksum_in_header: bool,
+    /// Point count and encoded size stored in each index entry
+    index_block_stats: bool,
+    /// Value sum, minimum and maximum per index entry
+    index_block_summary: bool,
+    /// Series keys in the index and bloom filter escaped as
+    /// [`SeriesKey::encode`] does, rather than joined unescaped
+    escaped_keys: bool,
+}
+
+impl FormatLayout {
+    fn for_version(version: u32) -> Result<Self> {
+        match version {
+            1 => Ok(Self { checksum_in_header: false, index_block_stats: false, index_block_summary: false, escaped_keys: false }),
+            2 => Ok(Self { checksum_in_header: true, index_block_stats: false, index_block_summary: false, escaped_keys: false }),
+            3 => Ok(Self { checksum_in_header: true, index_block_stats: true, index_block_summary: false, escaped_keys: false }),
+            4 => Ok(Self { checksum_in_header: true, index_block_stats: true, index_block_summary: true, escaped_keys: false }),
+            5 => Ok(Self { checksum_in_header: true, index_block_stats: true, index_block_summary: true, escaped_keys: true }),
+            _ => Err(FluxError::InvalidFormat(format!(
+                "Unsupported version: {} (readable versions are {} to {})",
+                version, MIN_READ_VERSION, FORMAT_VERSION
+            ))),
         }
-        self.current_size += size;
-        self.blocks.insert(offset, block);
     }
 }
 
+/// Fixed-size header and footer fields
+struct Layout {
+    meta: SSTableMeta,
+    format: FormatLayout,
+    checksum: ChecksumKind,
+    index_offset: u64,
+    index_size: u64,
+    bloom_offset: u64,
+    bloom_size: u64,
+}
+
 impl SSTableReader {
-    /// Open an SSTable file
+    /// Open an SSTable file with a block cache of its own
     pub fn open(path: PathBuf) -> Result<Self> {
+        Self::open_with_cache(path, Arc::new(BlockCache::new(crate::config::BLOCK_CACHE_SIZE)))
+    }
+
+    /// Open an SSTable file, caching its blocks in a shared `cache`
+    pub fn open_with_cache(path: PathBuf, cache: Arc<BlockCache>) -> Result<Self> {
         let mut file = File::open(&path)?;
+        let layout = Self::read_layout(&mut file, &path)?;
+        let index = Self::read_index(&mut file, &layout)?;
+        let Layout { mut meta, format, checksum, bloom_offset, bloom_size, .. } = layout;
+
+        // Read bloom filter
+        file.seek(SeekFrom::Start(bloom_offset))?;
+        let mut bloom_data = vec![0u8; bloom_size as usize];
+        file.read_exact(&mut bloom_data)?;
+        let bloom_filter = Self::parse_bloom(&bloom_data)?;
+
+        (meta.min_key, meta.max_key) = Self::key_range(&index)?;
+
+        Ok(Self {
+            cache_id: cache.table_id(&path),
+            cache,
+            files: FilePool::new(path, file),
+            meta,
+            index,
+            bloom_filter,
+            escaped_keys: format.escaped_keys,
+            checksum,
+            decoded: AtomicUsize::new(0),
+        })
+    }
+
+    /// Read an SSTable's metadata from its header, footer and index,
+    /// without the bloom filter or any data block
+    pub fn read_meta(path: PathBuf) -> Result<SSTableMeta> {
+        let mut file = File::open(&path)?;
+        let layout = Self::read_layout(&mut file, &path)?;
+        let index = Self::read_index(&mut file, &layout)?;
+        let mut meta = layout.meta;
+        (meta.min_key, meta.max_key) = Self::key_range(&index)?;
+        Ok(meta)
+    }
+
+    /// Read and parse the index, with series keys as
+    /// [`SeriesKey::encode`] gives them whatever the file's format version
+    fn read_index(file: &mut File, layout: &Layout) -> Result<Vec<IndexEntry>> {
+        file.seek(SeekFrom::Start(layout.index_offset))?;
+        let mut index_data = vec![0u8; layout.index_size as usize];
+        file.read_exact(&mut index_data)?;
+        let mut index = Self::parse_index(&index_data, layout.format)?;
+        if !layout.format.escaped_keys {
+            for entry in &mut index {
+                entry.series_key = SeriesKey::decode_unescaped(&entry.series_key).encode();
+            }
+        }
+        Ok(index)
+    }
+
+    /// Smallest and largest series key of an index, which is in key order
+    fn key_range(index: &[IndexEntry]) -> Result<(SeriesKey, SeriesKey)> {
+        match (index.first(), index.last()) {
+            (Some(first), Some(last)) => Ok((SeriesKey::decode(&first.series_key)?, SeriesKey::decode(&last.series_key)?)),
+            _ => Ok((SeriesKey::new(""), SeriesKey::new(""))),
+        }
+    }
+
+    fn read_layout(file: &mut File, path: &Path) -> Result<Layout> {
         let file_size = file.metadata()?.len();
 
         // Read footer
@@ -99,57 +235,41 @@ impl SSTableReader {
             return Err(FluxError::InvalidFormat("Invalid SSTable header".into()));
         }
         
-        let version = cursor.get_u32_le();
-        if version != FORMAT_VERSION {
-            return Err(FluxError::InvalidFormat(format!(
-                "Unsupported version: {}",
-                version
-            )));
-        }
+        let format = FormatLayout::for_version(cursor.get_u32_le())?;
         
         let entry_count = cursor.get_u64_le() as usize;
         let min_timestamp = cursor.get_i64_le();
         let max_timestamp = cursor.get_i64_le();
-
-        // Read index
-        file.seek(SeekFrom::Start(index_offset))?;
-        let mut index_data = vec![0u8; index_size as usize];
-        file.read_exact(&mut index_data)?;
-        let index = Self::parse_index(&index_data)?;
-
-        // Read bloom filter
-        file.seek(SeekFrom::Start(bloom_offset))?;
-        let mut bloom_data = vec![0u8; bloom_size as usize];
-        file.read_exact(&mut bloom_data)?;
-        let bloom_filter = Self::parse_bloom(&bloom_data)?;
-
-        // Extract key range from index
-        let (min_key, max_key) = if index.is_empty() {
-            (SeriesKey::new(""), SeriesKey::new(""))
+        
+        let checksum = if format.checksum_in_header {
+            let mut kind = [0u8; 1];
+            file.read_exact(&mut kind)?;
+            ChecksumKind::try_from(kind[0])?
         } else {
-            let min = Self::parse_series_key(&index.first().unwrap().series_key);
-            let max = Self::parse_series_key(&index.last().unwrap().series_key);
-            (min, max)
+            ChecksumKind::Crc32
         };
 
         let meta = SSTableMeta {
-            path: path.clone(),
+            path: path.to_path_buf(),
             id: 0, // Will be set by caller
             level: 0,
             entry_count,
             file_size,
             min_timestamp,
             max_timestamp,
-            min_key,
-            max_key,
+            // Read from the index
+            min_key: SeriesKey::new(""),
+            max_key: SeriesKey::new(""),
         };
 
-        Ok(Self {
-            path,
+        Ok(Layout {
             meta,
-            index,
-            bloom_filter,
-            cache: Arc::new(RwLock::new(BlockCache::new(64 * 1024 * 1024))), // 64MB cache
+            format,
+            checksum,
+            index_offset,
+            index_size,
+            bloom_offset,
+            bloom_size,
         })
     }
 
@@ -158,9 +278,19 @@ impl SSTableReader {
         &self.meta
     }
 
+    /// Set the ID and level of this SSTable (not stored in the file itself)
+    pub fn set_location(&mut self, id: u64, level: u32) {
+        self.meta.id = id;
+        self.meta.level = level;
+    }
+
     /// Check if SSTable may contain a series (bloom filter check)
     pub fn may_contain(&self, series_key: &SeriesKey) -> bool {
-        self.bloom_filter.may_contain(&series_key.canonical())
+        if self.escaped_keys {
+            self.bloom_filter.may_contain(&series_key.encode())
+        } else {
+            self.bloom_filter.may_contain(&series_key.encode_unescaped())
+        }
     }
 
     /// Query data points for a series in a time range
@@ -177,7 +307,7 @@ impl SSTableReader {
             return Ok(vec![]);
         }
 
-        let key_str = series_key.canonical();
+        let key_str = series_key.encode();
         let mut field_data: BTreeMap<i64, Fields> = BTreeMap::new();
 
         // Find matching index entries
@@ -190,8 +320,7 @@ impl SSTableReader {
             }
 
             // Read block
-            let block = self.read_block(entry.offset, entry.size)?;
-            let points = block.decompress()?;
+            let points = self.decode(entry)?;
 
             for (ts, val) in points {
                 if ts >= time_range.start && ts <= time_range.end {
@@ -213,6 +342,27 @@ impl SSTableReader {
         Ok(results)
     }
 
+    /// Read every point in the SSTable, grouped by series and timestamp
+    pub fn scan(&self) -> Result<Vec<(SeriesKey, DataPoint)>> {
+        let mut field_data: BTreeMap<(String, i64), Fields> = BTreeMap::new();
+
+        for entry in &self.index {
+            for (ts, val) in self.decode(entry)? {
+                field_data
+                    .entry((entry.series_key.clone(), ts))
+                    .or_default()
+                    .insert(entry.field_name.clone(), FieldValue::Float(val));
+            }
+        }
+
+        field_data
+            .into_iter()
+            .map(|((key, ts), fields)| {
+                Ok((SeriesKey::decode(&key)?, DataPoint { timestamp: ts, fields }))
+            })
+            .collect()
+    }
+
     /// Query a specific field
     pub fn query_field(
         &self,
@@ -227,7 +377,7 @@ impl SSTableReader {
             return Ok(vec![]);
         }
 
-        let key_str = series_key.canonical();
+        let key_str = series_key.encode();
         let mut results = Vec::new();
 
         for entry in &self.index {
@@ -238,8 +388,7 @@ impl SSTableReader {
                 continue;
             }
 
-            let block = self.read_block(entry.offset, entry.size)?;
-            let points = block.decompress()?;
+            let points = self.decode(entry)?;
 
             for (ts, val) in points {
                 if ts >= time_range.start && ts <= time_range.end {
@@ -251,45 +400,155 @@ impl SSTableReader {
         Ok(results)
     }
 
-    fn read_block(&self, offset: u64, size: u32) -> Result<DataBlock> {
-        // Check cache first
-        {
-            let cache = self.cache.read();
-            if let Some(block) = cache.get(offset) {
-                return Ok(DataBlock {
-                    field_name: block.field_name.clone(),
-                    data: block.data.clone(),
-                    count: block.count,
-                    first_timestamp: block.first_timestamp,
-                    last_timestamp: block.last_timestamp,
-                });
+    /// Count, sum and extremes of a field's values in a time range
+    ///
+    /// Blocks lying wholly inside the range are answered from the summary in
+    /// their index entry; only blocks straddling a range bound, or from
+    /// tables older than format version 4, are decompressed.
+    pub fn summarize_field(
+        &self,
+        series_key: &SeriesKey,
+        field_name: &str,
+        time_range: &TimeRange,
+    ) -> Result<BlockSummary> {
+        let mut summary = BlockSummary::default();
+        if !self.meta.overlaps_time(time_range.start, time_range.end) || !self.may_contain(series_key) {
+            return Ok(summary);
+        }
+
+        let key_str = series_key.encode();
+        for entry in &self.index {
+            if entry.series_key != key_str || entry.field_name != field_name {
+                continue;
+            }
+            if entry.max_time < time_range.start || entry.min_time > time_range.end {
+                continue;
+            }
+
+            let inside = entry.min_time >= time_range.start && entry.max_time <= time_range.end;
+            match entry.summary {
+                Some(block) if inside => summary.merge(&block),
+                _ => {
+                    for (ts, val) in self.decode(entry)? {
+                        if ts >= time_range.start && ts <= time_range.end {
+                            summary.add(val);
+                        }
+                    }
+                }
             }
         }
 
-        // Read from file
-        let mut file = File::open(&self.path)?;
-        file.seek(SeekFrom::Start(offset))?;
-        let mut data = vec![0u8; size as usize];
-        file.read_exact(&mut data)?;
+        Ok(summary)
+    }
 
-        let block = DataBlock::from_bytes(&data)?;
-
-        // Cache the block
-        {
-            let mut cache = self.cache.write();
-            cache.insert(offset, DataBlock {
-                field_name: block.field_name.clone(),
-                data: block.data.clone(),
-                count: block.count,
-                first_timestamp: block.first_timestamp,
-                last_timestamp: block.last_timestamp,
-            });
+    /// Earliest and latest block bounds of a field within a time range
+    ///
+    /// Taken from the index, so the span may reach past the first and last
+    /// points actually in range, but never misses one.
+    pub fn field_time_span(
+        &self,
+        series_key: &SeriesKey,
+        field_name: &str,
+        time_range: &TimeRange,
+    ) -> Option<(Timestamp, Timestamp)> {
+        let key_str = series_key.encode();
+        self.index
+            .iter()
+            .filter(|e| e.series_key == key_str && e.field_name == field_name)
+            .filter(|e| e.max_time >= time_range.start && e.min_time <= time_range.end)
+            .fold(None, |span, e| match span {
+                None => Some((e.min_time, e.max_time)),
+                Some((min, max)) => Some((e.min_time.min(min), e.max_time.max(max))),
+            })
+    }
+
+    /// Number of blocks this reader has decompressed
+    pub fn blocks_decoded(&self) -> usize {
+        self.decoded.load(Ordering::Relaxed)
+    }
+
+    /// Series with data in `time_range`, in key order
+    ///
+    /// Answered from the index alone; a series counts if any of its blocks
+    /// overlaps the range.
+    pub fn series_keys(&self, time_range: &TimeRange) -> Result<Vec<SeriesKey>> {
+        let mut keys: Vec<SeriesKey> = Vec::new();
+        let mut last: Option<&str> = None;
+        for entry in &self.index {
+            if entry.max_time < time_range.start || entry.min_time > time_range.end {
+                continue;
+            }
+            if last != Some(entry.series_key.as_str()) {
+                keys.push(SeriesKey::decode(&entry.series_key)?);
+                last = Some(&entry.series_key);
+            }
         }
+        Ok(keys)
+    }
 
+    /// Whether any series of `measurement` is stored, at any time
+    ///
+    /// Compares the encoded keys in the index, so nothing is decoded.
+    pub fn has_measurement(&self, measurement: &str) -> bool {
+        let prefix = SeriesKey::new(measurement).encode();
+        self.index.iter().any(|entry| match entry.series_key.strip_prefix(prefix.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with(','),
+            None => false,
+        })
+    }
+
+    /// Compression statistics per series field, in key order
+    ///
+    /// Tables written before format version 3 don't record point counts in
+    /// their index, so their blocks are read to compute them.
+    pub fn compression_stats(&self) -> Result<Vec<FieldCompression>> {
+        let mut stats: Vec<FieldCompression> = Vec::new();
+
+        for entry in &self.index {
+            let (count, encoded_size) = match entry.block_stats {
+                Some(block_stats) => block_stats,
+                None => {
+                    let block = self.read_block(entry.offset, entry.size)?;
+                    (block.count as u32, block.data.len() as u32)
+                }
+            };
+            let field = FieldCompression {
+                series_key: SeriesKey::decode(&entry.series_key)?,
+                field: entry.field_name.clone(),
+                points: count as u64,
+                encoded_bytes: encoded_size as u64,
+                stored_bytes: entry.size as u64,
+            };
+
+            match stats.last_mut() {
+                Some(last) if last.series_key == field.series_key && last.field == field.field => {
+                    last.merge(&field)
+                }
+                _ => stats.push(field),
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn decode(&self, entry: &IndexEntry) -> Result<Vec<(Timestamp, f64)>> {
+        let block = self.read_block(entry.offset, entry.size)?;
+        self.decoded.fetch_add(1, Ordering::Relaxed);
+        block.decompress()
+    }
+
+    fn read_block(&self, offset: u64, size: u32) -> Result<Arc<DataBlock>> {
+        if let Some(block) = self.cache.get(self.cache_id, offset) {
+            return Ok(block);
+        }
+
+        let data = self.files.read_at(offset, size)?;
+        let block = Arc::new(DataBlock::from_bytes(&data, self.checksum)?);
+        self.cache.insert(self.cache_id, offset, block.clone());
         Ok(block)
     }
 
-    fn parse_index(data: &[u8]) -> Result<Vec<IndexEntry>> {
+    fn parse_index(data: &[u8], format: FormatLayout) -> Result<Vec<IndexEntry>> {
         let mut cursor = std::io::Cursor::new(data);
         let count = cursor.get_u32_le() as usize;
         let mut entries = Vec::with_capacity(count);
@@ -311,6 +570,20 @@ impl SSTableReader {
             let size = cursor.get_u32_le();
             let min_time = cursor.get_i64_le();
             let max_time = cursor.get_i64_le();
+            let block_stats = if format.index_block_stats {
+                Some((cursor.get_u32_le(), cursor.get_u32_le()))
+            } else {
+                None
+            };
+            let summary = match block_stats {
+                Some((count, _)) if format.index_block_summary => Some(BlockSummary {
+                    count: count as u64,
+                    sum: cursor.get_f64_le(),
+                    min: cursor.get_f64_le(),
+                    max: cursor.get_f64_le(),
+                }),
+                _ => None,
+            };
 
             entries.push(IndexEntry {
                 series_key,
@@ -319,6 +592,8 @@ impl SSTableReader {
                 size,
                 min_time,
                 max_time,
+                block_stats,
+                summary,
             });
         }
 
@@ -339,20 +614,252 @@ impl SSTableReader {
 
         Ok(BloomFilter::from_bytes(bloom_data, num_hashes))
     }
+}
 
-    fn parse_series_key(canonical: &str) -> SeriesKey {
-        let parts: Vec<&str> = canonical.splitn(2, ',').collect();
-        let measurement = parts[0];
-        let mut key = SeriesKey::new(measurement);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sstable::{SSTableBuilder, SSTableConfig};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_reader_uses_recorded_checksum() {
+        let temp_dir = TempDir::new().unwrap();
+        let key = SeriesKey::new("cpu");
+
+        for kind in [ChecksumKind::Crc32, ChecksumKind::Crc32c] {
+            let path = temp_dir.path().join(format!("{:?}.flux", kind));
+            let config = SSTableConfig { checksum: kind, ..Default::default() };
+            let mut builder = SSTableBuilder::new(path.clone(), 1, 0, config);
+            for i in 0..100 {
+                builder.add(&key, &DataPoint::new(i, "value", FieldValue::Float(i as f64))).unwrap();
+            }
+            builder.finish().unwrap();
 
-        if parts.len() > 1 {
-            for tag in parts[1].split(',') {
-                if let Some((k, v)) = tag.split_once('=') {
-                    key = key.with_tag(k, v);
-                }
+            let reader = SSTableReader::open(path).unwrap();
+            assert_eq!(reader.checksum, kind);
+            let points = reader.query(&key, &TimeRange::new(0, 100)).unwrap();
+            assert_eq!(points.len(), 100);
+        }
+    }
+
+    /// Rewrite a current-version SSTable at `path` in the layout of an
+    /// older `version`
+    fn downgrade(path: &Path, version: u32) {
+        let data = std::fs::read(path).unwrap();
+        let mut footer = &data[data.len() - 36..];
+        let index_offset = footer.get_u64_le() as usize;
+        let index_size = footer.get_u64_le() as usize;
+        let bloom_offset = footer.get_u64_le() as usize;
+        let bloom_size = footer.get_u64_le() as usize;
+
+        // Version 1 has no checksum kind byte after the 32-byte header
+        let shift = if version < 2 { 1 } else { 0 };
+        let mut out = data[..32].to_vec();
+        out[4..8].copy_from_slice(&version.to_le_bytes());
+        out.extend_from_slice(&data[32 + shift..index_offset]);
+
+        let mut index = &data[index_offset..index_offset + index_size];
+        let count = index.get_u32_le();
+        let new_index_offset = out.len();
+        out.extend_from_slice(&count.to_le_bytes());
+        // Keys were stored unescaped before version 5, bloom filter included
+        let mut bloom = BloomFilter::new(1000, 10);
+        for _ in 0..count {
+            let key_len = index.get_u16_le() as usize;
+            let mut key = String::from_utf8(index[..key_len].to_vec()).unwrap();
+            index.advance(key_len);
+            if version < 5 {
+                key = SeriesKey::decode(&key).unwrap().encode_unescaped();
+            }
+            bloom.add(&key);
+            out.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            out.extend_from_slice(key.as_bytes());
+            let field_len = index.get_u16_le() as usize;
+            out.extend_from_slice(&(field_len as u16).to_le_bytes());
+            out.extend_from_slice(&index[..field_len]);
+            index.advance(field_len);
+            out.extend_from_slice(&(index.get_u64_le() - shift as u64).to_le_bytes());
+            out.extend_from_slice(&index[..20]);
+            index.advance(20);
+            // Block stats arrived in version 3, value summaries in version 4
+            if version >= 3 {
+                out.extend_from_slice(&index[..8]);
+            }
+            if version >= 4 {
+                out.extend_from_slice(&index[8..32]);
+            }
+            index.advance(8 + 24);
+        }
+        let new_index_size = out.len() - new_index_offset;
+
+        let new_bloom_offset = out.len();
+        if version < 5 {
+            out.extend_from_slice(&(bloom.as_bytes().len() as u32).to_le_bytes());
+            out.push(bloom.num_hashes() as u8);
+            out.extend_from_slice(bloom.as_bytes());
+        } else {
+            out.extend_from_slice(&data[bloom_offset..bloom_offset + bloom_size]);
+        }
+        let new_bloom_size = out.len() - new_bloom_offset;
+        for value in [new_index_offset, new_index_size, new_bloom_offset, new_bloom_size] {
+            out.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+        out.extend_from_slice(b"FLUX");
+        std::fs::write(path, out).unwrap();
+    }
+
+    #[test]
+    fn test_reads_older_format_versions() {
+        let temp_dir = TempDir::new().unwrap();
+        let cpu = SeriesKey::new("cpu").with_tag("host", "a");
+        let mem = SeriesKey::new("mem");
+
+        // Version 1 files always use CRC32
+        let config = SSTableConfig { checksum: ChecksumKind::Crc32, ..Default::default() };
+        let mut expected = None;
+        for version in [FORMAT_VERSION, 4, 3, 2, 1] {
+            let path = temp_dir.path().join(format!("v{}.flux", version));
+            let mut builder = SSTableBuilder::new(path.clone(), 1, 0, config.clone());
+            for ts in 0..500 {
+                builder.add(&cpu, &DataPoint::new(ts, "usage", FieldValue::Float(ts as f64))).unwrap();
+                builder.add(&mem, &DataPoint::new(ts, "used", FieldValue::Integer(ts * 2))).unwrap();
+            }
+            builder.finish().unwrap();
+            if version != FORMAT_VERSION {
+                downgrade(&path, version);
+            }
+
+            let reader = SSTableReader::open(path.clone()).unwrap();
+            assert_eq!(reader.meta().entry_count, 1000);
+            assert_eq!(reader.index.iter().all(|e| e.block_stats.is_some()), version >= 3);
+            assert_eq!(reader.index.iter().all(|e| e.summary.is_some()), version >= 4);
+            let read = (
+                reader.scan().unwrap(),
+                reader.series_keys(&TimeRange::new(0, 500)).unwrap(),
+                reader.query(&cpu, &TimeRange::new(100, 199)).unwrap().len(),
+                reader.compression_stats().unwrap().iter().map(|s| s.points).collect::<Vec<_>>(),
+                reader.summarize_field(&cpu, "usage", &TimeRange::new(50, 449)).unwrap(),
+            );
+            assert_eq!(read.2, 100);
+            match &expected {
+                None => expected = Some(read),
+                Some(expected) => assert_eq!(&read, expected, "version {}", version),
             }
         }
 
-        key
+        // Versions outside the readable range are rejected with both bounds
+        let path = temp_dir.path().join("v1.flux");
+        let mut data = std::fs::read(&path).unwrap();
+        data[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, data).unwrap();
+        let err = SSTableReader::open(path).err().unwrap();
+        assert!(err.to_string().contains("readable versions are 1 to"), "{}", err);
+    }
+
+    #[test]
+    fn test_reads_unescaped_keys_of_older_versions() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("v4.flux");
+        let spaced = SeriesKey::new("cpu").with_tag("host", "web 1");
+        let windows = SeriesKey::new("disk").with_tag("path", r"C:\data");
+        let mut builder = SSTableBuilder::new(path.clone(), 1, 0, SSTableConfig::default());
+        for key in [&spaced, &windows] {
+            for ts in 0..100 {
+                builder.add(key, &DataPoint::new(ts, "used", FieldValue::Float(ts as f64))).unwrap();
+            }
+        }
+        builder.finish().unwrap();
+        downgrade(&path, 4);
+
+        // The file holds `cpu,host=web 1`, not the escaped `cpu,host=web\ 1`
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.windows(14).any(|w| w == b"cpu,host=web 1"));
+
+        let reader = SSTableReader::open(path).unwrap();
+        for key in [&spaced, &windows] {
+            assert!(reader.may_contain(key), "{}", key);
+            assert_eq!(reader.query(key, &TimeRange::new(0, 49)).unwrap().len(), 50, "{}", key);
+        }
+        assert_eq!(reader.series_keys(&TimeRange::new(0, 100)).unwrap(), vec![spaced.clone(), windows.clone()]);
+        assert_eq!(reader.scan().unwrap().len(), 200);
+        assert_eq!((&reader.meta().min_key, &reader.meta().max_key), (&spaced, &windows));
+    }
+
+    #[test]
+    fn test_block_reads_reuse_file_handles() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sst.flux");
+        let keys: Vec<SeriesKey> = (0..50).map(|i| SeriesKey::new(format!("m{:02}", i))).collect();
+        let mut builder = SSTableBuilder::new(path.clone(), 1, 0, SSTableConfig::default());
+        for key in &keys {
+            for ts in 0..10 {
+                builder.add(key, &DataPoint::new(ts, "value", FieldValue::Float(ts as f64))).unwrap();
+            }
+        }
+        builder.finish().unwrap();
+
+        // Every query misses the block cache; sequential reads share the
+        // handle the reader was opened with
+        let reader = Arc::new(SSTableReader::open(path).unwrap());
+        for key in &keys[..25] {
+            assert_eq!(reader.query(key, &TimeRange::new(0, 10)).unwrap().len(), 10);
+        }
+        assert_eq!(reader.files.opened.load(Ordering::Relaxed), 0);
+
+        // Concurrent reads open at most one handle per extra reader
+        let threads: Vec<_> = keys[25..]
+            .chunks(5)
+            .map(|chunk| {
+                let reader = reader.clone();
+                let chunk = chunk.to_vec();
+                std::thread::spawn(move || {
+                    for key in &chunk {
+                        assert_eq!(reader.query(key, &TimeRange::new(0, 10)).unwrap().len(), 10);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(reader.files.opened.load(Ordering::Relaxed) < 5);
+        assert!(reader.files.idle.lock().len() <= FilePool::MAX_IDLE);
+    }
+
+    #[test]
+    fn test_compression_stats() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sst.flux");
+        let constant = SeriesKey::new("cpu").with_tag("host", "idle");
+        let noisy = SeriesKey::new("cpu").with_tag("host", "noisy");
+
+        let mut builder = SSTableBuilder::new(path.clone(), 1, 0, SSTableConfig::default());
+        for ts in 0..1000 {
+            let point = DataPoint::new(ts * 1_000_000_000, "usage", FieldValue::Float(42.0));
+            builder.add(&constant, &point).unwrap();
+        }
+        let mut x: u64 = 0x2545F4914F6CDD1D;
+        for ts in 0..1000 {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            let point = DataPoint::new(ts * 1_000_000_000, "usage", FieldValue::Float(x as f64 / 7.0));
+            builder.add(&noisy, &point).unwrap();
+        }
+        builder.finish().unwrap();
+
+        let reader = SSTableReader::open(path).unwrap();
+        let stats = reader.compression_stats().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].series_key, constant);
+        assert_eq!(stats[0].field, "usage");
+        assert_eq!(stats[0].points, 1000);
+        assert!(stats[0].stored_bytes > 0);
+
+        // Regular timestamps and a repeated value cost about 2 bits a point
+        assert!(stats[0].bytes_per_point() < 0.5, "{}", stats[0].bytes_per_point());
+        assert!(stats[0].compression_ratio() > 32.0);
+        assert!(stats[1].bytes_per_point() > 4.0, "{}", stats[1].bytes_per_point());
     }
 }
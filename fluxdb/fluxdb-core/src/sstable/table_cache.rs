@@ -0,0 +1,136 @@
+//! Bounded cache of open SSTable readers
+//!
+//! Databases keep only [`SSTableMeta`] for their SSTables; full readers
+//! (index, bloom filter) are opened on first access and the least recently
+//! used ones are closed once the limit is reached. Their blocks go to a
+//! [`BlockCache`] that outlives them.
+
+use super::{BlockCache, SSTableMeta, SSTableReader};
+use crate::Result;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// LRU of open SSTable readers, keyed by file path
+pub struct TableCache {
+    capacity: usize,
+    blocks: Arc<BlockCache>,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    readers: HashMap<PathBuf, Arc<SSTableReader>>,
+    /// Least recently used first
+    order: VecDeque<PathBuf>,
+}
+
+impl TableCache {
+    /// Create a cache holding at most `capacity` open readers, whose
+    /// blocks are cached in `blocks`
+    pub fn new(capacity: usize, blocks: Arc<BlockCache>) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            blocks,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Get the reader for an SSTable, opening it if needed
+    ///
+    /// Evicted readers stay usable by callers still holding them.
+    pub fn get(&self, meta: &SSTableMeta) -> Result<Arc<SSTableReader>> {
+        {
+            let mut state = self.state.lock();
+            if let Some(reader) = state.readers.get(&meta.path).cloned() {
+                state.touch(&meta.path);
+                return Ok(reader);
+            }
+        }
+
+        // Open outside the lock so slow opens don't block cache hits
+        let mut reader = SSTableReader::open_with_cache(meta.path.clone(), self.blocks.clone())?;
+        reader.set_location(meta.id, meta.level);
+        let reader = Arc::new(reader);
+
+        let mut state = self.state.lock();
+        if let Some(existing) = state.readers.get(&meta.path).cloned() {
+            state.touch(&meta.path);
+            return Ok(existing);
+        }
+        while state.readers.len() >= self.capacity {
+            match state.order.pop_front() {
+                Some(oldest) => {
+                    state.readers.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        state.readers.insert(meta.path.clone(), reader.clone());
+        state.order.push_back(meta.path.clone());
+        Ok(reader)
+    }
+
+    /// Close the reader for an SSTable that is being removed, and drop its
+    /// cached blocks
+    pub fn evict(&self, path: &Path) {
+        {
+            let mut state = self.state.lock();
+            if state.readers.remove(path).is_some() {
+                state.order.retain(|p| p != path);
+            }
+        }
+        self.blocks.forget(path);
+    }
+
+    /// Number of readers currently open
+    pub fn open_count(&self) -> usize {
+        self.state.lock().readers.len()
+    }
+}
+
+impl CacheState {
+    fn touch(&mut self, path: &Path) {
+        if let Some(pos) = self.order.iter().position(|p| p == path) {
+            let path = self.order.remove(pos).unwrap();
+            self.order.push_back(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sstable::{SSTableBuilder, SSTableConfig};
+    use crate::{DataPoint, FieldValue, SeriesKey};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_table_cache_evicts_least_recently_used() {
+        let temp_dir = TempDir::new().unwrap();
+        let metas: Vec<SSTableMeta> = (0..3)
+            .map(|id| {
+                let path = temp_dir.path().join(format!("sst_{:020}.flux", id));
+                let mut builder = SSTableBuilder::new(path, id, 0, SSTableConfig::default());
+                builder
+                    .add(&SeriesKey::new("cpu"), &DataPoint::new(id as i64, "value", FieldValue::Float(1.0)))
+                    .unwrap();
+                builder.finish().unwrap()
+            })
+            .collect();
+
+        let cache = TableCache::new(2, Arc::new(BlockCache::new(1024 * 1024)));
+        let first = cache.get(&metas[0]).unwrap();
+        assert_eq!(first.meta().id, 0);
+        cache.get(&metas[1]).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.get(&metas[0]).unwrap()));
+
+        // metas[1] is now least recently used
+        cache.get(&metas[2]).unwrap();
+        assert_eq!(cache.open_count(), 2);
+        let state = cache.state.lock();
+        assert!(state.readers.contains_key(&metas[0].path));
+        assert!(!state.readers.contains_key(&metas[1].path));
+    }
+}
@@ -1,81 +1,197 @@
 //! Database - manages a single database instance
 
 use crate::memtable::{ImmutableMemTable, MemTable};
-use crate::query::{QueryExecutor, QueryParser, QueryPlan, QueryPlanner, QueryResult};
-use crate::sstable::{SSTableBuilder, SSTableConfig, SSTableMeta, SSTableReader};
-use crate::wal::{WalConfig, WalEntry, WalReader, WalWriter};
-use crate::{DataPoint, Point, Result, FluxError, SeriesKey, TimeRange};
-use parking_lot::{RwLock, Mutex};
-use std::collections::BTreeMap;
-use std::path::PathBuf;
+use crate::query::{
+    AggregateFunc, Cursor, DropMeasurementStatement, PlanType, Query, QueryExecutor, QueryParser, QueryPlan, QueryPlanner, QueryResult, QueryRow, QueryValue,
+};
+use crate::sstable::{self, BlockCache, BlockSummary, FieldCompression, SSTableMeta, SSTableReader, TableCache};
+use crate::wal::{WalConfig, WalEntry, WalEntryType, WalReader, WalWriter};
+use super::flusher::Flusher;
+use super::measurement_locks::MeasurementLocks;
+use super::sstable_set::SSTableSet;
+use super::written_fields::WrittenFields;
+use super::{FieldOrder, SeriesRewrite, StorageConfig, WriteConsistency};
+use crate::{DataPoint, FieldValue, Point, Result, FluxError, SeriesKey, TimeRange, Timestamp};
+use arc_swap::ArcSwap;
+use crossbeam_channel::{bounded, Sender};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
 use tracing::{info, warn};
 
+/// File in a replica's database directory holding the last primary WAL
+/// segment it applied
+const REPLICA_POSITION_FILE: &str = "replica_position";
+
+/// File in the database directory recording the order each measurement's
+/// fields were first written in, with `FieldOrder::Written`
+const FIELD_ORDER_FILE: &str = "field_order.json";
+
+/// File in the database directory marking a truncation in progress, with
+/// the first SSTable ID and WAL segment that survive it
+const TRUNCATE_FILE: &str = "truncate_pending";
+
+/// Distinct series and tag values of one measurement
+#[derive(Debug, Default)]
+struct MeasurementCardinality {
+    series: HashSet<SeriesKey>,
+    tag_values: HashMap<String, HashSet<String>>,
+}
+
+impl MeasurementCardinality {
+    fn add(&mut self, key: &SeriesKey) {
+        if self.series.insert(key.clone()) {
+            for (tag, value) in &key.tags {
+                self.tag_values.entry(tag.clone()).or_default().insert(value.clone());
+            }
+        }
+    }
+}
+
 /// A single FluxDB database
 pub struct Database {
     name: String,
-    data_dir: PathBuf,
     
     // Write path
     wal: Arc<WalWriter>,
     memtable: Arc<RwLock<MemTable>>,
-    immutable_memtables: Arc<Mutex<Vec<ImmutableMemTable>>>,
+    immutable_memtables: Arc<Mutex<Vec<Arc<ImmutableMemTable>>>>,
+    /// Held shared by writes and exclusively by `sync`
+    write_gate: RwLock<()>,
+    /// Held shared by writes and exclusively by series rewrites, per
+    /// measurement
+    measurement_locks: MeasurementLocks,
     
     // Read path
-    sstables: Arc<RwLock<Vec<SSTableReader>>>,
+    /// Swapped whole on every change, so a query reading a snapshot sees
+    /// one consistent file set throughout
+    sstables: Arc<ArcSwap<SSTableSet>>,
+    table_cache: Arc<TableCache>,
+    /// Aggregates query groups when `query_parallelism` is above 1
+    query_pool: Option<rayon::ThreadPool>,
+    
+    // Background flush
+    flusher: Arc<Flusher>,
+    flush_tx: Option<Sender<()>>,
+    flush_thread: Option<JoinHandle<()>>,
     
     // Configuration
-    memtable_size_limit: usize,
-    sstable_config: SSTableConfig,
+    config: StorageConfig,
+    
+    // Latest timestamp per series, tracked in strict ordering mode
+    last_timestamps: Mutex<HashMap<SeriesKey, Timestamp>>,
+    
+    // Known series per measurement, tracked when cardinality is limited
+    cardinality: Mutex<HashMap<String, MeasurementCardinality>>,
+    
+    // Order fields were first written in, with `FieldOrder::Written`
+    written_fields: Option<WrittenFields>,
     
     // Counters
     next_memtable_id: AtomicU64,
-    next_sstable_id: AtomicU64,
 }
 
 impl Database {
-    /// Create or open a database
-    pub fn open(
-        name: &str,
-        data_dir: PathBuf,
-        wal_config: WalConfig,
-        sstable_config: SSTableConfig,
-        memtable_size_limit: usize,
-    ) -> Result<Self> {
-        let db_dir = data_dir.join(name);
+    /// Create or open a database with a block cache of its own
+    pub fn open(name: &str, config: &StorageConfig) -> Result<Self> {
+        Self::open_with_block_cache(name, config, Arc::new(BlockCache::new(config.block_cache_size)))
+    }
+
+    /// Create or open a database whose SSTable blocks are cached in the
+    /// shared `block_cache`
+    pub fn open_with_block_cache(name: &str, config: &StorageConfig, block_cache: Arc<BlockCache>) -> Result<Self> {
+        let db_dir = config.data_dir.join(name);
         std::fs::create_dir_all(&db_dir)?;
         
         let wal_dir = db_dir.join("wal");
         let wal_config = WalConfig {
             dir: wal_dir,
-            ..wal_config
+            ..config.wal.clone()
         };
         
         // Open WAL
         let wal = Arc::new(WalWriter::new(wal_config.clone())?);
+        Self::finish_truncate(&db_dir, &wal)?;
         
         // Create initial memtable
-        let memtable = Arc::new(RwLock::new(MemTable::new(0)));
+        let memtable = Arc::new(RwLock::new(MemTable::with_kind(0, config.memtable_kind)));
         
-        // Load existing SSTables
+        // Load existing SSTable metadata; readers are opened on first use
         let sstables = Self::load_sstables(&db_dir)?;
         let next_sstable_id = sstables.iter()
-            .map(|s| s.meta().id)
+            .map(|s| s.id)
             .max()
             .unwrap_or(0) + 1;
         
+        let immutable_memtables = Arc::new(Mutex::new(Vec::new()));
+        let sstables = Arc::new(ArcSwap::from_pointee(SSTableSet::new(sstables)));
+        
+        // Start the background flusher
+        let flusher = Arc::new(Flusher::new(
+            db_dir.clone(),
+            config.clone(),
+            wal.clone(),
+            immutable_memtables.clone(),
+            sstables.clone(),
+            next_sstable_id,
+        ));
+        let (flush_tx, flush_rx) = bounded::<()>(config.max_immutable_memtables.max(1));
+        let flush_thread = {
+            let flusher = flusher.clone();
+            let name = name.to_string();
+            std::thread::Builder::new()
+                .name(format!("fluxdb-flush-{}", name))
+                .spawn(move || {
+                    for () in flush_rx {
+                        if let Err(e) = flusher.flush_pending() {
+                            warn!("Background flush failed for database {}: {}", name, e);
+                        }
+                    }
+                })?
+        };
+        
+        let query_pool = match config.query_parallelism {
+            0 | 1 => None,
+            threads => {
+                let name = name.to_string();
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .thread_name(move |i| format!("fluxdb-query-{}-{}", name, i))
+                    .build()
+                    .map_err(|e| FluxError::Config(format!("query thread pool: {}", e)))?;
+                Some(pool)
+            }
+        };
+        
+        let written_fields = match config.field_order {
+            FieldOrder::Written => Some(WrittenFields::open(db_dir.join(FIELD_ORDER_FILE))?),
+            FieldOrder::Alphabetical => None,
+        };
+        
         let db = Self {
             name: name.to_string(),
-            data_dir: db_dir,
             wal,
             memtable,
-            immutable_memtables: Arc::new(Mutex::new(Vec::new())),
-            sstables: Arc::new(RwLock::new(sstables)),
-            memtable_size_limit,
-            sstable_config,
+            immutable_memtables,
+            write_gate: RwLock::new(()),
+            measurement_locks: MeasurementLocks::new(),
+            sstables,
+            table_cache: Arc::new(TableCache::new(config.max_open_sstables, block_cache)),
+            query_pool,
+            flusher,
+            flush_tx: Some(flush_tx),
+            flush_thread: Some(flush_thread),
+            config: config.clone(),
+            last_timestamps: Mutex::new(HashMap::new()),
+            cardinality: Mutex::new(HashMap::new()),
+            written_fields,
             next_memtable_id: AtomicU64::new(1),
-            next_sstable_id: AtomicU64::new(next_sstable_id),
         };
         
         // Recover from WAL
@@ -89,21 +205,200 @@ impl Database {
         &self.name
     }
 
-    /// Write data points
-    pub fn write(&self, points: &[Point]) -> Result<()> {
-        // Write to WAL first
-        let entry = WalEntry::write(&self.name, points)?;
-        self.wal.append(&entry)?;
+    /// Current time according to the configured clock
+    pub fn now(&self) -> Timestamp {
+        self.config.clock.now()
+    }
+
+    /// Write data points, returning how many were written: fewer than
+    /// given when `duplicate_points` drops some
+    ///
+    /// Returns once the points' WAL entry is synced; see
+    /// [`write_with`](Self::write_with) for the other levels.
+    pub fn write(&self, points: &[Point]) -> Result<usize> {
+        self.write_with(points, WriteConsistency::default())
+    }
+
+    /// Write data points, returning once they are as durable as
+    /// `consistency` asks
+    pub fn write_with(&self, points: &[Point], consistency: WriteConsistency) -> Result<usize> {
+        // Checked and appended under the measurements' locks, so no rename
+        // or drop of them lands in between
+        let _measurements = self.lock_measurements(points);
+        let points = &*self.admit(points, true)?;
+        
+        self.append(points, consistency)?;
+        if consistency == WriteConsistency::Flushed {
+            // Whoever flushes first, the points are in SSTables once every
+            // pending MemTable has been
+            self.maybe_flush(true)?;
+        }
+        Ok(points.len())
+    }
+
+    /// Record the order a write about to be made lists its fields in, as
+    /// measurement and field name pairs
+    ///
+    /// Points keep their fields sorted, so with `FieldOrder::Written` the
+    /// caller that parsed the write passes the order here first; fields
+    /// already recorded keep their place. Does nothing with any other field
+    /// order or on a read-only database.
+    pub fn record_field_order<'a>(&self, fields: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<()> {
+        match &self.written_fields {
+            Some(written_fields) if !self.config.read_only => written_fields.record(fields),
+            _ => Ok(()),
+        }
+    }
+
+    /// Make every check a write of `points` would, without writing them;
+    /// returns how many points the write would store
+    ///
+    /// Ordering and cardinality are checked against what is stored now,
+    /// but unlike a write the points don't count towards later checks.
+    pub fn check_write(&self, points: &[Point]) -> Result<usize> {
+        let _measurements = self.lock_measurements(points);
+        Ok(self.admit(points, false)?.len())
+    }
+
+    /// `points` as a write stores them, or why the write is rejected;
+    /// `record` notes them as seen for later ordering and cardinality checks
+    fn admit<'a>(&self, points: &'a [Point], record: bool) -> Result<Cow<'a, [Point]>> {
+        if self.config.read_only {
+            return Err(FluxError::ReadOnly(format!("database {} is a replica", self.name)));
+        }
+        self.config.check_names(points)?;
+        self.config.check_timestamps(points)?;
+        let points = self.config.duplicate_points.dedup(points);
+        if self.config.strict_ordering {
+            self.check_ordering(&points, record)?;
+        }
+        if self.config.max_series_per_measurement.is_some() || self.config.max_tag_values_per_key.is_some() {
+            self.check_cardinality(&points, record)?;
+        }
+        Ok(points)
+    }
+
+    /// Wait until every write that has returned or is in progress is
+    /// synced to the WAL and visible to queries
+    pub fn sync(&self) -> Result<()> {
+        // Appends hold the gate shared until their points are in the
+        // MemTable, so taking it exclusively waits out those in flight
+        drop(self.write_gate.write());
+        self.wal.sync()
+    }
+
+    /// Read the next complete WAL segment after `after` for a replica
+    ///
+    /// When nothing newer is complete the active segment is sealed first, so
+    /// replicas pick up recent writes without waiting for rotation. Returns
+    /// `None` if there is nothing new, and fails if the segment was already
+    /// truncated after a flush; the replica must then be rebuilt.
+    pub fn wal_segment_after(&self, after: Option<u64>) -> Result<Option<(u64, Vec<u8>)>> {
+        let next = after.map_or(0, |id| id + 1);
+        let mut active = self.wal.current_segment();
+        if next >= active {
+            active = self.wal.seal()?;
+        }
+        
+        match next.cmp(&active) {
+            std::cmp::Ordering::Less => match std::fs::read(self.wal.segment_path(next)) {
+                Ok(data) => Ok(Some((next, data))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    Err(FluxError::WalSegmentUnavailable(next))
+                }
+                Err(e) => Err(e.into()),
+            },
+            std::cmp::Ordering::Equal => Ok(None),
+            // The replica is ahead of us, e.g. our data directory was reset
+            std::cmp::Ordering::Greater => Err(FluxError::WalSegmentUnavailable(next)),
+        }
+    }
+
+    /// Apply a WAL segment received from the primary and record it as the
+    /// replica's position
+    ///
+    /// Renames, relabels and dropped measurements logged in the segment are
+    /// applied in order with the writes. The position is saved after the
+    /// points are written, so a crash in between re-applies the segment on
+    /// restart; that is harmless because points are keyed by series and
+    /// timestamp and a repeated rewrite finds nothing left to change.
+    /// Returns the number of points applied.
+    pub fn apply_replicated_segment(&self, segment_id: u64, data: &[u8]) -> Result<usize> {
+        let mut applied = 0;
+        for entry in WalReader::parse_segment(data)? {
+            if entry.database != self.name {
+                continue;
+            }
+            match entry.entry_type {
+                WalEntryType::Write => {
+                    let points = entry.get_points()?;
+                    let _measurements = self.lock_measurements(&points);
+                    self.append(&points, WriteConsistency::Memtable)?;
+                    applied += points.len();
+                }
+                WalEntryType::Rewrite => {
+                    self.apply_rewrite(&entry.get_rewrite()?)?;
+                }
+                _ => {}
+            }
+        }
+        // One sync for the whole segment, before it is recorded as applied
+        self.wal.sync()?;
         
-        // Then write to memtable
+        let path = self.config.data_dir.join(&self.name).join(REPLICA_POSITION_FILE);
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, segment_id.to_string())?;
+        std::fs::rename(&tmp, &path)?;
+        
+        Ok(applied)
+    }
+
+    /// Last primary WAL segment applied by this replica
+    pub fn replica_position(&self) -> Result<Option<u64>> {
+        let path = self.config.data_dir.join(&self.name).join(REPLICA_POSITION_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(s) => s.trim().parse().map(Some).map_err(|_| {
+                FluxError::InvalidFormat(format!("Invalid replica position in {:?}", path))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Hold the locks of the measurements `points` belong to shared
+    fn lock_measurements(&self, points: &[Point]) -> Vec<RwLockReadGuard<'_, ()>> {
+        self.measurement_locks.shared(points.iter().map(|p| p.key.measurement.as_str()))
+    }
+
+    /// Write points to the WAL and MemTable, syncing the WAL entry unless
+    /// `consistency` is [`WriteConsistency::Memtable`]
+    fn append(&self, points: &[Point], consistency: WriteConsistency) -> Result<()> {
         {
+            // Released before flushing, which freezes the MemTable under
+            // the gate held exclusively
+            let _gate = self.write_gate.read();
+            if let Some(written_fields) = &self.written_fields {
+                written_fields.record(points.iter().flat_map(|point| {
+                    let measurement = point.key.measurement.as_str();
+                    point.data.fields.0.keys().map(move |name| (measurement, name.as_str()))
+                }))?;
+            }
+            
+            // Write to WAL first
+            let entry = WalEntry::write(&self.name, points)?;
+            match consistency {
+                WriteConsistency::Memtable => self.wal.append_unsynced(&entry)?,
+                WriteConsistency::WalSynced | WriteConsistency::Flushed => self.wal.append_synced(&entry)?,
+            };
+            
+            // Then write to memtable
             let memtable = self.memtable.read();
             memtable.insert_batch(points);
         }
         
         // Check if memtable needs flushing
-        if self.memtable.read().should_flush(self.memtable_size_limit) {
-            self.maybe_flush()?;
+        if self.memtable.read().should_flush(self.config.memtable_size_limit, self.config.memtable_entry_limit) {
+            self.maybe_flush(false)?;
         }
         
         Ok(())
@@ -111,17 +406,206 @@ impl Database {
 
     /// Query data
     pub fn query(&self, sql: &str) -> Result<QueryResult> {
+        if let Some(drop) = QueryParser::parse_drop_measurement(sql)? {
+            return self.execute_drop_measurement(&drop);
+        }
+        if let Some(statement) = QueryParser::parse_explain(sql) {
+            return self.explain(statement);
+        }
+
         // Parse SQL
         let query = QueryParser::parse(sql)?;
+        self.execute(sql, &query)
+    }
+
+    /// Execute a parsed query
+    ///
+    /// `SELECT ... INTO` may only target this database here; writing into
+    /// another database goes through [`super::StorageEngine::query`].
+    pub fn execute(&self, sql: &str, query: &Query) -> Result<QueryResult> {
+        match query.into.as_ref().and_then(|into| into.database.as_deref()) {
+            Some(database) if database != self.name => Err(FluxError::Query(format!(
+                "SELECT INTO database '{}' requires the storage engine",
+                database
+            ))),
+            _ if query.into.is_some() => self.select_into(sql, query, self),
+            _ => self.select(sql, query).map(|(_, result)| result),
+        }
+    }
+
+    /// Run `query` and write its rows into `target` as points
+    ///
+    /// Returns a single `written` row with the number of points written.
+    pub fn select_into(&self, sql: &str, query: &Query, target: &Database) -> Result<QueryResult> {
+        let into = query.into.as_ref()
+            .ok_or_else(|| FluxError::Query("SELECT INTO requires an INTO clause".into()))?;
+
+        let (plan, result) = self.select(sql, query)?;
+        let points = QueryExecutor::into_points(&plan, &result, &into.measurement);
+        target.write(&points)?;
+
+        Ok(QueryResult {
+            columns: vec!["written".to_string()],
+            rows: vec![QueryRow {
+                time: None,
+                series: None,
+                values: vec![QueryValue::Integer(points.len() as i64)],
+            }],
+            execution_time_ms: result.execution_time_ms,
+            rows_affected: Some(points.len()),
+            memory_bytes: result.memory_bytes,
+        })
+    }
+
+    /// Describe how the SELECT `sql` would run, without running it
+    ///
+    /// Rows pair a step with its detail: the plan summary, whether the
+    /// whole result comes from per-block value summaries, which also needs
+    /// the filters to match a single series, and then for each
+    /// aggregation whether it could or requires a scan of every value.
+    pub fn explain(&self, sql: &str) -> Result<QueryResult> {
+        let mut plan = QueryPlanner::plan(&QueryParser::parse(sql)?)?;
+        plan.integer_aggregates = self.config.integer_aggregates;
+        plan.integer_overflow = self.config.integer_overflow;
+        plan.null_semantics = self.config.null_semantics;
+        plan.type_mismatch = self.config.type_mismatch;
+
+        let summarized = plan.summarizable() && self.single_series(&plan)?.is_some();
+
+        let row = |step: &str, detail: &str| QueryRow {
+            time: None,
+            series: None,
+            values: vec![QueryValue::String(step.to_string()), QueryValue::String(detail.to_string())],
+        };
+        let mut rows = vec![
+            row("plan", &plan.summary()),
+            row("summary pushdown", if summarized { "yes" } else { "no" }),
+            row("limit pushdown", if plan.time_limit().is_some() { "yes" } else { "no" }),
+        ];
+        for agg in &plan.aggregations {
+            rows.push(row(&agg.alias, if agg.requires_scan { "requires scan" } else { "from summaries" }));
+        }
+        Ok(QueryResult {
+            columns: vec!["step".to_string(), "detail".to_string()],
+            rows,
+            ..Default::default()
+        })
+    }
+
+    /// Execute `DROP MEASUREMENT`, returning a single `dropped` row with
+    /// the number of series removed
+    pub fn execute_drop_measurement(&self, statement: &DropMeasurementStatement) -> Result<QueryResult> {
+        let start = Instant::now();
+        let dropped = self.drop_measurement(&statement.measurement)?;
+        Ok(QueryResult {
+            columns: vec!["dropped".to_string()],
+            rows: vec![QueryRow {
+                time: None,
+                series: None,
+                values: vec![QueryValue::Integer(dropped as i64)],
+            }],
+            execution_time_ms: start.elapsed().as_secs_f64() * 1000.0,
+            rows_affected: Some(dropped),
+            memory_bytes: 0,
+        })
+    }
+
+    /// Run a plain `SELECT` one page at a time
+    ///
+    /// Returns up to `page_size` rows following `after` in series then time
+    /// order, and a cursor to pass back for the next page while rows remain.
+    /// The page size takes the place of `LIMIT`, so queries that order,
+    /// skip, limit, deduplicate, aggregate or transform rows are rejected.
+    pub fn query_page(&self, sql: &str, after: Option<Cursor>, page_size: usize) -> Result<(QueryResult, Option<Cursor>)> {
+        if page_size == 0 {
+            return Err(FluxError::Query("Page size must be positive".into()));
+        }
+        let query = QueryParser::parse(sql)?;
+        let mut plan = QueryPlanner::plan(&query)?;
+        if plan.sort.is_some() || plan.offset.is_some() || plan.limit.is_some() || plan.distinct
+            || !plan.aggregations.is_empty() || !plan.transforms.is_empty() || query.into.is_some()
+        {
+            return Err(FluxError::Query(
+                "Paged queries must be plain SELECTs without ORDER BY, LIMIT, OFFSET, DISTINCT, INTO or functions".into(),
+            ));
+        }
+        plan.after = after;
+        // One row more than a page tells whether another page follows
+        plan.limit = Some(page_size + 1);
+
+        let (_, mut result) = self.select_plan(sql, plan)?;
+        if result.rows.len() <= page_size {
+            return Ok((result, None));
+        }
+        result.rows.truncate(page_size);
+        let next = result.rows.last().and_then(Cursor::after);
+        Ok((result, next))
+    }
+
+    fn select(&self, sql: &str, query: &Query) -> Result<(QueryPlan, QueryResult)> {
+        self.select_plan(sql, QueryPlanner::plan(query)?)
+    }
+
+    fn select_plan(&self, sql: &str, mut plan: QueryPlan) -> Result<(QueryPlan, QueryResult)> {
+        let start = Instant::now();
         
-        // Create plan
-        let plan = QueryPlanner::plan(&query)?;
+        plan.max_memory = Some(self.config.max_query_memory);
+        plan.spill_threshold = self.config.aggregation_spill_threshold;
+        if let (Some(written_fields), PlanType::TableScan) = (&self.written_fields, &plan.plan_type) {
+            plan.field_order = written_fields.order(&plan.measurement);
+        }
+        plan.integer_aggregates = self.config.integer_aggregates;
+        plan.integer_overflow = self.config.integer_overflow;
+        plan.null_semantics = self.config.null_semantics;
+        plan.type_mismatch = self.config.type_mismatch;
+        plan.now = Some(self.now());
         
-        // Collect data from all sources
-        let data = self.collect_data(&plan)?;
+        let result = match self.summary_aggregate(&plan)? {
+            Some(result) => result,
+            None => {
+                // Collect data from all sources
+                let data = self.plan_data(&plan)?;
+                // Only a query that found nothing can be of a missing
+                // measurement
+                if data.is_empty()
+                    && matches!(plan.plan_type, PlanType::TableScan)
+                    && !self.has_measurement(&plan.measurement)?
+                {
+                    return Err(FluxError::MeasurementNotFound(plan.measurement.clone()));
+                }
+
+                // Execute query
+                match &self.query_pool {
+                    Some(pool) => {
+                        plan.parallelism = self.config.query_parallelism;
+                        pool.install(|| QueryExecutor::execute(&plan, data))?
+                    }
+                    None => QueryExecutor::execute(&plan, data)?,
+                }
+            }
+        };
         
-        // Execute query
-        QueryExecutor::execute(&plan, data)
+        let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+        if self.is_slow_query(elapsed_ms) {
+            warn!(
+                target: "fluxdb::slow_query",
+                database = %self.name,
+                sql,
+                plan = %plan.summary(),
+                rows = result.rows.len(),
+                elapsed_ms,
+                execution_time_ms = result.execution_time_ms,
+                "slow query"
+            );
+        }
+        Ok((plan, result))
+    }
+
+    /// Whether a query that took `elapsed_ms`, data collection included,
+    /// belongs in the slow query log
+    fn is_slow_query(&self, elapsed_ms: f64) -> bool {
+        self.config.slow_query_threshold_ms
+            .is_some_and(|threshold| elapsed_ms >= threshold as f64)
     }
 
     /// Query a specific series
@@ -130,39 +614,120 @@ impl Database {
         series_key: &SeriesKey,
         time_range: &TimeRange,
     ) -> Result<Vec<DataPoint>> {
-        let mut results = Vec::new();
+        // Read newest sources first so a point being flushed meanwhile is
+        // seen at least once
+        let memtable_points = self.memtable.read().query(series_key, time_range);
+        let immutable_points: Vec<Vec<DataPoint>> = self.immutable_memtables.lock()
+            .iter()
+            .map(|imm| imm.query(series_key, time_range))
+            .collect();
         
-        // Query memtable
+        // Then merge oldest first, so newer points win field by field
+        let mut merged = BTreeMap::new();
         {
-            let memtable = self.memtable.read();
-            results.extend(memtable.query(series_key, time_range));
+            let sstables = self.sstables.load_full();
+            for meta in Self::oldest_first(&sstables, time_range) {
+                for point in self.table_cache.get(meta)?.query(series_key, time_range)? {
+                    merge_newer(&mut merged, point.timestamp, point);
+                }
+            }
+        }
+        for point in immutable_points.into_iter().flatten().chain(memtable_points) {
+            merge_newer(&mut merged, point.timestamp, point);
         }
         
-        // Query immutable memtables
-        {
-            let immutables = self.immutable_memtables.lock();
-            for imm in immutables.iter() {
-                results.extend(imm.query(series_key, time_range));
+        Ok(merged.into_values().collect())
+    }
+
+    /// Values of one field of a series in `time_range`, oldest first
+    ///
+    /// The typed form of a single-series query, read like
+    /// [`query_series`](Self::query_series) without any SQL to parse or
+    /// plan; points without `field` are left out.
+    pub fn query_field(
+        &self,
+        series_key: &SeriesKey,
+        field: &str,
+        time_range: &TimeRange,
+    ) -> Result<Vec<(Timestamp, FieldValue)>> {
+        Ok(self
+            .query_series(series_key, time_range)?
+            .into_iter()
+            .filter_map(|mut point| Some((point.timestamp, point.fields.0.remove(field)?)))
+            .collect())
+    }
+
+    /// Count, sum and extremes of one field of a series in `time_range`
+    ///
+    /// SSTable blocks wholly inside the range contribute the summaries in
+    /// their index entries without being decoded. That only holds while no
+    /// timestamp is stored by more than one source, so when the sources'
+    /// spans overlap the points are merged as by
+    /// [`query_series`](Self::query_series) instead.
+    pub fn summarize_field(
+        &self,
+        series_key: &SeriesKey,
+        field: &str,
+        time_range: &TimeRange,
+    ) -> Result<BlockSummary> {
+        // Newest sources first, as in query_series
+        let memtable_points = self.memtable.read().query(series_key, time_range);
+        let immutable_points: Vec<Vec<DataPoint>> = self.immutable_memtables.lock()
+            .iter()
+            .map(|imm| imm.query(series_key, time_range))
+            .collect();
+        let mut in_memory = BTreeMap::new();
+        for point in immutable_points.into_iter().flatten().chain(memtable_points) {
+            merge_newer(&mut in_memory, point.timestamp, point);
+        }
+        let values: Vec<(Timestamp, f64)> = in_memory
+            .into_values()
+            .filter_map(|point| Some((point.timestamp, point.fields.get(field)?.as_f64()?)))
+            .collect();
+
+        let sstables = self.sstables.load_full();
+        let mut readers = Vec::new();
+        let mut spans = Vec::new();
+        for meta in Self::oldest_first(&sstables, time_range) {
+            let reader = self.table_cache.get(meta)?;
+            if let Some(span) = reader.field_time_span(series_key, field, time_range) {
+                spans.push(span);
+                readers.push(reader);
             }
         }
-        
-        // Query SSTables
-        {
-            let sstables = self.sstables.read();
-            for sstable in sstables.iter() {
-                if sstable.meta().overlaps_time(time_range.start, time_range.end) {
-                    results.extend(sstable.query(series_key, time_range)?);
+        if let (Some((first, _)), Some((last, _))) = (values.first(), values.last()) {
+            spans.push((*first, *last));
+        }
+        spans.sort_unstable();
+        let disjoint = spans.windows(2).all(|pair| pair[0].1 < pair[1].0);
+
+        let mut summary = BlockSummary::default();
+        if disjoint {
+            for reader in readers {
+                summary.merge(&reader.summarize_field(series_key, field, time_range)?);
+            }
+            for (_, value) in values {
+                summary.add(value);
+            }
+        } else {
+            for point in self.query_series(series_key, time_range)? {
+                if let Some(value) = point.fields.get(field).and_then(|v| v.as_f64()) {
+                    summary.add(value);
                 }
             }
         }
-        
-        // Sort by timestamp
-        results.sort_by_key(|p| p.timestamp);
-        
-        // Remove duplicates (keep latest)
-        results.dedup_by(|a, b| a.timestamp == b.timestamp);
-        
-        Ok(results)
+        Ok(summary)
+    }
+
+    /// SSTables overlapping `time_range`, oldest data first: deeper levels
+    /// hold older data, and within a level higher IDs were written later
+    fn oldest_first<'a>(sstables: &'a [SSTableMeta], time_range: &TimeRange) -> Vec<&'a SSTableMeta> {
+        let mut tables: Vec<&SSTableMeta> = sstables
+            .iter()
+            .filter(|meta| meta.overlaps_time(time_range.start, time_range.end))
+            .collect();
+        tables.sort_by_key(|meta| (std::cmp::Reverse(meta.level), meta.id));
+        tables
     }
 
     /// Get latest value for a series
@@ -183,9 +748,9 @@ impl Database {
         }
         
         // Check SSTables from newest to oldest
-        let sstables = self.sstables.read();
-        for sstable in sstables.iter().rev() {
-            let points = sstable.query(series_key, &TimeRange::new(i64::MIN, i64::MAX))?;
+        let sstables = self.sstables.load_full();
+        for meta in sstables.iter().rev() {
+            let points = self.table_cache.get(meta)?.query(series_key, &TimeRange::new(i64::MIN, i64::MAX))?;
             if let Some(point) = points.last() {
                 return Ok(Some(point.clone()));
             }
@@ -196,195 +761,1064 @@ impl Database {
 
     /// Force flush memtable to disk
     pub fn flush(&self) -> Result<()> {
-        self.maybe_flush()
+        self.maybe_flush(true)
     }
 
-    /// Get database statistics
-    pub fn stats(&self) -> DatabaseStats {
-        let memtable_size = self.memtable.read().size();
-        let immutable_count = self.immutable_memtables.lock().len();
-        let sstable_count = self.sstables.read().len();
-        let total_entries: usize = self.sstables.read()
-            .iter()
-            .map(|s| s.meta().entry_count)
-            .sum();
-        let total_size: u64 = self.sstables.read()
-            .iter()
-            .map(|s| s.meta().file_size)
-            .sum();
+    /// Remove every point, from the MemTables, SSTables and WAL, keeping
+    /// the database open with its configuration
+    ///
+    /// Writes and flushes wait while it runs. Before anything is removed a
+    /// marker file records the first SSTable ID and WAL segment that
+    /// survive; should the process stop partway, opening the database
+    /// finishes the job, so the old data never comes back in part.
+    pub fn truncate(&self) -> Result<()> {
+        if self.config.read_only {
+            return Err(FluxError::ReadOnly(format!("database {} is a replica", self.name)));
+        }
+        let _gate = self.write_gate.write();
+        let _flush = self.flusher.flush_lock.lock();
         
-        DatabaseStats {
-            name: self.name.clone(),
-            memtable_size,
-            immutable_memtables: immutable_count,
-            sstables: sstable_count,
-            total_entries,
-            total_size_bytes: total_size,
+        let marker = self.config.data_dir.join(&self.name).join(TRUNCATE_FILE);
+        let wal_segment = self.wal.seal()?;
+        let contents = format!("{} {}", self.flusher.next_sstable_id(), wal_segment);
+        let tmp = marker.with_extension("tmp");
+        std::fs::write(&tmp, &contents)?;
+        std::fs::rename(&tmp, &marker)?;
+        
+        let new_id = self.next_memtable_id.fetch_add(1, Ordering::SeqCst);
+        *self.memtable.write() = MemTable::with_kind(new_id, self.config.memtable_kind);
+        self.flusher.discard_pending();
+        self.wal.truncate_before(wal_segment)?;
+        self.last_timestamps.lock().clear();
+        self.cardinality.lock().clear();
+        if let Some(written_fields) = &self.written_fields {
+            written_fields.clear()?;
+        }
+        // The marker goes once the SSTables do, after queries still
+        // reading them finish
+        let tables = self.sstables.load().to_vec();
+        self.replace_sstables(&tables, Vec::new(), Some((marker, contents)));
+        
+        info!("Truncated database {}", self.name);
+        Ok(())
+    }
+
+    /// Rename a measurement across all stored data
+    ///
+    /// Series of `new` that already exist are merged with the renamed ones.
+    /// See [`Database::rewrite_series`] for how the change is applied.
+    /// Returns the number of points moved.
+    pub fn rename_measurement(&self, old: &str, new: &str) -> Result<usize> {
+        self.config.name_validation.check("measurement", new, self.config.max_name_length)?;
+        self.rewrite_series(&SeriesRewrite::RenameMeasurement {
+            from: old.to_string(),
+            to: new.to_string(),
+        })
+        .map(|(points, _)| points)
+    }
+
+    /// Rename the tag key `old_key` to `new_key` on every series of
+    /// `measurement`, keeping the tag values
+    ///
+    /// Fails without changing anything if a series already has a
+    /// `new_key` tag. Returns the number of points moved.
+    pub fn relabel_tag(&self, measurement: &str, old_key: &str, new_key: &str) -> Result<usize> {
+        self.config.name_validation.check("tag", new_key, self.config.max_name_length)?;
+        self.rewrite_series(&SeriesRewrite::RelabelTag {
+            measurement: measurement.to_string(),
+            from: old_key.to_string(),
+            to: new_key.to_string(),
+        })
+        .map(|(points, _)| points)
+    }
+
+    /// Remove every series of `measurement`, wherever it is stored
+    ///
+    /// See [`Database::rewrite_series`] for how the change is applied.
+    /// Returns the number of series removed.
+    pub fn drop_measurement(&self, measurement: &str) -> Result<usize> {
+        self.rewrite_series(&SeriesRewrite::DropMeasurement {
+            measurement: measurement.to_string(),
+        })
+        .map(|(_, series)| series)
+    }
+
+    /// Rewrite the series keys of all stored data
+    ///
+    /// Writes to the measurements involved are blocked while the rewrite
+    /// runs; writes to others carry on. The MemTable is flushed
+    /// first, then every SSTable holding an affected series is rewritten
+    /// at its level into new SSTables. The new files are swapped in and the
+    /// old ones dropped under a single lock, so a query sees either only
+    /// the old keys or only the new ones, never both.
+    ///
+    /// The rewrite is logged to the WAL before the swap so that replaying
+    /// older writes on restart doesn't bring the old keys back. A crash
+    /// between writing the new files and deleting the old ones can leave
+    /// both on disk; removing whichever set is unwanted repairs it.
+    ///
+    /// Returns the number of points and of distinct series changed or
+    /// removed.
+    fn rewrite_series(&self, rewrite: &SeriesRewrite) -> Result<(usize, usize)> {
+        if self.config.read_only {
+            return Err(FluxError::ReadOnly(format!("database {} is a replica", self.name)));
         }
+        self.apply_rewrite(rewrite)
     }
 
-    fn collect_data(&self, plan: &QueryPlan) -> Result<Vec<(SeriesKey, DataPoint)>> {
-        let mut data = Vec::new();
-        let measurement = &plan.measurement;
-        
-        // Collect from memtable
-        {
-            let memtable = self.memtable.read();
-            for (key, point) in memtable.iter() {
-                if key.series_key.measurement == *measurement {
-                    data.push((key.series_key.clone(), point.clone()));
-                }
+    /// [`rewrite_series`](Self::rewrite_series) without the replica check,
+    /// for replicas applying a rewrite their primary logged
+    fn apply_rewrite(&self, rewrite: &SeriesRewrite) -> Result<(usize, usize)> {
+        let changed = self.rewrite_sstables(rewrite)?;
+        if let Some(written_fields) = &self.written_fields {
+            match rewrite {
+                SeriesRewrite::RenameMeasurement { from, to } => written_fields.rename(from, to)?,
+                SeriesRewrite::DropMeasurement { measurement } => written_fields.remove(measurement)?,
+                SeriesRewrite::RelabelTag { .. } => {}
             }
         }
+        Ok(changed)
+    }
+
+    fn rewrite_sstables(&self, rewrite: &SeriesRewrite) -> Result<(usize, usize)> {
+        let _measurements = self.measurement_locks.exclusive(rewrite.measurements());
+        self.flush()?;
+        let _flush = self.flusher.flush_lock.lock();
         
-        // Collect from immutable memtables
-        {
-            let immutables = self.immutable_memtables.lock();
-            for imm in immutables.iter() {
-                for (key, point) in imm.iter() {
-                    if key.series_key.measurement == *measurement {
-                        data.push((key.series_key.clone(), point.clone()));
+        // Rewrite affected SSTables oldest first, keeping their levels so
+        // newer data still wins when they are later compacted
+        let mut old_tables = self.sstables.load().to_vec();
+        old_tables.sort_by_key(|m| m.id);
+        let mut replaced = Vec::new();
+        let mut written = Vec::new();
+        let mut moved = 0;
+        let mut series = BTreeSet::new();
+        let result = (|| -> Result<()> {
+            for meta in old_tables {
+                let mut changed = false;
+                let mut points: BTreeMap<(SeriesKey, Timestamp), DataPoint> = BTreeMap::new();
+                for (key, point) in self.table_cache.get(&meta)?.scan()? {
+                    if rewrite.drops(&key) {
+                        changed = true;
+                        moved += 1;
+                        series.insert(key);
+                        continue;
                     }
+                    let key = match rewrite.apply(&key)? {
+                        Some(new_key) => {
+                            changed = true;
+                            moved += 1;
+                            series.insert(key);
+                            new_key
+                        }
+                        None => key,
+                    };
+                    // Series merged by the rewrite keep every field
+                    merge_newer(&mut points, (key, point.timestamp), point);
                 }
-            }
-        }
-        
-        // Collect from SSTables
-        {
-            let sstables = self.sstables.read();
-            for sstable in sstables.iter() {
-                if !sstable.meta().overlaps_time(plan.time_range.start, plan.time_range.end) {
-                    continue;
-                }
-                
-                // This is a simplified implementation - in production,
-                // we would use the bloom filter and index more efficiently
-                let series_key = SeriesKey::new(measurement);
-                let points = sstable.query(&series_key, &plan.time_range)?;
-                for point in points {
-                    data.push((series_key.clone(), point));
+                if changed {
+                    let points = points.into_iter().map(|((key, _), point)| (key, point));
+                    written.extend(self.flusher.write_sstables(meta.level, points)?);
+                    replaced.push(meta);
                 }
             }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            for meta in &written {
+                let _ = std::fs::remove_file(&meta.path);
+            }
+            return Err(e);
         }
+        if replaced.is_empty() {
+            return Ok((0, 0));
+        }
+        
+        self.wal.append(&WalEntry::rewrite(&self.name, rewrite)?)?;
+        self.wal.sync()?;
+        self.replace_sstables(&replaced, written, None);
+        self.last_timestamps.lock().clear();
+        self.cardinality.lock().clear();
         
-        Ok(data)
+        info!("Rewrote {} points in {} SSTables of database {}: {:?}", moved, replaced.len(), self.name, rewrite);
+        Ok((moved, series.len()))
     }
 
-    fn maybe_flush(&self) -> Result<()> {
-        let old_memtable;
-        let new_id;
+    /// Publish `written` in place of `replaced` in a single swap; the
+    /// replaced files, then `marker`, are deleted once no query reads a
+    /// snapshot holding them
+    ///
+    /// Callers hold the flush lock, so the set can't change in between.
+    fn replace_sstables(&self, replaced: &[SSTableMeta], written: Vec<SSTableMeta>, marker: Option<(PathBuf, String)>) {
+        let next: Vec<SSTableMeta> = self.sstables.load()
+            .iter()
+            .filter(|m| !replaced.iter().any(|old| old.id == m.id))
+            .cloned()
+            .chain(written)
+            .collect();
+        let old = self.sstables.swap(Arc::new(SSTableSet::new(next)));
         
+        // Queries keep their snapshot with load_full, so the old set is
+        // dropped, here or by the last of them, once none reads it
+        old.retire(replaced.to_vec(), self.table_cache.clone(), marker);
+    }
+
+    /// Get metadata for all SSTables, ordered by level then ID
+    pub fn sstables(&self) -> Vec<SSTableMeta> {
+        let mut metas: Vec<SSTableMeta> = self.sstables.load().to_vec();
+        metas.sort_by_key(|m| (m.level, m.id));
+        metas
+    }
+
+    /// Sorted distinct values of tag `key` among the series of
+    /// `measurement` with data in `time_range`
+    ///
+    /// Looks at MemTables and SSTable indexes, so no data blocks are read.
+    pub fn tag_values(&self, measurement: &str, key: &str, time_range: &TimeRange) -> Result<Vec<String>> {
+        let mut series = Vec::new();
         {
-            let mut memtable = self.memtable.write();
-            if !memtable.should_flush(self.memtable_size_limit) {
-                return Ok(());
+            let memtable = self.memtable.read();
+            for series_key in memtable.measurement_series(measurement) {
+                if !memtable.query(&series_key, time_range).is_empty() {
+                    series.push(series_key);
+                }
             }
-            
-            new_id = self.next_memtable_id.fetch_add(1, Ordering::SeqCst);
-            old_memtable = std::mem::replace(&mut *memtable, MemTable::new(new_id));
         }
-        
-        // Move to immutable
-        let immutable = ImmutableMemTable::from(old_memtable);
-        
-        {
-            let mut immutables = self.immutable_memtables.lock();
-            immutables.push(immutable);
+        for imm in self.immutable_memtables.lock().iter() {
+            for series_key in imm.measurement_series(measurement) {
+                if !imm.query(&series_key, time_range).is_empty() {
+                    series.push(series_key);
+                }
+            }
         }
-        
-        // Flush to SSTable (in production, this would be async)
-        self.flush_immutable()?;
-        
-        Ok(())
-    }
-
-    fn flush_immutable(&self) -> Result<()> {
-        let imm = {
-            let mut immutables = self.immutable_memtables.lock();
-            if immutables.is_empty() {
-                return Ok(());
+        let sstables = self.sstables.load_full();
+        for meta in sstables.iter() {
+            if !meta.overlaps_time(time_range.start, time_range.end) {
+                continue;
             }
-            immutables.remove(0)
-        };
-        
-        let sstable_id = self.next_sstable_id.fetch_add(1, Ordering::SeqCst);
-        let sstable_path = self.data_dir.join(format!("sst_{:020}.flux", sstable_id));
-        
-        let meta = SSTableBuilder::build_from_memtable(
-            sstable_path.clone(),
-            sstable_id,
-            0, // L0
-            &imm,
-            self.sstable_config.clone(),
-        )?;
-        
-        info!("Flushed memtable {} to SSTable {}", imm.id(), sstable_id);
-        
-        // Open the new SSTable
-        let reader = SSTableReader::open(sstable_path)?;
-        
-        {
-            let mut sstables = self.sstables.write();
-            sstables.push(reader);
+            let keys = self.table_cache.get(meta)?.series_keys(time_range)?;
+            series.extend(keys.into_iter().filter(|k| k.measurement == measurement));
         }
         
-        // Truncate WAL
-        let _ = self.wal.truncate_before(sstable_id);
-        
-        Ok(())
+        let mut values: Vec<String> = series
+            .into_iter()
+            .filter_map(|mut series_key| series_key.tags.remove(key))
+            .collect();
+        values.sort();
+        values.dedup();
+        Ok(values)
     }
 
-    fn recover(&self, wal_config: WalConfig) -> Result<()> {
-        let reader = WalReader::new(wal_config);
-        let entries = reader.recover()?;
-        
-        if entries.is_empty() {
-            return Ok(());
+    /// Raw `(timestamp, value)` arrays of one field, a series at a time
+    ///
+    /// A fast path for callers that do their own processing: values come
+    /// straight from MemTables and decoded SSTable blocks without building
+    /// query rows. Series are yielded in key order, each block sorted by
+    /// timestamp; series without numeric values for `field` in range are
+    /// left out, as are non-numeric values, which SSTables don't store.
+    pub fn scan_field_blocks<'a>(
+        &'a self,
+        measurement: &str,
+        field: &'a str,
+        time_range: &TimeRange,
+    ) -> Result<impl Iterator<Item = Result<FieldBlock>> + 'a> {
+        let mut series = BTreeSet::new();
+        series.extend(self.memtable.read().measurement_series(measurement));
+        for imm in self.immutable_memtables.lock().iter() {
+            series.extend(imm.measurement_series(measurement));
         }
-        
-        info!("Recovering {} WAL entries", entries.len());
-        
-        for entry in entries {
-            if entry.database != self.name {
+        let sstables = self.sstables.load_full();
+        for meta in sstables.iter() {
+            if !meta.overlaps_time(time_range.start, time_range.end) {
                 continue;
             }
-            
-            let points = entry.get_points()?;
-            let memtable = self.memtable.read();
-            memtable.insert_batch(&points);
+            let keys = self.table_cache.get(meta)?.series_keys(time_range)?;
+            series.extend(keys.into_iter().filter(|k| k.measurement == measurement));
         }
-        
-        Ok(())
+
+        let time_range = *time_range;
+        Ok(series
+            .into_iter()
+            .map(move |key| {
+                let block = self.scan_field_block(&key, field, &time_range)?;
+                Ok((key, block))
+            })
+            .filter(|scanned| !matches!(scanned, Ok((_, block)) if block.is_empty())))
     }
 
-    fn load_sstables(db_dir: &PathBuf) -> Result<Vec<SSTableReader>> {
-        let mut sstables = Vec::new();
-        
-        if !db_dir.exists() {
-            return Ok(sstables);
+    /// One series' values for [`Database::scan_field_blocks`]; merged like
+    /// [`Database::query_series`], so the newest source wins at a timestamp
+    fn scan_field_block(
+        &self,
+        series_key: &SeriesKey,
+        field: &str,
+        time_range: &TimeRange,
+    ) -> Result<Vec<(Timestamp, f64)>> {
+        // Newest sources first, as in query_series
+        let memtable_points = self.memtable.read().query(series_key, time_range);
+        let immutable_points: Vec<Vec<DataPoint>> = self.immutable_memtables.lock()
+            .iter()
+            .map(|imm| imm.query(series_key, time_range))
+            .collect();
+
+        // Then oldest first, each replacing what came before; a newer
+        // non-numeric value hides an older numeric one
+        let mut merged: BTreeMap<Timestamp, Option<f64>> = BTreeMap::new();
+        {
+            let sstables = self.sstables.load_full();
+            for meta in Self::oldest_first(&sstables, time_range) {
+                for (ts, value) in self.table_cache.get(meta)?.query_field(series_key, field, time_range)? {
+                    merged.insert(ts, Some(value));
+                }
+            }
         }
-        
-        for entry in std::fs::read_dir(db_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if let Some(ext) = path.extension() {
-                if ext == "flux" {
-                    match SSTableReader::open(path.clone()) {
-                        Ok(reader) => sstables.push(reader),
-                        Err(e) => warn!("Failed to open SSTable {:?}: {}", path, e),
+        for point in immutable_points.into_iter().flatten().chain(memtable_points) {
+            if let Some(value) = point.fields.get(field) {
+                merged.insert(point.timestamp, value.as_f64());
+            }
+        }
+
+        Ok(merged.into_iter().filter_map(|(ts, value)| Some((ts, value?))).collect())
+    }
+
+    /// Compression statistics per series field, merged across SSTables
+    ///
+    /// Only flushed data is covered; points still in MemTables aren't
+    /// compressed yet.
+    pub fn compression_stats(&self) -> Result<Vec<FieldCompression>> {
+        let mut merged: BTreeMap<(SeriesKey, String), FieldCompression> = BTreeMap::new();
+        let sstables = self.sstables.load_full();
+        for meta in sstables.iter() {
+            for stats in self.table_cache.get(meta)?.compression_stats()? {
+                let key = (stats.series_key.clone(), stats.field.clone());
+                match merged.get_mut(&key) {
+                    Some(existing) => existing.merge(&stats),
+                    None => {
+                        merged.insert(key, stats);
+                    }
+                }
+            }
+        }
+        Ok(merged.into_values().collect())
+    }
+
+    /// Get database statistics
+    pub fn stats(&self) -> DatabaseStats {
+        let memtable_size = self.memtable.read().size();
+        let immutable_count = self.immutable_memtables.lock().len();
+        let sstables = self.sstables.load_full();
+        let sstable_count = sstables.len();
+        let total_entries: usize = sstables
+            .iter()
+            .map(|s| s.entry_count)
+            .sum();
+        let total_size: u64 = sstables
+            .iter()
+            .map(|s| s.file_size)
+            .sum();
+        
+        DatabaseStats {
+            name: self.name.clone(),
+            memtable_size,
+            immutable_memtables: immutable_count,
+            sstables: sstable_count,
+            total_entries,
+            total_size_bytes: total_size,
+        }
+    }
+
+    /// Input rows for `plan`, after materializing each of its CTEs once
+    fn plan_data(&self, plan: &QueryPlan) -> Result<Vec<(SeriesKey, DataPoint)>> {
+        let mut ctes: HashMap<&str, Vec<(SeriesKey, DataPoint)>> = HashMap::new();
+        for (name, cte) in &plan.ctes {
+            let data = self.source_data(cte, &ctes)?;
+            let result = QueryExecutor::execute(cte, data)?;
+            ctes.insert(name, QueryExecutor::materialize(cte, &result, name));
+        }
+        self.source_data(plan, &ctes)
+    }
+
+    /// Rows `plan` reads: a materialized CTE's, its subquery's result, or
+    /// stored data
+    fn source_data(
+        &self,
+        plan: &QueryPlan,
+        ctes: &HashMap<&str, Vec<(SeriesKey, DataPoint)>>,
+    ) -> Result<Vec<(SeriesKey, DataPoint)>> {
+        match &plan.plan_type {
+            PlanType::CteScan(name) => ctes
+                .get(name.as_str())
+                .cloned()
+                .ok_or_else(|| FluxError::Query(format!("CTE {} isn't materialized", name))),
+            // Each result row becomes a point, so the outer query can
+            // aggregate the subquery's aggregates
+            PlanType::Subquery(inner) => {
+                let data = self.source_data(inner, ctes)?;
+                let result = QueryExecutor::execute(inner, data)?;
+                Ok(QueryExecutor::materialize(inner, &result, &plan.measurement))
+            }
+            PlanType::Constant(_) => Ok(Vec::new()),
+            PlanType::TableScan if plan.time_limit().is_some() => self.time_limited_data(plan),
+            PlanType::TableScan if plan.row_limit().is_some() => self.row_limited_data(plan),
+            PlanType::TableScan => match self.single_series(plan)? {
+                Some(series_key) => Ok(self
+                    .query_series(&series_key, &plan.time_range)?
+                    .into_iter()
+                    .map(|point| (series_key.clone(), point))
+                    .collect()),
+                None => self.collect_data(plan),
+            },
+            _ => self.collect_data(plan),
+        }
+    }
+
+    /// The result of a [summarizable](QueryPlan::summarizable) plan over a
+    /// single series, built from [`summarize_field`](Self::summarize_field)
+    ///
+    /// None when the plan needs its points read, including when a field
+    /// has no values, which the executor reports differently.
+    fn summary_aggregate(&self, plan: &QueryPlan) -> Result<Option<QueryResult>> {
+        if !plan.summarizable() {
+            return Ok(None);
+        }
+        let series_key = match self.single_series(plan)? {
+            Some(series_key) => series_key,
+            None => return Ok(None),
+        };
+
+        let start = Instant::now();
+        let mut summaries: HashMap<&str, BlockSummary> = HashMap::new();
+        let mut values = Vec::with_capacity(plan.aggregations.len());
+        for agg in &plan.aggregations {
+            let summary = match summaries.get(agg.field.as_str()) {
+                Some(summary) => *summary,
+                None => {
+                    let summary = self.summarize_field(&series_key, &agg.field, &plan.time_range)?;
+                    summaries.insert(&agg.field, summary);
+                    summary
+                }
+            };
+            if summary.count == 0 {
+                return Ok(None);
+            }
+            values.push(match agg.function {
+                AggregateFunc::Count => QueryValue::Integer(summary.count as i64),
+                AggregateFunc::Sum => QueryValue::Float(summary.sum),
+                AggregateFunc::Mean => QueryValue::Float(summary.sum / summary.count as f64),
+                AggregateFunc::Min => QueryValue::Float(summary.min),
+                AggregateFunc::Max => QueryValue::Float(summary.max),
+                _ => return Ok(None),
+            });
+        }
+
+        Ok(Some(QueryResult {
+            columns: plan.aggregations.iter().map(|agg| agg.alias.clone()).collect(),
+            rows: vec![QueryRow { time: None, series: None, values }],
+            execution_time_ms: start.elapsed().as_secs_f64() * 1000.0,
+            ..Default::default()
+        }))
+    }
+
+    /// The series a scan reads when its tag filters match exactly one of
+    /// the measurement's series, which is then read directly rather than
+    /// scanning the whole measurement
+    ///
+    /// Only series names are looked at, from MemTables and SSTable indexes.
+    fn single_series(&self, plan: &QueryPlan) -> Result<Option<SeriesKey>> {
+        let matches = |key: &SeriesKey| plan.matches_tags(key);
+        let mut found: Option<SeriesKey> = None;
+        let mut consider = |key: SeriesKey| match &found {
+            Some(existing) => *existing == key,
+            None => {
+                found = Some(key);
+                true
+            }
+        };
+
+        for key in self.memtable.read().measurement_series(&plan.measurement) {
+            if matches(&key) && !consider(key) {
+                return Ok(None);
+            }
+        }
+        for imm in self.immutable_memtables.lock().iter() {
+            for key in imm.measurement_series(&plan.measurement) {
+                if matches(&key) && !consider(key) {
+                    return Ok(None);
+                }
+            }
+        }
+        let sstables = self.sstables.load_full();
+        for meta in Self::oldest_first(&sstables, &plan.time_range) {
+            for key in self.table_cache.get(meta)?.series_keys(&plan.time_range)? {
+                if key.measurement == plan.measurement && matches(&key) && !consider(key) {
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Points of a [time-limited](QueryPlan::time_limit) scan: those at
+    /// the first timestamps in the plan's order, enough to fill its LIMIT
+    ///
+    /// The data's time span is read in windows from the end the scan
+    /// starts at, each twice as long as the last, until they hold enough
+    /// points, so a "latest N" query reads only the newest blocks. Points
+    /// tied with the last one needed are all kept, leaving the executor's
+    /// ordering to pick between them as it would over a full scan. Columns
+    /// of `SELECT *` come from the points read.
+    fn time_limited_data(&self, plan: &QueryPlan) -> Result<Vec<(SeriesKey, DataPoint)>> {
+        let (descending, wanted) = match plan.time_limit() {
+            Some((_, 0)) => return Ok(Vec::new()),
+            Some(limit) => limit,
+            None => return self.collect_data(plan),
+        };
+        let sstables = self.sstables.load_full();
+        let Some(mut unread) = self.data_span(&sstables).and_then(|span| span.intersect(&plan.time_range)) else {
+            return Ok(Vec::new());
+        };
+
+        // Series of the measurement matching the tag filters
+        let matches = |key: &SeriesKey| key.measurement == plan.measurement && plan.matches_tags(key);
+        let mut series: BTreeSet<SeriesKey> = BTreeSet::new();
+        series.extend(self.memtable.read().measurement_series(&plan.measurement).into_iter().filter(matches));
+        for imm in self.immutable_memtables.lock().iter() {
+            series.extend(imm.measurement_series(&plan.measurement).into_iter().filter(matches));
+        }
+        for meta in Self::oldest_first(&sstables, &unread) {
+            series.extend(self.table_cache.get(meta)?.series_keys(&unread)?.into_iter().filter(matches));
+        }
+
+        let mut points: Vec<(SeriesKey, DataPoint)> = Vec::new();
+        let mut width = (unread.end.saturating_sub(unread.start) / 64).max(1);
+        loop {
+            let window = if descending {
+                TimeRange::new(unread.end.saturating_sub(width - 1).max(unread.start), unread.end)
+            } else {
+                TimeRange::new(unread.start, unread.start.saturating_add(width - 1).min(unread.end))
+            };
+            for key in &series {
+                for point in self.query_series(key, &window)? {
+                    points.push((key.clone(), point));
+                }
+            }
+            if points.len() >= wanted || window == unread {
+                break;
+            }
+            unread = if descending {
+                TimeRange::new(unread.start, window.start - 1)
+            } else {
+                TimeRange::new(window.end + 1, unread.end)
+            };
+            width = width.saturating_mul(2);
+        }
+
+        if points.len() > wanted {
+            let mut timestamps: Vec<Timestamp> = points.iter().map(|(_, point)| point.timestamp).collect();
+            if descending {
+                timestamps.sort_unstable_by(|a, b| b.cmp(a));
+                let cutoff = timestamps[wanted - 1];
+                points.retain(|(_, point)| point.timestamp >= cutoff);
+            } else {
+                timestamps.sort_unstable();
+                let cutoff = timestamps[wanted - 1];
+                points.retain(|(_, point)| point.timestamp <= cutoff);
+            }
+        }
+        points.sort_by(|(a, p), (b, q)| a.cmp(b).then(p.timestamp.cmp(&q.timestamp)));
+        Ok(points)
+    }
+
+    /// Points of a [row-limited](QueryPlan::row_limit) scan, enough to
+    /// fill its LIMIT
+    ///
+    /// Series are read one at a time in the order their rows come out,
+    /// starting after a paged query's cursor, and each is read in windows
+    /// from its start, sized from how many points the last one held, until
+    /// enough points pass the plan's filters. So a page costs about its own
+    /// size however much data follows it.
+    fn row_limited_data(&self, plan: &QueryPlan) -> Result<Vec<(SeriesKey, DataPoint)>> {
+        let wanted = match plan.row_limit() {
+            Some(0) => return Ok(Vec::new()),
+            Some(wanted) => wanted,
+            None => return self.collect_data(plan),
+        };
+        let sstables = self.sstables.load_full();
+        let Some(span) = self.data_span(&sstables) else {
+            return Ok(Vec::new());
+        };
+
+        // Matching series in the order of the rows' series column
+        let matches = |key: &SeriesKey| key.measurement == plan.measurement && plan.matches_tags(key);
+        let mut series: BTreeMap<String, SeriesKey> = BTreeMap::new();
+        let mut add = |key: SeriesKey| {
+            series.entry(key.encode()).or_insert(key);
+        };
+        self.memtable.read().measurement_series(&plan.measurement).into_iter().filter(matches).for_each(&mut add);
+        for imm in self.immutable_memtables.lock().iter() {
+            imm.measurement_series(&plan.measurement).into_iter().filter(matches).for_each(&mut add);
+        }
+        for meta in Self::oldest_first(&sstables, &plan.time_range) {
+            self.table_cache.get(meta)?.series_keys(&plan.time_range)?.into_iter().filter(matches).for_each(&mut add);
+        }
+
+        let mut points = Vec::new();
+        let mut rows = 0;
+        for key in series.into_values() {
+            let Some(mut unread) = plan.series_time_range(&key).and_then(|range| range.intersect(&span)) else {
+                continue;
+            };
+            let mut width: i64 = 1;
+            loop {
+                let window = TimeRange::new(unread.start, unread.start.saturating_add(width - 1).min(unread.end));
+                let mut passed = 0;
+                for point in self.query_series(&key, &window)? {
+                    if QueryExecutor::matches_filters(plan, &key, &point)? {
+                        passed += 1;
+                    }
+                    points.push((key.clone(), point));
+                }
+                rows += passed;
+                if rows >= wanted {
+                    return Ok(points);
+                }
+                if window.end == unread.end {
+                    break;
+                }
+                unread = TimeRange::new(window.end + 1, unread.end);
+                // Wide enough for the rows still needed at the density
+                // seen so far, and at least twice as wide
+                let factor = 2 * ((wanted - rows) / passed.max(1) + 1);
+                width = width.saturating_mul(i64::try_from(factor).unwrap_or(i64::MAX));
+            }
+        }
+        Ok(points)
+    }
+
+    /// The span of timestamps stored anywhere, `None` with no data
+    fn data_span(&self, sstables: &[SSTableMeta]) -> Option<TimeRange> {
+        let mut span: Option<TimeRange> = None;
+        let mut widen = |range: TimeRange| {
+            span = Some(match span {
+                Some(span) => TimeRange::new(span.start.min(range.start), span.end.max(range.end)),
+                None => range,
+            });
+        };
+        if let Some(range) = self.memtable.read().time_range() {
+            widen(range);
+        }
+        for imm in self.immutable_memtables.lock().iter() {
+            if let Some(range) = imm.time_range() {
+                widen(range);
+            }
+        }
+        for meta in sstables {
+            widen(TimeRange::new(meta.min_timestamp, meta.max_timestamp));
+        }
+        span
+    }
+
+    /// Points of the plan's measurement in its time range, from the series
+    /// its tag filters match
+    ///
+    /// Series are picked by name from MemTables and SSTable indexes before
+    /// any point is read, so other series' data is never touched. A paged
+    /// query's cursor narrows this further: series before it are skipped
+    /// and its own series is read from just after its timestamp.
+    fn collect_data(&self, plan: &QueryPlan) -> Result<Vec<(SeriesKey, DataPoint)>> {
+        let mut memtable_data = Vec::new();
+        let measurement = &plan.measurement;
+        let matches = |key: &SeriesKey| key.measurement == *measurement && plan.matches_tags(key);
+        
+        // Collect from memtable, range-scanning each matching series
+        {
+            let memtable = self.memtable.read();
+            if let Some(range) = memtable.time_range().and_then(|r| r.intersect(&plan.time_range)) {
+                for series_key in memtable.measurement_series(measurement).into_iter().filter(matches) {
+                    let Some(range) = plan.series_time_range(&series_key).and_then(|r| r.intersect(&range)) else {
+                        continue;
+                    };
+                    for point in memtable.query(&series_key, &range) {
+                        memtable_data.push((series_key.clone(), point));
+                    }
+                }
+            }
+        }
+        
+        // Collect from immutable memtables, oldest first
+        let mut immutable_data = Vec::new();
+        {
+            let immutables = self.immutable_memtables.lock();
+            for imm in immutables.iter() {
+                let range = match imm.time_range().and_then(|r| r.intersect(&plan.time_range)) {
+                    Some(range) => range,
+                    None => continue,
+                };
+                for series_key in imm.measurement_series(measurement).into_iter().filter(matches) {
+                    let Some(range) = plan.series_time_range(&series_key).and_then(|r| r.intersect(&range)) else {
+                        continue;
+                    };
+                    for point in imm.query(&series_key, &range) {
+                        immutable_data.push((series_key.clone(), point));
+                    }
+                }
+            }
+        }
+        
+        // Collect from SSTables, merging the sources oldest first so a
+        // point stored more than once keeps its newest fields
+        let mut merged = BTreeMap::new();
+        {
+            let sstables = self.sstables.load_full();
+            for meta in Self::oldest_first(&sstables, &plan.time_range) {
+                let reader = self.table_cache.get(meta)?;
+                for series_key in reader.series_keys(&plan.time_range)?.into_iter().filter(matches) {
+                    let Some(range) = plan.series_time_range(&series_key) else {
+                        continue;
+                    };
+                    for point in reader.query(&series_key, &range)? {
+                        merge_newer(&mut merged, (series_key.clone(), point.timestamp), point);
+                    }
+                }
+            }
+        }
+        for (series_key, point) in immutable_data.into_iter().chain(memtable_data) {
+            merge_newer(&mut merged, (series_key, point.timestamp), point);
+        }
+        
+        Ok(merged.into_iter().map(|((series_key, _), point)| (series_key, point)).collect())
+    }
+
+    /// Reject the batch if any point is older than its series' latest
+    ///
+    /// Series are seeded from stored data the first time they are seen, so
+    /// tracking survives flushes and restarts.
+    fn check_ordering(&self, points: &[Point], record: bool) -> Result<()> {
+        let mut last_timestamps = self.last_timestamps.lock();
+        let mut updates: HashMap<&SeriesKey, Timestamp> = HashMap::new();
+        
+        for point in points {
+            let last = match updates.get(&point.key) {
+                Some(&ts) => Some(ts),
+                None => match last_timestamps.get(&point.key) {
+                    Some(&ts) => Some(ts),
+                    None => self.get_latest(&point.key)?.map(|p| p.timestamp),
+                },
+            };
+            if matches!(last, Some(last) if point.data.timestamp < last) {
+                return Err(FluxError::Query("out-of-order timestamp".into()));
+            }
+            updates.insert(&point.key, point.data.timestamp);
+        }
+        if !record {
+            return Ok(());
+        }
+        
+        for (key, ts) in updates {
+            last_timestamps.insert(key.clone(), ts);
+        }
+        Ok(())
+    }
+
+    /// Reject the batch if it creates a series beyond the configured series
+    /// or tag value limits of its measurement
+    ///
+    /// Writes to existing series always pass. Measurements are seeded from
+    /// stored data the first time they are seen, like `check_ordering`.
+    fn check_cardinality(&self, points: &[Point], record: bool) -> Result<()> {
+        let mut cardinality = self.cardinality.lock();
+        let mut new_series: Vec<&SeriesKey> = Vec::new();
+        for point in points {
+            let measurement = &point.key.measurement;
+            if !cardinality.contains_key(measurement) {
+                let known = self.measurement_cardinality(measurement)?;
+                cardinality.insert(measurement.clone(), known);
+            }
+            if !cardinality[measurement].series.contains(&point.key) && !new_series.contains(&&point.key) {
+                new_series.push(&point.key);
+            }
+        }
+        if new_series.is_empty() {
+            return Ok(());
+        }
+        
+        // Check the whole batch before recording any of it
+        let mut added: HashMap<&str, MeasurementCardinality> = HashMap::new();
+        for key in &new_series {
+            let known = &cardinality[&key.measurement];
+            let pending = added.entry(key.measurement.as_str()).or_default();
+            pending.series.insert((*key).clone());
+            if let Some(max) = self.config.max_series_per_measurement {
+                if known.series.len() + pending.series.len() > max {
+                    return Err(FluxError::CardinalityLimit(format!(
+                        "measurement {} is limited to {} series; rejected new series {}",
+                        key.measurement,
+                        max,
+                        key.encode()
+                    )));
+                }
+            }
+            for (tag, value) in &key.tags {
+                let known_values = known.tag_values.get(tag);
+                if known_values.is_some_and(|values| values.contains(value)) {
+                    continue;
+                }
+                let values = pending.tag_values.entry(tag.clone()).or_default();
+                values.insert(value.clone());
+                if let Some(max) = self.config.max_tag_values_per_key {
+                    let count = known_values.map_or(0, |values| values.len());
+                    if count + values.len() > max {
+                        return Err(FluxError::CardinalityLimit(format!(
+                            "tag {} of measurement {} is limited to {} values; rejected new series {}",
+                            tag,
+                            key.measurement,
+                            max,
+                            key.encode()
+                        )));
                     }
                 }
             }
         }
         
+        if !record {
+            return Ok(());
+        }
+        for key in new_series {
+            if let Some(known) = cardinality.get_mut(&key.measurement) {
+                known.add(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether any series of `measurement` is stored, at any time
+    ///
+    /// Tells a query of a measurement that was never written, or was
+    /// dropped, from one that found no points in its range. SSTables whose
+    /// key range can't hold the measurement aren't opened.
+    fn has_measurement(&self, measurement: &str) -> Result<bool> {
+        if !self.memtable.read().measurement_series(measurement).is_empty()
+            || self.immutable_memtables.lock().iter().any(|imm| !imm.measurement_series(measurement).is_empty())
+        {
+            return Ok(true);
+        }
+        let sstables = self.sstables.load_full();
+        for meta in sstables.iter() {
+            let in_range = meta.min_key.measurement.as_str() <= measurement
+                && measurement <= meta.max_key.measurement.as_str();
+            if in_range && self.table_cache.get(meta)?.has_measurement(measurement) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Series and tag values of `measurement` across all stored data
+    fn measurement_cardinality(&self, measurement: &str) -> Result<MeasurementCardinality> {
+        let mut known = MeasurementCardinality::default();
+        for key in self.memtable.read().measurement_series(measurement) {
+            known.add(&key);
+        }
+        for imm in self.immutable_memtables.lock().iter() {
+            for key in imm.measurement_series(measurement) {
+                known.add(&key);
+            }
+        }
+        let everything = TimeRange::new(Timestamp::MIN, Timestamp::MAX);
+        let sstables = self.sstables.load_full();
+        for meta in sstables.iter() {
+            for key in self.table_cache.get(meta)?.series_keys(&everything)? {
+                if key.measurement == measurement {
+                    known.add(&key);
+                }
+            }
+        }
+        Ok(known)
+    }
+
+    fn maybe_flush(&self, force: bool) -> Result<()> {
+        // Backpressure: don't queue another immutable MemTable until the
+        // flusher has caught up. A forced flush drains the queue itself.
+        if !force {
+            self.flusher.wait_for_room(self.config.max_immutable_memtables)?;
+        }
+        
+        let old_memtable = {
+            // With no append between its WAL entry and its MemTable insert,
+            // every write logged before the seal is in the frozen MemTable
+            // and every later one in the next
+            let _gate = self.write_gate.write();
+            let mut memtable = self.memtable.write();
+            if memtable.is_empty() {
+                None
+            } else if !force && !memtable.should_flush(self.config.memtable_size_limit, self.config.memtable_entry_limit) {
+                return Ok(());
+            } else {
+                let wal_segment = self.wal.seal()?;
+                let new_id = self.next_memtable_id.fetch_add(1, Ordering::SeqCst);
+                let old = std::mem::replace(&mut *memtable, MemTable::with_kind(new_id, self.config.memtable_kind));
+                Some((old, wal_segment))
+            }
+        };
+        
+        // Move to immutable
+        match old_memtable {
+            Some((old_memtable, wal_segment)) => {
+                let immutable = Arc::new(ImmutableMemTable::from(old_memtable, wal_segment));
+                self.immutable_memtables.lock().push(immutable);
+            }
+            // A forced flush still drains anything already pending
+            None if force => return self.flusher.flush_pending(),
+            None => return Ok(()),
+        }
+        
+        if force {
+            return self.flusher.flush_pending();
+        }
+        
+        // Hand off to the background flusher
+        match &self.flush_tx {
+            Some(tx) if tx.send(()).is_ok() => Ok(()),
+            _ => self.flusher.flush_pending(),
+        }
+    }
+
+    fn recover(&self, wal_config: WalConfig) -> Result<()> {
+        let reader = WalReader::new(wal_config);
+        let entries = reader.recover()?;
+        
+        if entries.is_empty() {
+            return Ok(());
+        }
+        
+        info!("Recovering {} WAL entries", entries.len());
+        
+        for entry in entries {
+            if entry.database != self.name {
+                continue;
+            }
+            match entry.entry_type {
+                WalEntryType::Write => {
+                    let points = entry.get_points()?;
+                    let memtable = self.memtable.read();
+                    memtable.insert_batch(&points);
+                }
+                // Writes replayed so far predate the rewrite
+                WalEntryType::Rewrite => self.rewrite_memtable(&entry.get_rewrite()?)?,
+                _ => {}
+            }
+        }
+        
+        Ok(())
+    }
+
+    fn rewrite_memtable(&self, rewrite: &SeriesRewrite) -> Result<()> {
+        let mut memtable = self.memtable.write();
+        let rewritten = MemTable::with_kind(memtable.id(), memtable.kind());
+        for (key, data) in memtable.iter() {
+            if rewrite.drops(&key.series_key) {
+                continue;
+            }
+            let series_key = rewrite.apply(&key.series_key)?.unwrap_or(key.series_key);
+            rewritten.insert(&Point::new(series_key, data));
+        }
+        *memtable = rewritten;
+        Ok(())
+    }
+
+    /// Finish a [`truncate`](Self::truncate) whose marker is still in
+    /// `db_dir`, removing the SSTables and WAL segments it left behind
+    fn finish_truncate(db_dir: &PathBuf, wal: &WalWriter) -> Result<()> {
+        let marker = db_dir.join(TRUNCATE_FILE);
+        let contents = match std::fs::read_to_string(&marker) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let invalid = || FluxError::InvalidFormat(format!("Invalid truncation marker in {:?}", marker));
+        let (sstable_id, wal_segment) = contents.trim().split_once(' ').ok_or_else(invalid)?;
+        let sstable_id: u64 = sstable_id.parse().map_err(|_| invalid())?;
+        let wal_segment: u64 = wal_segment.parse().map_err(|_| invalid())?;
+        
+        for meta in Self::load_sstables(db_dir)? {
+            if meta.id < sstable_id {
+                std::fs::remove_file(&meta.path)?;
+            }
+        }
+        wal.truncate_before(wal_segment)?;
+        match std::fs::remove_file(db_dir.join(FIELD_ORDER_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        std::fs::remove_file(&marker)?;
+        
+        info!("Finished truncating database in {:?}", db_dir);
+        Ok(())
+    }
+
+    fn load_sstables(db_dir: &PathBuf) -> Result<Vec<SSTableMeta>> {
+        let mut sstables = Vec::new();
+        
+        if !db_dir.exists() {
+            return Ok(sstables);
+        }
+        
+        // SSTables sit either directly in the database directory (flat
+        // layout) or one level down in shard directories
+        Self::load_sstables_from(db_dir, &mut sstables)?;
+        for entry in std::fs::read_dir(db_dir)? {
+            let entry = entry?;
+            let is_shard = entry.file_name().to_string_lossy().starts_with("shard_");
+            if is_shard && entry.file_type()?.is_dir() {
+                Self::load_sstables_from(&entry.path(), &mut sstables)?;
+            }
+        }
+        
         // Sort by ID (oldest first)
-        sstables.sort_by_key(|s| s.meta().id);
+        sstables.sort_by_key(|s| s.id);
         
         Ok(sstables)
     }
+
+    fn load_sstables_from(dir: &Path, sstables: &mut Vec<SSTableMeta>) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            
+            if let Some(ext) = path.extension() {
+                if ext == "flux" {
+                    match SSTableReader::read_meta(path.clone()) {
+                        Ok(mut meta) => {
+                            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+                            let (id, level) = sstable::parse_file_name(name).unwrap_or((0, None));
+                            meta.id = id;
+                            meta.level = level.unwrap_or(0);
+                            sstables.push(meta);
+                        }
+                        Err(e) => warn!("Failed to open SSTable {:?}: {}", path, e),
+                    }
+                }
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        // Closing the queue stops the flush thread once it drains
+        self.flush_tx.take();
+        if let Some(thread) = self.flush_thread.take() {
+            let _ = thread.join();
+        }
+    }
 }
 
+/// A series and its `(timestamp, value)` pairs for one field, as yielded by
+/// [`Database::scan_field_blocks`]
+pub type FieldBlock = (SeriesKey, Vec<(Timestamp, f64)>);
+
 /// Database statistics
 #[derive(Debug, Clone)]
 pub struct DatabaseStats {
@@ -395,3 +1829,1682 @@ pub struct DatabaseStats {
     pub total_entries: usize,
     pub total_size_bytes: u64,
 }
+
+/// Add `point` to `merged` as the newer of any point already at `key`:
+/// the fields it carries replace the old values and the rest are kept
+///
+/// This is the rule compaction merges SSTables by.
+fn merge_newer<K: Ord>(merged: &mut BTreeMap<K, DataPoint>, key: K, point: DataPoint) {
+    match merged.entry(key) {
+        Entry::Vacant(entry) => {
+            entry.insert(point);
+        }
+        Entry::Occupied(mut entry) => {
+            for (name, value) in point.fields.0 {
+                entry.get_mut().fields.insert(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::DuplicatePoints;
+    use crate::{FieldValue, Fields};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_overlapping_sstables_merge_newest_per_field() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let key = SeriesKey::new("cpu").with_tag("host", "a");
+        let point = |ts: i64, fields: &[(&str, f64)]| {
+            let mut data = Fields::new();
+            for (name, value) in fields {
+                data.insert(*name, FieldValue::Float(*value));
+            }
+            Point::new(key.clone(), DataPoint { timestamp: ts, fields: data })
+        };
+
+        db.write(&[point(1, &[("a", 1.0), ("b", 1.0)]), point(2, &[("a", 2.0), ("b", 2.0)]), point(3, &[("a", 3.0)])]).unwrap();
+        db.flush().unwrap();
+        // Rewrites ts 2 and 3 in a second SSTable, overlapping the first
+        db.write(&[point(2, &[("a", 20.0)]), point(3, &[("b", 30.0)]), point(4, &[("a", 4.0)])]).unwrap();
+        db.flush().unwrap();
+        // And ts 3 once more, still in the MemTable
+        db.write(&[point(3, &[("a", 300.0)])]).unwrap();
+        assert_eq!(db.sstables().len(), 2);
+
+        let points = db.query_series(&key, &TimeRange::new(0, 10)).unwrap();
+        let fields: Vec<(i64, Option<f64>, Option<f64>)> = points
+            .iter()
+            .map(|p| {
+                let get = |name| match p.fields.get(name) {
+                    Some(FieldValue::Float(v)) => Some(*v),
+                    _ => None,
+                };
+                (p.timestamp, get("a"), get("b"))
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                (1, Some(1.0), Some(1.0)),
+                (2, Some(20.0), Some(2.0)),
+                (3, Some(300.0), Some(30.0)),
+                (4, Some(4.0), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flush_keeps_wal_of_unflushed_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_entry_limit: Some(50),
+            ..Default::default()
+        };
+        let key = SeriesKey::new("cpu");
+        let point = |ts: i64| Point::new(key.clone(), DataPoint::new(ts, "usage", FieldValue::Float(ts as f64)));
+        let count = |db: &Database| db.query_series(&key, &TimeRange::new(0, i64::MAX)).unwrap().len();
+
+        {
+            let db = Database::open("testdb", &config).unwrap();
+            db.write(&(0..40).map(point).collect::<Vec<_>>()).unwrap();
+            db.flush().unwrap();
+            // The MemTable filling up is frozen and flushed in the
+            // background while later writes go to the next one
+            for ts in 40..170 {
+                db.write(&[point(ts)]).unwrap();
+            }
+            db.flusher.flush_pending().unwrap();
+            assert!(db.sstables().len() >= 3);
+            assert_eq!(count(&db), 170);
+        }
+
+        // The points only in the WAL are recovered, and those flushed not
+        // replayed twice
+        let db = Database::open("testdb", &config).unwrap();
+        assert_eq!(count(&db), 170);
+        assert!(db.memtable.read().len() < 50);
+    }
+
+    #[test]
+    fn test_write_does_not_wait_for_flush() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_size_limit: 1024,
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let key = SeriesKey::new("cpu");
+
+        // Stall the background flusher mid-flush
+        let guard = db.flusher.flush_lock.lock();
+
+        let points: Vec<Point> = (0..100)
+            .map(|i| Point::new(key.clone(), DataPoint::new(i, "value", FieldValue::Float(i as f64))))
+            .collect();
+        db.write(&points).unwrap();
+
+        // The write returned while the flush is stuck; data is served from
+        // the immutable MemTable in the meantime
+        assert_eq!(db.stats().immutable_memtables, 1);
+        assert!(db.sstables().is_empty());
+        let results = db.query_series(&key, &TimeRange::new(0, 100)).unwrap();
+        assert_eq!(results.len(), 100);
+
+        drop(guard);
+        db.flush().unwrap();
+
+        assert_eq!(db.stats().immutable_memtables, 0);
+        assert!(!db.sstables().is_empty());
+        let results = db.query_series(&key, &TimeRange::new(0, 100)).unwrap();
+        assert_eq!(results.len(), 100);
+    }
+
+    #[test]
+    fn test_scan_field_blocks_matches_sql() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let cpu = SeriesKey::new("cpu");
+        let cpu_b = SeriesKey::new("cpu").with_tag("host", "b");
+        let point = |key: &SeriesKey, ts: i64, value: FieldValue| {
+            Point::new(key.clone(), DataPoint::new(ts, "usage", value))
+        };
+
+        // Half of `cpu` flushed, the rest and `cpu,host=b` still in memory
+        let flushed: Vec<Point> = (0..50).map(|i| point(&cpu, i * 10, FieldValue::Float(i as f64 / 2.0))).collect();
+        db.write(&flushed).unwrap();
+        db.flush().unwrap();
+        let buffered: Vec<Point> = (50..100)
+            .map(|i| point(&cpu, i * 10, FieldValue::Integer(i)))
+            .chain((0..20).map(|i| point(&cpu_b, i * 25, FieldValue::Float(100.0 + i as f64))))
+            .chain([
+                point(&cpu_b, 1, FieldValue::String("n/a".into())),
+                Point::new(SeriesKey::new("mem"), DataPoint::new(300, "usage", FieldValue::Float(1.0))),
+            ])
+            .collect();
+        db.write(&buffered).unwrap();
+
+        let range = TimeRange::new(200, 800);
+        let blocks: Vec<FieldBlock> = db
+            .scan_field_blocks("cpu", "usage", &range)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        let keys: Vec<&SeriesKey> = blocks.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![&cpu, &cpu_b]);
+        assert_eq!(blocks[0].1.len(), 61);
+        assert_eq!(blocks[1].1.len(), 12);
+        for (_, block) in &blocks {
+            assert!(block.windows(2).all(|w| w[0].0 < w[1].0));
+        }
+
+        // The same values as going through SQL
+        let result = db.query("SELECT usage FROM cpu WHERE time >= 200 AND time <= 800").unwrap();
+        let mut expected: BTreeMap<String, Vec<(Timestamp, f64)>> = BTreeMap::new();
+        for row in &result.rows {
+            let value = row.values[0].as_f64().unwrap();
+            expected.entry(row.series.clone().unwrap()).or_default().push((row.time.unwrap(), value));
+        }
+        for block in expected.values_mut() {
+            block.sort_by_key(|(ts, _)| *ts);
+        }
+        let scanned: BTreeMap<String, Vec<(Timestamp, f64)>> =
+            blocks.into_iter().map(|(key, block)| (key.encode(), block)).collect();
+        assert_eq!(scanned, expected);
+
+        // The MemTable wins over flushed data at the same timestamp
+        db.write(&[point(&cpu, 200, FieldValue::Float(-1.0))]).unwrap();
+        let mut blocks = db.scan_field_blocks("cpu", "usage", &TimeRange::new(200, 200)).unwrap();
+        assert_eq!(blocks.next().unwrap().unwrap(), (cpu, vec![(200, -1.0)]));
+        assert_eq!(blocks.next().unwrap().unwrap(), (cpu_b, vec![(200, 108.0)]));
+        assert!(blocks.next().is_none());
+    }
+
+    #[test]
+    fn test_scan_field_blocks_keeps_newest_overwrite() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let cpu = SeriesKey::new("cpu");
+
+        // The same timestamp written three times, each copy in its own SSTable
+        for value in [1.0, 2.0, 3.0] {
+            db.write(&[Point::new(cpu.clone(), DataPoint::new(100, "usage", FieldValue::Float(value)))]).unwrap();
+            db.flush().unwrap();
+        }
+        let mut blocks = db.scan_field_blocks("cpu", "usage", &TimeRange::new(0, 200)).unwrap();
+        assert_eq!(blocks.next().unwrap().unwrap(), (cpu.clone(), vec![(100, 3.0)]));
+        assert!(blocks.next().is_none());
+        drop(blocks);
+
+        // And a buffered copy wins over all of them
+        db.write(&[Point::new(cpu.clone(), DataPoint::new(100, "usage", FieldValue::Float(4.0)))]).unwrap();
+        let mut blocks = db.scan_field_blocks("cpu", "usage", &TimeRange::new(0, 200)).unwrap();
+        assert_eq!(blocks.next().unwrap().unwrap(), (cpu, vec![(100, 4.0)]));
+    }
+
+    #[test]
+    fn test_memtable_flush_triggers() {
+        let temp_dir = TempDir::new().unwrap();
+        let key = SeriesKey::new("cpu");
+        let points = |start: i64, count: i64, fields: usize| -> Vec<Point> {
+            (start..start + count)
+                .map(|ts| {
+                    let mut data = DataPoint::new(ts, "value", FieldValue::Float(ts as f64));
+                    for i in 1..fields {
+                        data.fields.insert(format!("field_{}", i), FieldValue::Float(i as f64));
+                    }
+                    Point::new(key.clone(), data)
+                })
+                .collect()
+        };
+
+        // Many tiny points reach the entry limit long before the size limit
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_entry_limit: Some(50),
+            ..Default::default()
+        };
+        let db = Database::open("by_count", &config).unwrap();
+        db.write(&points(0, 49, 1)).unwrap();
+        assert_eq!(db.memtable.read().len(), 49);
+        db.write(&points(49, 1, 1)).unwrap();
+        assert!(db.memtable.read().is_empty());
+        db.flush().unwrap();
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len(), 50);
+
+        // A few wide points reach the size limit well below the entry limit
+        let wide = points(0, 4, 200);
+        let config = StorageConfig {
+            memtable_size_limit: wide[..3].iter().map(|p| p.data.size()).sum(),
+            ..config
+        };
+        let db = Database::open("by_size", &config).unwrap();
+        db.write(&wide[..2]).unwrap();
+        assert_eq!(db.memtable.read().len(), 2);
+        db.write(&wide[2..]).unwrap();
+        assert!(db.memtable.read().is_empty());
+        db.flush().unwrap();
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_strict_ordering() {
+        let temp_dir = TempDir::new().unwrap();
+        let key = SeriesKey::new("cpu");
+        let point = |ts| Point::new(key.clone(), DataPoint::new(ts, "value", FieldValue::Float(1.0)));
+
+        let config = StorageConfig {
+            data_dir: temp_dir.path().join("lenient"),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        db.write(&[point(100)]).unwrap();
+        db.write(&[point(50)]).unwrap();
+
+        let config = StorageConfig {
+            data_dir: temp_dir.path().join("strict"),
+            strict_ordering: true,
+            ..Default::default()
+        };
+        {
+            let db = Database::open("testdb", &config).unwrap();
+            db.write(&[point(100), point(100)]).unwrap();
+            let err = db.write(&[point(50)]).unwrap_err();
+            assert!(matches!(err, FluxError::Query(ref msg) if msg == "out-of-order timestamp"));
+
+            // Out of order within a single batch
+            assert!(db.write(&[point(200), point(150)]).is_err());
+            db.flush().unwrap();
+            assert!(db.write(&[point(99)]).is_err());
+        }
+
+        // Tracking is rebuilt from SSTables after a restart
+        let db = Database::open("testdb", &config).unwrap();
+        assert!(db.write(&[point(99)]).is_err());
+        db.write(&[point(101)]).unwrap();
+    }
+
+    #[test]
+    fn test_cardinality_limits() {
+        let temp_dir = TempDir::new().unwrap();
+        let point = |host: &str, region: &str| {
+            let key = SeriesKey::new("cpu").with_tag("host", host).with_tag("region", region);
+            Point::new(key, DataPoint::new(1, "value", FieldValue::Float(1.0)))
+        };
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            max_series_per_measurement: Some(3),
+            max_tag_values_per_key: Some(2),
+            ..Default::default()
+        };
+        {
+            let db = Database::open("testdb", &config).unwrap();
+            db.write(&[point("a", "us"), point("b", "us"), point("a", "eu")]).unwrap();
+
+            // A third region breaks the tag value limit
+            let err = db.write(&[point("b", "ap")]).unwrap_err();
+            assert!(matches!(err, FluxError::CardinalityLimit(ref msg) if msg.contains("region")));
+
+            // A fourth series breaks the series limit, rejecting the whole batch
+            let err = db.write(&[point("a", "us"), point("b", "eu")]).unwrap_err();
+            assert!(matches!(err, FluxError::CardinalityLimit(ref msg) if msg.contains("3 series")));
+
+            // Existing series and other measurements still accept writes
+            db.write(&[point("a", "us"), point("b", "us")]).unwrap();
+            let mem = Point::new(SeriesKey::new("mem"), DataPoint::new(1, "value", FieldValue::Float(1.0)));
+            db.write(&[mem]).unwrap();
+            db.flush().unwrap();
+        }
+
+        // Known series are rebuilt from SSTables after a restart
+        let db = Database::open("testdb", &config).unwrap();
+        assert!(db.write(&[point("b", "eu")]).is_err());
+        db.write(&[point("a", "eu")]).unwrap();
+        assert_eq!(db.query_series(&point("b", "us").key, &TimeRange::new(0, 10)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_cte_query() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let reading = |room: &str, ts: i64, temp: f64| {
+            Point::new(SeriesKey::new("sensors").with_tag("room", room), DataPoint::new(ts, "temp", FieldValue::Float(temp)))
+        };
+        db.write(&[
+            reading("a", 1, 70.0),
+            reading("a", 2, 85.0),
+            reading("a", 3, 90.0),
+            reading("b", 1, 95.0),
+            reading("b", 2, 60.0),
+        ])
+        .unwrap();
+
+        // Raw rows keep their series through the CTE
+        let result = db.query(
+            "WITH hot AS (SELECT temp FROM sensors WHERE temp > 80) SELECT mean(temp) FROM hot GROUP BY room",
+        )
+        .unwrap();
+        let rows: Vec<_> = result.rows.iter().map(|r| r.values.clone()).collect();
+        assert_eq!(rows, vec![
+            vec![QueryValue::String("a".into()), QueryValue::Float(87.5)],
+            vec![QueryValue::String("b".into()), QueryValue::Float(95.0)],
+        ]);
+
+        // A CTE read by a later CTE and by the outer query
+        let result = db.query(
+            "WITH hot AS (SELECT temp FROM sensors WHERE temp > 80), \
+             peak AS (SELECT max(temp) AS hottest FROM hot GROUP BY room) \
+             SELECT count(temp) FROM hot",
+        )
+        .unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Integer(3)]);
+        let result = db.query(
+            "WITH hot AS (SELECT temp FROM sensors WHERE temp > 80), \
+             peak AS (SELECT max(temp) AS hottest FROM hot GROUP BY room) \
+             SELECT hottest FROM peak WHERE room = 'a'",
+        )
+        .unwrap();
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(90.0)]);
+
+        // Subqueries read CTEs too, at any depth
+        let result = db.query(
+            "WITH hot AS (SELECT temp FROM sensors WHERE temp > 80) \
+             SELECT count(peak) FROM (SELECT max(temp) AS peak FROM (SELECT temp FROM hot) GROUP BY room)",
+        )
+        .unwrap();
+        assert_eq!(result.rows[0].values, vec![QueryValue::Integer(2)]);
+
+        // Joins only read stored measurements, so naming a CTE is an error
+        let err = db
+            .query("WITH hot AS (SELECT temp FROM sensors WHERE temp > 80) SELECT * FROM sensors JOIN hot USING (room)")
+            .unwrap_err();
+        assert!(err.to_string().contains("CTE hot can't be used in a JOIN"), "{}", err);
+    }
+
+    #[test]
+    fn test_aggregate_over_subquery() {
+        const HOUR: i64 = 3_600_000_000_000;
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let reading = |host: &str, ts: i64, value: f64| {
+            Point::new(SeriesKey::new("cpu").with_tag("host", host), DataPoint::new(ts, "value", FieldValue::Float(value)))
+        };
+        db.write(&[
+            // Hourly means: a 15, 40, 25; b 10, 70, 5
+            reading("a", 0, 10.0),
+            reading("a", 10, 20.0),
+            reading("a", HOUR, 40.0),
+            reading("a", 2 * HOUR, 20.0),
+            reading("a", 2 * HOUR + 10, 30.0),
+            reading("b", 5, 10.0),
+            reading("b", HOUR + 5, 70.0),
+            reading("b", 2 * HOUR + 5, 5.0),
+        ])
+        .unwrap();
+
+        // Means across both hosts per hour: 12.5, 55, 18.75
+        let result = db
+            .query("SELECT max(hourly_mean) FROM (SELECT mean(value) AS hourly_mean FROM cpu GROUP BY time('1h'))")
+            .unwrap();
+        assert_eq!(result.columns, vec!["max_hourly_mean"]);
+        assert_eq!(result.rows[0].values, vec![QueryValue::Float(55.0)]);
+
+        // Grouped tags carry through to the outer query
+        let result = db
+            .query(
+                "SELECT max(hourly_mean), count(hourly_mean) FROM \
+                 (SELECT mean(value) AS hourly_mean FROM cpu GROUP BY time('1h'), host) AS hourly GROUP BY host",
+            )
+            .unwrap();
+        let rows: Vec<_> = result.rows.iter().map(|r| r.values.clone()).collect();
+        assert_eq!(rows, vec![
+            vec![QueryValue::String("a".into()), QueryValue::Float(40.0), QueryValue::Integer(3)],
+            vec![QueryValue::String("b".into()), QueryValue::Float(70.0), QueryValue::Integer(3)],
+        ]);
+    }
+
+    #[test]
+    fn test_single_series_fast_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let host = |name: &str| SeriesKey::new("cpu").with_tag("host", name).with_tag("dc", "east");
+        let points: Vec<Point> = (0..20)
+            .flat_map(|ts| {
+                ["a", "b"].map(|name| {
+                    let mut data = DataPoint::new(ts * 10, "usage", FieldValue::Float(ts as f64));
+                    data.fields.insert("idle", FieldValue::Integer(100 - ts));
+                    Point::new(host(name), data)
+                })
+            })
+            .collect();
+        db.write(&points).unwrap();
+
+        let generic = |sql: &str| {
+            let plan = QueryPlanner::plan(&QueryParser::parse(sql).unwrap()).unwrap();
+            QueryExecutor::execute(&plan, db.collect_data(&plan).unwrap()).unwrap()
+        };
+        let single = |sql: &str| {
+            let plan = QueryPlanner::plan(&QueryParser::parse(sql).unwrap()).unwrap();
+            db.single_series(&plan).unwrap()
+        };
+
+        let rows = |result: &QueryResult| -> Vec<_> {
+            result.rows.iter().map(|row| (row.time, row.series.clone(), row.values.clone())).collect()
+        };
+        for (sql, name) in [
+            ("SELECT usage FROM cpu WHERE host = 'a' AND time >= 50 AND time <= 110", "a"),
+            ("SELECT * FROM cpu WHERE host = 'b' AND dc = 'east' ORDER BY time DESC LIMIT 3", "b"),
+            ("SELECT mean(usage) FROM cpu WHERE host = 'a' GROUP BY time(50)", "a"),
+        ] {
+            assert_eq!(single(sql), Some(host(name)), "{}", sql);
+            let fast = db.query(sql).unwrap();
+            let expected = generic(sql);
+            assert!(!fast.rows.is_empty(), "{}", sql);
+            assert_eq!(fast.columns, expected.columns, "{}", sql);
+            assert_eq!(rows(&fast), rows(&expected), "{}", sql);
+        }
+
+        // Filters matching several series, or none, scan the measurement
+        assert_eq!(single("SELECT usage FROM cpu WHERE dc = 'east'"), None);
+        assert_eq!(single("SELECT usage FROM cpu WHERE host = 'c'"), None);
+
+        // The typed API reads the same values without SQL
+        let values = db.query_field(&host("a"), "usage", &TimeRange::new(50, 110)).unwrap();
+        let sql = db.query("SELECT usage FROM cpu WHERE host = 'a' AND time >= 50 AND time <= 110").unwrap();
+        let expected: Vec<_> = sql
+            .rows
+            .iter()
+            .map(|row| (row.time.unwrap(), row.values[0].clone()))
+            .collect();
+        let values: Vec<_> = values
+            .into_iter()
+            .map(|(ts, value)| (ts, QueryValue::Float(value.as_f64().unwrap())))
+            .collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn test_range_aggregates_skip_interior_blocks() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let cpu = SeriesKey::new("cpu");
+        for batch in 0..4 {
+            let points: Vec<Point> = (batch * 100..(batch + 1) * 100)
+                .map(|ts| Point::new(cpu.clone(), DataPoint::new(ts, "usage", FieldValue::Float((ts % 7) as f64))))
+                .collect();
+            db.write(&points).unwrap();
+            db.flush().unwrap();
+        }
+        let tables = db.sstables();
+        assert_eq!(tables.len(), 4);
+
+        let sql = "SELECT count(usage), sum(usage), mean(usage), min(usage), max(usage) FROM cpu WHERE time >= 50 AND time <= 349";
+        let generic = || {
+            let plan = QueryPlanner::plan(&QueryParser::parse(sql).unwrap()).unwrap();
+            QueryExecutor::execute(&plan, db.collect_data(&plan).unwrap()).unwrap()
+        };
+        let rows = |result: &QueryResult| -> Vec<_> {
+            result.rows.iter().map(|row| (row.time, row.series.clone(), row.values.clone())).collect()
+        };
+        let decoded = || -> Vec<usize> {
+            tables.iter().map(|meta| db.table_cache.get(meta).unwrap().blocks_decoded()).collect()
+        };
+
+        let result = db.query(sql).unwrap();
+        // Only the tables holding the range bounds had a block decoded
+        assert_eq!(decoded(), vec![1, 0, 0, 1]);
+        assert_eq!(result.rows[0].values[0], QueryValue::Integer(300));
+        let expected = generic();
+        assert_eq!(result.columns, expected.columns);
+        assert_eq!(rows(&result), rows(&expected));
+
+        // A point stored again in the MemTable overlaps the tables, whose
+        // points are then merged instead
+        db.write(&[Point::new(cpu.clone(), DataPoint::new(150, "usage", FieldValue::Float(100.0)))]).unwrap();
+        let result = db.query(sql).unwrap();
+        assert_eq!(result.rows[0].values[0], QueryValue::Integer(300));
+        assert_eq!(result.rows[0].values[4], QueryValue::Float(100.0));
+        assert_eq!(rows(&result), rows(&generic()));
+    }
+
+    #[test]
+    fn test_tag_filters_prune_series_before_scanning() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let regions = ["east", "west", "north", "south"];
+        let series = |host: usize| {
+            SeriesKey::new("cpu")
+                .with_tag("host", format!("h{:02}", host))
+                .with_tag("region", regions[host % regions.len()])
+        };
+        let points: Vec<Point> = (0..40)
+            .flat_map(|host| {
+                (0..10).map(move |ts| Point::new(series(host), DataPoint::new(ts, "usage", FieldValue::Float(host as f64))))
+            })
+            .collect();
+        db.write(&points).unwrap();
+        db.flush().unwrap();
+        // A newer copy of one point of a matching series, still in the MemTable
+        db.write(&[Point::new(series(4), DataPoint::new(3, "usage", FieldValue::Float(100.0)))]).unwrap();
+
+        let tables = db.sstables();
+        let decoded = || -> usize {
+            tables.iter().map(|meta| db.table_cache.get(meta).unwrap().blocks_decoded()).sum()
+        };
+        let result = db.query("SELECT usage FROM cpu WHERE region = 'east'").unwrap();
+        // One block per matching series, and none of the other 30
+        assert_eq!(decoded(), 10);
+        assert_eq!(result.rows.len(), 100);
+        let mut hosts: Vec<String> = result.rows.iter().map(|row| row.series.clone().unwrap()).collect();
+        hosts.dedup();
+        assert_eq!(hosts.len(), 10);
+        assert!(hosts.iter().all(|key| key.contains("region=east")), "{:?}", hosts);
+        let values: Vec<f64> = result
+            .rows
+            .iter()
+            .filter(|row| row.series.as_deref() == Some(series(4).encode().as_str()))
+            .map(|row| match row.values[0] {
+                QueryValue::Float(v) => v,
+                ref other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(values, vec![4.0, 4.0, 4.0, 100.0, 4.0, 4.0, 4.0, 4.0, 4.0, 4.0]);
+
+        let result = db.query("SELECT usage FROM cpu WHERE region = 'nowhere'").unwrap();
+        assert!(result.rows.is_empty());
+        assert_eq!(decoded(), 10);
+    }
+
+    #[test]
+    fn test_paged_queries_collect_only_after_the_cursor() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let series = |host: usize| SeriesKey::new("cpu").with_tag("host", format!("h{}", host));
+        let points = |from: i64| -> Vec<Point> {
+            (0..3)
+                .flat_map(|host| {
+                    (from..from + 500).map(move |ts| Point::new(series(host), DataPoint::new(ts, "usage", FieldValue::Float((ts % 2) as f64))))
+                })
+                .collect()
+        };
+        // Half of each series in an SSTable, the other half in the MemTable
+        db.write(&points(0)).unwrap();
+        db.flush().unwrap();
+        db.write(&points(500)).unwrap();
+
+        // Data collection starts right after the cursor
+        let mut plan = QueryPlanner::plan(&QueryParser::parse("SELECT usage FROM cpu").unwrap()).unwrap();
+        plan.after = Some(Cursor { series: series(1).encode(), timestamp: 249 });
+        let data = db.collect_data(&plan).unwrap();
+        assert_eq!(data.len(), 750 + 1_000);
+        assert_eq!((&data[0].0, data[0].1.timestamp), (&series(1), 250));
+        plan.after = Some(Cursor { series: series(2).encode(), timestamp: 999 });
+        assert!(db.collect_data(&plan).unwrap().is_empty());
+
+        // A page reads about its own size, not everything after the cursor
+        plan.after = Some(Cursor { series: series(0).encode(), timestamp: 899 });
+        plan.limit = Some(401);
+        let data = db.row_limited_data(&plan).unwrap();
+        assert!(data.len() >= 401 && data.len() < 1_000, "{}", data.len());
+        assert_eq!((&data[0].0, data[0].1.timestamp), (&series(0), 900));
+
+        // So walking every page reads each row once, in order, with or
+        // without filters
+        let walk = |sql: &str| {
+            let mut after = None;
+            let mut rows = Vec::new();
+            loop {
+                let (page, next) = db.query_page(sql, after, 400).unwrap();
+                rows.extend(page.rows.into_iter().map(|row| (row.series.unwrap(), row.time.unwrap())));
+                match next {
+                    Some(next) => after = Some(next),
+                    None => break,
+                }
+            }
+            rows
+        };
+        let expected: Vec<(String, i64)> = (0..3).flat_map(|host| (0..1_000).map(move |ts| (series(host).encode(), ts))).collect();
+        assert_eq!(walk("SELECT usage FROM cpu"), expected);
+        let odd: Vec<(String, i64)> = expected.into_iter().filter(|(_, ts)| ts % 2 == 1).collect();
+        assert_eq!(walk("SELECT usage FROM cpu WHERE usage > 0"), odd);
+    }
+
+    #[test]
+    fn test_configured_null_semantics() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let points = vec![
+            Point::new(SeriesKey::new("http"), DataPoint::new(1, "code", FieldValue::Integer(200))),
+            Point::new(SeriesKey::new("http"), DataPoint::new(2, "code", FieldValue::Integer(404))),
+            Point::new(SeriesKey::new("http"), DataPoint::new(3, "bytes", FieldValue::Integer(10))),
+        ];
+        let sql = "SELECT * FROM http WHERE code NOT IN (404)";
+
+        // Lenient by default, keeping the point without `code`
+        let db = Database::open("lenient", &config).unwrap();
+        db.write(&points).unwrap();
+        assert_eq!(db.query(sql).unwrap().rows.len(), 2);
+
+        // Paged queries follow the configuration too
+        config.null_semantics = crate::query::NullSemantics::Sql;
+        let db = Database::open("sql", &config).unwrap();
+        db.write(&points).unwrap();
+        let times = |rows: Vec<QueryRow>| rows.iter().map(|row| row.time.unwrap()).collect::<Vec<_>>();
+        assert_eq!(times(db.query(sql).unwrap().rows), vec![1]);
+        assert_eq!(times(db.query_page(sql, None, 10).unwrap().0.rows), vec![1]);
+    }
+
+    #[test]
+    fn test_configured_type_mismatch() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let points = vec![
+            Point::new(SeriesKey::new("devices"), DataPoint::new(1, "reading", FieldValue::String("high".into()))),
+            Point::new(SeriesKey::new("devices"), DataPoint::new(2, "reading", FieldValue::Integer(42))),
+        ];
+        let sql = "SELECT * FROM devices WHERE reading > 0";
+
+        // Excluded by default
+        let db = Database::open("lenient", &config).unwrap();
+        db.write(&points).unwrap();
+        assert_eq!(db.query(sql).unwrap().rows.len(), 1);
+
+        config.type_mismatch = crate::query::TypeMismatch::Error;
+        let db = Database::open("strict", &config).unwrap();
+        db.write(&points).unwrap();
+        assert!(matches!(db.query(sql), Err(FluxError::Query(_))));
+        assert!(matches!(db.query_page(sql, None, 10), Err(FluxError::Query(_))));
+    }
+
+    #[test]
+    fn test_time_ordered_limit_reads_newest_blocks() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let cpu = SeriesKey::new("cpu");
+        for batch in 0..20 {
+            let points: Vec<Point> = (batch * 100..(batch + 1) * 100)
+                .map(|ts| Point::new(cpu.clone(), DataPoint::new(ts, "usage", FieldValue::Float((ts % 7) as f64))))
+                .collect();
+            db.write(&points).unwrap();
+            db.flush().unwrap();
+        }
+        let tables = db.sstables();
+        assert_eq!(tables.len(), 20);
+        let decoded = || -> usize {
+            tables.iter().map(|meta| db.table_cache.get(meta).unwrap().blocks_decoded()).sum()
+        };
+        let full_scan = |sql: &str| {
+            let plan = QueryPlanner::plan(&QueryParser::parse(sql).unwrap()).unwrap();
+            QueryExecutor::execute(&plan, db.collect_data(&plan).unwrap()).unwrap()
+        };
+        let rows = |result: &QueryResult| -> Vec<_> {
+            result.rows.iter().map(|row| (row.time, row.values.clone())).collect()
+        };
+
+        let sql = "SELECT * FROM cpu ORDER BY time DESC LIMIT 10";
+        let result = db.query(sql).unwrap();
+        assert_eq!(decoded(), 1);
+        let times: Vec<_> = result.rows.iter().map(|row| row.time.unwrap()).collect();
+        assert_eq!(times, (1990..2000).rev().collect::<Vec<_>>());
+        assert_eq!(rows(&result), rows(&full_scan(sql)));
+
+        // Oldest first, with an offset and a newer copy of a point in the
+        // MemTable
+        db.write(&[Point::new(cpu.clone(), DataPoint::new(3, "usage", FieldValue::Float(100.0)))]).unwrap();
+        let sql = "SELECT usage FROM cpu WHERE time >= 1 ORDER BY time ASC LIMIT 5 OFFSET 1";
+        let before = decoded();
+        let result = db.query(sql).unwrap();
+        assert_eq!(decoded() - before, 1);
+        assert_eq!(result.rows[1].values, vec![QueryValue::Float(100.0)]);
+        assert_eq!(rows(&result), rows(&full_scan(sql)));
+
+        // Ordered by a field, every point has to be read
+        let sql = "SELECT * FROM cpu ORDER BY usage DESC LIMIT 10";
+        let before = decoded();
+        let result = db.query(sql).unwrap();
+        assert_eq!(decoded() - before, 20);
+        assert_eq!(rows(&result), rows(&full_scan(sql)));
+        assert!(db.query("SELECT * FROM cpu ORDER BY time DESC LIMIT 0").unwrap().rows.is_empty());
+
+        let pushdown = |sql: &str| db.query(sql).unwrap().rows[2].values[1].as_string().unwrap();
+        assert_eq!(pushdown("EXPLAIN SELECT * FROM cpu ORDER BY time DESC LIMIT 10"), "yes");
+        assert_eq!(pushdown("EXPLAIN SELECT * FROM cpu WHERE usage > 3 ORDER BY time DESC LIMIT 10"), "no");
+        assert_eq!(pushdown("EXPLAIN SELECT * FROM cpu ORDER BY usage DESC LIMIT 10"), "no");
+    }
+
+    #[test]
+    fn test_write_consistency_levels() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let wal_on_disk = || std::fs::metadata(db.wal.segment_path(db.wal.current_segment())).unwrap().len();
+        let at = |ts: i64| vec![Point::new(SeriesKey::new("cpu"), DataPoint::new(ts, "usage", FieldValue::Float(1.0)))];
+        let count = || db.query("SELECT count(usage) FROM cpu").unwrap().rows[0].values[0].clone();
+
+        // Queryable at once, but not yet on disk
+        db.write_with(&at(1), WriteConsistency::Memtable).unwrap();
+        assert_eq!(count(), QueryValue::Integer(1));
+        assert_eq!(wal_on_disk(), 0);
+
+        // Synced together with the write before it
+        db.write_with(&at(2), WriteConsistency::WalSynced).unwrap();
+        let synced = wal_on_disk();
+        assert_eq!(WalReader::parse_segment(&std::fs::read(db.wal.segment_path(db.wal.current_segment())).unwrap()).unwrap().len(), 2);
+
+        // Plain writes sync their WAL entry too
+        db.write(&at(4)).unwrap();
+        assert!(wal_on_disk() > synced);
+
+        // In an SSTable, and so out of the MemTable, before returning
+        db.write_with(&at(3), WriteConsistency::Flushed).unwrap();
+        assert!(db.memtable.read().is_empty());
+        assert!(db.immutable_memtables.lock().is_empty());
+        let stored: usize = db.sstables().iter().map(|meta| meta.entry_count).sum();
+        assert_eq!(stored, 4);
+        assert_eq!(count(), QueryValue::Integer(4));
+
+        assert_eq!(WriteConsistency::from_name("WAL_SYNCED"), Some(WriteConsistency::WalSynced));
+        assert_eq!(WriteConsistency::from_name("eventually"), None);
+    }
+
+    #[test]
+    fn test_missing_and_empty_measurements() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let cpu = SeriesKey::new("cpu").with_tag("host", "a");
+        db.write(&[Point::new(cpu.clone(), DataPoint::new(100, "usage", FieldValue::Float(1.0)))]).unwrap();
+
+        // Known from the MemTable, then from an SSTable, with no points in range
+        for flushed in [false, true] {
+            if flushed {
+                db.flush().unwrap();
+            }
+            let result = db.query("SELECT usage FROM cpu WHERE time >= 200").unwrap();
+            assert!(result.rows.is_empty());
+            let result = db.query("SELECT count(usage) FROM cpu WHERE time >= 200 AND host = 'b'").unwrap();
+            assert!(result.rows.is_empty());
+        }
+
+        for sql in [
+            "SELECT usage FROM memory",
+            "SELECT count(usage) FROM memory WHERE time >= 200",
+            "SELECT * FROM memory ORDER BY time DESC LIMIT 1",
+        ] {
+            let err = db.query(sql).unwrap_err();
+            assert!(matches!(err, FluxError::MeasurementNotFound(ref name) if name == "memory"), "{}", err);
+        }
+
+        // A stored measurement's name isn't enough of a prefix
+        let err = db.query("SELECT usage FROM cp").unwrap_err();
+        assert!(matches!(err, FluxError::MeasurementNotFound(ref name) if name == "cp"), "{}", err);
+    }
+
+    #[test]
+    fn test_select_star_in_written_field_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            field_order: FieldOrder::Written,
+            ..Default::default()
+        };
+        let cpu = SeriesKey::new("cpu").with_tag("host", "a");
+        let point = |ts: i64, names: &[&str]| {
+            let mut fields = crate::Fields::new();
+            for (i, name) in names.iter().enumerate() {
+                fields.insert(*name, FieldValue::Float(i as f64));
+            }
+            Point::new(cpu.clone(), DataPoint { timestamp: ts, fields })
+        };
+        let columns = |db: &Database| db.query("SELECT * FROM cpu").unwrap().columns;
+        let write = |db: &Database, ts: i64, names: &[&str]| {
+            db.record_field_order(names.iter().map(|name| ("cpu", *name))).unwrap();
+            db.write(&[point(ts, names)]).unwrap();
+        };
+
+        {
+            let db = Database::open("testdb", &config).unwrap();
+            write(&db, 1, &["zeta", "alpha"]);
+            // A field first seen later goes last, whatever order this
+            // write lists them in
+            write(&db, 2, &["mid", "alpha", "zeta"]);
+            assert_eq!(columns(&db), vec!["time", "series", "zeta", "alpha", "mid"]);
+            db.flush().unwrap();
+            assert_eq!(columns(&db), vec!["time", "series", "zeta", "alpha", "mid"]);
+            // New fields written without an order follow alphabetically
+            db.write(&[point(3, &["omega", "beta"])]).unwrap();
+            assert_eq!(columns(&db), vec!["time", "series", "zeta", "alpha", "mid", "beta", "omega"]);
+        }
+
+        // The order survives reopening; without the option fields sort
+        let db = Database::open("testdb", &config).unwrap();
+        assert_eq!(columns(&db), vec!["time", "series", "zeta", "alpha", "mid", "beta", "omega"]);
+        drop(db);
+        let alphabetical = StorageConfig {
+            field_order: FieldOrder::Alphabetical,
+            ..config
+        };
+        let db = Database::open("testdb", &alphabetical).unwrap();
+        assert_eq!(columns(&db), vec!["time", "series", "alpha", "beta", "mid", "omega", "zeta"]);
+    }
+
+    #[test]
+    fn test_select_without_from() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            clock: Arc::new(crate::MockClock::new(1_700_000_000_000_000_000)),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+
+        let result = db.query("SELECT 1 + 1, 7 / 2.0 AS half, -(2 * 3) % 4, now()").unwrap();
+        assert_eq!(result.columns, vec!["1 + 1", "half", "-(2 * 3) % 4", "now()"]);
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(
+            result.rows[0].values,
+            vec![
+                QueryValue::Integer(2),
+                QueryValue::Float(3.5),
+                QueryValue::Integer(-2),
+                QueryValue::Integer(1_700_000_000_000_000_000),
+            ]
+        );
+
+        assert!(db.query("SELECT 1 / 0").is_err());
+        assert!(db.query("SELECT 9223372036854775807 + 1").is_err());
+    }
+
+    #[test]
+    fn test_explain_marks_aggregates_requiring_scan() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let explain = |sql: &str| -> Vec<(String, String)> {
+            db.query(sql)
+                .unwrap()
+                .rows
+                .iter()
+                .map(|row| (row.values[0].as_string().unwrap(), row.values[1].as_string().unwrap()))
+                .collect()
+        };
+
+        let steps = explain("EXPLAIN SELECT count(value), percentile(value) FROM cpu");
+        assert_eq!(steps[0].0, "plan");
+        assert_eq!(steps[1], ("summary pushdown".to_string(), "no".to_string()));
+        assert_eq!(steps[2], ("limit pushdown".to_string(), "no".to_string()));
+        assert_eq!(steps[3], ("count_value".to_string(), "from summaries".to_string()));
+        assert_eq!(steps[4], ("percentile_value".to_string(), "requires scan".to_string()));
+
+        // Summaries only answer for a single series
+        let sql = "EXPLAIN SELECT count(value), max(value) FROM cpu WHERE time >= 10";
+        assert_eq!(explain(sql)[1].1, "no");
+        let host = |name: &str| SeriesKey::new("cpu").with_tag("host", name);
+        db.write(&[Point::new(host("a"), DataPoint::new(10, "value", FieldValue::Float(1.0)))]).unwrap();
+        let steps = explain(sql);
+        assert_eq!(steps[1], ("summary pushdown".to_string(), "yes".to_string()));
+        assert!(steps[3..].iter().all(|(_, detail)| detail == "from summaries"));
+        db.write(&[Point::new(host("b"), DataPoint::new(10, "value", FieldValue::Float(2.0)))]).unwrap();
+        assert_eq!(explain(sql)[1].1, "no");
+        assert_eq!(explain(&format!("{} AND host = 'b'", sql))[1].1, "yes");
+        let steps = explain("EXPLAIN SELECT count(DISTINCT value) FROM cpu");
+        assert_eq!(steps[3].1, "requires scan");
+    }
+
+    #[test]
+    fn test_duplicate_points_in_batch() {
+        let temp_dir = TempDir::new().unwrap();
+        let cpu = SeriesKey::new("cpu");
+        let point = |key: &SeriesKey, ts, value| Point::new(key.clone(), DataPoint::new(ts, "value", FieldValue::Float(value)));
+        let batch = [point(&cpu, 1, 1.0), point(&cpu, 2, 2.0), point(&cpu, 1, 3.0), point(&SeriesKey::new("mem"), 1, 4.0)];
+
+        for (policy, logged, value) in [
+            (DuplicatePoints::KeepAll, 4, 3.0),
+            (DuplicatePoints::KeepFirst, 3, 1.0),
+            (DuplicatePoints::KeepLast, 3, 3.0),
+        ] {
+            let config = StorageConfig {
+                data_dir: temp_dir.path().join(format!("{:?}", policy)),
+                duplicate_points: policy,
+                ..Default::default()
+            };
+            let db = Database::open("testdb", &config).unwrap();
+            db.write(&batch).unwrap();
+            db.sync().unwrap();
+
+            let wal_config = WalConfig { dir: config.data_dir.join("testdb").join("wal"), ..config.wal.clone() };
+            let entries = WalReader::new(wal_config).recover().unwrap();
+            assert_eq!(entries.len(), 1);
+            let points = entries[0].get_points().unwrap();
+            assert_eq!(points.len(), logged, "{:?}", policy);
+
+            assert_eq!(db.memtable.read().len(), 3);
+            let stored = db.query_series(&cpu, &TimeRange::new(1, 1)).unwrap();
+            assert_eq!(stored.len(), 1);
+            assert_eq!(stored[0].fields.get("value"), Some(&FieldValue::Float(value)), "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_immutable_memtable_backpressure() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            memtable_size_limit: 1024,
+            max_immutable_memtables: 2,
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let key = SeriesKey::new("cpu");
+        let done = std::sync::atomic::AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            // Stall the flusher while a writer fills many MemTables
+            let guard = db.flusher.flush_lock.lock();
+            s.spawn(|| {
+                for batch in 0..20 {
+                    let points: Vec<Point> = (0..100)
+                        .map(|i| {
+                            let ts = batch * 100 + i;
+                            Point::new(key.clone(), DataPoint::new(ts, "value", FieldValue::Float(ts as f64)))
+                        })
+                        .collect();
+                    db.write(&points).unwrap();
+                }
+                done.store(true, Ordering::SeqCst);
+            });
+
+            for _ in 0..20 {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+                assert!(db.stats().immutable_memtables <= 2);
+            }
+            // The writer is parked waiting for the flusher
+            assert!(!done.load(Ordering::SeqCst));
+            assert_eq!(db.stats().immutable_memtables, 2);
+            drop(guard);
+        });
+
+        assert!(done.load(Ordering::SeqCst));
+        db.flush().unwrap();
+        let results = db.query_series(&key, &TimeRange::new(0, 2000)).unwrap();
+        assert_eq!(results.len(), 2000);
+    }
+
+    #[test]
+    fn test_collect_data_prunes_memtable_by_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+
+        // 10 hosts x 5,000 points, plus another measurement in between
+        let mut points = Vec::new();
+        for host in 0..10 {
+            let key = SeriesKey::new("cpu").with_tag("host", format!("h{}", host));
+            for i in 0..5_000 {
+                points.push(Point::new(key.clone(), DataPoint::new(i * 1000, "usage", FieldValue::Float(i as f64))));
+            }
+        }
+        for i in 0..5_000 {
+            points.push(Point::new(SeriesKey::new("disk"), DataPoint::new(i * 1000, "free", FieldValue::Integer(i))));
+        }
+        db.write(&points).unwrap();
+        assert_eq!(db.stats().immutable_memtables, 0);
+
+        let query = QueryParser::parse("SELECT usage FROM cpu WHERE time >= 100000 AND time <= 104000").unwrap();
+        let plan = QueryPlanner::plan(&query).unwrap();
+
+        // Only the 5 matching timestamps of each host are collected
+        let data = db.collect_data(&plan).unwrap();
+        assert_eq!(data.len(), 50);
+        assert!(data.iter().all(|(key, point)| {
+            key.measurement == "cpu" && plan.time_range.contains(point.timestamp)
+        }));
+
+        let plan = QueryPlanner::plan(&QueryParser::parse("SELECT usage FROM cpu WHERE time > 10000000000").unwrap()).unwrap();
+        assert!(db.collect_data(&plan).unwrap().is_empty());
+    }
+
+    /// Merge every SSTable into new ones and swap them in, as compaction does
+    fn merge_all_sstables(db: &Database) {
+        let _flush = db.flusher.flush_lock.lock();
+        let tables = db.sstables();
+        let everything = TimeRange::new(Timestamp::MIN, Timestamp::MAX);
+        let mut points = BTreeMap::new();
+        for meta in Database::oldest_first(&tables, &everything) {
+            for (key, point) in db.table_cache.get(meta).unwrap().scan().unwrap() {
+                merge_newer(&mut points, (key, point.timestamp), point);
+            }
+        }
+        let points = points.into_iter().map(|((key, _), point)| (key, point));
+        let written = db.flusher.write_sstables(0, points).unwrap();
+        db.replace_sstables(&tables, written, None);
+    }
+
+    #[test]
+    fn test_sstable_names_record_level_and_time_range() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let key = SeriesKey::new("cpu");
+        let points = |from: i64| -> Vec<Point> {
+            (from..from + 10)
+                .map(|ts| Point::new(key.clone(), DataPoint::new(ts, "value", FieldValue::Float(1.0))))
+                .collect()
+        };
+        let file_name = |meta: &SSTableMeta| meta.path.file_name().unwrap().to_str().unwrap().to_string();
+
+        let (legacy, moved) = {
+            let db = Database::open("testdb", &config).unwrap();
+            db.write(&points(1000)).unwrap();
+            db.flush().unwrap();
+            db.write(&points(2000)).unwrap();
+            db.flush().unwrap();
+            let tables = db.sstables();
+            assert_eq!(
+                tables.iter().map(file_name).collect::<Vec<_>>(),
+                vec![
+                    format!("sst_L0_{:020}_1000_1009.flux", tables[0].id),
+                    format!("sst_L0_{:020}_2000_2009.flux", tables[1].id),
+                ]
+            );
+
+            // Rewrite the second file into L1, as compaction would
+            let _flush = db.flusher.flush_lock.lock();
+            let rewritten = db.table_cache.get(&tables[1]).unwrap().scan().unwrap();
+            let written = db.flusher.write_sstables(1, rewritten).unwrap();
+            assert_eq!(file_name(&written[0]), format!("sst_L1_{:020}_2000_2009.flux", written[0].id));
+            db.replace_sstables(&tables[1..], written.clone(), None);
+
+            // Give the first the name files had before names carried levels
+            let legacy = tables[0].path.with_file_name(format!("sst_{:020}.flux", tables[0].id));
+            std::fs::rename(&tables[0].path, &legacy).unwrap();
+            (legacy, written[0].clone())
+        };
+
+        let db = Database::open("testdb", &config).unwrap();
+        let tables = db.sstables();
+        assert_eq!(tables.len(), 2);
+        assert_eq!((tables[0].path.clone(), tables[0].level), (legacy, 0));
+        assert_eq!((tables[1].path.clone(), tables[1].id, tables[1].level), (moved.path, moved.id, 1));
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 3000)).unwrap().len(), 20);
+
+        assert_eq!(sstable::parse_file_name("sst_L2_00000000000000000042_-5_7.flux"), Some((42, Some(2))));
+        assert_eq!(sstable::parse_file_name("sst_00000000000000000042.flux"), Some((42, None)));
+        assert_eq!(sstable::parse_file_name("sst_L2_00000000000000000042.flux"), None);
+    }
+
+    #[test]
+    fn test_queries_see_consistent_sstable_sets() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            max_open_sstables: 2,
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let key = SeriesKey::new("cpu");
+        for batch in 0..4 {
+            let points: Vec<Point> = (0..250)
+                .map(|i| Point::new(key.clone(), DataPoint::new(batch * 250 + i, "value", FieldValue::Float(1.0))))
+                .collect();
+            db.write(&points).unwrap();
+            db.flush().unwrap();
+        }
+
+        let stop = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut queries = 0;
+                        while !stop.load(Ordering::Relaxed) {
+                            let points = db.query_series(&key, &TimeRange::new(0, 1_000)).unwrap();
+                            assert_eq!(points.len(), 1_000);
+                            let result = db.query("SELECT count(value) FROM cpu").unwrap();
+                            assert_eq!(result.rows[0].values, vec![QueryValue::Integer(1_000)]);
+                            queries += 1;
+                        }
+                        queries
+                    })
+                })
+                .collect();
+
+            for _ in 0..20 {
+                merge_all_sstables(&db);
+            }
+            stop.store(true, Ordering::Relaxed);
+            for reader in readers {
+                assert!(reader.join().unwrap() > 0);
+            }
+        });
+
+        // Replaced files are gone once no query reads them
+        let files = std::fs::read_dir(temp_dir.path().join("testdb"))
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "flux"))
+            .count();
+        assert_eq!(files, db.sstables().len());
+    }
+
+    #[test]
+    fn test_replaced_sstables_outlive_snapshots_without_blocking() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let key = SeriesKey::new("cpu");
+        for batch in 0..2 {
+            db.write(&[Point::new(key.clone(), DataPoint::new(batch, "value", FieldValue::Float(1.0)))]).unwrap();
+            db.flush().unwrap();
+        }
+        let marker = temp_dir.path().join("testdb").join(TRUNCATE_FILE);
+
+        // A rewrite returns while a query still holds the old set, whose
+        // files stay readable until that snapshot drops
+        let snapshot = db.sstables.load_full();
+        merge_all_sstables(&db);
+        assert!(snapshot.iter().all(|meta| meta.path.exists()));
+        assert_eq!(db.table_cache.get(&snapshot[0]).unwrap().scan().unwrap().len(), 1);
+        drop(snapshot);
+        assert_eq!(db.sstables().len(), 1);
+        let files = || std::fs::read_dir(temp_dir.path().join("testdb")).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "flux"))
+            .count();
+        assert_eq!(files(), 1);
+
+        // So does truncate, keeping its marker until the files are gone
+        let snapshot = db.sstables.load_full();
+        db.truncate().unwrap();
+        assert!(db.sstables().is_empty());
+        assert_eq!(files(), 1);
+        assert!(marker.exists());
+        drop(snapshot);
+        assert_eq!(files(), 0);
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_sstables_open_lazily() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            max_open_sstables: 4,
+            ..Default::default()
+        };
+        let key = SeriesKey::new("cpu");
+
+        {
+            let db = Database::open("testdb", &config).unwrap();
+            for batch in 0..20 {
+                let points: Vec<Point> = (0..10)
+                    .map(|i| {
+                        let ts = batch * 10 + i;
+                        Point::new(key.clone(), DataPoint::new(ts, "value", FieldValue::Float(ts as f64)))
+                    })
+                    .collect();
+                db.write(&points).unwrap();
+                db.flush().unwrap();
+            }
+            assert_eq!(db.sstables().len(), 20);
+        }
+
+        // Startup reads metadata only
+        let db = Database::open("testdb", &config).unwrap();
+        assert_eq!(db.sstables().len(), 20);
+        assert_eq!(db.stats().total_entries, 200);
+        assert_eq!(db.table_cache.open_count(), 0);
+
+        // Queries open readers on demand, never holding more than the limit
+        let results = db.query_series(&key, &TimeRange::new(0, 200)).unwrap();
+        assert_eq!(results.len(), 200);
+        assert_eq!(db.table_cache.open_count(), 4);
+
+        let results = db.query_series(&key, &TimeRange::new(50, 59)).unwrap();
+        assert_eq!(results.len(), 10);
+        assert_eq!(db.get_latest(&key).unwrap().unwrap().timestamp, 199);
+        assert!(db.table_cache.open_count() <= 4);
+    }
+
+    #[test]
+    fn test_sstable_key_ranges_survive_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let first = SeriesKey::new("cpu").with_tag("host", "a b");
+        let last = SeriesKey::new("mem").with_tag("host", "z");
+        let written = {
+            let db = Database::open("testdb", &config).unwrap();
+            let points: Vec<Point> = [&last, &first, &SeriesKey::new("disk")]
+                .iter()
+                .map(|key| Point::new((*key).clone(), DataPoint::new(1, "value", FieldValue::Float(1.0))))
+                .collect();
+            db.write(&points).unwrap();
+            db.flush().unwrap();
+            db.sstables()
+        };
+        assert_eq!((&written[0].min_key, &written[0].max_key), (&first, &last));
+
+        // Reopening reads the range from the index without opening readers
+        let db = Database::open("testdb", &config).unwrap();
+        let tables = db.sstables();
+        assert_eq!((&tables[0].min_key, &tables[0].max_key), (&first, &last));
+        assert!(!tables[0].may_contain_series(&SeriesKey::new("net")));
+        assert_eq!(db.table_cache.open_count(), 0);
+    }
+
+    #[test]
+    fn test_flush_splits_sstables_at_target_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        config.sstable.target_file_size = 8 * 1024;
+        let db = Database::open("testdb", &config).unwrap();
+
+        let points: Vec<Point> = (0..50)
+            .flat_map(|s| {
+                let key = SeriesKey::new("cpu").with_tag("host", format!("host{:02}", s));
+                (0..200).map(move |ts| {
+                    let value = FieldValue::Float((ts * 7919 % 1000) as f64 / 3.0);
+                    Point::new(key.clone(), DataPoint::new(ts, "value", value))
+                })
+            })
+            .collect();
+        db.write(&points).unwrap();
+        db.flush().unwrap();
+
+        let mut sstables = db.sstables();
+        assert!(sstables.len() > 1, "expected several SSTables, got {}", sstables.len());
+        sstables.sort_by(|a, b| a.min_key.cmp(&b.min_key));
+        for meta in sstables.iter() {
+            // Files split between series, so each holds whole series and
+            // stays within a series (plus index and bloom filter) of the target
+            assert_eq!(meta.entry_count % 200, 0);
+            assert!(meta.file_size < 2 * config.sstable.target_file_size, "{} bytes", meta.file_size);
+        }
+        for pair in sstables.windows(2) {
+            assert!(pair[0].max_key < pair[1].min_key);
+        }
+        assert_eq!(sstables.iter().map(|m| m.entry_count).sum::<usize>(), 50 * 200);
+
+        let key = SeriesKey::new("cpu").with_tag("host", "host37");
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 200)).unwrap().len(), 200);
+    }
+
+    /// Run `f` with tracing output captured as plain text
+    #[test]
+    fn test_rename_measurement() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let points = |measurement: &str, start: i64| -> Vec<Point> {
+            (start..start + 10)
+                .map(|ts| Point::new(SeriesKey::new(measurement), DataPoint::new(ts, "value", FieldValue::Float(ts as f64))))
+                .collect()
+        };
+        let count = |db: &Database, measurement: &str| -> QueryValue {
+            let result = db.query(&format!("SELECT count(value) FROM {}", measurement)).unwrap();
+            result.rows.first().map(|r| r.values[0].clone()).unwrap_or(QueryValue::Null)
+        };
+
+        {
+            let db = Database::open("testdb", &config).unwrap();
+            db.write(&points("temp", 0)).unwrap();
+            db.flush().unwrap();
+            db.write(&points("humidity", 0)).unwrap();
+            // Still in the MemTable when renamed
+            db.write(&points("temp", 10)).unwrap();
+
+            assert_eq!(db.rename_measurement("temp", "temperature").unwrap(), 20);
+            assert_eq!(count(&db, "temperature"), QueryValue::Integer(20));
+            let err = db.query("SELECT count(value) FROM temp").unwrap_err();
+            assert!(matches!(err, FluxError::MeasurementNotFound(ref name) if name == "temp"), "{}", err);
+            assert_eq!(count(&db, "humidity"), QueryValue::Integer(10));
+
+            // Nothing left to rename
+            assert_eq!(db.rename_measurement("temp", "temperature").unwrap(), 0);
+        }
+
+        // Replaying the WAL must not bring the old name back
+        let db = Database::open("testdb", &config).unwrap();
+        let series: Vec<String> = db.memtable.read().series_keys().iter().map(|k| k.encode()).collect();
+        assert!(!series.contains(&"temp".to_string()), "{:?}", series);
+        assert_eq!(
+            db.query("SELECT count(value) FROM temperature WHERE time >= 15").unwrap().rows[0].values[0],
+            QueryValue::Integer(5)
+        );
+    }
+
+    #[test]
+    fn test_rename_during_concurrent_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Arc::new(Database::open("testdb", &config).unwrap());
+        let total = 2_000;
+
+        let writer = {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                for ts in 0..total {
+                    let point = DataPoint::new(ts, "value", FieldValue::Float(ts as f64));
+                    db.write(&[Point::new(SeriesKey::new("cpu"), point)]).unwrap();
+                }
+            })
+        };
+        while db.query_series(&SeriesKey::new("cpu"), &TimeRange::new(0, total)).unwrap().len() < 200 {
+            std::thread::yield_now();
+        }
+        let renamed = db.rename_measurement("cpu", "cpu_old").unwrap();
+        writer.join().unwrap();
+
+        // Each point is under exactly one name: every write before the
+        // rename was moved, none after it was
+        let timestamps = |measurement: &str| -> Vec<Timestamp> {
+            db.query_series(&SeriesKey::new(measurement), &TimeRange::new(0, total))
+                .unwrap()
+                .iter()
+                .map(|p| p.timestamp)
+                .collect()
+        };
+        let (old, new) = (timestamps("cpu_old"), timestamps("cpu"));
+        assert_eq!(old.len(), renamed);
+        assert!(renamed >= 200);
+        assert_eq!(old.iter().chain(&new).copied().collect::<Vec<_>>(), (0..total).collect::<Vec<_>>());
+
+        // Only the measurement being changed is locked
+        let cpu = MeasurementLocks::shard("cpu");
+        let other = ["mem", "disk", "net"].into_iter().find(|m| MeasurementLocks::shard(m) != cpu).unwrap();
+        let _locked = db.measurement_locks.exclusive(["cpu"]);
+        db.write(&[Point::new(SeriesKey::new(other), DataPoint::new(0, "value", FieldValue::Float(1.0)))]).unwrap();
+    }
+
+    #[test]
+    fn test_drop_measurement() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let point = |measurement: &str, host: &str, ts: i64| {
+            let key = SeriesKey::new(measurement).with_tag("host", host);
+            Point::new(key, DataPoint::new(ts, "value", FieldValue::Float(ts as f64)))
+        };
+        let count = |db: &Database, measurement: &str, host: &str| -> usize {
+            let key = SeriesKey::new(measurement).with_tag("host", host);
+            db.query_series(&key, &TimeRange::new(0, 10)).unwrap().len()
+        };
+
+        {
+            let db = Database::open("testdb", &config).unwrap();
+            db.write(&[point("temperature", "a", 1), point("temperature", "b", 2), point("cpu", "a", 3)]).unwrap();
+            db.flush().unwrap();
+            // Still in the MemTable when dropped
+            db.write(&[point("temperature", "a", 4), point("temperature", "c", 5)]).unwrap();
+
+            let result = db.query("DROP MEASUREMENT temperature").unwrap();
+            assert_eq!(result.columns, vec!["dropped"]);
+            assert_eq!(result.rows[0].values, vec![QueryValue::Integer(3)]);
+            assert_eq!(result.rows_affected, Some(3));
+
+            for host in ["a", "b", "c"] {
+                assert_eq!(count(&db, "temperature", host), 0);
+            }
+            assert_eq!(count(&db, "cpu", "a"), 1);
+            assert!(db.tag_values("temperature", "host", &TimeRange::new(i64::MIN, i64::MAX)).unwrap().is_empty());
+
+            // Rewritten SSTables leave the dropped series out of their bloom
+            // filters, so reads for them stop before touching the index
+            let sstables = db.sstables();
+            assert!(!sstables.is_empty());
+            for meta in sstables.iter() {
+                let reader = db.table_cache.get(meta).unwrap();
+                for host in ["a", "b"] {
+                    assert!(!reader.may_contain(&SeriesKey::new("temperature").with_tag("host", host)));
+                }
+                assert!(reader.may_contain(&SeriesKey::new("cpu").with_tag("host", "a")));
+            }
+
+            // Nothing left to drop
+            assert_eq!(db.drop_measurement("temperature").unwrap(), 0);
+        }
+
+        // Replaying the WAL must not bring the measurement back
+        let db = Database::open("testdb", &config).unwrap();
+        let series: Vec<String> = db.memtable.read().series_keys().iter().map(|k| k.encode()).collect();
+        assert!(series.iter().all(|k| !k.starts_with("temperature")), "{:?}", series);
+        assert_eq!(count(&db, "temperature", "a"), 0);
+        assert_eq!(count(&db, "cpu", "a"), 1);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            strict_ordering: true,
+            ..Default::default()
+        };
+        let db_dir = temp_dir.path().join("testdb");
+        let cpu = SeriesKey::new("cpu").with_tag("host", "a");
+        let point = |ts: i64| Point::new(cpu.clone(), DataPoint::new(ts, "usage", FieldValue::Float(ts as f64)));
+        let sstable_files = || -> usize {
+            std::fs::read_dir(&db_dir)
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "flux"))
+                .count()
+        };
+        // The measurement is gone along with its points
+        let count = |db: &Database| match db.query("SELECT usage FROM cpu") {
+            Err(FluxError::MeasurementNotFound(_)) => 0,
+            result => result.unwrap().rows.len(),
+        };
+
+        {
+            let db = Database::open("testdb", &config).unwrap();
+            db.write(&[point(1000), point(2000)]).unwrap();
+            db.flush().unwrap();
+            db.write(&[point(3000)]).unwrap();
+            assert_eq!(count(&db), 3);
+
+            db.truncate().unwrap();
+            assert_eq!(count(&db), 0);
+            assert!(db.sstables().is_empty());
+            assert_eq!(sstable_files(), 0);
+            assert!(!db_dir.join(TRUNCATE_FILE).exists());
+
+            // Still usable, and earlier timestamps are no longer out of order
+            db.write(&[point(500)]).unwrap();
+            assert_eq!(count(&db), 1);
+        }
+
+        // Nothing from before the truncation is replayed
+        let db = Database::open("testdb", &config).unwrap();
+        assert_eq!(db.query("SELECT usage FROM cpu").unwrap().rows[0].time, Some(500));
+        assert_eq!(count(&db), 1);
+
+        // A truncation interrupted after its marker is finished on open
+        db.flush().unwrap();
+        db.write(&[point(600)]).unwrap();
+        let marker = format!("{} {}", db.flusher.next_sstable_id(), db.wal.seal().unwrap());
+        std::fs::write(db_dir.join(TRUNCATE_FILE), marker).unwrap();
+        drop(db);
+        assert_eq!(sstable_files(), 1);
+        let db = Database::open("testdb", &config).unwrap();
+        assert_eq!(count(&db), 0);
+        assert_eq!(sstable_files(), 0);
+        assert!(!db_dir.join(TRUNCATE_FILE).exists());
+    }
+
+    #[test]
+    fn test_relabel_tag() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let db = Database::open("testdb", &config).unwrap();
+        let point = |tags: &[(&str, &str)], ts: i64| {
+            let key = tags.iter().fold(SeriesKey::new("cpu"), |key, (k, v)| key.with_tag(*k, *v));
+            Point::new(key, DataPoint::new(ts, "usage", FieldValue::Float(1.0)))
+        };
+        db.write(&[point(&[("hst", "a")], 1), point(&[("hst", "b")], 2), point(&[("dc", "x")], 3)]).unwrap();
+
+        assert_eq!(db.relabel_tag("cpu", "hst", "host").unwrap(), 2);
+        let mut keys: Vec<String> = db
+            .compression_stats()
+            .unwrap()
+            .iter()
+            .map(|s| s.series_key.encode())
+            .collect();
+        keys.sort();
+        assert_eq!(keys, vec!["cpu,dc=x", "cpu,host=a", "cpu,host=b"]);
+        let moved = db.query_series(&SeriesKey::new("cpu").with_tag("host", "b"), &TimeRange::new(0, 10)).unwrap();
+        assert_eq!(moved.len(), 1);
+
+        // A clash with an existing tag changes nothing
+        db.write(&[point(&[("host", "c"), ("dc", "y")], 4)]).unwrap();
+        let before = db.sstables();
+        assert!(db.relabel_tag("cpu", "dc", "host").is_err());
+        assert_eq!(db.sstables().len(), before.len() + 1);
+        assert!(db.sstables().iter().all(|m| m.path.exists()));
+    }
+
+    fn capture_logs(f: impl FnOnce()) -> String {
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+
+        let logs = buffer.0.lock();
+        String::from_utf8_lossy(&logs).into_owned()
+    }
+
+    #[test]
+    fn test_slow_query_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let run = |threshold: Option<u64>| {
+            let config = StorageConfig {
+                data_dir: temp_dir.path().to_path_buf(),
+                slow_query_threshold_ms: threshold,
+                ..Default::default()
+            };
+            capture_logs(|| {
+                let db = Database::open("testdb", &config).unwrap();
+                let key = SeriesKey::new("cpu").with_tag("host", "a");
+                let points: Vec<Point> = (0..1000)
+                    .map(|i| Point::new(key.clone(), DataPoint::new(i, "usage", FieldValue::Float(i as f64))))
+                    .collect();
+                db.write(&points).unwrap();
+                db.query("SELECT mean(usage) FROM cpu WHERE time >= 0 AND host = 'a' GROUP BY time('100ns')")
+                    .unwrap();
+            })
+        };
+
+        // With a zero threshold every query is slow
+        let logs = run(Some(0));
+        assert!(logs.contains("WARN"));
+        assert!(logs.contains("slow query"));
+        assert!(logs.contains("sql=\"SELECT mean(usage) FROM cpu"));
+        assert!(logs.contains("plan=scan cpu select=mean(usage) time="));
+        assert!(logs.contains("group_by=time(100ns)"));
+        assert!(logs.contains("rows=10"));
+        assert!(logs.contains("execution_time_ms="));
+
+        assert!(!run(Some(60_000)).contains("slow query"));
+        assert!(!run(None).contains("slow query"));
+    }
+}
@@ -1,10 +1,14 @@
 //! Storage engine - top-level coordinator
 
-use super::{Database, StorageConfig};
-use crate::{Point, Result, FluxError};
-use crate::query::QueryResult;
-use parking_lot::RwLock;
+use super::idempotency::{Claim, IdempotencyKeys};
+use super::{Database, StorageConfig, WriteConsistency};
+use crate::{Point, Result, FluxError, Timestamp};
+use crate::query::{Cursor, QueryParser, QueryResult};
+use crate::sstable::BlockCache;
+use crate::wal::{WalConfig, WalEntry, WalWriter};
+use parking_lot::{Mutex, RwLock};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tracing::info;
 
@@ -12,6 +16,9 @@ use tracing::info;
 pub struct StorageEngine {
     config: StorageConfig,
     databases: RwLock<HashMap<String, Arc<Database>>>,
+    idempotency: Mutex<IdempotencyKeys>,
+    /// SSTable blocks cached for every database, within one budget
+    block_cache: Arc<BlockCache>,
 }
 
 impl StorageEngine {
@@ -20,6 +27,8 @@ impl StorageEngine {
         std::fs::create_dir_all(&config.data_dir)?;
         
         let engine = Self {
+            idempotency: Mutex::new(IdempotencyKeys::new(config.idempotency_keys, config.idempotency_ttl_ms)),
+            block_cache: Arc::new(BlockCache::new(config.block_cache_size)),
             config,
             databases: RwLock::new(HashMap::new()),
         };
@@ -32,19 +41,18 @@ impl StorageEngine {
 
     /// Create a new database
     pub fn create_database(&self, name: &str) -> Result<Arc<Database>> {
+        self.check_writable()?;
+        self.open_new_database(name)
+    }
+
+    fn open_new_database(&self, name: &str) -> Result<Arc<Database>> {
         let mut databases = self.databases.write();
         
         if databases.contains_key(name) {
             return Err(FluxError::Config(format!("Database {} already exists", name)));
         }
         
-        let db = Database::open(
-            name,
-            self.config.data_dir.clone(),
-            self.config.wal.clone(),
-            self.config.sstable.clone(),
-            self.config.memtable_size_limit,
-        )?;
+        let db = Database::open_with_block_cache(name, &self.config, self.block_cache.clone())?;
         
         let db = Arc::new(db);
         databases.insert(name.to_string(), db.clone());
@@ -54,6 +62,49 @@ impl StorageEngine {
         Ok(db)
     }
 
+    /// Create a database (if absent) and write its initial points atomically
+    ///
+    /// A new database is staged in a hidden directory whose WAL holds a
+    /// CreateDatabase entry followed by the Write, then renamed into place
+    /// and opened, replaying both. A crash before the rename leaves only the
+    /// staging directory, which is discarded on startup, so recovery sees
+    /// either the database with its points or nothing.
+    pub fn create_and_write(&self, name: &str, points: &[Point]) -> Result<Arc<Database>> {
+        self.check_writable()?;
+        let mut databases = self.databases.write();
+        
+        if let Some(db) = databases.get(name).cloned() {
+            drop(databases);
+            db.write(points)?;
+            return Ok(db);
+        }
+        self.config.check_names(points)?;
+        
+        let staging_dir = self.staging_dir(name);
+        if staging_dir.exists() {
+            std::fs::remove_dir_all(&staging_dir)?;
+        }
+        
+        {
+            let wal = WalWriter::new(WalConfig {
+                dir: staging_dir.join("wal"),
+                ..self.config.wal.clone()
+            })?;
+            wal.append(&WalEntry::create_database(name))?;
+            wal.append(&WalEntry::write(name, points)?)?;
+            wal.sync()?;
+        }
+        
+        std::fs::rename(&staging_dir, self.config.data_dir.join(name))?;
+        
+        let db = Arc::new(Database::open_with_block_cache(name, &self.config, self.block_cache.clone())?);
+        databases.insert(name.to_string(), db.clone());
+        
+        info!("Created database {} with {} points", name, points.len());
+        
+        Ok(db)
+    }
+
     /// Get or create a database
     pub fn get_or_create_database(&self, name: &str) -> Result<Arc<Database>> {
         // Check if exists
@@ -75,14 +126,17 @@ impl StorageEngine {
 
     /// Drop a database
     pub fn drop_database(&self, name: &str) -> Result<()> {
+        self.check_writable()?;
         let mut databases = self.databases.write();
         
         if databases.remove(name).is_none() {
             return Err(FluxError::DatabaseNotFound(name.to_string()));
         }
         
-        // Remove data directory
+        // Remove data directory, and the cached blocks of its SSTables so a
+        // database recreated under the same name doesn't read them
         let db_path = self.config.data_dir.join(name);
+        self.block_cache.forget_dir(&db_path);
         if db_path.exists() {
             std::fs::remove_dir_all(&db_path)?;
         }
@@ -97,17 +151,161 @@ impl StorageEngine {
         self.databases.read().keys().cloned().collect()
     }
 
-    /// Write points to a database
-    pub fn write(&self, database: &str, points: &[Point]) -> Result<()> {
-        let db = self.get_or_create_database(database)?;
-        db.write(points)
+    /// Current time according to the configured clock
+    pub fn now(&self) -> Timestamp {
+        self.config.clock.now()
+    }
+
+    /// The configuration the engine was created with
+    pub fn config(&self) -> &StorageConfig {
+        &self.config
+    }
+
+    /// Write points to a database, returning how many were written; see
+    /// [`Database::write`]
+    ///
+    /// A missing database is created unless `auto_create_databases` is off.
+    pub fn write(&self, database: &str, points: &[Point]) -> Result<usize> {
+        self.write_with(database, points, WriteConsistency::default())
+    }
+
+    /// Write points to a database, returning once they are as durable as
+    /// `consistency` asks; see [`Database::write_with`]
+    pub fn write_with(&self, database: &str, points: &[Point], consistency: WriteConsistency) -> Result<usize> {
+        self.write_target(database)?.write_with(points, consistency)
+    }
+
+    /// Record the order a write about to be made to a database lists its
+    /// fields in; see [`Database::record_field_order`]
+    pub fn record_field_order<'a>(
+        &self,
+        database: &str,
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<()> {
+        self.write_target(database)?.record_field_order(fields)
+    }
+
+    /// The database a write goes to, created if missing unless
+    /// `auto_create_databases` is off
+    fn write_target(&self, database: &str) -> Result<Arc<Database>> {
+        if self.config.auto_create_databases {
+            self.get_or_create_database(database)
+        } else {
+            self.get_database(database)
+                .ok_or_else(|| FluxError::DatabaseNotFound(database.to_string()))
+        }
+    }
+
+    /// Make every check a write of points to a database would, without
+    /// writing them; see [`Database::check_write`]
+    ///
+    /// A missing database isn't created, so a write to one is only checked
+    /// for names and duplicates, not for ordering or cardinality within
+    /// the batch.
+    pub fn check_write(&self, database: &str, points: &[Point]) -> Result<usize> {
+        match self.get_database(database) {
+            Some(db) => db.check_write(points),
+            None if self.config.auto_create_databases => {
+                self.check_writable()?;
+                self.config.check_names(points)?;
+                self.config.check_timestamps(points)?;
+                Ok(self.config.duplicate_points.dedup(points).len())
+            }
+            None => Err(FluxError::DatabaseNotFound(database.to_string())),
+        }
+    }
+
+    /// Write points unless a write with the same idempotency key was
+    /// already applied to this database; returns how many points were
+    /// written, or `None` for a repeat
+    ///
+    /// Keys are kept in memory for `idempotency_ttl_ms`, up to
+    /// `idempotency_keys` of them, so a retry after a restart, after the
+    /// TTL or after the key was evicted is applied again. A key only counts
+    /// as applied once its write succeeded; a retry arriving while the
+    /// first attempt is still running fails with `WriteInProgress`, and
+    /// one after that attempt failed is applied.
+    pub fn write_idempotent(
+        &self,
+        database: &str,
+        key: &str,
+        points: &[Point],
+        consistency: WriteConsistency,
+    ) -> Result<Option<usize>> {
+        match self.idempotency.lock().claim(database, key, self.now()) {
+            Claim::Claimed => {}
+            Claim::Applied => return Ok(None),
+            Claim::InFlight => {
+                return Err(FluxError::WriteInProgress(format!(
+                    "a write with idempotency key {:?} is still running",
+                    key
+                )))
+            }
+        }
+        let result = self.write_with(database, points, consistency);
+        let mut keys = self.idempotency.lock();
+        match result {
+            Ok(_) => keys.complete(database, key, self.now()),
+            Err(_) => keys.release(database, key),
+        }
+        result.map(Some)
+    }
+
+    /// Apply a WAL segment replicated from the primary, creating the
+    /// database if needed
+    ///
+    /// This is the only way data enters a read-only engine.
+    pub fn apply_replicated(&self, database: &str, segment_id: u64, data: &[u8]) -> Result<usize> {
+        let db = match self.get_database(database) {
+            Some(db) => db,
+            None => self.open_new_database(database)?,
+        };
+        db.apply_replicated_segment(segment_id, data)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.config.read_only {
+            return Err(FluxError::ReadOnly("this instance is a replica".into()));
+        }
+        Ok(())
     }
 
     /// Execute a query
+    ///
+    /// `SELECT ... INTO db.measurement` writes into another database,
+    /// creating it if it doesn't exist yet.
     pub fn query(&self, database: &str, sql: &str) -> Result<QueryResult> {
         let db = self.get_database(database)
             .ok_or_else(|| FluxError::DatabaseNotFound(database.to_string()))?;
-        db.query(sql)
+        if let Some(drop) = QueryParser::parse_drop_measurement(sql)? {
+            return db.execute_drop_measurement(&drop);
+        }
+        if let Some(statement) = QueryParser::parse_explain(sql) {
+            return db.explain(statement);
+        }
+        let query = QueryParser::parse(sql)?;
+
+        match query.into.as_ref().and_then(|into| into.database.as_deref()) {
+            Some(target) if target != database => {
+                let target = self.get_or_create_database(target)?;
+                db.select_into(sql, &query, &target)
+            }
+            _ => db.execute(sql, &query),
+        }
+    }
+
+    /// Run a plain `SELECT` against a database one page at a time; see
+    /// [`Database::query_page`]
+    pub fn query_page(
+        &self,
+        database: &str,
+        sql: &str,
+        after: Option<Cursor>,
+        page_size: usize,
+    ) -> Result<(QueryResult, Option<Cursor>)> {
+        let db = self.get_database(database)
+            .ok_or_else(|| FluxError::DatabaseNotFound(database.to_string()))?;
+        db.query_page(sql, after, page_size)
     }
 
     /// Flush all databases
@@ -142,18 +340,16 @@ impl StorageEngine {
             if entry.file_type()?.is_dir() {
                 let name = entry.file_name().to_string_lossy().to_string();
                 
-                // Skip hidden directories
+                // Skip hidden directories, discarding interrupted creations
                 if name.starts_with('.') {
+                    if name.ends_with(STAGING_SUFFIX) {
+                        tracing::warn!("Discarding incomplete database creation: {}", name);
+                        std::fs::remove_dir_all(entry.path())?;
+                    }
                     continue;
                 }
                 
-                match Database::open(
-                    &name,
-                    self.config.data_dir.clone(),
-                    self.config.wal.clone(),
-                    self.config.sstable.clone(),
-                    self.config.memtable_size_limit,
-                ) {
+                match Database::open_with_block_cache(&name, &self.config, self.block_cache.clone()) {
                     Ok(db) => {
                         let mut databases = self.databases.write();
                         databases.insert(name.clone(), Arc::new(db));
@@ -168,8 +364,15 @@ impl StorageEngine {
         
         Ok(())
     }
+
+    fn staging_dir(&self, name: &str) -> PathBuf {
+        self.config.data_dir.join(format!(".{}{}", name, STAGING_SUFFIX))
+    }
 }
 
+/// Suffix of the hidden directory a database is built in by `create_and_write`
+const STAGING_SUFFIX: &str = ".creating";
+
 /// Storage engine statistics
 #[derive(Debug, Clone)]
 pub struct EngineStats {
@@ -182,7 +385,8 @@ pub struct EngineStats {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{DataPoint, FieldValue, SeriesKey};
+    use crate::storage::DataLayout;
+    use crate::{DataPoint, FieldValue, SeriesKey, TimeRange};
     use tempfile::TempDir;
 
     #[test]
@@ -214,4 +418,367 @@ mod tests {
         let result = engine.query("testdb", "SELECT * FROM temperature").unwrap();
         assert!(!result.rows.is_empty());
     }
+
+    #[test]
+    fn test_drop_and_recreate_database() {
+        let temp_dir = TempDir::new().unwrap();
+        let engine = StorageEngine::new(StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .unwrap();
+        let key = SeriesKey::new("cpu").with_tag("host", "a");
+        let write = |value: f64| {
+            let db = engine.create_database("d").unwrap();
+            engine.write("d", &[Point::new(key.clone(), DataPoint::new(1, "usage", FieldValue::Float(value)))]).unwrap();
+            db.flush().unwrap();
+            engine.query("d", "SELECT usage FROM cpu").unwrap()
+        };
+
+        let result = write(1.0);
+        assert_eq!(result.rows[0].values[0], crate::query::QueryValue::Float(1.0));
+        engine.drop_database("d").unwrap();
+
+        // The new database's SSTable has the same path as the dropped one's
+        let result = write(2.0);
+        assert_eq!(result.rows[0].values[0], crate::query::QueryValue::Float(2.0));
+    }
+
+    #[test]
+    fn test_write_auto_creates_databases() {
+        let temp_dir = TempDir::new().unwrap();
+        let points = vec![Point::new(SeriesKey::new("cpu"), DataPoint::new(1, "value", FieldValue::Float(1.0)))];
+
+        let engine = StorageEngine::new(StorageConfig {
+            data_dir: temp_dir.path().join("lenient"),
+            ..Default::default()
+        })
+        .unwrap();
+        engine.write("typo", &points).unwrap();
+        assert_eq!(engine.list_databases(), vec!["typo".to_string()]);
+
+        let data_dir = temp_dir.path().join("strict");
+        let engine = StorageEngine::new(StorageConfig {
+            data_dir: data_dir.clone(),
+            auto_create_databases: false,
+            ..Default::default()
+        })
+        .unwrap();
+        let err = engine.write("typo", &points).unwrap_err();
+        assert!(matches!(err, FluxError::DatabaseNotFound(ref name) if name == "typo"), "{}", err);
+        assert!(engine.list_databases().is_empty());
+        assert!(!data_dir.join("typo").exists());
+
+        // Databases created explicitly still take writes
+        engine.create_database("metrics").unwrap();
+        engine.write("metrics", &points).unwrap();
+        let db = engine.get_database("metrics").unwrap();
+        assert_eq!(db.query_series(&SeriesKey::new("cpu"), &TimeRange::new(0, 10)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_sharded_layout() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            layout: DataLayout::Sharded { shards: 4 },
+            ..Default::default()
+        };
+
+        let points: Vec<Point> = (0..50)
+            .map(|i| {
+                let key = SeriesKey::new(format!("m{}", i));
+                Point::new(key, DataPoint::new(1000, "value", FieldValue::Float(i as f64)))
+            })
+            .collect();
+
+        let sstable_count = {
+            let engine = StorageEngine::new(config.clone()).unwrap();
+            engine.write("testdb", &points).unwrap();
+            engine.flush_all().unwrap();
+            engine.get_database("testdb").unwrap().sstables().len()
+        };
+        assert!(sstable_count > 1);
+
+        // Every SSTable lives in a shard directory, none at the top level
+        let db_dir = temp_dir.path().join("testdb");
+        let mut sharded_files = 0;
+        for entry in std::fs::read_dir(&db_dir).unwrap() {
+            let path = entry.unwrap().path();
+            assert_ne!(path.extension().and_then(|e| e.to_str()), Some("flux"));
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if name.starts_with("shard_") {
+                sharded_files += std::fs::read_dir(&path).unwrap().count();
+            }
+        }
+        assert_eq!(sharded_files, sstable_count);
+
+        // Reopen and make sure the shards load back
+        let engine = StorageEngine::new(config).unwrap();
+        let db = engine.get_database("testdb").unwrap();
+        assert_eq!(db.sstables().len(), sstable_count);
+        let latest = db.get_latest(&SeriesKey::new("m7")).unwrap().unwrap();
+        assert_eq!(latest.fields.get("value"), Some(&FieldValue::Float(7.0)));
+    }
+
+    #[test]
+    fn test_create_and_write_recovery() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let key = SeriesKey::new("cpu");
+        let points: Vec<Point> = (0..10)
+            .map(|i| Point::new(key.clone(), DataPoint::new(i, "value", FieldValue::Float(i as f64))))
+            .collect();
+
+        // Crash after the CreateDatabase entry but before the Write
+        {
+            let engine = StorageEngine::new(config.clone()).unwrap();
+            let wal = WalWriter::new(WalConfig {
+                dir: engine.staging_dir("partial").join("wal"),
+                ..config.wal.clone()
+            })
+            .unwrap();
+            wal.append(&WalEntry::create_database("partial")).unwrap();
+            wal.sync().unwrap();
+        }
+
+        {
+            let engine = StorageEngine::new(config.clone()).unwrap();
+            assert!(engine.get_database("partial").is_none());
+            assert!(!engine.staging_dir("partial").exists());
+
+            let db = engine.create_and_write("testdb", &points).unwrap();
+            assert_eq!(db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len(), 10);
+        }
+
+        // Both entries are replayed after a restart without a flush
+        let engine = StorageEngine::new(config).unwrap();
+        assert_eq!(engine.list_databases(), vec!["testdb".to_string()]);
+        let db = engine.get_database("testdb").unwrap();
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len(), 10);
+
+        // An existing database just takes the write
+        let more = vec![Point::new(key.clone(), DataPoint::new(50, "value", FieldValue::Float(50.0)))];
+        engine.create_and_write("testdb", &more).unwrap();
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len(), 11);
+    }
+
+    #[test]
+    fn test_select_into_rollup() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).unwrap();
+
+        // Two sensors, one point a minute for three hours
+        const HOUR: i64 = 3_600_000_000_000;
+        let mut points = Vec::new();
+        for sensor in ["s1", "s2"] {
+            let key = SeriesKey::new("temperature").with_tag("sensor", sensor);
+            for i in 0..180 {
+                let value = if sensor == "s1" { (i / 60) as f64 } else { 100.0 };
+                points.push(Point::new(key.clone(), DataPoint::new(i * 60_000_000_000, "value", FieldValue::Float(value))));
+            }
+        }
+        engine.write("testdb", &points).unwrap();
+
+        let result = engine.query(
+            "testdb",
+            "SELECT mean(value) AS value INTO temperature_1h FROM temperature GROUP BY time('1h'), sensor",
+        ).unwrap();
+        assert_eq!(result.columns, vec!["written"]);
+        assert_eq!(result.rows_affected, Some(6));
+
+        // The rollup is queryable, with tags and timestamps carried over
+        let db = engine.get_database("testdb").unwrap();
+        let rollup = SeriesKey::new("temperature_1h").with_tag("sensor", "s1");
+        let rows = db.query_series(&rollup, &TimeRange::new(0, 3 * HOUR)).unwrap();
+        let rows: Vec<_> = rows.iter().map(|p| (p.timestamp, p.fields.get("value").cloned())).collect();
+        assert_eq!(rows, vec![
+            (0, Some(FieldValue::Float(0.0))),
+            (HOUR, Some(FieldValue::Float(1.0))),
+            (2 * HOUR, Some(FieldValue::Float(2.0))),
+        ]);
+
+        let result = engine.query("testdb", "SELECT count(value) FROM temperature_1h").unwrap();
+        assert_eq!(result.rows[0].values, vec![crate::query::QueryValue::Integer(6)]);
+
+        // INTO another database creates it
+        let result = engine.query(
+            "testdb",
+            "SELECT max(value) INTO rollups.temperature_max FROM temperature GROUP BY sensor",
+        ).unwrap();
+        assert_eq!(result.rows_affected, Some(2));
+        let rollups = engine.get_database("rollups").unwrap();
+        let rows = rollups
+            .query_series(&SeriesKey::new("temperature_max").with_tag("sensor", "s2"), &TimeRange::new(0, 0))
+            .unwrap();
+        assert_eq!(rows[0].fields.get("max_value"), Some(&FieldValue::Float(100.0)));
+
+        // A database can't reach into another on its own
+        assert!(db.query("SELECT max(value) INTO rollups.t FROM temperature").is_err());
+    }
+
+    #[test]
+    fn test_replicate_wal_segments() {
+        let primary_dir = TempDir::new().unwrap();
+        let replica_dir = TempDir::new().unwrap();
+        let primary = StorageEngine::new(StorageConfig {
+            data_dir: primary_dir.path().to_path_buf(),
+            ..Default::default()
+        }).unwrap();
+        let replica_config = StorageConfig {
+            data_dir: replica_dir.path().to_path_buf(),
+            read_only: true,
+            ..Default::default()
+        };
+        let replica = StorageEngine::new(replica_config.clone()).unwrap();
+
+        let key = SeriesKey::new("cpu");
+        let batch = |start: i64| -> Vec<Point> {
+            (start..start + 10)
+                .map(|i| Point::new(key.clone(), DataPoint::new(i, "value", FieldValue::Float(i as f64))))
+                .collect()
+        };
+        let sync = |replica: &StorageEngine| {
+            let db = primary.get_database("testdb").unwrap();
+            loop {
+                let after = replica.get_database("testdb").and_then(|r| r.replica_position().unwrap());
+                match db.wal_segment_after(after).unwrap() {
+                    Some((id, data)) => replica.apply_replicated("testdb", id, &data).unwrap(),
+                    None => break,
+                };
+            }
+        };
+
+        primary.write("testdb", &batch(0)).unwrap();
+        sync(&replica);
+        let db = replica.get_database("testdb").unwrap();
+        assert_eq!(db.replica_position().unwrap(), Some(0));
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len(), 10);
+
+        // Only new segments are shipped
+        primary.write("testdb", &batch(10)).unwrap();
+        sync(&replica);
+        assert_eq!(db.replica_position().unwrap(), Some(1));
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len(), 20);
+
+        // Renames reach the replica too, ordered with the writes around them
+        let primary_db = primary.get_database("testdb").unwrap();
+        primary_db.rename_measurement("cpu", "processor").unwrap();
+        primary.write("testdb", &batch(20)).unwrap();
+        sync(&replica);
+        let renamed = SeriesKey::new("processor");
+        assert_eq!(db.query_series(&renamed, &TimeRange::new(0, 100)).unwrap().len(), 20);
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len(), 10);
+        let position = db.replica_position().unwrap();
+
+        // Replicas reject direct writes and schema changes
+        assert!(matches!(replica.write("testdb", &batch(30)), Err(FluxError::ReadOnly(_))));
+        assert!(matches!(db.rename_measurement("processor", "cpu"), Err(FluxError::ReadOnly(_))));
+        assert!(matches!(replica.create_database("other"), Err(FluxError::ReadOnly(_))));
+        assert!(matches!(replica.drop_database("testdb"), Err(FluxError::ReadOnly(_))));
+
+        // The position and data survive a restart
+        drop(db);
+        drop(replica);
+        let replica = StorageEngine::new(replica_config).unwrap();
+        let db = replica.get_database("testdb").unwrap();
+        assert_eq!(db.replica_position().unwrap(), position);
+        assert_eq!(db.query_series(&renamed, &TimeRange::new(0, 100)).unwrap().len(), 20);
+        assert_eq!(db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len(), 10);
+
+        // Segments truncated by the flush the rename started with can't be
+        // shipped
+        assert!(matches!(
+            primary_db.wal_segment_after(None),
+            Err(FluxError::WalSegmentUnavailable(0))
+        ));
+    }
+
+    #[test]
+    fn test_idempotent_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let clock = Arc::new(crate::MockClock::new(0));
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            clock: clock.clone(),
+            idempotency_keys: 2,
+            idempotency_ttl_ms: 1_000,
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).unwrap();
+        let key = SeriesKey::new("cpu");
+        let at = |ts| vec![Point::new(key.clone(), DataPoint::new(ts, "usage", FieldValue::Float(1.0)))];
+        let count = || {
+            let db = engine.get_database("testdb").unwrap();
+            db.query_series(&key, &TimeRange::new(0, 100)).unwrap().len()
+        };
+
+        // A repeated key is acknowledged without applying its points
+        assert_eq!(engine.write_idempotent("testdb", "batch-1", &at(1), WriteConsistency::default()).unwrap(), Some(1));
+        assert_eq!(engine.write_idempotent("testdb", "batch-1", &at(1), WriteConsistency::default()).unwrap(), None);
+        assert_eq!(engine.write_idempotent("testdb", "batch-1", &at(2), WriteConsistency::default()).unwrap(), None);
+        assert_eq!(count(), 1);
+
+        // Keys are per database
+        assert_eq!(engine.write_idempotent("otherdb", "batch-1", &at(1), WriteConsistency::default()).unwrap(), Some(1));
+
+        // Past capacity the least recently used key is forgotten
+        assert_eq!(engine.write_idempotent("testdb", "batch-2", &at(3), WriteConsistency::default()).unwrap(), Some(1));
+        assert_eq!(engine.write_idempotent("testdb", "batch-1", &at(4), WriteConsistency::default()).unwrap(), Some(1));
+        assert_eq!(count(), 3);
+
+        // As is one older than the TTL
+        assert_eq!(engine.write_idempotent("testdb", "batch-2", &at(5), WriteConsistency::default()).unwrap(), None);
+        clock.advance(std::time::Duration::from_secs(1));
+        assert_eq!(engine.write_idempotent("testdb", "batch-2", &at(5), WriteConsistency::default()).unwrap(), Some(1));
+        assert_eq!(count(), 4);
+    }
+
+    #[test]
+    fn test_idempotent_retry_of_failing_write() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            min_timestamp: Some(0),
+            ..Default::default()
+        };
+        let engine = StorageEngine::new(config).unwrap();
+        let key = SeriesKey::new("cpu");
+        let at = |ts| vec![Point::new(key.clone(), DataPoint::new(ts, "usage", FieldValue::Float(1.0)))];
+        let write = |key: &str, ts| engine.write_idempotent("testdb", key, &at(ts), WriteConsistency::default());
+        engine.write_with("testdb", &at(0), WriteConsistency::default()).unwrap();
+
+        // A retry while the first attempt holds the key is refused
+        assert_eq!(engine.idempotency.lock().claim("testdb", "held", engine.now()), Claim::Claimed);
+        assert!(matches!(write("held", 1), Err(FluxError::WriteInProgress(_))));
+        engine.idempotency.lock().release("testdb", "held");
+        assert_eq!(write("held", 1).unwrap(), Some(1));
+
+        // A first attempt that fails never turns its concurrent retry into
+        // an acknowledged no-op, and leaves the key free unless the retry
+        // was applied
+        for i in 0..100 {
+            let batch = format!("batch-{}", i);
+            let (first, retry) = std::thread::scope(|s| {
+                let first = s.spawn(|| write(&batch, -1));
+                let retry = s.spawn(|| write(&batch, 1));
+                (first.join().unwrap(), retry.join().unwrap())
+            });
+            assert!(
+                matches!(first, Err(FluxError::InvalidTimestamp(_) | FluxError::WriteInProgress(_)) | Ok(None)),
+                "{:?}",
+                first
+            );
+            assert!(!matches!(retry, Ok(None)), "retry of a failed write was not applied");
+            let applied = matches!(retry, Ok(Some(1)));
+            assert_eq!(write(&batch, 1).unwrap(), if applied { None } else { Some(1) });
+        }
+    }
 }
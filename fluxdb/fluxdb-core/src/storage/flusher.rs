@@ -0,0 +1,159 @@
+//! Flusher - turns immutable MemTables into L0 SSTables
+
+use super::sstable_set::SSTableSet;
+use super::StorageConfig;
+use crate::memtable::ImmutableMemTable;
+use crate::sstable::{SSTableMeta, SplittingBuilder};
+use crate::wal::WalWriter;
+use crate::{DataPoint, FluxError, Result, SeriesKey};
+use arc_swap::ArcSwap;
+use parking_lot::{Condvar, Mutex};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Builds SSTables from a database's immutable MemTables
+///
+/// Shared between the database and its background flush thread. Immutable
+/// MemTables stay visible to reads until their SSTables have been published.
+pub(super) struct Flusher {
+    data_dir: PathBuf,
+    config: StorageConfig,
+    wal: Arc<WalWriter>,
+    immutables: Arc<Mutex<Vec<Arc<ImmutableMemTable>>>>,
+    /// Signalled whenever an immutable MemTable is retired
+    drained: Condvar,
+    sstables: Arc<ArcSwap<SSTableSet>>,
+    next_sstable_id: AtomicU64,
+    /// Serializes flushes so each immutable MemTable is built exactly once
+    pub(super) flush_lock: Mutex<()>,
+}
+
+impl Flusher {
+    pub(super) fn new(
+        data_dir: PathBuf,
+        config: StorageConfig,
+        wal: Arc<WalWriter>,
+        immutables: Arc<Mutex<Vec<Arc<ImmutableMemTable>>>>,
+        sstables: Arc<ArcSwap<SSTableSet>>,
+        next_sstable_id: u64,
+    ) -> Self {
+        Self {
+            data_dir,
+            config,
+            wal,
+            immutables,
+            drained: Condvar::new(),
+            sstables,
+            next_sstable_id: AtomicU64::new(next_sstable_id),
+            flush_lock: Mutex::new(()),
+        }
+    }
+
+    /// Block until fewer than `max` immutable MemTables are pending
+    ///
+    /// Fails with a retryable error if the flusher hasn't made room within
+    /// the write stall timeout.
+    pub(super) fn wait_for_room(&self, max: usize) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_millis(crate::config::WRITE_STALL_TIMEOUT_MS);
+        let mut immutables = self.immutables.lock();
+        while immutables.len() >= max.max(1) {
+            if self.drained.wait_until(&mut immutables, deadline).timed_out() {
+                return Err(FluxError::Internal(format!(
+                    "write stalled: {} immutable MemTables awaiting flush, retry later",
+                    immutables.len()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// ID the next SSTable written gets; every existing one has a lower ID
+    pub(super) fn next_sstable_id(&self) -> u64 {
+        self.next_sstable_id.load(Ordering::SeqCst)
+    }
+
+    /// Drop every pending immutable MemTable without flushing it; callers
+    /// hold the flush lock
+    pub(super) fn discard_pending(&self) {
+        self.immutables.lock().clear();
+        self.drained.notify_all();
+    }
+
+    /// Flush every pending immutable MemTable, oldest first
+    pub(super) fn flush_pending(&self) -> Result<()> {
+        while self.flush_oldest()? {}
+        Ok(())
+    }
+
+    /// Flush the oldest immutable MemTable; returns false if none were pending
+    fn flush_oldest(&self) -> Result<bool> {
+        let _guard = self.flush_lock.lock();
+
+        let imm = match self.immutables.lock().first() {
+            Some(imm) => imm.clone(),
+            None => return Ok(false),
+        };
+
+        let points = imm.iter().into_iter().map(|(key, data)| (key.series_key, data));
+        let metas = self.write_sstables(0, points)?; // L0
+        for meta in &metas {
+            info!("Flushed memtable {} to SSTable {}", imm.id(), meta.id);
+        }
+
+        // Publish the SSTables before retiring the immutable MemTable
+        self.sstables.rcu(|tables| SSTableSet::new(tables.iter().chain(&metas).cloned().collect()));
+        self.immutables.lock().retain(|other| !Arc::ptr_eq(other, &imm));
+        self.drained.notify_all();
+
+        // Older MemTables were flushed first, so the segments before this
+        // one's hold nothing unflushed
+        if let Err(e) = self.wal.truncate_before(imm.wal_segment()) {
+            warn!("Failed to truncate WAL after flushing memtable {}: {}", imm.id(), e);
+        }
+
+        Ok(true)
+    }
+
+    /// Write points, sorted by series key and timestamp, to new SSTables at
+    /// `level` without publishing them
+    pub(super) fn write_sstables(
+        &self,
+        level: u32,
+        points: impl IntoIterator<Item = (SeriesKey, DataPoint)>,
+    ) -> Result<Vec<SSTableMeta>> {
+        // Route each series to the SSTables for its layout directory
+        let next_sstable_id = &self.next_sstable_id;
+        let new_file = |dir: &PathBuf| {
+            let dir = dir.clone();
+            move || (dir.clone(), next_sstable_id.fetch_add(1, Ordering::SeqCst))
+        };
+        let mut builders = BTreeMap::new();
+        for (key, data) in points {
+            let dir = self.config.layout.sstable_dir(&self.data_dir, &key.measurement);
+            let builder = match builders.entry(dir) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    std::fs::create_dir_all(entry.key())?;
+                    let next_file = new_file(entry.key());
+                    entry.insert(SplittingBuilder::new(
+                        level,
+                        self.config.sstable.clone(),
+                        next_file,
+                    ))
+                }
+            };
+            builder.add(&key, &data)?;
+        }
+
+        let mut metas = Vec::new();
+        for builder in builders.into_values() {
+            metas.extend(builder.finish()?);
+        }
+        Ok(metas)
+    }
+}
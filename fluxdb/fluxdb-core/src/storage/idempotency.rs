@@ -0,0 +1,111 @@
+//! Recently applied idempotency keys
+//!
+//! Writes may carry a client-chosen key so a retry after an ambiguous
+//! failure isn't applied twice. Keys are remembered per database for a
+//! TTL, in memory only, and the set is bounded: past capacity the least
+//! recently used key is evicted, so a retry arriving after that many newer
+//! keys is applied again.
+//!
+//! A key is claimed while its write runs and only recorded once the write
+//! succeeded, so a retry racing the first attempt can tell it apart from
+//! one that was applied.
+
+use crate::Timestamp;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+type Key = (String, String);
+
+/// What [`IdempotencyKeys::claim`] found for a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Claim {
+    /// Nobody holds the key; the caller writes and then completes or
+    /// releases it
+    Claimed,
+    /// A write with this key was applied within the TTL
+    Applied,
+    /// A write with this key is still running
+    InFlight,
+}
+
+pub(crate) struct IdempotencyKeys {
+    capacity: usize,
+    ttl: Timestamp,
+    /// Key to the time it was recorded and its place in `recency`
+    keys: HashMap<Key, (Timestamp, u64)>,
+    /// Keys from least to most recently used
+    recency: BTreeMap<u64, Key>,
+    next_use: u64,
+    /// Keys claimed by a write that hasn't finished yet
+    in_flight: HashSet<Key>,
+}
+
+impl IdempotencyKeys {
+    pub(crate) fn new(capacity: usize, ttl_ms: u64) -> Self {
+        Self {
+            capacity,
+            ttl: i64::try_from(ttl_ms).unwrap_or(i64::MAX).saturating_mul(1_000_000),
+            keys: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_use: 0,
+            in_flight: HashSet::new(),
+        }
+    }
+
+    /// Claim `key` for `database` at `now` unless a write with it is
+    /// running or was applied within the TTL
+    pub(crate) fn claim(&mut self, database: &str, key: &str, now: Timestamp) -> Claim {
+        if self.capacity == 0 {
+            return Claim::Claimed;
+        }
+        let key = (database.to_string(), key.to_string());
+        if self.in_flight.contains(&key) {
+            return Claim::InFlight;
+        }
+        if let Some((recorded, last_use)) = self.keys.get_mut(&key) {
+            let use_id = self.next_use;
+            self.next_use += 1;
+            self.recency.remove(last_use);
+            *last_use = use_id;
+            self.recency.insert(use_id, key.clone());
+            if now.saturating_sub(*recorded) < self.ttl {
+                return Claim::Applied;
+            }
+        }
+        self.in_flight.insert(key);
+        Claim::Claimed
+    }
+
+    /// Record a claimed `key` as applied at `now`
+    pub(crate) fn complete(&mut self, database: &str, key: &str, now: Timestamp) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = (database.to_string(), key.to_string());
+        self.in_flight.remove(&key);
+        let use_id = self.next_use;
+        self.next_use += 1;
+
+        if let Some((recorded, last_use)) = self.keys.get_mut(&key) {
+            self.recency.remove(last_use);
+            *last_use = use_id;
+            self.recency.insert(use_id, key);
+            *recorded = now;
+            return;
+        }
+
+        self.keys.insert(key.clone(), (now, use_id));
+        self.recency.insert(use_id, key);
+        while self.keys.len() > self.capacity {
+            match self.recency.pop_first() {
+                Some((_, oldest)) => self.keys.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    /// Give up a claimed `key` because its write failed, so a retry is
+    /// applied
+    pub(crate) fn release(&mut self, database: &str, key: &str) {
+        self.in_flight.remove(&(database.to_string(), key.to_string()));
+    }
+}
@@ -0,0 +1,46 @@
+//! Per-measurement locks between writes and schema changes
+//!
+//! Writes hold the locks of the measurements they touch shared, and
+//! changes to a measurement's series keys (rename, relabel, drop) hold
+//! them exclusively, so a write never lands halfway through a change to
+//! its measurement while writes to other measurements carry on. Names are
+//! hashed onto a fixed set of shards, so two measurements may share a lock;
+//! locks are always taken in shard order, which keeps that deadlock-free.
+
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+const SHARDS: usize = 64;
+
+pub(crate) struct MeasurementLocks {
+    shards: Vec<RwLock<()>>,
+}
+
+impl MeasurementLocks {
+    pub(crate) fn new() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::new(())).collect(),
+        }
+    }
+
+    /// Hold every lock of `measurements` shared, until the guards drop
+    pub(crate) fn shared<'a>(&self, measurements: impl IntoIterator<Item = &'a str>) -> Vec<RwLockReadGuard<'_, ()>> {
+        Self::shards_of(measurements).into_iter().map(|i| self.shards[i].read()).collect()
+    }
+
+    /// Hold every lock of `measurements` exclusively, until the guards drop
+    pub(crate) fn exclusive<'a>(&self, measurements: impl IntoIterator<Item = &'a str>) -> Vec<RwLockWriteGuard<'_, ()>> {
+        Self::shards_of(measurements).into_iter().map(|i| self.shards[i].write()).collect()
+    }
+
+    /// Shards of `measurements`, each once and in order
+    fn shards_of<'a>(measurements: impl IntoIterator<Item = &'a str>) -> Vec<usize> {
+        let mut shards: Vec<usize> = measurements.into_iter().map(Self::shard).collect();
+        shards.sort_unstable();
+        shards.dedup();
+        shards
+    }
+
+    pub(crate) fn shard(measurement: &str) -> usize {
+        crc32fast::hash(measurement.as_bytes()) as usize % SHARDS
+    }
+}
@@ -2,13 +2,237 @@
 
 mod engine;
 mod database;
+mod flusher;
+mod idempotency;
+mod measurement_locks;
+mod sstable_set;
+mod written_fields;
 
 pub use engine::StorageEngine;
-pub use database::Database;
+pub use database::{Database, FieldBlock};
 
+use crate::memtable::MemTableKind;
+use crate::query::{IntegerAggregates, IntegerOverflow, NullSemantics, TypeMismatch};
 use crate::sstable::SSTableConfig;
 use crate::wal::WalConfig;
-use std::path::PathBuf;
+use crate::{Clock, FluxError, Point, Result, SeriesKey, SystemClock, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Order `SELECT *` lists a measurement's fields in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FieldOrder {
+    /// Sorted by name
+    #[default]
+    Alphabetical,
+    /// In the order they were first written to the measurement, as
+    /// recorded in the database directory
+    Written,
+}
+
+/// On-disk layout of a database's SSTables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataLayout {
+    /// All SSTables directly in the database directory
+    #[default]
+    Flat,
+    /// SSTables spread across `shard_NNN` subdirectories by measurement hash
+    Sharded {
+        /// Number of shard directories
+        shards: u32,
+    },
+}
+
+impl DataLayout {
+    /// Directory that SSTables holding `measurement` are written to
+    pub fn sstable_dir(&self, db_dir: &Path, measurement: &str) -> PathBuf {
+        match self {
+            DataLayout::Flat => db_dir.to_path_buf(),
+            DataLayout::Sharded { shards } => {
+                let shard = crc32fast::hash(measurement.as_bytes()) % (*shards).max(1);
+                db_dir.join(format!("shard_{:03}", shard))
+            }
+        }
+    }
+}
+
+/// How strictly measurement, tag and field names are checked on write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NameValidation {
+    /// Accept any name
+    Off,
+    /// Reject empty names and names containing control characters
+    Basic,
+    /// Also reject `,` and `=`, which delimit the canonical series key
+    #[default]
+    Strict,
+}
+
+impl NameValidation {
+    /// Check a single name; `kind` ("measurement", "tag", "field") is used
+    /// in the error message
+    pub fn check(&self, kind: &str, name: &str, max_length: Option<usize>) -> Result<()> {
+        if let Some(max) = max_length {
+            if name.len() > max {
+                return Err(FluxError::InvalidName(format!(
+                    "{} name is {} bytes, limit is {}",
+                    kind,
+                    name.len(),
+                    max
+                )));
+            }
+        }
+
+        let reason = match self {
+            NameValidation::Off => None,
+            _ if name.is_empty() => Some("is empty"),
+            _ if name.chars().any(char::is_control) => Some("contains a control character"),
+            NameValidation::Strict if name.contains([',', '=']) => Some("contains ',' or '='"),
+            _ => None,
+        };
+        match reason {
+            Some(reason) => Err(FluxError::InvalidName(format!("{} name {:?} {}", kind, name, reason))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Which point a write keeps when its batch has several for the same
+/// series and timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePoints {
+    /// Log every point; the MemTable keeps the last
+    #[default]
+    KeepAll,
+    /// Keep only the first point, dropping later ones before the WAL
+    KeepFirst,
+    /// Keep only the last point, dropping earlier ones before the WAL
+    KeepLast,
+}
+
+impl DuplicatePoints {
+    /// `points` with duplicates collapsed per the policy, in order of each
+    /// key's first occurrence; borrowed when nothing was dropped
+    pub fn dedup<'a>(&self, points: &'a [Point]) -> Cow<'a, [Point]> {
+        if *self == DuplicatePoints::KeepAll {
+            return Cow::Borrowed(points);
+        }
+        let mut kept: Vec<usize> = Vec::with_capacity(points.len());
+        let mut slots: HashMap<(&SeriesKey, Timestamp), usize> = HashMap::with_capacity(points.len());
+        for (i, point) in points.iter().enumerate() {
+            match slots.get(&(&point.key, point.data.timestamp)) {
+                Some(&slot) if *self == DuplicatePoints::KeepLast => kept[slot] = i,
+                Some(_) => {}
+                None => {
+                    slots.insert((&point.key, point.data.timestamp), kept.len());
+                    kept.push(i);
+                }
+            }
+        }
+        if kept.len() == points.len() {
+            return Cow::Borrowed(points);
+        }
+        Cow::Owned(kept.into_iter().map(|i| points[i].clone()).collect())
+    }
+}
+
+/// When a write returns, and so how much of it survives a crash
+///
+/// Each level waits for the one before it: points are always logged to
+/// the WAL and inserted into the MemTable before a write returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteConsistency {
+    /// Return once the points are in the MemTable, leaving their WAL entry
+    /// unsynced until the next synced write or sync; fastest, but a crash
+    /// may lose them
+    Memtable,
+    /// Return once the points' WAL entry is synced to disk, whatever the
+    /// WAL's sync policy, so they survive a crash
+    #[default]
+    WalSynced,
+    /// Return once the MemTable holding the points has been flushed to
+    /// SSTables; slowest, as each such write flushes the MemTable
+    Flushed,
+}
+
+impl WriteConsistency {
+    /// Level named `memtable`, `wal_synced` or `flushed`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "memtable" => Some(WriteConsistency::Memtable),
+            "wal_synced" => Some(WriteConsistency::WalSynced),
+            "flushed" => Some(WriteConsistency::Flushed),
+            _ => None,
+        }
+    }
+}
+
+/// A change of series keys applied to existing data
+///
+/// See [`Database::rename_measurement`], [`Database::relabel_tag`] and
+/// [`Database::drop_measurement`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeriesRewrite {
+    /// Move every series of measurement `from` to measurement `to`
+    RenameMeasurement { from: String, to: String },
+    /// Rename tag key `from` to `to` on the series of `measurement`
+    RelabelTag { measurement: String, from: String, to: String },
+    /// Remove every series of `measurement`
+    DropMeasurement { measurement: String },
+}
+
+impl SeriesRewrite {
+    /// Measurements whose series the rewrite changes or creates
+    pub fn measurements(&self) -> Vec<&str> {
+        match self {
+            SeriesRewrite::RenameMeasurement { from, to } => vec![from, to],
+            SeriesRewrite::RelabelTag { measurement, .. } => vec![measurement],
+            SeriesRewrite::DropMeasurement { measurement } => vec![measurement],
+        }
+    }
+
+    /// Whether the rewrite removes the series `key` altogether
+    pub fn drops(&self, key: &SeriesKey) -> bool {
+        matches!(self, SeriesRewrite::DropMeasurement { measurement } if &key.measurement == measurement)
+    }
+
+    /// The rewritten key, or `None` if the rewrite doesn't change `key`
+    ///
+    /// Relabeling fails for a series that already has a tag named `to`,
+    /// since the two values can't both be kept.
+    pub fn apply(&self, key: &SeriesKey) -> Result<Option<SeriesKey>> {
+        match self {
+            SeriesRewrite::DropMeasurement { .. } => Ok(None),
+            SeriesRewrite::RenameMeasurement { from, to } => {
+                if &key.measurement != from {
+                    return Ok(None);
+                }
+                let mut key = key.clone();
+                key.measurement = to.clone();
+                Ok(Some(key))
+            }
+            SeriesRewrite::RelabelTag { measurement, from, to } => {
+                if &key.measurement != measurement || !key.tags.contains_key(from) {
+                    return Ok(None);
+                }
+                if key.tags.contains_key(to) {
+                    return Err(FluxError::Query(format!(
+                        "series {} already has a tag {}",
+                        key.encode(),
+                        to
+                    )));
+                }
+                let mut key = key.clone();
+                let value = key.tags.remove(from).unwrap_or_default();
+                key.tags.insert(to.clone(), value);
+                Ok(Some(key))
+            }
+        }
+    }
+}
 
 /// Storage engine configuration
 #[derive(Debug, Clone)]
@@ -21,12 +245,86 @@ pub struct StorageConfig {
     pub sstable: SSTableConfig,
     /// MemTable size limit in bytes
     pub memtable_size_limit: usize,
+    /// MemTable entry limit, flushing on whichever of this and the size
+    /// limit is reached first; `None` flushes on size alone
+    pub memtable_entry_limit: Option<usize>,
+    /// Structure backing MemTables
+    pub memtable_kind: MemTableKind,
     /// L0 compaction trigger (number of files)
     pub l0_compaction_trigger: usize,
     /// Level size multiplier
     pub level_size_multiplier: usize,
     /// Maximum number of levels
     pub max_levels: usize,
+    /// SSTable directory layout
+    pub layout: DataLayout,
+    /// Approximate bytes a query may hold in intermediate results
+    pub max_query_memory: usize,
+    /// Bytes of values a query buffers for median, percentile, stddev and
+    /// variance before spilling them to a temp file; `None` never spills
+    pub aggregation_spill_threshold: Option<usize>,
+    /// Threads a database uses to aggregate a query's groups in parallel;
+    /// 1 aggregates on the querying thread
+    pub query_parallelism: usize,
+    /// Whether `mean`, `median` and `sum` over integer fields return
+    /// floats or floored integers
+    pub integer_aggregates: IntegerAggregates,
+    /// Whether an integer `sum` too large for an i64 becomes a float or
+    /// fails the query
+    pub integer_overflow: IntegerOverflow,
+    /// Whether comparisons against a missing field keep the point or, as
+    /// in SQL, exclude it even when negated
+    pub null_semantics: NullSemantics,
+    /// Whether numeric comparisons against values that aren't numbers
+    /// exclude the point or fail the query
+    pub type_mismatch: TypeMismatch,
+    /// Order `SELECT *` lists fields in
+    pub field_order: FieldOrder,
+    /// Reject points older than the latest timestamp already written to
+    /// their series
+    pub strict_ordering: bool,
+    /// Immutable MemTables allowed to wait for flushing before writes block
+    pub max_immutable_memtables: usize,
+    /// SSTable readers kept open at once; the rest are opened on demand
+    pub max_open_sstables: usize,
+    /// Bytes of SSTable blocks cached across all databases
+    pub block_cache_size: usize,
+    /// Log queries taking at least this long at WARN; `None` disables the
+    /// slow query log
+    pub slow_query_threshold_ms: Option<u64>,
+    /// Reject writes and schema changes; replicas only take data from
+    /// their primary's WAL
+    pub read_only: bool,
+    /// Checks applied to measurement, tag and field names on write
+    pub name_validation: NameValidation,
+    /// Longest accepted measurement, tag or field name in bytes; `None`
+    /// allows any length
+    pub max_name_length: Option<usize>,
+    /// Earliest point timestamp a write accepts; earlier points fail the
+    /// write with `InvalidTimestamp`. `None` accepts any
+    pub min_timestamp: Option<Timestamp>,
+    /// Latest point timestamp a write accepts; `None` accepts any
+    pub max_timestamp: Option<Timestamp>,
+    /// Source of the current time, e.g. for writes without a timestamp
+    pub clock: Arc<dyn Clock>,
+    /// Create the database a write names if it doesn't exist yet; when
+    /// off, such writes fail with `DatabaseNotFound`
+    pub auto_create_databases: bool,
+    /// Most distinct series a measurement may have; writes creating more
+    /// fail with `CardinalityLimit`. `None` allows any number
+    pub max_series_per_measurement: Option<usize>,
+    /// Most distinct values a tag key may take within a measurement;
+    /// `None` allows any number
+    pub max_tag_values_per_key: Option<usize>,
+    /// What a write does with points of one batch sharing a series and
+    /// timestamp
+    pub duplicate_points: DuplicatePoints,
+    /// Idempotency keys remembered across all databases; past this the
+    /// least recently used is forgotten. 0 turns keyed writes into plain
+    /// writes
+    pub idempotency_keys: usize,
+    /// How long an idempotency key suppresses repeats of its write
+    pub idempotency_ttl_ms: u64,
 }
 
 impl Default for StorageConfig {
@@ -36,9 +334,117 @@ impl Default for StorageConfig {
             wal: WalConfig::default(),
             sstable: SSTableConfig::default(),
             memtable_size_limit: crate::config::MEMTABLE_SIZE_LIMIT,
+            memtable_entry_limit: None,
+            memtable_kind: MemTableKind::default(),
             l0_compaction_trigger: crate::config::L0_COMPACTION_TRIGGER,
             level_size_multiplier: crate::config::LEVEL_SIZE_RATIO,
             max_levels: 7,
+            layout: DataLayout::default(),
+            max_query_memory: crate::config::MAX_QUERY_MEMORY,
+            aggregation_spill_threshold: Some(crate::config::AGGREGATION_SPILL_THRESHOLD),
+            query_parallelism: 1,
+            integer_aggregates: IntegerAggregates::default(),
+            integer_overflow: IntegerOverflow::default(),
+            null_semantics: NullSemantics::default(),
+            type_mismatch: TypeMismatch::default(),
+            field_order: FieldOrder::default(),
+            strict_ordering: false,
+            max_immutable_memtables: crate::config::MAX_IMMUTABLE_MEMTABLES,
+            max_open_sstables: crate::config::MAX_OPEN_SSTABLES,
+            block_cache_size: crate::config::BLOCK_CACHE_SIZE,
+            slow_query_threshold_ms: Some(crate::config::SLOW_QUERY_THRESHOLD_MS),
+            read_only: false,
+            name_validation: NameValidation::default(),
+            max_name_length: None,
+            min_timestamp: None,
+            max_timestamp: None,
+            clock: Arc::new(SystemClock),
+            auto_create_databases: true,
+            max_series_per_measurement: None,
+            max_tag_values_per_key: None,
+            duplicate_points: DuplicatePoints::default(),
+            idempotency_keys: crate::config::IDEMPOTENCY_KEYS,
+            idempotency_ttl_ms: crate::config::IDEMPOTENCY_TTL_MS,
+        }
+    }
+}
+
+impl StorageConfig {
+    /// Check every name in `points` against the configured name rules
+    pub(crate) fn check_names(&self, points: &[Point]) -> Result<()> {
+        let rules = self.name_validation;
+        let max = self.max_name_length;
+        for point in points {
+            rules.check("measurement", &point.key.measurement, max)?;
+            for tag in point.key.tags.keys() {
+                rules.check("tag", tag, max)?;
+            }
+            for (field, _) in point.data.fields.iter() {
+                rules.check("field", field, max)?;
+            }
         }
+        Ok(())
+    }
+
+    /// Check every timestamp in `points` against the configured bounds
+    pub(crate) fn check_timestamps(&self, points: &[Point]) -> Result<()> {
+        let min = self.min_timestamp.unwrap_or(Timestamp::MIN);
+        let max = self.max_timestamp.unwrap_or(Timestamp::MAX);
+        match points.iter().find(|p| !(min..=max).contains(&p.data.timestamp)) {
+            Some(point) => Err(FluxError::InvalidTimestamp(format!(
+                "{} of series {} is outside the accepted range {}..={}",
+                point.data.timestamp,
+                point.key.encode(),
+                min,
+                max
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataPoint, FieldValue, SeriesKey};
+
+    #[test]
+    fn test_name_validation() {
+        let strict = NameValidation::Strict;
+        assert!(strict.check("tag", "host", None).is_ok());
+        assert!(matches!(strict.check("tag", "", None), Err(FluxError::InvalidName(_))));
+        assert!(strict.check("field", "bad\nname", None).is_err());
+        assert!(strict.check("measurement", "cpu,host", None).is_err());
+        assert!(strict.check("tag", "a=b", None).is_err());
+
+        // Basic only guards against empty and control characters
+        assert!(NameValidation::Basic.check("tag", "a=b", None).is_ok());
+        assert!(NameValidation::Basic.check("tag", "", None).is_err());
+        assert!(NameValidation::Off.check("tag", "", None).is_ok());
+
+        // The length limit applies at every level
+        assert!(strict.check("field", "usage", Some(5)).is_ok());
+        assert!(strict.check("field", "usage2", Some(5)).is_err());
+        assert!(NameValidation::Off.check("field", "usage2", Some(5)).is_err());
+    }
+
+    #[test]
+    fn test_check_names() {
+        let config = StorageConfig {
+            max_name_length: Some(8),
+            ..Default::default()
+        };
+        let point = |measurement: &str, tag: &str, field: &str| {
+            Point::new(
+                SeriesKey::new(measurement).with_tag(tag, "a"),
+                DataPoint::new(0, field, FieldValue::Float(1.0)),
+            )
+        };
+
+        assert!(config.check_names(&[point("cpu", "host", "usage")]).is_ok());
+        assert!(config.check_names(&[point("", "host", "usage")]).is_err());
+        assert!(config.check_names(&[point("cpu", "ho,st", "usage")]).is_err());
+        assert!(config.check_names(&[point("cpu", "host", "")]).is_err());
+        assert!(config.check_names(&[point("cpu", "host", "usage_percent")]).is_err());
     }
 }
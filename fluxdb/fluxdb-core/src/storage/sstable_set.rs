@@ -0,0 +1,69 @@
+//! The published set of a database's SSTables
+//!
+//! Queries read a snapshot of the set taken when they start, and flushes
+//! and rewrites swap in a new set rather than changing one in place. Files
+//! a rewrite replaced are handed to the set they were swapped out of and
+//! deleted when its last snapshot drops, so the rewrite doesn't wait for
+//! the queries still reading them.
+
+use crate::sstable::{SSTableMeta, TableCache};
+use parking_lot::Mutex;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::warn;
+
+pub(crate) struct SSTableSet {
+    tables: Vec<SSTableMeta>,
+    retired: Mutex<Vec<Retired>>,
+}
+
+/// Files to delete once the set holding them is dropped
+struct Retired {
+    tables: Vec<SSTableMeta>,
+    cache: Arc<TableCache>,
+    /// A marker file to remove after them, if it still has these contents
+    marker: Option<(PathBuf, String)>,
+}
+
+impl SSTableSet {
+    pub(crate) fn new(tables: Vec<SSTableMeta>) -> Self {
+        Self { tables, retired: Mutex::new(Vec::new()) }
+    }
+
+    /// Delete `tables`, which are no longer in the current set, when this
+    /// one is dropped, evicting them from `cache` first; then remove
+    /// `marker` unless it was rewritten in the meantime
+    pub(crate) fn retire(&self, tables: Vec<SSTableMeta>, cache: Arc<TableCache>, marker: Option<(PathBuf, String)>) {
+        self.retired.lock().push(Retired { tables, cache, marker });
+    }
+}
+
+impl Deref for SSTableSet {
+    type Target = [SSTableMeta];
+
+    fn deref(&self) -> &[SSTableMeta] {
+        &self.tables
+    }
+}
+
+impl Drop for Retired {
+    fn drop(&mut self) {
+        let mut deleted = true;
+        for meta in &self.tables {
+            self.cache.evict(&meta.path);
+            if let Err(e) = std::fs::remove_file(&meta.path) {
+                warn!("Failed to delete replaced SSTable {:?}: {}", meta.path, e);
+                deleted = false;
+            }
+        }
+        // Otherwise the marker lets opening the database finish the job
+        if let (true, Some((path, contents))) = (deleted, &self.marker) {
+            if std::fs::read_to_string(path).ok().as_deref() == Some(contents.as_str()) {
+                if let Err(e) = std::fs::remove_file(path) {
+                    warn!("Failed to remove {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+}
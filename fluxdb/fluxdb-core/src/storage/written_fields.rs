@@ -0,0 +1,97 @@
+//! Order each measurement's fields were first written in
+//!
+//! Points keep their fields sorted by name, so only whoever parsed a write
+//! knows the order it listed them in. Databases configured with
+//! [`FieldOrder::Written`](super::FieldOrder) record that order per
+//! measurement as new fields arrive, for `SELECT *` to list fields that way;
+//! fields a write brings without one are recorded alphabetically. The record
+//! is saved in the database directory whenever a measurement gains a field.
+
+use crate::{FluxError, Result};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+pub(crate) struct WrittenFields {
+    path: PathBuf,
+    orders: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl WrittenFields {
+    /// Load the record saved at `path`, if any
+    pub(crate) fn open(path: PathBuf) -> Result<Self> {
+        let orders = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+                FluxError::InvalidFormat(format!("Invalid field order in {:?}: {}", path, e))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            orders: Mutex::new(orders),
+        })
+    }
+
+    /// Append the fields their measurements haven't had before, taking
+    /// `fields` as measurement and field name pairs in the order written
+    pub(crate) fn record<'a>(&self, fields: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<()> {
+        let mut orders = self.orders.lock();
+        let mut changed = false;
+        for (measurement, name) in fields {
+            let order = orders.entry(measurement.to_string()).or_default();
+            if !order.iter().any(|known| known == name) {
+                order.push(name.to_string());
+                changed = true;
+            }
+        }
+        if changed {
+            self.save(&orders)?;
+        }
+        Ok(())
+    }
+
+    /// Fields of `measurement` in the order first written
+    pub(crate) fn order(&self, measurement: &str) -> Vec<String> {
+        self.orders.lock().get(measurement).cloned().unwrap_or_default()
+    }
+
+    /// Move the record of `from` to `to`, after any fields `to` already had
+    pub(crate) fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let mut orders = self.orders.lock();
+        let Some(moved) = orders.remove(from) else {
+            return Ok(());
+        };
+        let order = orders.entry(to.to_string()).or_default();
+        for name in moved {
+            if !order.contains(&name) {
+                order.push(name);
+            }
+        }
+        self.save(&orders)
+    }
+
+    /// Forget `measurement`, which was dropped
+    pub(crate) fn remove(&self, measurement: &str) -> Result<()> {
+        let mut orders = self.orders.lock();
+        if orders.remove(measurement).is_some() {
+            self.save(&orders)?;
+        }
+        Ok(())
+    }
+
+    /// Forget every measurement, as the database was truncated
+    pub(crate) fn clear(&self) -> Result<()> {
+        let mut orders = self.orders.lock();
+        orders.clear();
+        self.save(&orders)
+    }
+
+    fn save(&self, orders: &HashMap<String, Vec<String>>) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let bytes = serde_json::to_vec(orders).map_err(|e| FluxError::Internal(e.to_string()))?;
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 //! Core types for FluxDB
 
+use crate::{FluxError, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::fmt;
@@ -41,8 +42,73 @@ impl SeriesKey {
                 .sum::<usize>()
     }
 
-    /// Create a canonical string representation for hashing
-    pub fn canonical(&self) -> String {
+    /// The key's canonical string: `measurement,k1=v1,k2=v2` with tags in
+    /// key order
+    ///
+    /// This is how keys are stored in SSTable indexes and bloom filters and
+    /// shown in results. Commas, equals signs, spaces and backslashes in
+    /// any part are escaped with a backslash, as in line protocol, so
+    /// [`decode`](Self::decode) gets back exactly this key.
+    pub fn encode(&self) -> String {
+        let mut s = String::with_capacity(self.size() + 2 * self.tags.len());
+        escape_into(&mut s, &self.measurement);
+        for (k, v) in &self.tags {
+            s.push(',');
+            escape_into(&mut s, k);
+            s.push('=');
+            escape_into(&mut s, v);
+        }
+        s
+    }
+
+    /// Parse a key produced by [`encode`](Self::encode), or the series part
+    /// of a line protocol line
+    ///
+    /// A backslash before anything but `,`, `=`, space or another
+    /// backslash is kept as is, and so is an unescaped `=` in the
+    /// measurement or a tag value. Fails on a tag without `=`.
+    pub fn decode(encoded: &str) -> Result<Self> {
+        // Each comma-separated part, unescaped, with where its first
+        // unescaped `=` was
+        let mut parts: Vec<(String, Option<usize>)> = Vec::new();
+        let mut current = String::new();
+        let mut equals = None;
+        let mut chars = encoded.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.peek() {
+                    Some(&next) if ESCAPED.contains(&next) => {
+                        current.push(next);
+                        chars.next();
+                    }
+                    _ => current.push('\\'),
+                },
+                ',' => parts.push((std::mem::take(&mut current), equals.take())),
+                '=' if equals.is_none() => equals = Some(current.len()),
+                _ => current.push(c),
+            }
+        }
+        parts.push((current, equals));
+
+        let mut parts = parts.into_iter();
+        let (mut measurement, equals) = parts.next().unwrap_or_default();
+        if let Some(at) = equals {
+            measurement.insert(at, '=');
+        }
+        let mut key = SeriesKey::new(measurement);
+        for (tag, equals) in parts {
+            let at = equals.ok_or_else(|| {
+                FluxError::InvalidFormat(format!("Tag without a value in series key {:?}", encoded))
+            })?;
+            let (k, v) = tag.split_at(at);
+            key.tags.insert(k.to_string(), v.to_string());
+        }
+        Ok(key)
+    }
+
+    /// The key as SSTables before format version 5 stored it: joined like
+    /// [`encode`](Self::encode) but without escaping anything
+    pub(crate) fn encode_unescaped(&self) -> String {
         let mut s = self.measurement.clone();
         for (k, v) in &self.tags {
             s.push(',');
@@ -52,11 +118,38 @@ impl SeriesKey {
         }
         s
     }
+
+    /// Parse a key produced by [`encode_unescaped`](Self::encode_unescaped)
+    ///
+    /// The measurement runs to the first comma and each tag to the next;
+    /// a tag without `=` is skipped.
+    pub(crate) fn decode_unescaped(encoded: &str) -> Self {
+        let mut parts = encoded.splitn(2, ',');
+        let mut key = SeriesKey::new(parts.next().unwrap_or_default());
+        for tag in parts.next().into_iter().flat_map(|tags| tags.split(',')) {
+            if let Some((k, v)) = tag.split_once('=') {
+                key = key.with_tag(k, v);
+            }
+        }
+        key
+    }
+}
+
+/// Characters a backslash escapes in an encoded series key
+const ESCAPED: [char; 4] = [',', '=', ' ', '\\'];
+
+fn escape_into(s: &mut String, part: &str) {
+    for c in part.chars() {
+        if ESCAPED.contains(&c) {
+            s.push('\\');
+        }
+        s.push(c);
+    }
 }
 
 impl fmt::Display for SeriesKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.canonical())
+        write!(f, "{}", self.encode())
     }
 }
 
@@ -253,6 +346,17 @@ impl TimeRange {
         self.start <= other.end && self.end >= other.start
     }
 
+    /// Overlap of two ranges, or `None` if they are disjoint
+    pub fn intersect(&self, other: &TimeRange) -> Option<TimeRange> {
+        let start = self.start.max(other.start);
+        let end = self.end.min(other.end);
+        if start <= end {
+            Some(TimeRange::new(start, end))
+        } else {
+            None
+        }
+    }
+
     /// Duration in nanoseconds
     pub fn duration(&self) -> i64 {
         self.end - self.start
@@ -300,9 +404,50 @@ mod tests {
             .with_tag("location", "building-a");
 
         assert_eq!(
-            key.canonical(),
+            key.encode(),
             "temperature,location=building-a,sensor=sensor-001"
         );
+        assert_eq!(SeriesKey::decode(&key.encode()).unwrap(), key);
+    }
+
+    #[test]
+    fn test_series_key_escaping() {
+        let key = SeriesKey::new("disk io,x")
+            .with_tag("path", r"C:\data,1")
+            .with_tag("a=b", "c d=e");
+        assert_eq!(key.encode(), r"disk\ io\,x,a\=b=c\ d\=e,path=C:\\data\,1");
+        assert_eq!(SeriesKey::decode(&key.encode()).unwrap(), key);
+
+        // Line protocol leaves some characters unescaped
+        let key = SeriesKey::decode(r"a=b,k=v=w,dir=C:\tmp").unwrap();
+        assert_eq!(key, SeriesKey::new("a=b").with_tag("k", "v=w").with_tag("dir", r"C:\tmp"));
+        assert_eq!(SeriesKey::decode("").unwrap(), SeriesKey::new(""));
+        assert!(SeriesKey::decode("cpu,host").is_err());
+    }
+
+    #[test]
+    fn test_series_key_round_trip() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        // Mostly delimiters and escapes, to hit every adjacency of them
+        const ALPHABET: &[char] = &[',', '=', ' ', '\\', '"', 'a', 'b', 'é', '\n', '\t', '🦀'];
+        let mut rng = StdRng::seed_from_u64(7);
+        let text = |rng: &mut StdRng| -> String {
+            let len = rng.gen_range(0..8);
+            (0..len).map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())]).collect()
+        };
+
+        for _ in 0..5_000 {
+            let mut key = SeriesKey::new(text(&mut rng));
+            for _ in 0..rng.gen_range(0..4) {
+                let (k, v) = (text(&mut rng), text(&mut rng));
+                key = key.with_tag(k, v);
+            }
+            let encoded = key.encode();
+            assert_eq!(SeriesKey::decode(&encoded).unwrap(), key, "{:?}", encoded);
+            assert_eq!(key.to_string(), encoded);
+        }
     }
 
     #[test]
@@ -315,6 +460,13 @@ mod tests {
         assert!(!range1.overlaps(&range3));
         assert!(range1.contains(150));
         assert!(!range1.contains(250));
+
+        assert_eq!(range1.intersect(&range2), Some(TimeRange::new(150, 200)));
+        assert_eq!(range1.intersect(&range3), None);
+        assert_eq!(
+            range1.intersect(&TimeRange::new(200, 300)),
+            Some(TimeRange::new(200, 200))
+        );
     }
 
     #[test]
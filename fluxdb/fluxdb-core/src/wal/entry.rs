@@ -1,6 +1,7 @@
 //! WAL entry types and serialization
 
-use crate::{Point, Result, FluxError};
+use crate::storage::SeriesRewrite;
+use crate::{ChecksumKind, Point, Result, FluxError};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
 
@@ -18,6 +19,8 @@ pub enum WalEntryType {
     DropDatabase = 4,
     /// Checkpoint marker
     Checkpoint = 5,
+    /// Series keys rewritten (measurement rename or tag relabel)
+    Rewrite = 6,
 }
 
 impl TryFrom<u8> for WalEntryType {
@@ -30,6 +33,7 @@ impl TryFrom<u8> for WalEntryType {
             3 => Ok(WalEntryType::CreateDatabase),
             4 => Ok(WalEntryType::DropDatabase),
             5 => Ok(WalEntryType::Checkpoint),
+            6 => Ok(WalEntryType::Rewrite),
             _ => Err(FluxError::InvalidFormat(format!(
                 "Invalid WAL entry type: {}",
                 value
@@ -38,6 +42,12 @@ impl TryFrom<u8> for WalEntryType {
     }
 }
 
+/// Bit set on the entry type byte when the entry uses CRC32C
+///
+/// Entries written before checksums were configurable leave it clear and
+/// are verified with CRC32.
+const CRC32C_FLAG: u8 = 0x80;
+
 /// A single WAL entry
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WalEntry {
@@ -61,6 +71,26 @@ impl WalEntry {
         })
     }
 
+    /// Create an entry recording a series key rewrite
+    pub fn rewrite(database: &str, rewrite: &SeriesRewrite) -> Result<Self> {
+        let payload = bincode::serialize(rewrite)
+            .map_err(|e| FluxError::InvalidFormat(e.to_string()))?;
+        Ok(Self {
+            entry_type: WalEntryType::Rewrite,
+            database: database.to_string(),
+            payload,
+        })
+    }
+
+    /// Create a database creation entry
+    pub fn create_database(database: &str) -> Self {
+        Self {
+            entry_type: WalEntryType::CreateDatabase,
+            database: database.to_string(),
+            payload: vec![],
+        }
+    }
+
     /// Create a checkpoint entry
     pub fn checkpoint(database: &str) -> Self {
         Self {
@@ -74,20 +104,24 @@ impl WalEntry {
     ///
     /// Format:
     /// - 4 bytes: entry length (excluding this field)
-    /// - 1 byte: entry type
+    /// - 1 byte: entry type (high bit set for CRC32C)
     /// - 4 bytes: database name length
     /// - N bytes: database name
     /// - 4 bytes: payload length
     /// - N bytes: payload
-    /// - 4 bytes: CRC32 checksum
-    pub fn serialize_with_checksum(&self) -> Bytes {
+    /// - 4 bytes: CRC32 or CRC32C checksum
+    pub fn serialize_with_checksum(&self, checksum: ChecksumKind) -> Bytes {
         let mut buf = BytesMut::new();
 
         // Reserve space for length prefix
         buf.put_u32_le(0);
 
-        // Entry type
-        buf.put_u8(self.entry_type as u8);
+        // Entry type, recording the checksum kind
+        let flag = match checksum {
+            ChecksumKind::Crc32 => 0,
+            ChecksumKind::Crc32c => CRC32C_FLAG,
+        };
+        buf.put_u8(self.entry_type as u8 | flag);
 
         // Database name
         buf.put_u32_le(self.database.len() as u32);
@@ -98,7 +132,7 @@ impl WalEntry {
         buf.put_slice(&self.payload);
 
         // Calculate and write checksum (excluding length prefix)
-        let checksum = crc32fast::hash(&buf[4..]);
+        let checksum = checksum.checksum(&buf[4..]);
         buf.put_u32_le(checksum);
 
         // Write actual length
@@ -121,15 +155,23 @@ impl WalEntry {
         if data.len() < 4 + len {
             return Err(FluxError::InvalidFormat("Incomplete entry".into()));
         }
+        if len < 5 {
+            return Err(FluxError::Corruption(format!("Invalid entry length: {}", len)));
+        }
 
         let entry_data = &data[4..4 + len];
+        let checksum = if entry_data[0] & CRC32C_FLAG != 0 {
+            ChecksumKind::Crc32c
+        } else {
+            ChecksumKind::Crc32
+        };
 
         // Validate checksum
         let expected_checksum = {
             let mut c = std::io::Cursor::new(&entry_data[entry_data.len() - 4..]);
             c.get_u32_le()
         };
-        let actual_checksum = crc32fast::hash(&entry_data[..entry_data.len() - 4]);
+        let actual_checksum = checksum.checksum(&entry_data[..entry_data.len() - 4]);
 
         if expected_checksum != actual_checksum {
             return Err(FluxError::ChecksumMismatch {
@@ -141,7 +183,7 @@ impl WalEntry {
         let mut cursor = std::io::Cursor::new(entry_data);
 
         // Entry type
-        let entry_type = WalEntryType::try_from(cursor.get_u8())?;
+        let entry_type = WalEntryType::try_from(cursor.get_u8() & !CRC32C_FLAG)?;
 
         // Database name
         let db_len = cursor.get_u32_le() as usize;
@@ -164,6 +206,15 @@ impl WalEntry {
         Ok((entry, 4 + len))
     }
 
+    /// Get the rewrite from a rewrite entry
+    pub fn get_rewrite(&self) -> Result<SeriesRewrite> {
+        if self.entry_type != WalEntryType::Rewrite {
+            return Err(FluxError::InvalidFormat("Not a rewrite entry".into()));
+        }
+        bincode::deserialize(&self.payload)
+            .map_err(|e| FluxError::InvalidFormat(e.to_string()))
+    }
+
     /// Get the points from a write entry
     pub fn get_points(&self) -> Result<Vec<Point>> {
         if self.entry_type != WalEntryType::Write {
@@ -186,26 +237,42 @@ mod tests {
         let points = vec![Point::new(key, data)];
 
         let entry = WalEntry::write("testdb", &points).unwrap();
-        let serialized = entry.serialize_with_checksum();
+        for kind in [ChecksumKind::Crc32, ChecksumKind::Crc32c] {
+            let serialized = entry.serialize_with_checksum(kind);
 
-        let (deserialized, len) = WalEntry::deserialize_with_checksum(&serialized).unwrap();
-        assert_eq!(len, serialized.len());
-        assert_eq!(deserialized.entry_type, WalEntryType::Write);
-        assert_eq!(deserialized.database, "testdb");
+            let (deserialized, len) = WalEntry::deserialize_with_checksum(&serialized).unwrap();
+            assert_eq!(len, serialized.len());
+            assert_eq!(deserialized.entry_type, WalEntryType::Write);
+            assert_eq!(deserialized.database, "testdb");
 
-        let recovered_points = deserialized.get_points().unwrap();
-        assert_eq!(recovered_points.len(), 1);
+            let recovered_points = deserialized.get_points().unwrap();
+            assert_eq!(recovered_points.len(), 1);
+        }
     }
 
     #[test]
     fn test_checksum_validation() {
         let entry = WalEntry::checkpoint("testdb");
-        let mut serialized = entry.serialize_with_checksum().to_vec();
+        for kind in [ChecksumKind::Crc32, ChecksumKind::Crc32c] {
+            let mut serialized = entry.serialize_with_checksum(kind).to_vec();
 
-        // Corrupt the data
-        serialized[10] ^= 0xFF;
+            // Corrupt the data
+            serialized[10] ^= 0xFF;
 
-        let result = WalEntry::deserialize_with_checksum(&serialized);
-        assert!(matches!(result, Err(FluxError::ChecksumMismatch { .. })));
+            let result = WalEntry::deserialize_with_checksum(&serialized);
+            assert!(matches!(result, Err(FluxError::ChecksumMismatch { .. })));
+        }
+    }
+
+    #[test]
+    fn test_crc32_entry_format_unchanged() {
+        // CRC32 entries keep the original layout: plain type byte and a
+        // CRC32 over everything after the length prefix
+        let entry = WalEntry::checkpoint("testdb");
+        let serialized = entry.serialize_with_checksum(ChecksumKind::Crc32);
+        assert_eq!(serialized[4], WalEntryType::Checkpoint as u8);
+        let body = &serialized[4..serialized.len() - 4];
+        let stored = u32::from_le_bytes(serialized[serialized.len() - 4..].try_into().unwrap());
+        assert_eq!(stored, crc32fast::hash(body));
     }
 }
@@ -12,6 +12,7 @@ pub use entry::{WalEntry, WalEntryType};
 pub use reader::WalReader;
 pub use writer::WalWriter;
 
+use crate::ChecksumKind;
 use std::path::PathBuf;
 
 /// WAL sync policy
@@ -42,6 +43,16 @@ pub struct WalConfig {
     pub sync_policy: SyncPolicy,
     /// Maximum segment size in bytes
     pub segment_size: usize,
+    /// Memory-map segments during recovery instead of reading them into memory
+    pub mmap_recovery: bool,
+    /// Checksum algorithm for new entries
+    pub checksum: ChecksumKind,
+    /// On a damaged entry mid-segment, scan forward for the next valid
+    /// entry and keep recovering instead of dropping the rest of the segment
+    ///
+    /// Off by default: the skipped entry's writes are lost while later ones
+    /// are applied, which is worse than a clean cut-off for some workloads.
+    pub salvage_corrupt: bool,
 }
 
 impl Default for WalConfig {
@@ -50,6 +61,9 @@ impl Default for WalConfig {
             dir: PathBuf::from("data/wal"),
             sync_policy: SyncPolicy::default(),
             segment_size: crate::config::WAL_SEGMENT_SIZE,
+            mmap_recovery: false,
+            checksum: ChecksumKind::default(),
+            salvage_corrupt: false,
         }
     }
 }
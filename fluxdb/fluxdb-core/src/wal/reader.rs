@@ -2,9 +2,10 @@
 
 use super::{WalConfig, WalEntry};
 use crate::{FluxError, Result};
+use memmap2::Mmap;
 use std::fs::{self, File};
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tracing::{info, warn};
 
 /// WAL reader for recovering entries after crash
@@ -62,6 +63,12 @@ impl WalReader {
         Ok(entries)
     }
 
+    /// Parse the entries of a segment received from elsewhere, e.g. a
+    /// replication primary
+    pub fn parse_segment(data: &[u8]) -> Result<Vec<WalEntry>> {
+        Self::parse_entries(data, Path::new("replicated segment"), false)
+    }
+
     fn find_segments(&self) -> Result<Vec<PathBuf>> {
         let mut segments = Vec::new();
 
@@ -91,9 +98,24 @@ impl WalReader {
 
     fn read_segment(&self, path: &PathBuf) -> Result<Vec<WalEntry>> {
         let mut file = File::open(path)?;
+
+        if self.config.mmap_recovery {
+            // Mapping an empty file fails on some platforms
+            if file.metadata()?.len() == 0 {
+                return Ok(Vec::new());
+            }
+            // SAFETY: segments being recovered are not written concurrently;
+            // the writer only appends to the newest segment after recovery
+            let mmap = unsafe { Mmap::map(&file)? };
+            return Self::parse_entries(&mmap, path, self.config.salvage_corrupt);
+        }
+
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
+        Self::parse_entries(&data, path, self.config.salvage_corrupt)
+    }
 
+    fn parse_entries(data: &[u8], path: &Path, salvage: bool) -> Result<Vec<WalEntry>> {
         let mut entries = Vec::new();
         let mut offset = 0;
 
@@ -103,6 +125,23 @@ impl WalReader {
                     entries.push(entry);
                     offset += bytes_read;
                 }
+                Err(FluxError::InvalidFormat(msg)) if msg == "Entry too short" => {
+                    // Incomplete entry at end (crash during write)
+                    break;
+                }
+                Err(e) if salvage => match Self::resync(data, offset + 1) {
+                    Some(next) => {
+                        warn!(
+                            "Skipped {} damaged bytes at offset {} in {:?}: {}",
+                            next - offset, offset, path, e
+                        );
+                        offset = next;
+                    }
+                    None => {
+                        warn!("{} at offset {} in {:?}, no valid entries follow", e, offset, path);
+                        break;
+                    }
+                },
                 Err(FluxError::ChecksumMismatch { .. }) => {
                     // Corrupted entry, skip rest of segment
                     warn!(
@@ -111,10 +150,6 @@ impl WalReader {
                     );
                     break;
                 }
-                Err(FluxError::InvalidFormat(msg)) if msg == "Entry too short" => {
-                    // Incomplete entry at end (crash during write)
-                    break;
-                }
                 Err(FluxError::InvalidFormat(msg)) if msg == "Incomplete entry" => {
                     // Incomplete entry at end (crash during write)
                     break;
@@ -128,6 +163,12 @@ impl WalReader {
         Ok(entries)
     }
 
+    /// Find the next offset at or after `from` holding an entry whose
+    /// length prefix and checksum both check out
+    fn resync(data: &[u8], from: usize) -> Option<usize> {
+        (from..data.len()).find(|&offset| WalEntry::deserialize_with_checksum(&data[offset..]).is_ok())
+    }
+
     fn parse_segment_id(path: &PathBuf) -> Option<u64> {
         path.file_name()
             .and_then(|n| n.to_str())
@@ -170,4 +211,85 @@ mod tests {
         let entries = reader.recover().unwrap();
         assert_eq!(entries.len(), 10);
     }
+
+    #[test]
+    fn test_wal_mmap_recovery() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            segment_size: 256 * 1024,
+            ..Default::default()
+        };
+
+        // ~50KB entries spread over several segments
+        {
+            let writer = WalWriter::new(config.clone()).unwrap();
+            for i in 0..20 {
+                let key = SeriesKey::new("temp").with_tag("id", i.to_string());
+                let points: Vec<Point> = (0..1000)
+                    .map(|j| Point::new(key.clone(), DataPoint::new(j, "value", FieldValue::Float(j as f64))))
+                    .collect();
+                let entry = WalEntry::write("testdb", &points).unwrap();
+                writer.append(&entry).unwrap();
+            }
+            writer.sync().unwrap();
+        }
+        let reader = WalReader::new(config.clone());
+        let segments = reader.find_segments().unwrap();
+        assert!(segments.len() > 1);
+
+        // Chop the final entry in half, as if we crashed mid-write
+        let last = segments.last().unwrap();
+        let len = std::fs::metadata(last).unwrap().len();
+        std::fs::OpenOptions::new().write(true).open(last).unwrap().set_len(len - 1000).unwrap();
+
+        let mmap_reader = WalReader::new(WalConfig { mmap_recovery: true, ..config.clone() });
+        let mapped = mmap_reader.recover().unwrap();
+        let buffered = reader.recover().unwrap();
+        assert_eq!(mapped.len(), 19);
+        assert_eq!(mapped.len(), buffered.len());
+        assert_eq!(mapped.last().unwrap().get_points().unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn test_wal_salvage_past_corruption() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let mut sizes = Vec::new();
+        {
+            let writer = WalWriter::new(config.clone()).unwrap();
+            for i in 0..10 {
+                let key = SeriesKey::new("temp").with_tag("id", i.to_string());
+                let data = DataPoint::new(i * 1000, "value", FieldValue::Float(i as f64));
+                let entry = WalEntry::write("testdb", &[Point::new(key, data)]).unwrap();
+                sizes.push(entry.serialize_with_checksum(config.checksum).len());
+                writer.append(&entry).unwrap();
+            }
+            writer.sync().unwrap();
+        }
+
+        // Flip a payload byte in the fifth entry, as a bad sector would
+        let segment = WalReader::new(config.clone()).find_segments().unwrap().remove(0);
+        let mut data = std::fs::read(&segment).unwrap();
+        let offset = sizes[..4].iter().sum::<usize>() + sizes[4] / 2;
+        data[offset] ^= 0xFF;
+        std::fs::write(&segment, &data).unwrap();
+
+        // By default everything from the bad entry on is dropped
+        let entries = WalReader::new(config.clone()).recover().unwrap();
+        assert_eq!(entries.len(), 4);
+
+        let salvaging = WalReader::new(WalConfig { salvage_corrupt: true, ..config });
+        let entries = salvaging.recover().unwrap();
+        assert_eq!(entries.len(), 9);
+        let timestamps: Vec<i64> = entries
+            .iter()
+            .map(|e| e.get_points().unwrap()[0].data.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![0, 1000, 2000, 3000, 5000, 6000, 7000, 8000, 9000]);
+    }
 }
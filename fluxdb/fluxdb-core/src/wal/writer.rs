@@ -5,7 +5,7 @@ use crate::{FluxError, Result};
 use parking_lot::Mutex;
 use std::fs::{self, File, OpenOptions};
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Instant;
 
@@ -49,9 +49,27 @@ impl WalWriter {
         })
     }
 
-    /// Append an entry to the WAL
+    /// Append an entry to the WAL, syncing as the sync policy says
     pub fn append(&self, entry: &WalEntry) -> Result<u64> {
-        let serialized = entry.serialize_with_checksum();
+        self.append_as(entry, None)
+    }
+
+    /// Append an entry and sync it to disk before returning, whatever the
+    /// sync policy
+    pub fn append_synced(&self, entry: &WalEntry) -> Result<u64> {
+        self.append_as(entry, Some(true))
+    }
+
+    /// Append an entry without syncing, or even leaving the process's
+    /// buffer; it reaches disk with the next sync
+    pub fn append_unsynced(&self, entry: &WalEntry) -> Result<u64> {
+        self.append_as(entry, Some(false))
+    }
+
+    /// Append an entry, syncing if `sync` says so or, without a say, if
+    /// the sync policy does
+    fn append_as(&self, entry: &WalEntry, sync: Option<bool>) -> Result<u64> {
+        let serialized = entry.serialize_with_checksum(self.config.checksum);
         let mut inner = self.inner.lock();
 
         // Check if we need to rotate to a new segment
@@ -64,8 +82,7 @@ impl WalWriter {
         inner.bytes_written += serialized.len();
         inner.writes_since_sync += 1;
 
-        // Sync based on policy
-        if self.should_sync(&inner) {
+        if sync.unwrap_or_else(|| self.should_sync(&inner)) {
             inner.file.flush()?;
             inner.file.get_ref().sync_all()?;
             inner.writes_since_sync = 0;
@@ -91,6 +108,24 @@ impl WalWriter {
         self.inner.lock().segment_id
     }
 
+    /// Close the active segment if it holds any entries and start a new one
+    ///
+    /// Returns the ID of the active segment afterwards; every lower segment
+    /// is complete and won't be written again.
+    pub fn seal(&self) -> Result<u64> {
+        let mut inner = self.inner.lock();
+        inner.file.flush()?;
+        if inner.file.get_ref().metadata()?.len() > 0 {
+            self.rotate_segment(&mut inner)?;
+        }
+        Ok(inner.segment_id)
+    }
+
+    /// Path of a segment file
+    pub fn segment_path(&self, segment_id: u64) -> PathBuf {
+        Self::segment_file(&self.config.dir, segment_id)
+    }
+
     /// Truncate WAL up to the given segment (used after memtable flush)
     pub fn truncate_before(&self, segment_id: u64) -> Result<usize> {
         let mut truncated = 0;
@@ -156,8 +191,12 @@ impl WalWriter {
         Ok(max_id)
     }
 
+    fn segment_file(dir: &Path, segment_id: u64) -> PathBuf {
+        dir.join(format!("wal_{:020}.log", segment_id))
+    }
+
     fn open_segment(dir: &PathBuf, segment_id: u64) -> Result<File> {
-        let path = dir.join(format!("wal_{:020}.log", segment_id));
+        let path = Self::segment_file(dir, segment_id);
         OpenOptions::new()
             .create(true)
             .append(true)
@@ -169,7 +208,7 @@ impl WalWriter {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{DataPoint, FieldValue, Point, SeriesKey};
+    use crate::{ChecksumKind, DataPoint, FieldValue, Point, SeriesKey};
     use tempfile::TempDir;
 
     #[test]
@@ -179,6 +218,9 @@ mod tests {
             dir: temp_dir.path().to_path_buf(),
             sync_policy: SyncPolicy::Immediate,
             segment_size: 1024,
+            mmap_recovery: false,
+            salvage_corrupt: false,
+            checksum: ChecksumKind::Crc32c,
         };
 
         let writer = WalWriter::new(config).unwrap();
@@ -193,4 +235,55 @@ mod tests {
 
         writer.sync().unwrap();
     }
+
+    #[test]
+    fn test_wal_append_synced_and_unsynced() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let writer = WalWriter::new(config).unwrap();
+        let on_disk = || std::fs::metadata(writer.segment_path(0)).unwrap().len();
+        let points = vec![Point::new(SeriesKey::new("temp"), DataPoint::new(1, "value", FieldValue::Float(1.0)))];
+        let entry = WalEntry::write("testdb", &points).unwrap();
+
+        // Buffered in the process even though the policy syncs every write
+        writer.append_unsynced(&entry).unwrap();
+        assert_eq!(on_disk(), 0);
+
+        // Synced along with the earlier entry
+        writer.append_synced(&entry).unwrap();
+        let synced = on_disk();
+        assert!(synced > 0);
+        assert_eq!(crate::wal::WalReader::parse_segment(&std::fs::read(writer.segment_path(0)).unwrap()).unwrap().len(), 2);
+
+        writer.append_unsynced(&entry).unwrap();
+        assert_eq!(on_disk(), synced);
+        writer.sync().unwrap();
+        assert!(on_disk() > synced);
+    }
+
+    #[test]
+    fn test_wal_seal() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let writer = WalWriter::new(config).unwrap();
+
+        // Nothing to seal yet
+        assert_eq!(writer.seal().unwrap(), 0);
+
+        let points = vec![Point::new(SeriesKey::new("temp"), DataPoint::new(1, "value", FieldValue::Float(1.0)))];
+        writer.append(&WalEntry::write("testdb", &points).unwrap()).unwrap();
+        assert_eq!(writer.seal().unwrap(), 1);
+        assert_eq!(writer.current_segment(), 1);
+        assert!(std::fs::metadata(writer.segment_path(0)).unwrap().len() > 0);
+
+        let entries = crate::wal::WalReader::parse_segment(&std::fs::read(writer.segment_path(0)).unwrap()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(writer.seal().unwrap(), 1);
+    }
 }
@@ -1,16 +1,18 @@
 //! HTTP API endpoints
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    body::Body,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Json, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
-use fluxdb_core::storage::StorageEngine;
-use fluxdb_core::{DataPoint, FieldValue, Fields, Point, SeriesKey};
+use fluxdb_core::query::{Cursor, QueryRow, QueryValue};
+use fluxdb_core::storage::{StorageConfig, StorageEngine, WriteConsistency};
+use fluxdb_core::{DataPoint, FieldValue, Fields, FluxError, Point, SeriesKey, TimeRange, Timestamp};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
@@ -18,8 +20,17 @@ use tower_http::trace::TraceLayer;
 /// Application state
 pub type AppState = Arc<StorageEngine>;
 
+/// Default cap on request bodies (32MB)
+pub const DEFAULT_MAX_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+/// Rows per page when a query passes a cursor without a page size
+pub const DEFAULT_PAGE_SIZE: usize = 1000;
+
 /// Create the API router
-pub fn create_router(engine: Arc<StorageEngine>) -> Router {
+///
+/// Request bodies larger than `max_body_size` bytes are rejected with
+/// 413 Payload Too Large before they are buffered.
+pub fn create_router(engine: Arc<StorageEngine>, max_body_size: usize) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
@@ -29,10 +40,12 @@ pub fn create_router(engine: Arc<StorageEngine>) -> Router {
         // Health check
         .route("/health", get(health))
         .route("/ping", get(ping))
+        .route("/version", get(version))
         
         // Write endpoint (InfluxDB compatible)
         .route("/write", post(write))
         .route("/api/v2/write", post(write_v2))
+        .route("/api/v1/prom/text", post(write_prom_text))
         
         // Query endpoint
         .route("/query", get(query).post(query))
@@ -41,11 +54,21 @@ pub fn create_router(engine: Arc<StorageEngine>) -> Router {
         // Database management
         .route("/databases", get(list_databases))
         .route("/databases/:name", post(create_database).delete(drop_database))
+        .route("/databases/:name/sstables", get(list_sstables))
+        .route("/databases/:name/compression", get(compression))
+        .route("/databases/:name/tag-values", get(tag_values))
+        .route("/databases/:name/debug/series", get(debug_series))
+        .route("/databases/:name/sync", post(sync_database))
+        .route("/databases/:name/data", delete(truncate_database))
+        
+        // Replication
+        .route("/databases/:name/wal/segments", get(wal_segments))
         
         // Stats
         .route("/stats", get(stats))
         .route("/metrics", get(metrics))
         
+        .layer(DefaultBodyLimit::max(max_body_size))
         .layer(cors)
         .layer(TraceLayer::new_for_http())
         .with_state(engine)
@@ -60,12 +83,94 @@ pub struct WriteParams {
     db: Option<String>,
     database: Option<String>,
     precision: Option<String>,
+    /// Field name for lines that carry a bare value instead of `key=value`
+    /// pairs, e.g. `cpu_usage,host=a 0.42`; unset rejects such lines
+    default_field: Option<String>,
+    /// Answer 200 with a [`WriteSummary`] instead of an empty 204
+    verbose: Option<bool>,
+    /// When to acknowledge the write: `memtable`, `wal_synced` (default)
+    /// or `flushed`; see [`WriteConsistency`]
+    consistency: Option<String>,
+    /// Parse and check the body as a write would, answering 200 with a
+    /// [`DryRunReport`] and storing nothing
+    dry_run: Option<bool>,
+}
+
+/// Consistency level a write request asks for
+fn write_consistency(params: &WriteParams) -> Result<WriteConsistency, (StatusCode, Json<ErrorResponse>)> {
+    match params.consistency.as_deref() {
+        None => Ok(WriteConsistency::default()),
+        Some(name) => WriteConsistency::from_name(name).ok_or_else(|| {
+            let error = format!("Unknown consistency: {} (expected memtable, wal_synced or flushed)", name);
+            (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }))
+        }),
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct QueryParams {
     db: Option<String>,
     q: Option<String>,
+    /// Output layout: `rows` (default), `columnar`, or `jsonl` for one
+    /// JSON object per row
+    format: Option<String>,
+    /// Indent the JSON response; compact by default
+    pretty: Option<bool>,
+    /// Page through a plain SELECT, returning at most this many rows and
+    /// a `next_cursor` while more remain
+    page_size: Option<usize>,
+    /// `next_cursor` of the previous page, to resume after it
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TagValuesParams {
+    measurement: String,
+    key: String,
+    /// Only count series with data at or after this timestamp (ns)
+    start: Option<i64>,
+    /// Only count series with data at or before this timestamp (ns)
+    end: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WalSegmentParams {
+    /// Last segment the replica applied; omitted to start from the oldest
+    after: Option<u64>,
+}
+
+/// Response header carrying the ID of the shipped WAL segment
+pub const WAL_SEGMENT_HEADER: &str = "x-fluxdb-wal-segment";
+
+/// Request header naming a write so retries of it are applied only once
+pub const IDEMPOTENCY_KEY_HEADER: &str = "x-idempotency-key";
+
+/// Response header InfluxDB clients read the server version from
+pub const INFLUXDB_VERSION_HEADER: &str = "x-influxdb-version";
+
+/// Response header InfluxDB clients read the server build from
+pub const INFLUXDB_BUILD_HEADER: &str = "x-influxdb-build";
+
+#[derive(Debug, Deserialize)]
+pub struct PingParams {
+    /// Answer 200 with the version in a JSON body instead of 204
+    verbose: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PingResponse {
+    pub version: String,
+}
+
+/// What the server is and supports, for clients to discover features
+#[derive(Debug, Serialize)]
+pub struct VersionResponse {
+    pub version: String,
+    /// SSTable format version written
+    pub format_version: u32,
+    /// Features available on this server as configured; see
+    /// [`capabilities`]
+    pub capabilities: Vec<&'static str>,
 }
 
 #[derive(Debug, Serialize)]
@@ -90,6 +195,62 @@ pub struct DatabaseStats {
     pub total_entries: usize,
 }
 
+#[derive(Debug, Serialize)]
+pub struct SSTableLayoutResponse {
+    pub database: String,
+    pub levels: Vec<LevelLayout>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LevelLayout {
+    pub level: u32,
+    pub sstables: Vec<SSTableInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SSTableInfo {
+    pub id: u64,
+    pub level: u32,
+    pub entry_count: usize,
+    pub file_size: u64,
+    pub min_timestamp: i64,
+    pub max_timestamp: i64,
+    pub min_key: String,
+    pub max_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompressionResponse {
+    pub database: String,
+    pub series: Vec<FieldCompressionInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldCompressionInfo {
+    pub series: String,
+    pub field: String,
+    pub points: u64,
+    pub encoded_bytes: u64,
+    pub stored_bytes: u64,
+    pub bytes_per_point: f64,
+    pub compression_ratio: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagValuesResponse {
+    pub database: String,
+    pub measurement: String,
+    pub key: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DebugSeriesResponse {
+    pub database: String,
+    pub series: String,
+    pub points: Vec<DataPoint>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct QueryResponse {
     pub results: Vec<QueryResult>,
@@ -102,13 +263,44 @@ pub struct QueryResult {
     pub series: Option<Vec<SeriesResult>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// Cursor for the next page of a paged query; absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct SeriesResult {
     pub name: String,
-    pub columns: Vec<String>,
-    pub values: Vec<Vec<serde_json::Value>>,
+    #[serde(flatten)]
+    pub data: SeriesData,
+}
+
+/// Series values, either per row or per column
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum SeriesData {
+    /// `{"columns": [...], "values": [[...], ...]}`
+    Rows {
+        columns: Vec<String>,
+        values: Vec<Vec<serde_json::Value>>,
+    },
+    /// `{"columns": {"time": [...], "value": [...]}}`
+    Columnar { columns: ColumnMap },
+}
+
+/// Column arrays keyed by name, serialized in column order
+#[derive(Debug)]
+pub struct ColumnMap(pub Vec<(String, Vec<serde_json::Value>)>);
+
+impl Serialize for ColumnMap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, values) in &self.0 {
+            map.serialize_entry(name, values)?;
+        }
+        map.end()
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -116,6 +308,77 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
+/// Body of a verbose write response; `errors` says why points were dropped
+#[derive(Debug, Serialize)]
+pub struct WriteSummary {
+    pub points_written: usize,
+    pub points_dropped: usize,
+    pub errors: Vec<String>,
+}
+
+impl WriteSummary {
+    fn new(given: usize, written: usize) -> Self {
+        let points_dropped = given - written;
+        let errors = match points_dropped {
+            0 => Vec::new(),
+            n => vec![format!("{} points dropped as duplicates of others in the batch", n)],
+        };
+        Self {
+            points_written: written,
+            points_dropped,
+            errors,
+        }
+    }
+
+    /// 200 with the summary when `verbose`, otherwise 204 as InfluxDB answers
+    fn into_response(self, verbose: Option<bool>) -> Response {
+        if verbose.unwrap_or(false) {
+            Json(self).into_response()
+        } else {
+            StatusCode::NO_CONTENT.into_response()
+        }
+    }
+}
+
+/// Body of a dry-run write response: what the write would have stored,
+/// or in `errors` why it would have been rejected
+#[derive(Debug, Serialize)]
+pub struct DryRunReport {
+    pub points_parsed: usize,
+    pub points_written: usize,
+    pub points_dropped: usize,
+    /// Series of the parsed points, each once
+    pub series: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl DryRunReport {
+    fn new(points: &[Point], checked: Result<usize, FluxError>) -> Self {
+        let series: BTreeSet<String> = points.iter().map(|p| p.key.encode()).collect();
+        let (points_written, errors) = match checked {
+            Ok(written) => (written, WriteSummary::new(points.len(), written).errors),
+            Err(e) => (0, vec![e.to_string()]),
+        };
+        Self {
+            points_parsed: points.len(),
+            points_written,
+            points_dropped: points.len() - points_written,
+            series: series.into_iter().collect(),
+            errors,
+        }
+    }
+
+    fn parse_error(error: String) -> Self {
+        Self {
+            points_parsed: 0,
+            points_written: 0,
+            points_dropped: 0,
+            series: Vec::new(),
+            errors: vec![error],
+        }
+    }
+}
+
 // ============================================================================
 // Handlers
 // ============================================================================
@@ -127,95 +390,299 @@ async fn health() -> Json<HealthResponse> {
     })
 }
 
-async fn ping() -> &'static str {
-    "pong"
+async fn version(State(engine): State<AppState>) -> Json<VersionResponse> {
+    Json(VersionResponse {
+        version: fluxdb_core::VERSION.to_string(),
+        format_version: fluxdb_core::sstable::FORMAT_VERSION,
+        capabilities: capabilities(engine.config()),
+    })
 }
 
+/// Feature flags a client can rely on with this configuration
+///
+/// Query features are always built in; write-side ones depend on the
+/// configuration, and a read-only replica lists none of them.
+fn capabilities(config: &StorageConfig) -> Vec<&'static str> {
+    let mut capabilities = vec!["joins", "set_ops", "ctes", "explain", "paged_queries"];
+    if !config.read_only {
+        capabilities.extend(["line_protocol", "prometheus_write", "select_into", "drop_measurement"]);
+        if config.idempotency_keys > 0 {
+            capabilities.push("idempotent_writes");
+        }
+        if config.auto_create_databases {
+            capabilities.push("auto_create_databases");
+        }
+    }
+    if config.query_parallelism > 1 {
+        capabilities.push("parallel_queries");
+    }
+    capabilities
+}
+
+/// Liveness probe in InfluxDB's style: 204 with version headers, which
+/// client libraries use to detect the server
+async fn ping(Query(params): Query<PingParams>) -> Response {
+    let headers = [
+        (INFLUXDB_VERSION_HEADER, fluxdb_core::VERSION),
+        (INFLUXDB_BUILD_HEADER, "FluxDB"),
+    ];
+    if params.verbose.unwrap_or(false) {
+        let body = PingResponse {
+            version: fluxdb_core::VERSION.to_string(),
+        };
+        (headers, Json(body)).into_response()
+    } else {
+        (StatusCode::NO_CONTENT, headers).into_response()
+    }
+}
+
+/// Write a line protocol body; with an `X-Idempotency-Key` header a
+/// repeat of an already applied write is acknowledged without applying it
+///
+/// With `dry_run=true` the body is only parsed and checked, and parse and
+/// write errors are reported in a 200 [`DryRunReport`] rather than failing
+/// the request.
 async fn write(
     State(engine): State<AppState>,
     Query(params): Query<WriteParams>,
+    headers: HeaderMap,
     body: String,
-) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let consistency = write_consistency(&params)?;
     let db = params.db.or(params.database).unwrap_or_else(|| "default".to_string());
     let precision = params.precision.unwrap_or_else(|| "ns".to_string());
 
-    let points = parse_line_protocol(&body, &precision)
-        .map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+    let parsed = parse_line_protocol(&body, &precision, engine.now(), params.default_field.as_deref());
+    if params.dry_run.unwrap_or(false) {
+        let report = match parsed {
+            Ok(lines) => DryRunReport::new(&lines.points, engine.check_write(&db, &lines.points)),
+            Err(e) => DryRunReport::parse_error(e),
+        };
+        return Ok(Json(report).into_response());
+    }
+    let ParsedLines { points, field_order } =
+        parsed.map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
 
     engine
-        .write(&db, &points)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e.to_string() })))?;
+        .record_field_order(&db, field_order.iter().map(|(measurement, name)| (measurement.as_str(), name.as_str())))
+        .map_err(|e| (error_status(&e, StatusCode::INTERNAL_SERVER_ERROR), Json(ErrorResponse { error: e.to_string() })))?;
 
-    Ok(StatusCode::NO_CONTENT)
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|key| key.to_str())
+        .transpose()
+        .map_err(|_| {
+            let error = format!("{} must be visible ASCII", IDEMPOTENCY_KEY_HEADER);
+            (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }))
+        })?;
+    let written = match idempotency_key {
+        Some(key) => engine.write_idempotent(&db, key, &points, consistency),
+        None => engine.write_with(&db, &points, consistency).map(Some),
+    }
+    .map_err(|e| (error_status(&e, StatusCode::INTERNAL_SERVER_ERROR), Json(ErrorResponse { error: e.to_string() })))?;
+
+    let summary = match written {
+        Some(written) => WriteSummary::new(points.len(), written),
+        None => WriteSummary {
+            points_written: 0,
+            points_dropped: points.len(),
+            errors: vec!["a write with this idempotency key was already applied".to_string()],
+        },
+    };
+    Ok(summary.into_response(params.verbose))
 }
 
 async fn write_v2(
     State(engine): State<AppState>,
     Query(params): Query<WriteParams>,
+    headers: HeaderMap,
     body: String,
-) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    write(State(engine), Query(params), body).await
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    write(State(engine), Query(params), headers, body).await
+}
+
+/// Write a Prometheus text exposition body, as pushed by a scraper
+async fn write_prom_text(
+    State(engine): State<AppState>,
+    Query(params): Query<WriteParams>,
+    body: String,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let consistency = write_consistency(&params)?;
+    let db = params.db.or(params.database).unwrap_or_else(|| "default".to_string());
+
+    let parsed = parse_prom_text(&body, engine.now());
+    if params.dry_run.unwrap_or(false) {
+        let report = match parsed {
+            Ok(points) => DryRunReport::new(&points, engine.check_write(&db, &points)),
+            Err(e) => DryRunReport::parse_error(e),
+        };
+        return Ok(Json(report).into_response());
+    }
+    let points = parsed.map_err(|e| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e })))?;
+
+    let written = engine
+        .write_with(&db, &points, consistency)
+        .map_err(|e| (error_status(&e, StatusCode::INTERNAL_SERVER_ERROR), Json(ErrorResponse { error: e.to_string() })))?;
+
+    Ok(WriteSummary::new(points.len(), written).into_response(params.verbose))
 }
 
 async fn query(
     State(engine): State<AppState>,
     Query(params): Query<QueryParams>,
-) -> Result<Json<QueryResponse>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    if params.format.as_deref() == Some("jsonl") {
+        if params.page_size.is_some() || params.cursor.is_some() {
+            return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "Paging isn't supported with format=jsonl".into() })));
+        }
+        let (db, sql) = query_target(params)?;
+        let result = engine
+            .query(&db, &sql)
+            .map_err(|e| (error_status(&e, StatusCode::BAD_REQUEST), Json(ErrorResponse { error: e.to_string() })))?;
+        return Ok(jsonl_response(result));
+    }
+    let pretty = params.pretty.unwrap_or(false);
+    let response = execute_query(&engine, params)?;
+    Ok(json_response(&response, pretty))
+}
+
+/// Database and SQL a query request names
+fn query_target(params: QueryParams) -> Result<(String, String), (StatusCode, Json<ErrorResponse>)> {
     let db = params.db.unwrap_or_else(|| "default".to_string());
     let sql = params.q.ok_or_else(|| {
         (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "Missing query parameter 'q'".into() }))
     })?;
+    Ok((db, sql))
+}
 
-    match engine.query(&db, &sql) {
-        Ok(result) => {
+fn execute_query(
+    engine: &StorageEngine,
+    params: QueryParams,
+) -> Result<QueryResponse, (StatusCode, Json<ErrorResponse>)> {
+    let format = params.format.clone();
+    let page_size = params.page_size;
+    let after = params.cursor.as_deref().map(Cursor::decode).transpose().map_err(|e| {
+        (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: e.to_string() }))
+    })?;
+    let (db, sql) = query_target(params)?;
+    let columnar = match format.as_deref() {
+        None | Some("rows") => false,
+        Some("columnar") => true,
+        Some(other) => {
+            return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("Unknown format: {}", other) })));
+        }
+    };
+
+    let outcome = if page_size.is_none() && after.is_none() {
+        engine.query(&db, &sql).map(|result| (result, None))
+    } else {
+        engine.query_page(&db, &sql, after, page_size.unwrap_or(DEFAULT_PAGE_SIZE))
+    };
+
+    match outcome {
+        Ok((result, next)) => {
             let series = if result.rows.is_empty() {
                 None
             } else {
+                let values: Vec<Vec<serde_json::Value>> = result.rows.into_iter().map(row_values).collect();
+
+                let data = if columnar {
+                    // Transpose, padding short rows with nulls so every
+                    // column array has one entry per row
+                    let columns = result.columns.into_iter().enumerate().map(|(i, name)| {
+                        let column = values
+                            .iter()
+                            .map(|row| row.get(i).cloned().unwrap_or(serde_json::Value::Null))
+                            .collect();
+                        (name, column)
+                    }).collect();
+                    SeriesData::Columnar { columns: ColumnMap(columns) }
+                } else {
+                    SeriesData::Rows { columns: result.columns, values }
+                };
+
                 Some(vec![SeriesResult {
                     name: "result".to_string(),
-                    columns: result.columns,
-                    values: result.rows.into_iter().map(|row| {
-                        let mut vals = Vec::new();
-                        if let Some(ts) = row.time {
-                            vals.push(serde_json::json!(ts));
-                        }
-                        if let Some(series) = row.series {
-                            vals.push(serde_json::json!(series));
-                        }
-                        for v in row.values {
-                            vals.push(match v {
-                                fluxdb_core::query::QueryValue::Null => serde_json::Value::Null,
-                                fluxdb_core::query::QueryValue::Float(f) => serde_json::json!(f),
-                                fluxdb_core::query::QueryValue::Integer(i) => serde_json::json!(i),
-                                fluxdb_core::query::QueryValue::String(s) => serde_json::json!(s),
-                                fluxdb_core::query::QueryValue::Boolean(b) => serde_json::json!(b),
-                            });
-                        }
-                        vals
-                    }).collect(),
+                    data,
                 }])
             };
 
-            Ok(Json(QueryResponse {
+            Ok(QueryResponse {
                 results: vec![QueryResult {
                     statement_id: 0,
                     series,
                     error: None,
+                    next_cursor: next.map(|cursor| cursor.encode()),
                 }],
-            }))
+            })
         }
         Err(e) => {
-            Ok(Json(QueryResponse {
+            Ok(QueryResponse {
                 results: vec![QueryResult {
                     statement_id: 0,
                     series: None,
                     error: Some(e.to_string()),
+                    next_cursor: None,
                 }],
-            }))
+            })
         }
     }
 }
 
+/// A result row as JSON values in column order: time and series first when
+/// present, then the selected values
+fn row_values(row: QueryRow) -> Vec<serde_json::Value> {
+    let mut vals = Vec::new();
+    if let Some(ts) = row.time {
+        vals.push(serde_json::json!(ts));
+    }
+    if let Some(series) = row.series {
+        vals.push(serde_json::json!(series));
+    }
+    for v in row.values {
+        vals.push(match v {
+            QueryValue::Null => serde_json::Value::Null,
+            QueryValue::Float(f) => serde_json::json!(f),
+            QueryValue::Integer(i) => serde_json::json!(i),
+            QueryValue::String(s) => serde_json::json!(s),
+            QueryValue::Boolean(b) => serde_json::json!(b),
+        });
+    }
+    vals
+}
+
+/// Stream a query result as JSON lines, one object per row keyed by column
+///
+/// Rows are serialized as the body is sent. Null values are written as
+/// explicit `null`, so every line has every column.
+fn jsonl_response(result: fluxdb_core::query::QueryResult) -> Response {
+    let columns = result.columns;
+    let lines = result.rows.into_iter().map(move |row| {
+        let values = row_values(row).into_iter().chain(std::iter::repeat(serde_json::Value::Null));
+        let object: serde_json::Map<String, serde_json::Value> = columns.iter().cloned().zip(values).collect();
+        serde_json::Value::Object(object)
+    });
+    let body = futures_util::stream::iter(lines.map(|line| {
+        let mut line = line.to_string();
+        line.push('\n');
+        Ok::<_, std::convert::Infallible>(line)
+    }));
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(body)).into_response()
+}
+
+/// Serialize a JSON body, indented when `pretty` is set
+fn json_response<T: Serialize>(value: &T, pretty: bool) -> Response {
+    let body = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    };
+    match body {
+        Ok(body) => ([(header::CONTENT_TYPE, "application/json")], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e.to_string() })).into_response(),
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct QueryV2Request {
     pub query: String,
@@ -225,10 +692,14 @@ pub struct QueryV2Request {
 async fn query_v2(
     State(engine): State<AppState>,
     Json(req): Json<QueryV2Request>,
-) -> Result<Json<QueryResponse>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     let params = QueryParams {
         db: req.database,
         q: Some(req.query),
+        format: None,
+        pretty: None,
+        page_size: None,
+        cursor: None,
     };
     query(State(engine), Query(params)).await
 }
@@ -245,7 +716,7 @@ async fn create_database(
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
     engine
         .create_database(&name)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e.to_string() })))?;
+        .map_err(|e| (error_status(&e, StatusCode::INTERNAL_SERVER_ERROR), Json(ErrorResponse { error: e.to_string() })))?;
     
     Ok(StatusCode::CREATED)
 }
@@ -256,11 +727,216 @@ async fn drop_database(
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
     engine
         .drop_database(&name)
-        .map_err(|e| (StatusCode::NOT_FOUND, Json(ErrorResponse { error: e.to_string() })))?;
+        .map_err(|e| (error_status(&e, StatusCode::NOT_FOUND), Json(ErrorResponse { error: e.to_string() })))?;
     
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Read-your-writes barrier: returns once every earlier write to the
+/// database is durable and visible to queries
+async fn sync_database(
+    State(engine): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let db = engine.get_database(&name).ok_or_else(|| {
+        (StatusCode::NOT_FOUND, Json(ErrorResponse { error: format!("Database not found: {}", name) }))
+    })?;
+    db.sync()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e.to_string() })))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove every point of the database, keeping it and its configuration
+async fn truncate_database(
+    State(engine): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let db = engine.get_database(&name).ok_or_else(|| {
+        (StatusCode::NOT_FOUND, Json(ErrorResponse { error: format!("Database not found: {}", name) }))
+    })?;
+    db.truncate()
+        .map_err(|e| (error_status(&e, StatusCode::INTERNAL_SERVER_ERROR), Json(ErrorResponse { error: e.to_string() })))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_sstables(
+    State(engine): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<SSTableLayoutResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let db = engine.get_database(&name).ok_or_else(|| {
+        (StatusCode::NOT_FOUND, Json(ErrorResponse { error: format!("Database not found: {}", name) }))
+    })?;
+
+    // SSTables come back sorted by (level, id), so levels can be grouped in one pass
+    let mut levels: Vec<LevelLayout> = Vec::new();
+    for meta in db.sstables() {
+        let info = SSTableInfo {
+            id: meta.id,
+            level: meta.level,
+            entry_count: meta.entry_count,
+            file_size: meta.file_size,
+            min_timestamp: meta.min_timestamp,
+            max_timestamp: meta.max_timestamp,
+            min_key: meta.min_key.encode(),
+            max_key: meta.max_key.encode(),
+        };
+        match levels.last_mut() {
+            Some(level) if level.level == meta.level => level.sstables.push(info),
+            _ => levels.push(LevelLayout {
+                level: meta.level,
+                sstables: vec![info],
+            }),
+        }
+    }
+
+    Ok(Json(SSTableLayoutResponse {
+        database: name,
+        levels,
+    }))
+}
+
+/// Per-series, per-field compression of the flushed data
+async fn compression(
+    State(engine): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<CompressionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let db = engine.get_database(&name).ok_or_else(|| {
+        (StatusCode::NOT_FOUND, Json(ErrorResponse { error: format!("Database not found: {}", name) }))
+    })?;
+    let stats = db.compression_stats().map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e.to_string() }))
+    })?;
+
+    let series = stats
+        .into_iter()
+        .map(|s| FieldCompressionInfo {
+            series: s.series_key.encode(),
+            bytes_per_point: s.bytes_per_point(),
+            compression_ratio: s.compression_ratio(),
+            field: s.field,
+            points: s.points,
+            encoded_bytes: s.encoded_bytes,
+            stored_bytes: s.stored_bytes,
+        })
+        .collect();
+
+    Ok(Json(CompressionResponse {
+        database: name,
+        series,
+    }))
+}
+
+/// Sorted distinct values of one tag key within a measurement
+async fn tag_values(
+    State(engine): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<TagValuesParams>,
+) -> Result<Json<TagValuesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let db = engine.get_database(&name).ok_or_else(|| {
+        (StatusCode::NOT_FOUND, Json(ErrorResponse { error: format!("Database not found: {}", name) }))
+    })?;
+
+    let range = TimeRange::new(params.start.unwrap_or(i64::MIN), params.end.unwrap_or(i64::MAX));
+    let values = db
+        .tag_values(&params.measurement, &params.key, &range)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e.to_string() })))?;
+
+    Ok(Json(TagValuesResponse {
+        database: name,
+        measurement: params.measurement,
+        key: params.key,
+        values,
+    }))
+}
+
+/// Dump the raw stored points of one series, bypassing the query engine
+///
+/// Query parameters: `measurement` (required), `tag=key:value` (repeatable),
+/// and optional `start` / `end` timestamps in nanoseconds.
+async fn debug_series(
+    State(engine): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Json<DebugSeriesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+
+    let mut measurement = None;
+    let mut tags = Vec::new();
+    let mut start = i64::MIN;
+    let mut end = i64::MAX;
+    for (key, value) in params {
+        match key.as_str() {
+            "measurement" => measurement = Some(value),
+            "tag" => {
+                let (k, v) = value
+                    .split_once(':')
+                    .ok_or_else(|| bad_request(format!("Invalid tag, expected key:value: {}", value)))?;
+                tags.push((k.to_string(), v.to_string()));
+            }
+            "start" => {
+                start = value.parse().map_err(|_| bad_request(format!("Invalid start: {}", value)))?;
+            }
+            "end" => {
+                end = value.parse().map_err(|_| bad_request(format!("Invalid end: {}", value)))?;
+            }
+            _ => return Err(bad_request(format!("Unknown parameter: {}", key))),
+        }
+    }
+    let measurement = measurement.ok_or_else(|| bad_request("Missing measurement".to_string()))?;
+
+    let mut series_key = SeriesKey::new(measurement);
+    for (k, v) in tags {
+        series_key = series_key.with_tag(k, v);
+    }
+
+    let db = engine.get_database(&name).ok_or_else(|| {
+        (StatusCode::NOT_FOUND, Json(ErrorResponse { error: format!("Database not found: {}", name) }))
+    })?;
+
+    let points = db
+        .query_series(&series_key, &TimeRange::new(start, end))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e.to_string() })))?;
+
+    Ok(Json(DebugSeriesResponse {
+        database: name,
+        series: series_key.encode(),
+        points,
+    }))
+}
+
+/// Ship the next complete WAL segment after `after` to a replica
+///
+/// Responds with the raw segment bytes and its ID in the
+/// `X-FluxDB-Wal-Segment` header, 204 once the replica is caught up, and 410
+/// if the segment was truncated after a flush.
+async fn wal_segments(
+    State(engine): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<WalSegmentParams>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let db = engine.get_database(&name).ok_or_else(|| {
+        (StatusCode::NOT_FOUND, Json(ErrorResponse { error: format!("Database not found: {}", name) }))
+    })?;
+
+    match db.wal_segment_after(params.after) {
+        Ok(Some((segment_id, data))) => Ok((
+            [
+                (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                (header::HeaderName::from_static(WAL_SEGMENT_HEADER), segment_id.to_string()),
+            ],
+            data,
+        )
+            .into_response()),
+        Ok(None) => Ok(StatusCode::NO_CONTENT.into_response()),
+        Err(e @ FluxError::WalSegmentUnavailable(_)) => {
+            Err((StatusCode::GONE, Json(ErrorResponse { error: e.to_string() })))
+        }
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse { error: e.to_string() }))),
+    }
+}
+
 async fn stats(State(engine): State<AppState>) -> Json<StatsResponse> {
     let stats = engine.stats();
     Json(StatsResponse {
@@ -303,12 +979,42 @@ async fn metrics(State(engine): State<AppState>) -> String {
     output
 }
 
+/// Status for a storage error; writes to a replica are forbidden and
+/// invalid names and timestamps are the client's fault
+fn error_status(e: &FluxError, fallback: StatusCode) -> StatusCode {
+    match e {
+        FluxError::ReadOnly(_) => StatusCode::FORBIDDEN,
+        FluxError::InvalidName(_) | FluxError::InvalidTimestamp(_) => StatusCode::BAD_REQUEST,
+        FluxError::CardinalityLimit(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        FluxError::WriteInProgress(_) => StatusCode::CONFLICT,
+        FluxError::DatabaseNotFound(_) | FluxError::MeasurementNotFound(_) => StatusCode::NOT_FOUND,
+        _ => fallback,
+    }
+}
+
 // ============================================================================
 // Line Protocol Parser
 // ============================================================================
 
-fn parse_line_protocol(data: &str, precision: &str) -> Result<Vec<Point>, String> {
+/// Points parsed from a line protocol body
+struct ParsedLines {
+    points: Vec<Point>,
+    /// Measurement and field name pairs in the order the lines first list
+    /// them, which the points' sorted fields don't keep
+    field_order: Vec<(String, String)>,
+}
+
+/// Parse a line protocol body; lines without a timestamp get `now`, and a
+/// bare value is stored under `default_field` when one is given
+fn parse_line_protocol(
+    data: &str,
+    precision: &str,
+    now: Timestamp,
+    default_field: Option<&str>,
+) -> Result<ParsedLines, String> {
     let mut points = Vec::new();
+    let mut field_order = Vec::new();
+    let mut seen = HashSet::new();
     let precision_multiplier = match precision {
         "ns" => 1,
         "us" | "u" => 1_000,
@@ -323,59 +1029,82 @@ fn parse_line_protocol(data: &str, precision: &str) -> Result<Vec<Point>, String
             continue;
         }
 
-        let point = parse_line(line, precision_multiplier)?;
+        let (point, names) = parse_line(line, precision_multiplier, now, default_field)?;
+        for name in names {
+            let field = (point.key.measurement.clone(), name);
+            if seen.insert(field.clone()) {
+                field_order.push(field);
+            }
+        }
         points.push(point);
     }
 
-    Ok(points)
+    Ok(ParsedLines { points, field_order })
 }
 
-fn parse_line(line: &str, precision_multiplier: i64) -> Result<Point, String> {
+/// Parse one line into a point and its field names in the order listed
+fn parse_line(
+    line: &str,
+    precision_multiplier: i64,
+    now: Timestamp,
+    default_field: Option<&str>,
+) -> Result<(Point, Vec<String>), String> {
     // Format: measurement,tag1=val1,tag2=val2 field1=val1,field2=val2 timestamp
     // Example: temperature,sensor=s1,location=room1 value=23.5 1609459200000000000
 
-    let parts: Vec<&str> = line.splitn(3, ' ').collect();
-    if parts.len() < 2 {
-        return Err("Invalid line format".to_string());
-    }
+    // The series ends at the first space not escaped with a backslash
+    let mut escaped = false;
+    let key_end = line
+        .char_indices()
+        .find(|&(_, c)| {
+            let end = c == ' ' && !escaped;
+            escaped = c == '\\' && !escaped;
+            end
+        })
+        .map(|(i, _)| i)
+        .ok_or("Invalid line format")?;
+    let series_key = SeriesKey::decode(&line[..key_end]).map_err(|e| e.to_string())?;
 
-    // Parse measurement and tags
-    let measurement_tags: Vec<&str> = parts[0].split(',').collect();
-    let measurement = measurement_tags[0];
-    
-    let mut series_key = SeriesKey::new(measurement);
-    for tag in measurement_tags.iter().skip(1) {
-        if let Some((k, v)) = tag.split_once('=') {
-            series_key = series_key.with_tag(k, v);
-        }
-    }
+    let mut parts = line[key_end + 1..].splitn(2, ' ');
+    let field_set = parts.next().unwrap_or_default();
+    let timestamp = parts.next();
 
     // Parse fields
     let mut fields = Fields::new();
-    for field in parts[1].split(',') {
+    let mut names = Vec::new();
+    if !field_set.contains('=') {
+        let name = default_field
+            .ok_or_else(|| format!("Missing field name for bare value: {}", field_set))?;
+        fields.insert(name, parse_field_value(field_set)?);
+        names.push(name.to_string());
+    }
+    for field in field_set.split(',') {
         if let Some((k, v)) = field.split_once('=') {
             let value = parse_field_value(v)?;
             fields.insert(k, value);
+            names.push(k.to_string());
         }
     }
 
     // Parse timestamp
-    let timestamp = if parts.len() > 2 {
-        parts[2]
+    let timestamp = if let Some(timestamp) = timestamp {
+        timestamp
             .parse::<i64>()
-            .map_err(|_| "Invalid timestamp")?
-            * precision_multiplier
+            .ok()
+            .and_then(|timestamp| timestamp.checked_mul(precision_multiplier))
+            .ok_or_else(|| format!("Invalid timestamp: {}", timestamp))?
     } else {
-        chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0)
+        now
     };
 
-    Ok(Point::new(
+    let point = Point::new(
         series_key,
         DataPoint {
             timestamp,
             fields,
         },
-    ))
+    );
+    Ok((point, names))
 }
 
 fn parse_field_value(s: &str) -> Result<FieldValue, String> {
@@ -407,6 +1136,119 @@ fn parse_field_value(s: &str) -> Result<FieldValue, String> {
     Ok(FieldValue::Float(n))
 }
 
+// ============================================================================
+// Prometheus Text Parser
+// ============================================================================
+
+/// Parse a Prometheus text exposition body
+///
+/// Each sample becomes a point in the measurement named after the metric,
+/// with its labels as tags and its value in the `value` field. Histogram
+/// and summary samples keep their `_bucket`/`_sum`/`_count` suffixes, so
+/// each lands in its own series. `# HELP` and `# TYPE` lines are skipped,
+/// and samples without a timestamp get `now`.
+fn parse_prom_text(data: &str, now: Timestamp) -> Result<Vec<Point>, String> {
+    let mut points = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let point = parse_prom_sample(line, now).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        points.push(point);
+    }
+
+    Ok(points)
+}
+
+fn parse_prom_sample(line: &str, now: Timestamp) -> Result<Point, String> {
+    // Format: metric_name{label="value",...} value [timestamp_ms]
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or_else(|| "Missing sample value".to_string())?;
+    let name = &line[..name_end];
+    if name.is_empty() {
+        return Err("Missing metric name".to_string());
+    }
+
+    let mut series_key = SeriesKey::new(name);
+    let mut rest = &line[name_end..];
+    if let Some(labels) = rest.strip_prefix('{') {
+        let (labels, after) = parse_prom_labels(labels)?;
+        for (k, v) in labels {
+            series_key = series_key.with_tag(k, v);
+        }
+        rest = after;
+    }
+
+    let mut parts = rest.split_whitespace();
+    let value = parts.next().ok_or_else(|| "Missing sample value".to_string())?;
+    // Rust's float parser already accepts NaN, +Inf and -Inf
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid sample value: {}", value))?;
+
+    // Prometheus timestamps are milliseconds since the epoch
+    let timestamp = match parts.next() {
+        Some(ts) => ts
+            .parse::<i64>()
+            .map_err(|_| format!("Invalid timestamp: {}", ts))?
+            .checked_mul(1_000_000)
+            .ok_or_else(|| format!("Timestamp out of range: {}", ts))?,
+        None => now,
+    };
+    if let Some(extra) = parts.next() {
+        return Err(format!("Unexpected trailing data: {}", extra));
+    }
+
+    Ok(Point::new(series_key, DataPoint::new(timestamp, "value", FieldValue::Float(value))))
+}
+
+/// Label name/value pairs of a Prometheus sample
+type PromLabels = Vec<(String, String)>;
+
+/// Parse a label set up to its closing brace, returning the labels and
+/// whatever follows the brace
+fn parse_prom_labels(s: &str) -> Result<(PromLabels, &str), String> {
+    let mut labels = Vec::new();
+    let mut rest = s.trim_start();
+    loop {
+        if let Some(after) = rest.strip_prefix('}') {
+            return Ok((labels, after));
+        }
+
+        let eq = rest.find('=').ok_or_else(|| "Unterminated label set".to_string())?;
+        let name = rest[..eq].trim();
+        if name.is_empty() {
+            return Err("Empty label name".to_string());
+        }
+        rest = rest[eq + 1..]
+            .trim_start()
+            .strip_prefix('"')
+            .ok_or_else(|| format!("Label {} must have a quoted value", name))?;
+
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((i, '"')) => break i,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => return Err("Unterminated label value".to_string()),
+                },
+                Some((_, c)) => value.push(c),
+                None => return Err("Unterminated label value".to_string()),
+            }
+        };
+        labels.push((name.to_string(), value));
+
+        rest = rest[end + 1..].trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -414,11 +1256,38 @@ mod tests {
     #[test]
     fn test_parse_line_protocol() {
         let line = "temperature,sensor=s1,location=room1 value=23.5,humidity=45.2 1609459200000000000";
-        let point = parse_line(line, 1).unwrap();
+        let (point, names) = parse_line(line, 1, 0, None).unwrap();
         
         assert_eq!(point.key.measurement, "temperature");
+        assert_eq!(names, vec!["value", "humidity"]);
         assert_eq!(point.key.tags.get("sensor"), Some(&"s1".to_string()));
         assert_eq!(point.data.timestamp, 1609459200000000000);
+
+        // Escaped delimiters belong to the name or value
+        let line = r"disk\ io,path=C:\\tmp\,1,mount\=point=/a\ b used=1 10";
+        let (point, _) = parse_line(line, 1, 0, None).unwrap();
+        let key = SeriesKey::new("disk io").with_tag("path", r"C:\tmp,1").with_tag("mount=point", "/a b");
+        assert_eq!(point.key, key);
+        assert_eq!(point.data.timestamp, 10);
+        assert_eq!(SeriesKey::decode(&point.key.encode()).unwrap(), key);
+        assert!(parse_line("cpu,host value=1", 1, 0, None).is_err());
+        assert!(parse_line("cpu", 1, 0, None).is_err());
+    }
+
+    #[test]
+    fn test_parse_bare_value() {
+        let line = "cpu_usage,host=a 0.42 1000";
+        assert!(parse_line(line, 1, 0, None).is_err());
+
+        let (point, _) = parse_line(line, 1, 0, Some("value")).unwrap();
+        assert_eq!(point.key.measurement, "cpu_usage");
+        assert_eq!(point.data.fields.get("value"), Some(&FieldValue::Float(0.42)));
+        assert_eq!(point.data.timestamp, 1000);
+
+        // Lines with field names are unaffected by the default
+        let (point, _) = parse_line("cpu usage=1i", 1, 5, Some("value")).unwrap();
+        assert_eq!(point.data.fields.get("usage"), Some(&FieldValue::Integer(1)));
+        assert!(point.data.fields.get("value").is_none());
     }
 
     #[test]
@@ -428,4 +1297,902 @@ mod tests {
         assert!(matches!(parse_field_value("\"hello\""), Ok(FieldValue::String(_))));
         assert!(matches!(parse_field_value("true"), Ok(FieldValue::Boolean(true))));
     }
+
+    #[test]
+    fn test_parse_prom_text() {
+        let body = r#"
+# HELP http_requests_total Total HTTP requests
+# TYPE http_requests_total counter
+http_requests_total{method="post",path="/api \"v1\""} 1027 1395066363000
+http_requests_total{method="get"} 3
+# TYPE rpc_duration_seconds histogram
+rpc_duration_seconds_bucket{le="0.5"} 12 1395066363000
+rpc_duration_seconds_bucket{le="+Inf"} 14 1395066363000
+rpc_duration_seconds_sum 8.25 1395066363000
+rpc_duration_seconds_count 14 1395066363000
+up NaN
+"#;
+        let points = parse_prom_text(body, 42).unwrap();
+        assert_eq!(points.len(), 7);
+
+        let first = &points[0];
+        assert_eq!(first.key.measurement, "http_requests_total");
+        assert_eq!(first.key.tags.get("method"), Some(&"post".to_string()));
+        assert_eq!(first.key.tags.get("path"), Some(&"/api \"v1\"".to_string()));
+        assert_eq!(first.data.timestamp, 1_395_066_363_000_000_000);
+        assert!(matches!(first.data.fields.get("value"), Some(FieldValue::Float(v)) if *v == 1027.0));
+
+        // No timestamp means "now"
+        assert_eq!(points[1].data.timestamp, 42);
+
+        // Histogram parts are separate series
+        let names: Vec<_> = points[2..6].iter().map(|p| p.key.measurement.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "rpc_duration_seconds_bucket",
+                "rpc_duration_seconds_bucket",
+                "rpc_duration_seconds_sum",
+                "rpc_duration_seconds_count",
+            ]
+        );
+        assert_eq!(points[3].key.tags.get("le"), Some(&"+Inf".to_string()));
+        assert!(matches!(points[6].data.fields.get("value"), Some(FieldValue::Float(v)) if v.is_nan()));
+
+        assert!(parse_prom_text("up", 0).is_err());
+        assert!(parse_prom_text("up{job=\"a} 1", 0).is_err());
+        assert!(parse_prom_text("up 1 2 3", 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ping_headers() {
+        let response = ping(Query(PingParams { verbose: None })).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[INFLUXDB_VERSION_HEADER], fluxdb_core::VERSION);
+        assert_eq!(response.headers()[INFLUXDB_BUILD_HEADER], "FluxDB");
+
+        let response = ping(Query(PingParams { verbose: Some(true) })).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[INFLUXDB_VERSION_HEADER], fluxdb_core::VERSION);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["version"], fluxdb_core::VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_version_capabilities() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config.clone()).unwrap());
+        let Json(response) = version(State(engine)).await;
+        let body = serde_json::to_value(&response).unwrap();
+        assert_eq!(body["version"], fluxdb_core::VERSION);
+        assert_eq!(body["format_version"], fluxdb_core::sstable::FORMAT_VERSION);
+        let capabilities: Vec<&str> = body["capabilities"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c.as_str().unwrap())
+            .collect();
+        for expected in ["joins", "set_ops", "prometheus_write", "idempotent_writes", "auto_create_databases"] {
+            assert!(capabilities.contains(&expected), "{:?}", capabilities);
+        }
+        assert!(!capabilities.contains(&"parallel_queries"));
+
+        // The list follows the configuration
+        let replica = StorageConfig {
+            data_dir: temp_dir.path().join("replica"),
+            read_only: true,
+            idempotency_keys: 0,
+            query_parallelism: 4,
+            ..config
+        };
+        let Json(response) = version(State(Arc::new(StorageEngine::new(replica).unwrap()))).await;
+        assert!(response.capabilities.contains(&"joins"));
+        assert!(response.capabilities.contains(&"parallel_queries"));
+        for write_side in ["line_protocol", "prometheus_write", "idempotent_writes"] {
+            assert!(!response.capabilities.contains(&write_side), "{:?}", response.capabilities);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_rejects_invalid_names() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            max_name_length: Some(16),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = || WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: None,
+            default_field: None,
+            verbose: None,
+            consistency: None,
+            dry_run: None,
+        };
+
+        let ok = write(State(engine.clone()), Query(params()), HeaderMap::new(), "cpu,host=a usage=1 1000".to_string()).await;
+        assert_eq!(ok.unwrap().status(), StatusCode::NO_CONTENT);
+
+        for line in ["cpu,=a usage=1 1000", "cpu,host=a =1 1000", "cpu,host=a a_very_long_field_name=1 1000"] {
+            let result = write(State(engine.clone()), Query(params()), HeaderMap::new(), line.to_string()).await;
+            assert!(matches!(result, Err((StatusCode::BAD_REQUEST, _))), "{}", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_rejects_timestamps_out_of_bounds() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            min_timestamp: Some(1_500_000_000_000_000_000),
+            max_timestamp: Some(2_000_000_000_000_000_000),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = || WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: Some("s".to_string()),
+            default_field: None,
+            verbose: None,
+            consistency: None,
+            dry_run: None,
+        };
+        let write_line = |line: &str| write(State(engine.clone()), Query(params()), HeaderMap::new(), line.to_string());
+
+        let ok = write_line("cpu,host=a usage=1 1700000000").await;
+        assert_eq!(ok.unwrap().status(), StatusCode::NO_CONTENT);
+
+        // A timestamp typed in the wrong precision lands in 1970, and the
+        // whole batch is rejected for it
+        let Err((status, Json(error))) = write_line("cpu,host=a usage=2 1700000001\ncpu,host=a usage=3 1700000").await else {
+            panic!("out of bounds timestamp accepted");
+        };
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error.error.contains("1700000000000000 of series cpu,host=a"), "{}", error.error);
+
+        // Past what nanoseconds can hold
+        let result = write_line("cpu,host=a usage=4 99999999999").await;
+        assert!(matches!(result, Err((StatusCode::BAD_REQUEST, _))));
+
+        let result = engine.query("testdb", "SELECT usage FROM cpu").unwrap();
+        assert_eq!(result.rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_write_records_field_order() {
+        use fluxdb_core::storage::FieldOrder;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            field_order: FieldOrder::Written,
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = || WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: None,
+            default_field: None,
+            verbose: None,
+            consistency: None,
+            dry_run: None,
+        };
+        let body = "cpu,host=a zeta=1,alpha=2 1000\ncpu,host=b mid=3,zeta=4 2000";
+        write(State(engine.clone()), Query(params()), HeaderMap::new(), body.to_string()).await.unwrap();
+
+        let result = engine.query("testdb", "SELECT * FROM cpu").unwrap();
+        assert_eq!(result.columns, vec!["time", "series", "zeta", "alpha", "mid"]);
+    }
+
+    #[tokio::test]
+    async fn test_write_consistency_param() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = |consistency: &str| WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: None,
+            default_field: None,
+            verbose: None,
+            consistency: Some(consistency.to_string()),
+            dry_run: None,
+        };
+
+        let result = write(State(engine.clone()), Query(params("flushed")), HeaderMap::new(), "cpu usage=1 1000".to_string()).await;
+        assert_eq!(result.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(engine.get_database("testdb").unwrap().sstables().len(), 1);
+
+        let result = write(State(engine.clone()), Query(params("quorum")), HeaderMap::new(), "cpu usage=1 2000".to_string()).await;
+        assert!(matches!(result, Err((StatusCode::BAD_REQUEST, _))));
+    }
+
+    #[tokio::test]
+    async fn test_write_dry_run() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            strict_ordering: true,
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = |dry_run| WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: None,
+            default_field: None,
+            verbose: None,
+            consistency: None,
+            dry_run,
+        };
+        let dry_run = |body: &str| {
+            let engine = engine.clone();
+            let body = body.to_string();
+            async move {
+                let response = write(State(engine), Query(params(Some(true))), HeaderMap::new(), body).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        let report = dry_run("cpu,host=b usage=1 1000\ncpu,host=a usage=2 1000\nmem,host=a used=3i 1000").await;
+        assert_eq!(report["points_parsed"], 3);
+        assert_eq!(report["points_written"], 3);
+        assert_eq!(report["series"], serde_json::json!(["cpu,host=a", "cpu,host=b", "mem,host=a"]));
+        assert_eq!(report["errors"], serde_json::json!([]));
+        // Nothing stored, and the database isn't even created
+        assert!(engine.list_databases().is_empty());
+
+        let report = dry_run("cpu,host=a usage=oops 1000").await;
+        assert_eq!(report["points_parsed"], 0);
+        assert_eq!(report["errors"].as_array().unwrap().len(), 1);
+        let report = dry_run("cpu,host=a bad\x01name=1 1000").await;
+        assert_eq!(report["points_written"], 0);
+        assert_eq!(report["points_dropped"], 1);
+        assert!(report["errors"][0].as_str().unwrap().contains("name"), "{}", report);
+
+        // Checked against stored data, without counting towards later writes
+        write(State(engine.clone()), Query(params(None)), HeaderMap::new(), "cpu,host=a usage=1 5000".to_string()).await.unwrap();
+        let report = dry_run("cpu,host=a usage=2 4000").await;
+        assert!(report["errors"][0].as_str().unwrap().contains("out-of-order"), "{}", report);
+        let report = dry_run("cpu,host=a usage=2 9000").await;
+        assert_eq!(report["points_written"], 1);
+        write(State(engine.clone()), Query(params(None)), HeaderMap::new(), "cpu,host=a usage=3 6000".to_string()).await.unwrap();
+
+        let result = engine.query("testdb", "SELECT usage FROM cpu").unwrap();
+        assert_eq!(result.rows.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_write_defaults_timestamp_to_clock() {
+        use fluxdb_core::storage::StorageConfig;
+        use fluxdb_core::MockClock;
+        use std::time::Duration;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let clock = Arc::new(MockClock::new(1_000_000_000));
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = || WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: Some("s".to_string()),
+            default_field: None,
+            verbose: None,
+            consistency: None,
+            dry_run: None,
+        };
+
+        // Every line of a request without its own timestamp shares the
+        // request's arrival time
+        let body = "cpu,host=a usage=1\ncpu,host=b usage=2\ncpu,host=c usage=3 5".to_string();
+        write(State(engine.clone()), Query(params()), HeaderMap::new(), body).await.unwrap();
+        clock.advance(Duration::from_secs(1));
+        write(State(engine.clone()), Query(params()), HeaderMap::new(), "cpu,host=a usage=4".to_string()).await.unwrap();
+
+        let result = engine.query("testdb", "SELECT usage FROM cpu").unwrap();
+        let mut times: Vec<_> = result.rows.iter().filter_map(|r| r.time).collect();
+        times.sort();
+        assert_eq!(times, vec![1_000_000_000, 1_000_000_000, 2_000_000_000, 5_000_000_000]);
+    }
+
+    #[tokio::test]
+    async fn test_write_verbose_response() {
+        use fluxdb_core::storage::{DuplicatePoints, StorageConfig};
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            duplicate_points: DuplicatePoints::KeepLast,
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = |verbose| WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: None,
+            default_field: None,
+            verbose,
+            consistency: None,
+            dry_run: None,
+        };
+        let body = || "cpu,host=a usage=1 1000\ncpu,host=a usage=2 1000\ncpu,host=b usage=3 1000".to_string();
+
+        // InfluxDB's empty 204 unless asked
+        let response = write(State(engine.clone()), Query(params(None)), HeaderMap::new(), body()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = write(State(engine.clone()), Query(params(Some(true))), HeaderMap::new(), body()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(summary["points_written"], 2);
+        assert_eq!(summary["points_dropped"], 1);
+        assert_eq!(summary["errors"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_write_idempotency_key() {
+        use fluxdb_core::storage::StorageConfig;
+        use fluxdb_core::MockClock;
+        use std::time::Duration;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let clock = Arc::new(MockClock::new(1_000));
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = || WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: None,
+            default_field: None,
+            verbose: None,
+            consistency: None,
+            dry_run: None,
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(IDEMPOTENCY_KEY_HEADER, "batch-1".parse().unwrap());
+
+        // The retry would land at a later timestamp if it were applied
+        let body = || "cpu usage=1".to_string();
+        let first = write(State(engine.clone()), Query(params()), headers.clone(), body()).await;
+        assert_eq!(first.unwrap().status(), StatusCode::NO_CONTENT);
+        clock.advance(Duration::from_secs(1));
+        let retry = write(State(engine.clone()), Query(params()), headers, body()).await;
+        assert_eq!(retry.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(engine.query("testdb", "SELECT usage FROM cpu").unwrap().rows.len(), 1);
+
+        // Unkeyed writes are always applied
+        write(State(engine.clone()), Query(params()), HeaderMap::new(), body()).await.unwrap();
+        assert_eq!(engine.query("testdb", "SELECT usage FROM cpu").unwrap().rows.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_write_body_size_limit() {
+        use fluxdb_core::storage::StorageConfig;
+        use http_body_util::Full;
+        use hyper::body::Bytes;
+        use hyper_util::rt::TokioIo;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+
+        // Serve for real: the limit is enforced while extracting the body
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = create_router(engine.clone(), 1024);
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+        let post = |body: String| async move {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await.unwrap();
+            tokio::spawn(connection);
+            let request = hyper::Request::post("/write?db=testdb")
+                .header(header::HOST, addr.to_string())
+                .body(Full::new(Bytes::from(body)))
+                .unwrap();
+            sender.send_request(request).await.unwrap().status()
+        };
+
+        assert_eq!(post("cpu,host=a usage=1 1000".to_string()).await, StatusCode::NO_CONTENT);
+
+        let oversized: String = (0..100).map(|i| format!("cpu,host=a usage={} {}\n", i, i)).collect();
+        assert!(oversized.len() > 1024);
+        assert_eq!(post(oversized).await, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let result = engine.query("testdb", "SELECT count(usage) FROM cpu").unwrap();
+        assert_eq!(result.rows[0].values[0], fluxdb_core::query::QueryValue::Integer(1));
+    }
+
+    #[tokio::test]
+    async fn test_list_sstables() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+
+        let points = parse_line_protocol(
+            "cpu,host=a usage=10.5 1000\ncpu,host=a usage=11.5 2000\ncpu,host=b usage=12.5 3000",
+            "ns",
+            0,
+            None,
+        ).unwrap().points;
+        engine.write("testdb", &points).unwrap();
+        engine.flush_all().unwrap();
+
+        let Json(layout) = list_sstables(State(engine.clone()), Path("testdb".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(layout.database, "testdb");
+        assert_eq!(layout.levels.len(), 1);
+
+        let sst = &layout.levels[0].sstables[0];
+        assert_eq!(sst.level, 0);
+        assert!(sst.id > 0);
+        assert_eq!(sst.entry_count, 3);
+        assert!(sst.file_size > 0);
+        assert_eq!(sst.min_timestamp, 1000);
+        assert_eq!(sst.max_timestamp, 3000);
+        assert_eq!(sst.min_key, "cpu,host=a");
+        assert_eq!(sst.max_key, "cpu,host=b");
+
+        let missing = list_sstables(State(engine), Path("nope".to_string())).await;
+        assert!(matches!(missing, Err((StatusCode::NOT_FOUND, _))));
+    }
+
+    #[tokio::test]
+    async fn test_compression_stats() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+
+        let lines: Vec<String> = (0..500)
+            .map(|i| format!("cpu,host=a usage=50 {}", i * 1_000_000_000i64))
+            .collect();
+        let points = parse_line_protocol(&lines.join("\n"), "ns", 0, None).unwrap().points;
+        engine.write("testdb", &points).unwrap();
+        engine.flush_all().unwrap();
+
+        let Json(response) = compression(State(engine.clone()), Path("testdb".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(response.database, "testdb");
+        assert_eq!(response.series.len(), 1);
+
+        let usage = &response.series[0];
+        assert_eq!(usage.series, "cpu,host=a");
+        assert_eq!(usage.field, "usage");
+        assert_eq!(usage.points, 500);
+        assert!(usage.bytes_per_point < 0.5, "{}", usage.bytes_per_point);
+        assert!(usage.compression_ratio > 32.0);
+
+        let missing = compression(State(engine), Path("nope".to_string())).await;
+        assert!(matches!(missing, Err((StatusCode::NOT_FOUND, _))));
+    }
+
+    #[tokio::test]
+    async fn test_sync_makes_writes_visible() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: None,
+            default_field: None,
+            verbose: None,
+            consistency: None,
+            dry_run: None,
+        };
+
+        write(State(engine.clone()), Query(params), HeaderMap::new(), "cpu usage=42 1000".to_string()).await.unwrap();
+        let status = sync_database(State(engine.clone()), Path("testdb".to_string())).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let result = engine.query("testdb", "SELECT usage FROM cpu").unwrap();
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].time, Some(1000));
+        assert_eq!(result.rows[0].values, vec![fluxdb_core::query::QueryValue::Float(42.0)]);
+
+        let missing = sync_database(State(engine), Path("nope".to_string())).await;
+        assert!(matches!(missing, Err((StatusCode::NOT_FOUND, _))));
+    }
+
+    #[tokio::test]
+    async fn test_truncate_database() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let params = WriteParams {
+            db: Some("testdb".to_string()),
+            database: None,
+            precision: None,
+            default_field: None,
+            verbose: None,
+            consistency: Some("flushed".to_string()),
+            dry_run: None,
+        };
+        write(State(engine.clone()), Query(params), HeaderMap::new(), "cpu usage=42 1000".to_string()).await.unwrap();
+        assert_eq!(engine.get_database("testdb").unwrap().sstables().len(), 1);
+
+        let status = truncate_database(State(engine.clone()), Path("testdb".to_string())).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(engine.list_databases(), vec!["testdb".to_string()]);
+        assert!(engine.get_database("testdb").unwrap().sstables().is_empty());
+        assert!(engine.query("testdb", "SELECT usage FROM cpu").is_err());
+
+        let missing = truncate_database(State(engine), Path("nope".to_string())).await;
+        assert!(matches!(missing, Err((StatusCode::NOT_FOUND, _))));
+    }
+
+    #[tokio::test]
+    async fn test_tag_values() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+
+        let points = parse_line_protocol(
+            "cpu,host=web2,dc=east usage=1 1000\ncpu,host=db1 usage=2 2000\nmem,host=cache usage=3 2500",
+            "ns",
+            0,
+            None,
+        ).unwrap().points;
+        engine.write("testdb", &points).unwrap();
+        engine.flush_all().unwrap();
+        let points = parse_line_protocol("cpu,host=web1 usage=4 3000\ncpu,host=db1 usage=5 4000", "ns", 0, None).unwrap().points;
+        engine.write("testdb", &points).unwrap();
+
+        let params = |start: Option<i64>, end: Option<i64>| TagValuesParams {
+            measurement: "cpu".to_string(),
+            key: "host".to_string(),
+            start,
+            end,
+        };
+
+        let Json(response) = tag_values(State(engine.clone()), Path("testdb".to_string()), Query(params(None, None)))
+            .await
+            .unwrap();
+        assert_eq!(response.key, "host");
+        assert_eq!(response.values, vec!["db1", "web1", "web2"]);
+
+        // Only series seen in the window, from both SSTables and the MemTable
+        let Json(response) = tag_values(State(engine.clone()), Path("testdb".to_string()), Query(params(Some(1500), Some(3500))))
+            .await
+            .unwrap();
+        assert_eq!(response.values, vec!["db1", "web1"]);
+
+        let missing = tag_values(State(engine), Path("nope".to_string()), Query(params(None, None))).await;
+        assert!(matches!(missing, Err((StatusCode::NOT_FOUND, _))));
+    }
+
+    #[tokio::test]
+    async fn test_debug_series() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+
+        // Older points end up in an SSTable, newer ones stay in the MemTable
+        let flushed = parse_line_protocol("cpu,host=a usage=1,idle=9 1000\ncpu,host=a usage=2,idle=8 2000", "ns", 0, None).unwrap().points;
+        engine.write("testdb", &flushed).unwrap();
+        engine.flush_all().unwrap();
+        let buffered = parse_line_protocol("cpu,host=a usage=3,idle=7 3000\ncpu,host=b usage=4 3000", "ns", 0, None).unwrap().points;
+        engine.write("testdb", &buffered).unwrap();
+
+        let params = |pairs: &[(&str, &str)]| {
+            Query(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>())
+        };
+
+        let Json(resp) = debug_series(
+            State(engine.clone()),
+            Path("testdb".to_string()),
+            params(&[("measurement", "cpu"), ("tag", "host:a")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.series, "cpu,host=a");
+        let times: Vec<_> = resp.points.iter().map(|p| p.timestamp).collect();
+        assert_eq!(times, vec![1000, 2000, 3000]);
+        assert_eq!(resp.points[0].fields.get("idle"), Some(&FieldValue::Float(9.0)));
+        assert_eq!(resp.points[2].fields.get("usage"), Some(&FieldValue::Float(3.0)));
+
+        let Json(resp) = debug_series(
+            State(engine.clone()),
+            Path("testdb".to_string()),
+            params(&[("measurement", "cpu"), ("tag", "host:a"), ("start", "1500"), ("end", "2500")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.points.len(), 1);
+        assert_eq!(resp.points[0].timestamp, 2000);
+
+        let bad = debug_series(State(engine.clone()), Path("testdb".to_string()), params(&[("tag", "host:a")])).await;
+        assert!(matches!(bad, Err((StatusCode::BAD_REQUEST, _))));
+
+        let missing = debug_series(State(engine), Path("nope".to_string()), params(&[("measurement", "cpu")])).await;
+        assert!(matches!(missing, Err((StatusCode::NOT_FOUND, _))));
+    }
+
+    #[tokio::test]
+    async fn test_query_columnar_format() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+
+        // Host b has no temp readings, so its mean(temp) is null
+        let points = parse_line_protocol(
+            "cpu,host=a usage=10,temp=50 1000\ncpu,host=a usage=20,temp=60 2000\ncpu,host=b usage=30 3000",
+            "ns",
+            0,
+            None,
+        ).unwrap().points;
+        engine.write("testdb", &points).unwrap();
+
+        let run = |format: Option<&str>| {
+            let params = QueryParams {
+                db: Some("testdb".to_string()),
+                q: Some("SELECT mean(usage), mean(temp) FROM cpu GROUP BY time('1000ns'), host".to_string()),
+                format: format.map(String::from),
+                pretty: None,
+                page_size: None,
+                cursor: None,
+            };
+            execute_query(&engine, params)
+        };
+
+        let rows = run(None).unwrap();
+        let columnar = run(Some("columnar")).unwrap();
+        let rows = serde_json::to_value(&rows).unwrap();
+        let columnar = serde_json::to_value(&columnar).unwrap();
+
+        let row_series = &rows["results"][0]["series"][0];
+        let col_series = &columnar["results"][0]["series"][0];
+        let names = row_series["columns"].as_array().unwrap();
+        let values = row_series["values"].as_array().unwrap();
+        assert_eq!(values.len(), 3);
+        assert_eq!(col_series["columns"].as_object().unwrap().len(), names.len());
+
+        // Each column array is the matching slice of the row-oriented values
+        for (i, name) in names.iter().enumerate() {
+            let expected: Vec<_> = values.iter().map(|row| row[i].clone()).collect();
+            assert_eq!(col_series["columns"][name.as_str().unwrap()], serde_json::json!(expected));
+        }
+        let temps = col_series["columns"]["mean_temp"].as_array().unwrap();
+        assert!(temps.iter().any(|v| v.is_null()));
+
+        let bad = run(Some("xml"));
+        assert!(matches!(bad, Err((StatusCode::BAD_REQUEST, _))));
+    }
+
+    #[tokio::test]
+    async fn test_query_pages_with_cursor() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let lines: Vec<String> = (0..3)
+            .flat_map(|host| (0..20).map(move |ts| format!("cpu,host=h{} usage={} {}", host, ts, ts)))
+            .collect();
+        engine.write("testdb", &parse_line_protocol(&lines.join("\n"), "ns", 0, None).unwrap().points).unwrap();
+
+        let run = |q: &str, page_size: Option<usize>, cursor: Option<String>| {
+            let params = QueryParams {
+                db: Some("testdb".to_string()),
+                q: Some(q.to_string()),
+                format: None,
+                pretty: None,
+                page_size,
+                cursor,
+            };
+            execute_query(&engine, params).map(|response| serde_json::to_value(&response).unwrap())
+        };
+        let rows = |response: &serde_json::Value| -> Vec<(String, i64)> {
+            response["results"][0]["series"][0]["values"]
+                .as_array()
+                .map(|values| {
+                    values.iter().map(|row| (row[1].as_str().unwrap().to_string(), row[0].as_i64().unwrap())).collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let everything = rows(&run("SELECT usage FROM cpu", None, None).unwrap());
+        assert_eq!(everything.len(), 60);
+
+        // Pages of 7 cover every row exactly once, in order
+        let mut paged = Vec::new();
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let response = run("SELECT usage FROM cpu", Some(7), cursor).unwrap();
+            let page = rows(&response);
+            assert!(page.len() <= 7);
+            paged.extend(page);
+            pages += 1;
+            cursor = response["results"][0]["next_cursor"].as_str().map(String::from);
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(pages, 9);
+        assert_eq!(paged, everything);
+
+        let bad = run("SELECT usage FROM cpu", Some(7), Some("not a cursor".to_string()));
+        assert!(matches!(bad, Err((StatusCode::BAD_REQUEST, _))));
+        let ordered = run("SELECT usage FROM cpu ORDER BY usage", Some(7), None).unwrap();
+        assert!(ordered["results"][0]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_query_jsonl_format() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let points = parse_line_protocol(
+            "cpu,host=a usage=10,temp=50 1000\ncpu,host=a usage=20,temp=60 2000\ncpu,host=b usage=30 3000",
+            "ns",
+            0,
+            None,
+        ).unwrap().points;
+        engine.write("testdb", &points).unwrap();
+
+        let run = |q: &str| {
+            let params = QueryParams {
+                db: Some("testdb".to_string()),
+                q: Some(q.to_string()),
+                format: Some("jsonl".to_string()),
+                pretty: None,
+                page_size: None,
+                cursor: None,
+            };
+            let engine = engine.clone();
+            async move {
+                let response = query(State(engine), Query(params)).await.unwrap();
+                assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-ndjson");
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                String::from_utf8(body.to_vec())
+                    .unwrap()
+                    .lines()
+                    .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let lines = run("SELECT mean(usage), mean(temp) FROM cpu GROUP BY time('1000ns'), host").await;
+        assert_eq!(lines.len(), 3);
+        for line in &lines {
+            let object = line.as_object().unwrap();
+            assert_eq!(object.len(), 4, "{}", line);
+            assert!(object["time"].is_i64());
+        }
+        let b = lines.iter().find(|line| line["host"] == "b").unwrap();
+        assert_eq!(b["mean_usage"], serde_json::json!(30.0));
+        // Missing values are explicit nulls
+        assert!(b["mean_temp"].is_null());
+
+        // Failures get an error status before any line is streamed
+        let failed = |q: &str| {
+            let params = QueryParams {
+                db: Some("testdb".to_string()),
+                q: Some(q.to_string()),
+                format: Some("jsonl".to_string()),
+                pretty: None,
+                page_size: None,
+                cursor: None,
+            };
+            query(State(engine.clone()), Query(params))
+        };
+        assert!(matches!(failed("SELECT FROM").await, Err((StatusCode::BAD_REQUEST, _))));
+        assert!(matches!(failed("SELECT usage FROM mem").await, Err((StatusCode::NOT_FOUND, _))));
+    }
+
+    #[tokio::test]
+    async fn test_query_pretty_json() {
+        use fluxdb_core::storage::StorageConfig;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+        let points = parse_line_protocol("cpu,host=a usage=10 1000\ncpu,host=a usage=20 2000", "ns", 0, None).unwrap().points;
+        engine.write("testdb", &points).unwrap();
+
+        let run = |pretty: Option<bool>| {
+            let params = QueryParams {
+                db: Some("testdb".to_string()),
+                q: Some("SELECT usage FROM cpu".to_string()),
+                format: None,
+                pretty,
+                page_size: None,
+                cursor: None,
+            };
+            let engine = engine.clone();
+            async move {
+                let response = query(State(engine), Query(params)).await.unwrap();
+                assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let compact = run(None).await;
+        let pretty = run(Some(true)).await;
+        assert!(!compact.contains('\n'));
+        assert!(pretty.contains('\n'));
+        assert_eq!(run(Some(false)).await, compact);
+
+        // Same document either way
+        let compact: serde_json::Value = serde_json::from_str(&compact).unwrap();
+        let pretty: serde_json::Value = serde_json::from_str(&pretty).unwrap();
+        assert_eq!(compact, pretty);
+    }
 }
@@ -2,12 +2,14 @@
 
 mod api;
 mod protocol;
+mod replica;
 
 use fluxdb_core::storage::{StorageConfig, StorageEngine};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::{info, Level};
+use std::time::Duration;
+use tracing::{error, info, Level};
 use tracing_subscriber::FmtSubscriber;
 
 /// Server configuration
@@ -15,19 +17,66 @@ use tracing_subscriber::FmtSubscriber;
 pub struct ServerConfig {
     /// HTTP listen address
     pub http_addr: SocketAddr,
+    /// Streaming write listen address (`mode=stream`), disabled if `None`
+    ///
+    /// The listener is unauthenticated, so it is off unless enabled with
+    /// the `FLUXDB_STREAM_ADDR` environment variable.
+    pub stream_addr: Option<SocketAddr>,
     /// Data directory
     pub data_dir: PathBuf,
+    /// Primary to replicate from (`host:port`); the server is read-only if set
+    ///
+    /// Set with the `FLUXDB_REPLICA_OF` environment variable.
+    pub replica_of: Option<String>,
+    /// How often a replica polls its primary for new WAL segments
+    pub replica_poll_interval: Duration,
+    /// Largest accepted request body in bytes; bigger writes get 413
+    ///
+    /// Set with the `FLUXDB_MAX_BODY_SIZE` environment variable.
+    pub max_body_size: usize,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             http_addr: "0.0.0.0:8086".parse().unwrap(),
+            stream_addr: None,
             data_dir: PathBuf::from("data"),
+            replica_of: None,
+            replica_poll_interval: Duration::from_secs(1),
+            max_body_size: api::DEFAULT_MAX_BODY_SIZE,
         }
     }
 }
 
+impl ServerConfig {
+    /// The default configuration with overrides from environment variables,
+    /// looked up with `var`
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if let Some(addr) = var("FLUXDB_STREAM_ADDR") {
+            config.stream_addr = Some(addr.parse()?);
+        }
+        if let Some(primary) = var("FLUXDB_REPLICA_OF") {
+            let primary = primary.trim();
+            let valid = primary
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+            if !valid {
+                anyhow::bail!("FLUXDB_REPLICA_OF must be host:port, got {:?}", primary);
+            }
+            config.replica_of = Some(primary.to_string());
+        }
+        if let Some(size) = var("FLUXDB_MAX_BODY_SIZE") {
+            config.max_body_size = size
+                .trim()
+                .parse()
+                .map_err(|_| anyhow::anyhow!("FLUXDB_MAX_BODY_SIZE must be a number of bytes, got {:?}", size))?;
+        }
+        Ok(config)
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Initialize logging
@@ -38,7 +87,7 @@ async fn main() -> anyhow::Result<()> {
         .pretty()
         .init();
 
-    let config = ServerConfig::default();
+    let config = ServerConfig::from_env(|name| std::env::var(name).ok())?;
     
     info!("Starting FluxDB server...");
     info!("Data directory: {:?}", config.data_dir);
@@ -47,14 +96,38 @@ async fn main() -> anyhow::Result<()> {
     // Initialize storage engine
     let storage_config = StorageConfig {
         data_dir: config.data_dir.clone(),
+        read_only: config.replica_of.is_some(),
         ..Default::default()
     };
     
     let engine = StorageEngine::new(storage_config)?;
     let engine = Arc::new(engine);
 
+    // Tail the primary's WAL in replica mode
+    if let Some(primary) = config.replica_of.clone() {
+        tokio::spawn(replica::run(
+            engine.clone(),
+            replica::ReplicaConfig {
+                primary,
+                poll_interval: config.replica_poll_interval,
+            },
+        ));
+    }
+
+    // Start the streaming write listener alongside HTTP
+    if let Some(stream_addr) = config.stream_addr {
+        let listener = tokio::net::TcpListener::bind(&stream_addr).await?;
+        info!("FluxDB stream listener on {}", stream_addr);
+        let engine = engine.clone();
+        tokio::spawn(async move {
+            if let Err(e) = protocol::stream::serve(listener, engine).await {
+                error!("Stream listener failed: {}", e);
+            }
+        });
+    }
+
     // Create router
-    let app = api::create_router(engine.clone());
+    let app = api::create_router(engine.clone(), config.max_body_size);
 
     // Start server
     let listener = tokio::net::TcpListener::bind(&config.http_addr).await?;
@@ -64,3 +137,34 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn from_vars(vars: &[(&str, &str)]) -> anyhow::Result<ServerConfig> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        ServerConfig::from_env(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_config_from_env() {
+        let config = from_vars(&[]).unwrap();
+        assert_eq!(config.replica_of, None);
+        assert_eq!(config.stream_addr, None);
+        assert_eq!(config.max_body_size, api::DEFAULT_MAX_BODY_SIZE);
+
+        let config = from_vars(&[("FLUXDB_REPLICA_OF", "primary.local:8086"), ("FLUXDB_STREAM_ADDR", "127.0.0.1:8089")]).unwrap();
+        assert_eq!(config.replica_of.as_deref(), Some("primary.local:8086"));
+        assert_eq!(config.stream_addr, Some("127.0.0.1:8089".parse().unwrap()));
+
+        for bad in ["primary.local", ":8086", "primary.local:http", ""] {
+            assert!(from_vars(&[("FLUXDB_REPLICA_OF", bad)]).is_err(), "{:?}", bad);
+        }
+
+        let config = from_vars(&[("FLUXDB_MAX_BODY_SIZE", "1048576")]).unwrap();
+        assert_eq!(config.max_body_size, 1024 * 1024);
+        assert!(from_vars(&[("FLUXDB_MAX_BODY_SIZE", "1MB")]).is_err());
+    }
+}
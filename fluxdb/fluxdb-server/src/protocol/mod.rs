@@ -3,6 +3,8 @@
 // Line protocol parsing is in api/mod.rs
 // This module can be extended with additional protocols
 
+pub mod stream;
+
 pub mod line_protocol {
     //! InfluxDB line protocol support
     //! Already implemented in api/mod.rs
@@ -0,0 +1,179 @@
+//! Streaming write protocol (`mode=stream`)
+//!
+//! Producers open a persistent TCP connection and send framed
+//! [`WriteRequest`] messages, receiving one [`WriteResponse`] per frame.
+//!
+//! Frame format:
+//! - 4 bytes: payload length (big-endian u32)
+//! - N bytes: bincode-encoded payload
+//!
+//! Requests are processed in order, so responses line up with frames.
+
+use fluxdb_core::storage::StorageEngine;
+use fluxdb_core::Point;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+/// Largest payload accepted in a single frame (64MB)
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// A batch of points for one database
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteRequest {
+    pub database: String,
+    pub points: Vec<Point>,
+}
+
+/// Per-frame acknowledgement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WriteResponse {
+    /// All points in the frame were written
+    Ack { points: usize },
+    /// The frame was rejected; the connection stays usable
+    Nack { error: String },
+}
+
+/// Read one frame, returning `None` on a clean end of stream
+///
+/// `read_exact` keeps reading across partial TCP reads until the whole
+/// frame has arrived.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    match reader.read_exact(&mut len_buf).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame too large: {} bytes", len),
+        ));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+/// Write one length-prefixed frame
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Frame too large: {} bytes", payload.len()),
+        ));
+    }
+    writer.write_all(&(payload.len() as u32).to_be_bytes()).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
+
+/// Accept streaming connections until the listener fails
+pub async fn serve(listener: TcpListener, engine: Arc<StorageEngine>) -> io::Result<()> {
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let engine = engine.clone();
+        tokio::spawn(async move {
+            debug!("Stream connection from {}", peer);
+            if let Err(e) = handle_connection(socket, engine).await {
+                warn!("Stream connection {} closed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut socket: TcpStream, engine: Arc<StorageEngine>) -> io::Result<()> {
+    while let Some(frame) = read_frame(&mut socket).await? {
+        let response = handle_frame(&frame, &engine);
+        let encoded = bincode::serialize(&response)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_frame(&mut socket, &encoded).await?;
+    }
+    Ok(())
+}
+
+fn handle_frame(frame: &[u8], engine: &StorageEngine) -> WriteResponse {
+    let request: WriteRequest = match bincode::deserialize(frame) {
+        Ok(request) => request,
+        Err(e) => {
+            return WriteResponse::Nack {
+                error: format!("Invalid write request: {}", e),
+            }
+        }
+    };
+
+    match engine.write(&request.database, &request.points) {
+        Ok(points) => WriteResponse::Ack { points },
+        Err(e) => WriteResponse::Nack { error: e.to_string() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fluxdb_core::storage::StorageConfig;
+    use fluxdb_core::{DataPoint, FieldValue, SeriesKey};
+
+    async fn read_response(socket: &mut TcpStream) -> WriteResponse {
+        let frame = read_frame(socket).await.unwrap().unwrap();
+        bincode::deserialize(&frame).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_writes() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = StorageConfig {
+            data_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let engine = Arc::new(StorageEngine::new(config).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, engine.clone()));
+
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        for batch in 0..3 {
+            let points = (0..10)
+                .map(|i| {
+                    let ts = batch * 10 + i;
+                    Point::new(SeriesKey::new("cpu"), DataPoint::new(ts, "usage", FieldValue::Float(ts as f64)))
+                })
+                .collect();
+            let request = WriteRequest { database: "testdb".to_string(), points };
+            let payload = bincode::serialize(&request).unwrap();
+
+            if batch == 1 {
+                // Split the frame across writes to exercise partial reads
+                let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+                frame.extend_from_slice(&payload);
+                let (head, tail) = frame.split_at(6);
+                socket.write_all(head).await.unwrap();
+                socket.flush().await.unwrap();
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                socket.write_all(tail).await.unwrap();
+            } else {
+                write_frame(&mut socket, &payload).await.unwrap();
+            }
+
+            assert_eq!(read_response(&mut socket).await, WriteResponse::Ack { points: 10 });
+        }
+
+        // A garbage frame is rejected without dropping the connection
+        write_frame(&mut socket, b"not bincode").await.unwrap();
+        assert!(matches!(read_response(&mut socket).await, WriteResponse::Nack { .. }));
+
+        let db = engine.get_database("testdb").unwrap();
+        let points = db
+            .query_series(&SeriesKey::new("cpu"), &fluxdb_core::TimeRange::new(0, 100))
+            .unwrap();
+        assert_eq!(points.len(), 30);
+    }
+}
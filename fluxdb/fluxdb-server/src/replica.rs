@@ -0,0 +1,188 @@
+//! Read-only replica mode
+//!
+//! A replica polls its primary over HTTP and applies the primary's WAL to
+//! its own storage, one database at a time:
+//!
+//! 1. `GET /databases` lists the primary's databases
+//! 2. `GET /databases/:name/wal/segments?after=<id>` returns the next
+//!    complete WAL segment after the last one applied, until the primary
+//!    answers 204
+//!
+//! The last applied segment is stored with each database (see
+//! `Database::replica_position`), so a restarted replica resumes where it
+//! stopped. Segments the primary has truncated after a flush can't be
+//! replayed; replication of that database stops with an error and the
+//! replica has to be rebuilt from a copy of the primary's data directory.
+
+use crate::api::WAL_SEGMENT_HEADER;
+use anyhow::{anyhow, bail, Context};
+use fluxdb_core::storage::StorageEngine;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::{header, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+/// Replica configuration
+#[derive(Debug, Clone)]
+pub struct ReplicaConfig {
+    /// Primary's HTTP address (`host:port`)
+    pub primary: String,
+    /// Delay between polls once caught up
+    pub poll_interval: Duration,
+}
+
+/// Replicate from the primary until the process exits
+pub async fn run(engine: Arc<StorageEngine>, config: ReplicaConfig) {
+    info!("Replicating from primary {}", config.primary);
+    loop {
+        match sync_once(&engine, &config.primary).await {
+            Ok(0) => {}
+            Ok(points) => debug!("Replicated {} points from {}", points, config.primary),
+            Err(e) => warn!("Replication from {} failed: {:#}", config.primary, e),
+        }
+        tokio::time::sleep(config.poll_interval).await;
+    }
+}
+
+/// Apply everything the primary has written since the last sync
+///
+/// Returns the number of points applied. A database that can't be synced
+/// is logged and skipped so the others keep replicating.
+pub async fn sync_once(engine: &StorageEngine, primary: &str) -> anyhow::Result<usize> {
+    let response = get(primary, "/databases").await?;
+    if response.status() != StatusCode::OK {
+        bail!("listing databases returned {}", response.status());
+    }
+    let databases: Vec<String> = serde_json::from_slice(response.body())?;
+
+    let mut applied = 0;
+    for database in databases {
+        match sync_database(engine, primary, &database).await {
+            Ok(points) => applied += points,
+            Err(e) => warn!("Replicating database {} failed: {:#}", database, e),
+        }
+    }
+    Ok(applied)
+}
+
+async fn sync_database(engine: &StorageEngine, primary: &str, database: &str) -> anyhow::Result<usize> {
+    let mut applied = 0;
+    loop {
+        let after = match engine.get_database(database) {
+            Some(db) => db.replica_position()?,
+            None => None,
+        };
+        let path = match after {
+            Some(id) => format!("/databases/{}/wal/segments?after={}", database, id),
+            None => format!("/databases/{}/wal/segments", database),
+        };
+
+        let response = get(primary, &path).await?;
+        match response.status() {
+            StatusCode::OK => {
+                let segment_id = response
+                    .headers()
+                    .get(WAL_SEGMENT_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| anyhow!("response is missing a valid {} header", WAL_SEGMENT_HEADER))?;
+                applied += engine.apply_replicated(database, segment_id, response.body())?;
+            }
+            StatusCode::NO_CONTENT => return Ok(applied),
+            StatusCode::GONE => bail!(
+                "primary no longer has the WAL segment after {:?}; rebuild the replica from a copy of the primary",
+                after
+            ),
+            status => bail!("fetching WAL segments returned {}", status),
+        }
+    }
+}
+
+/// Issue a GET on a fresh HTTP/1.1 connection and read the whole body
+async fn get(primary: &str, path: &str) -> anyhow::Result<Response<Bytes>> {
+    let stream = TcpStream::connect(primary)
+        .await
+        .with_context(|| format!("connecting to {}", primary))?;
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("Replica connection closed: {}", e);
+        }
+    });
+
+    let request = Request::get(path)
+        .header(header::HOST, primary)
+        .body(Empty::<Bytes>::new())?;
+    let (parts, body) = sender.send_request(request).await?.into_parts();
+    let body = body.collect().await?.to_bytes();
+    Ok(Response::from_parts(parts, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fluxdb_core::query::QueryValue;
+    use fluxdb_core::storage::StorageConfig;
+    use fluxdb_core::{DataPoint, FieldValue, FluxError, Point, SeriesKey};
+
+    fn points(start: i64) -> Vec<Point> {
+        (start..start + 5)
+            .map(|i| {
+                let key = SeriesKey::new("cpu").with_tag("host", "a");
+                Point::new(key, DataPoint::new(i, "usage", FieldValue::Float(i as f64)))
+            })
+            .collect()
+    }
+
+    fn count(engine: &StorageEngine) -> QueryValue {
+        let result = engine.query("testdb", "SELECT count(usage) FROM cpu").unwrap();
+        result.rows[0].values[0].clone()
+    }
+
+    #[tokio::test]
+    async fn test_replicate_from_primary() {
+        let primary_dir = tempfile::TempDir::new().unwrap();
+        let replica_dir = tempfile::TempDir::new().unwrap();
+        let primary = Arc::new(
+            StorageEngine::new(StorageConfig {
+                data_dir: primary_dir.path().to_path_buf(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let replica = StorageEngine::new(StorageConfig {
+            data_dir: replica_dir.path().to_path_buf(),
+            read_only: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let router = crate::api::create_router(primary.clone(), crate::api::DEFAULT_MAX_BODY_SIZE);
+        tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        primary.write("testdb", &points(0)).unwrap();
+        primary.write("testdb", &points(5)).unwrap();
+        assert_eq!(sync_once(&replica, &addr).await.unwrap(), 10);
+        assert_eq!(count(&replica), QueryValue::Integer(10));
+
+        // Caught up: nothing more to apply
+        assert_eq!(sync_once(&replica, &addr).await.unwrap(), 0);
+
+        primary.write("testdb", &points(10)).unwrap();
+        assert_eq!(sync_once(&replica, &addr).await.unwrap(), 5);
+        assert_eq!(count(&replica), QueryValue::Integer(15));
+        let db = replica.get_database("testdb").unwrap();
+        assert_eq!(db.replica_position().unwrap(), Some(1));
+
+        // The replica itself only serves reads
+        assert!(matches!(replica.write("testdb", &points(100)), Err(FluxError::ReadOnly(_))));
+    }
+}